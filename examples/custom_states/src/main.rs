@@ -8,7 +8,7 @@ use tokio::signal;
 struct MeetingBehavior;
 
 impl CustomBehavior for MeetingBehavior {
-    fn on_incoming_chime(&self, chime: &ChimeMessage, state: &CustomLcgpState) -> BehaviorResult {
+    fn on_incoming_chime(&self, chime: &ChimeMessage, _state: &CustomLcgpState) -> BehaviorResult {
         // In meeting mode, we don't chime but log the attempt
         info!(
             "Meeting mode: Silently logged chime from {}",
@@ -20,6 +20,7 @@ impl CustomBehavior for MeetingBehavior {
             auto_response: Some(ChimeResponse::Negative), // Auto-decline
             delay_ms: Some(2000),                         // Wait 2 seconds before responding
             next_state: None,
+            note: Some("In a meeting, will call you back".to_string()), // Tells the ringer why
         }
     }
 
@@ -35,12 +36,14 @@ impl CustomBehavior for MeetingBehavior {
                 auto_response: None,
                 delay_ms: None,
                 next_state: Some("Available".to_string()),
+                note: None,
             },
-            ChimeResponse::Negative => BehaviorResult {
+            ChimeResponse::Negative | ChimeResponse::Snooze { .. } => BehaviorResult {
                 should_chime: false,
                 auto_response: None,
                 delay_ms: None,
                 next_state: None, // Stay in meeting mode
+                note: None,
             },
         }
     }
@@ -52,10 +55,11 @@ impl CustomBehavior for MeetingBehavior {
             auto_response: Some(ChimeResponse::Negative),
             delay_ms: None,
             next_state: None,
+            note: None,
         }
     }
 
-    fn evaluate_conditions(&self, state: &CustomLcgpState) -> bool {
+    fn evaluate_conditions(&self, _state: &CustomLcgpState) -> bool {
         // This would check calendar integration, but for demo we'll keep it simple
         true
     }
@@ -65,7 +69,7 @@ impl CustomBehavior for MeetingBehavior {
 struct FocusBehavior;
 
 impl CustomBehavior for FocusBehavior {
-    fn on_incoming_chime(&self, chime: &ChimeMessage, state: &CustomLcgpState) -> BehaviorResult {
+    fn on_incoming_chime(&self, chime: &ChimeMessage, _state: &CustomLcgpState) -> BehaviorResult {
         // In focus mode, we collect chimes and respond later
         info!(
             "Focus mode: Queuing chime from {} for later",
@@ -77,12 +81,13 @@ impl CustomBehavior for FocusBehavior {
             auto_response: None,   // No immediate response
             delay_ms: Some(30000), // Wait 30 seconds before auto-responding
             next_state: None,
+            note: None,
         }
     }
 
     fn on_user_response(
         &self,
-        response: &ChimeResponse,
+        _response: &ChimeResponse,
         _state: &CustomLcgpState,
     ) -> BehaviorResult {
         BehaviorResult {
@@ -90,6 +95,7 @@ impl CustomBehavior for FocusBehavior {
             auto_response: None,
             delay_ms: None,
             next_state: Some("ChillGrinding".to_string()), // Transition to chill grinding
+            note: None,
         }
     }
 
@@ -100,6 +106,7 @@ impl CustomBehavior for FocusBehavior {
             auto_response: Some(ChimeResponse::Positive),
             delay_ms: None,
             next_state: Some("Available".to_string()),
+            note: Some("Back from focusing".to_string()),
         }
     }
 
@@ -134,14 +141,23 @@ struct Args {
     /// Available chords (comma-separated)
     #[arg(long, default_value = "C,Am,F,G,Dm,Em")]
     chords: String,
+
+    /// JSON file of custom states, loaded over the built-in ones. Created
+    /// with the built-in states if missing, as a starting point to edit.
+    #[arg(long)]
+    states_file: Option<std::path::PathBuf>,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
 
-    let args = Args::parse();
+    let started = std::time::Instant::now();
+    let result = run(Args::parse()).await;
+    std::process::exit(ShutdownSummary::new("custom_states", &result, started.elapsed()).report());
+}
 
+async fn run(args: Args) -> Result<()> {
     info!("Starting custom state chime: {}", args.name);
     info!("Connecting to MQTT broker: {}", args.broker);
 
@@ -161,13 +177,16 @@ async fn main() -> Result<()> {
         args.description,
         notes,
         chords,
-        args.user.clone(),
+        args.user.clone().into(),
         &args.broker,
     )
     .await?;
 
     // Register custom states
     setup_custom_states(&chime).await?;
+    if let Some(path) = &args.states_file {
+        load_states_file(&chime, path)?;
+    }
 
     chime.start().await?;
 
@@ -175,9 +194,12 @@ async fn main() -> Result<()> {
     info!("  mode <mode>  - Set LCGP mode (DoNotDisturb, Available, ChillGrinding, Grinding, or custom state name)");
     info!("  custom <state> - Set custom state");
     info!("  list-custom - List available custom states");
+    info!("  define-state <name> [chime=<bool>] [auto=<pos|neg|none>] [delay=<ms>] [priority=<n>] - Define or replace a custom state");
+    info!("  delete-state <name> - Delete a custom state");
     info!("  ring <user> <chime_id> [notes] [chords] - Ring another chime");
-    info!("  respond <pos|neg> [chime_id] - Respond to a chime");
-    info!("  condition <key> <value> - Set condition (true/false)");
+    info!("  respond <pos|neg|snooze [minutes]> [chime_id] - Respond to a chime");
+    info!("  condition <key> <value> - Set condition (true/false, a number or text)");
+    info!("  preview [from_node] [notes] - Show how a ring would be handled right now, without ringing");
     info!("  status - Show current status");
     info!("  quit - Exit");
 
@@ -220,7 +242,32 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn setup_custom_states(chime: &ChimeInstance) -> Result<()> {
+// File states replace built-in ones of the same name but keep their behavior,
+// since behaviors are registered by name
+fn load_states_file(chime: &ChimeInstance, path: &std::path::Path) -> Result<()> {
+    if !path.exists() {
+        let saved = chime.lcgp_handler.save_states_to_file(path)?;
+        info!("Wrote {} built-in states to {}", saved, path.display());
+        return Ok(());
+    }
+
+    let report = chime.lcgp_handler.load_states_from_file(path)?;
+    for (name, errors) in &report.invalid {
+        error!("Skipped invalid state '{}': {}", name, errors.join("; "));
+    }
+    info!(
+        "Loaded {} states from {} ({} replaced built-in ones, config only: {:?})",
+        report.imported.len(),
+        path.display(),
+        report.overwritten.len(),
+        report.without_behavior
+    );
+    Ok(())
+}
+
+// The states this example starts with. Each must pass `CustomLcgpState::validate`,
+// or --states-file would skip it when reading back the file written from them.
+fn builtin_states() -> Vec<CustomLcgpState> {
     // Create "Meeting" state
     let meeting_state = CustomLcgpState {
         name: "Meeting".to_string(),
@@ -246,8 +293,9 @@ async fn setup_custom_states(chime: &ChimeInstance) -> Result<()> {
     let focus_state = CustomLcgpState {
         name: "Focus".to_string(),
         should_chime: false,
+        // FocusBehavior answers once its 30 second delay runs out
         auto_response: None,
-        auto_response_delay: Some(30000), // 30 seconds
+        auto_response_delay: None,
         description: Some("Focus mode, delayed response after 30 seconds".to_string()),
         priority: Some(50), // Medium priority
         active_hours: None, // Available anytime
@@ -275,10 +323,14 @@ async fn setup_custom_states(chime: &ChimeInstance) -> Result<()> {
         conditions: vec![],
     };
 
+    vec![meeting_state, focus_state, lunch_state]
+}
+
+async fn setup_custom_states(chime: &ChimeInstance) -> Result<()> {
     // Register states
-    chime.lcgp_handler.register_custom_state(meeting_state);
-    chime.lcgp_handler.register_custom_state(focus_state);
-    chime.lcgp_handler.register_custom_state(lunch_state);
+    for state in builtin_states() {
+        chime.lcgp_handler.register_custom_state(state);
+    }
 
     // Register custom behaviors
     chime
@@ -346,22 +398,78 @@ async fn handle_command(chime: &ChimeInstance, command: &str) -> Result<()> {
         }
 
         "list-custom" => {
-            let states = chime.lcgp_handler.get_available_custom_states();
-            println!("Available custom states: {:?}", states);
+            let states = chime.lcgp_handler.describe_custom_states();
+            if states.is_empty() {
+                println!("No custom states registered");
+            } else {
+                println!("Available custom states:");
+                for state in states {
+                    let kind = if state.has_behavior {
+                        "smart (custom behavior)"
+                    } else {
+                        "config-driven"
+                    };
+                    println!("  {} - {}", state.name, kind);
+                    if let Some(desc) = &state.description {
+                        println!("    {}", desc);
+                    }
+                }
+            }
+        }
+
+        "define-state" => {
+            if parts.len() < 2 {
+                println!("Usage: define-state <name> [chime=<bool>] [auto=<pos|neg|none>] [delay=<ms>] [priority=<n>]");
+                println!("Example: define-state Gym chime=false auto=neg delay=1000 priority=60");
+                return Ok(());
+            }
+
+            match CustomLcgpState::from_args(parts[1], &parts[2..]) {
+                Ok(state) => {
+                    println!(
+                        "Defined custom state '{}' - should_chime: {}, auto_response: {:?}",
+                        state.name, state.should_chime, state.auto_response
+                    );
+                    // Replaces a state of the same name, keeping its behavior
+                    chime.lcgp_handler.register_custom_state(state);
+                }
+                Err(e) => println!("Invalid state: {}", e),
+            }
+        }
+
+        "delete-state" => {
+            if parts.len() != 2 {
+                println!("Usage: delete-state <name>");
+                return Ok(());
+            }
+
+            let name = parts[1];
+            let was_current = chime.lcgp_node.get_mode() == LcgpMode::Custom(name.to_string());
+            match chime.lcgp_handler.remove_custom_state(name) {
+                Some(_) => println!("Deleted custom state '{}'", name),
+                None => println!("No custom state '{}'", name),
+            }
+            // The handler publishes the fallback to Available itself
+            if was_current && chime.lcgp_node.get_mode() == LcgpMode::Available {
+                println!("Mode set to: Available");
+            }
         }
 
         "condition" => {
-            if parts.len() != 3 {
+            if parts.len() < 3 {
                 println!("Usage: condition <key> <value>");
                 println!("Example: condition calendar_busy true");
+                println!("Example: condition system_load 0.9");
+                println!("Example: condition location home office");
                 return Ok(());
             }
 
+            // true/false, a number, or text (the rest of the line)
             let key = parts[1].to_string();
-            let value = parts[2].parse::<bool>().unwrap_or(false);
+            let Ok(value) = parts[2..].join(" ").parse::<ConditionValue>();
 
-            chime.lcgp_handler.set_condition(key.clone(), value);
-            println!("Condition set: {} = {}", key, value);
+            println!("Condition set: {} = {:?}", key, value);
+            chime.lcgp_handler.set_condition(key, value);
         }
 
         "ring" => {
@@ -384,34 +492,58 @@ async fn handle_command(chime: &ChimeInstance, command: &str) -> Result<()> {
             };
 
             chime
-                .ring_other_chime(user, chime_id, notes, chords, None)
+                .ring_other_chime(&user.into(), &chime_id.into(), notes, chords, None)
                 .await?;
             println!("Sent ring request to {}/{}", user, chime_id);
         }
 
         "respond" => {
             if parts.len() < 2 {
-                println!("Usage: respond <pos|neg> [chime_id]");
+                println!("Usage: respond <pos|neg|snooze [minutes]> [chime_id]");
                 return Ok(());
             }
 
-            let response = match parts[1] {
-                "pos" => ChimeResponse::Positive,
-                "neg" => ChimeResponse::Negative,
-                _ => {
-                    println!("Invalid response. Use: pos or neg");
+            let (response, rest) = match ChimeResponse::from_args(&parts[1..]) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    println!("{}. Use: pos, neg or snooze [minutes]", e);
                     return Ok(());
                 }
             };
 
-            let chime_id = if parts.len() > 2 {
-                Some(parts[2].to_string())
-            } else {
-                None
-            };
+            let chime_id = rest.first().map(|id| ChimeId::from(*id));
 
-            chime.respond_to_chime(response, chime_id).await?;
-            println!("Sent response: {:?}", parts[1]);
+            chime.respond_to_chime(response.clone(), chime_id).await?;
+            println!("Sent response: {:?}", response);
+        }
+
+        "preview" => {
+            let mut ring = chime.lcgp_node.create_chime_message(
+                None,
+                Some(chime.info.id.clone()),
+                parts
+                    .get(2)
+                    .map(|notes| notes.split(',').map(|s| s.trim().to_string()).collect()),
+                None,
+            );
+            if let Some(from_node) = parts.get(1) {
+                ring.from_node = from_node.to_string();
+            }
+
+            let preview = chime.lcgp_handler.preview_reaction(&ring);
+            println!("State: {}", preview.active_state);
+            println!("Would chime: {}", preview.would_chime);
+            match &preview.auto_response {
+                Some((response, Some(delay))) => {
+                    println!("Auto response: {:?} after {}ms", response, delay)
+                }
+                Some((response, None)) => println!("Auto response: {:?} right away", response),
+                None if preview.would_chime => println!("Auto response: none, waits for you"),
+                None => println!("Auto response: none, the ring is swallowed"),
+            }
+            if let Some(note) = &preview.note {
+                println!("Message: {}", note);
+            }
         }
 
         "status" => {
@@ -438,3 +570,29 @@ async fn handle_command(chime: &ChimeInstance, command: &str) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn saved_builtin_states_load_back_without_skips() {
+        let path =
+            std::env::temp_dir().join(format!("custom_states_builtin_{}.json", std::process::id()));
+        let saving = LcgpHandler::new(Arc::new(LcgpNode::new("saving".to_string())));
+        for state in builtin_states() {
+            saving.register_custom_state(state);
+        }
+        assert_eq!(saving.save_states_to_file(&path).unwrap(), 3);
+
+        let loading = LcgpHandler::new(Arc::new(LcgpNode::new("loading".to_string())));
+        let report = loading.load_states_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(report.invalid.is_empty(), "{:?}", report.invalid);
+        let mut imported = report.imported;
+        imported.sort();
+        assert_eq!(imported, vec!["Focus", "Lunch", "Meeting"]);
+    }
+}
@@ -1,18 +1,27 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::Json,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
     routing::{get, post},
     Router,
 };
 use chimenet::*;
 use clap::Parser;
+use futures::{Stream, StreamExt};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::result::Result as StdResult;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, RwLock};
 use tower_http::cors::{Any, CorsLayer};
 
 #[derive(Parser)]
@@ -22,6 +31,9 @@ struct Args {
     #[arg(short, long, default_value = "tcp://localhost:1883")]
     broker: String,
 
+    #[command(flatten)]
+    broker_args: BrokerArgs,
+
     /// HTTP server port
     #[arg(short, long, default_value = "3030")]
     port: u16,
@@ -29,6 +41,30 @@ struct Args {
     /// Users to monitor (comma-separated)
     #[arg(short, long, default_value = "default_user")]
     users: String,
+
+    /// Maximum queued MQTT messages per user before new ones are dropped
+    #[arg(long, default_value = "256")]
+    queue_capacity: usize,
+}
+
+// Maximum number of events kept in memory across all users
+const MAX_EVENTS: usize = 1000;
+
+// Events buffered per /events/stream client; one that falls further behind
+// skips the missed events rather than holding up the others
+const EVENT_STREAM_CAPACITY: usize = 256;
+
+// How often each user monitor checks its broker connection
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting {
+        attempts: u32,
+        last_error: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +85,7 @@ struct ServiceStatus {
     pub active_chimes: usize,
     pub online_chimes: usize,
     pub custom_states: usize,
+    pub connections: HashMap<String, ConnectionState>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +95,7 @@ struct UserStats {
     pub online_chimes: usize,
     pub last_activity: Option<chrono::DateTime<chrono::Utc>>,
     pub events_count: usize,
+    pub dropped_messages: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -77,6 +115,25 @@ struct ResponseStats {
     pub avg_response_time_ms: Option<f64>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ModeDurations {
+    pub window_secs: u64,
+    pub modes: Vec<ModeDuration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ModeDuration {
+    pub mode: LcgpMode,
+    pub seconds: u64,
+    pub fraction: f64, // Share of the window spent in this mode
+}
+
+// Default window for mode durations: one day
+const DEFAULT_MODE_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+// Longest a ring request may wait for the chime's answer (wait_secs)
+const MAX_RING_WAIT_SECS: u64 = 60;
+
 type SharedState = Arc<RwLock<ServiceState>>;
 
 struct ServiceState {
@@ -85,9 +142,15 @@ struct ServiceState {
     events: Vec<ChimeEvent>,
     chime_lists: HashMap<String, ChimeList>,
     chime_statuses: HashMap<String, HashMap<String, ChimeStatus>>,
+    mode_histories: HashMap<String, HashMap<String, ModeHistory>>,
     custom_states: HashMap<String, CustomLcgpState>,
     user_stats: HashMap<String, UserStats>,
     mqtt_clients: HashMap<String, Arc<ChimeNetMqtt>>,
+    dropped_messages: HashMap<String, Arc<AtomicU64>>,
+    connections: HashMap<String, ConnectionState>,
+    event_tx: broadcast::Sender<ChimeEvent>,
+    // Every event since startup by type; `events` only keeps the latest
+    events_by_type: HashMap<String, u64>,
 }
 
 impl ServiceState {
@@ -98,14 +161,25 @@ impl ServiceState {
             events: Vec::new(),
             chime_lists: HashMap::new(),
             chime_statuses: HashMap::new(),
+            mode_histories: HashMap::new(),
             custom_states: HashMap::new(),
             user_stats: HashMap::new(),
             mqtt_clients: HashMap::new(),
+            dropped_messages: HashMap::new(),
+            connections: HashMap::new(),
+            event_tx: broadcast::channel(EVENT_STREAM_CAPACITY).0,
+            events_by_type: HashMap::new(),
         }
     }
 
     fn add_event(&mut self, event: ChimeEvent) {
+        *self
+            .events_by_type
+            .entry(event.event_type.clone())
+            .or_default() += 1;
         self.events.push(event.clone());
+        // Fails only when no stream is open
+        let _ = self.event_tx.send(event.clone());
 
         // Update user stats
         let user_stats = self
@@ -117,17 +191,43 @@ impl ServiceState {
                 online_chimes: 0,
                 last_activity: None,
                 events_count: 0,
+                dropped_messages: 0,
             });
 
         user_stats.events_count += 1;
         user_stats.last_activity = Some(event.timestamp);
 
-        // Keep only last 1000 events
-        if self.events.len() > 1000 {
-            self.events.remove(0);
+        // Keep only the last MAX_EVENTS events, evicting from the user holding
+        // the most events so a noisy user can't push everyone else out
+        if self.events.len() > MAX_EVENTS {
+            self.evict_event();
         }
     }
 
+    fn evict_event(&mut self) {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for event in &self.events {
+            *counts.entry(event.user.as_str()).or_default() += 1;
+        }
+
+        let heaviest_user = counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(user, _)| user.to_string());
+
+        let index = heaviest_user
+            .and_then(|user| self.events.iter().position(|e| e.user == user))
+            .unwrap_or(0);
+        self.events.remove(index);
+    }
+
+    fn dropped_messages_for(&self, user: &str) -> u64 {
+        self.dropped_messages
+            .get(user)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
     fn update_user_stats(&mut self, user: &str) {
         let chimes = self
             .chime_lists
@@ -149,6 +249,7 @@ impl ServiceState {
                 online_chimes: 0,
                 last_activity: None,
                 events_count: 0,
+                dropped_messages: 0,
             });
 
         user_stats.total_chimes = chimes;
@@ -173,11 +274,76 @@ impl ServiceState {
             active_chimes,
             online_chimes,
             custom_states: self.custom_states.len(),
+            connections: self.connections.clone(),
         }
     }
 
+    // Prometheus text exposition format
+    fn render_metrics(&self) -> String {
+        let status = self.get_status();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (labels, value) in samples {
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+        let label = |name: &str, value: &str| format!("{{{}=\"{}\"}}", name, escape_label(value));
+
+        let mut by_type: Vec<_> = self.events_by_type.iter().collect();
+        by_type.sort();
+        metric(
+            "chimenet_events_total",
+            "counter",
+            "Chime events seen since the service started.",
+            vec![(String::new(), self.events_by_type.values().sum())],
+        );
+        metric(
+            "chimenet_events_by_type_total",
+            "counter",
+            "Chime events seen since the service started, by type.",
+            by_type
+                .into_iter()
+                .map(|(event_type, count)| (label("type", event_type), *count))
+                .collect(),
+        );
+        metric(
+            "chimenet_online_chimes",
+            "gauge",
+            "Chimes whose last status was online.",
+            vec![(String::new(), status.online_chimes as u64)],
+        );
+        metric(
+            "chimenet_active_chimes",
+            "gauge",
+            "Chimes in the monitored users' chime lists.",
+            vec![(String::new(), status.active_chimes as u64)],
+        );
+        metric(
+            "chimenet_user_chimes",
+            "gauge",
+            "Chimes in each monitored user's chime list.",
+            self.monitored_users
+                .iter()
+                .map(|user| {
+                    let chimes = self
+                        .chime_lists
+                        .get(user)
+                        .map_or(0, |list| list.chimes.len());
+                    (label("user", user), chimes as u64)
+                })
+                .collect(),
+        );
+        out
+    }
+
     fn get_user_stats(&self, user: &str) -> Option<UserStats> {
-        self.user_stats.get(user).cloned()
+        let mut stats = self.user_stats.get(user).cloned()?;
+        stats.dropped_messages = self.dropped_messages_for(user);
+        Some(stats)
     }
 
     fn get_chime_details(&self, user: &str, chime_id: &str) -> Option<ChimeDetails> {
@@ -250,14 +416,41 @@ impl ServiceState {
     fn get_custom_states(&self) -> Vec<CustomLcgpState> {
         self.custom_states.values().cloned().collect()
     }
+
+    // The client publishing on `user`'s behalf. Monitored users get one once
+    // their first connect succeeds; until then the broker is unreachable (502).
+    fn mqtt_client(
+        &self,
+        user: &str,
+    ) -> StdResult<Arc<ChimeNetMqtt>, (StatusCode, Json<ErrorResponse>)> {
+        if let Some(mqtt) = self.mqtt_clients.get(user) {
+            return Ok(mqtt.clone());
+        }
+        let (status, error) = if self.monitored_users.iter().any(|u| u == user) {
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("Not connected to the MQTT broker for user {} yet", user),
+            )
+        } else {
+            (
+                StatusCode::NOT_FOUND,
+                format!("User {} is not monitored", user),
+            )
+        };
+        Err((status, Json(ErrorResponse { error })))
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
 
-    let args = Args::parse();
+    let started = std::time::Instant::now();
+    let result = run(Args::parse()).await;
+    std::process::exit(ShutdownSummary::new("http_service", &result, started.elapsed()).report());
+}
 
+async fn run(args: Args) -> Result<()> {
     info!("Starting ChimeNet HTTP Service on port {}", args.port);
     info!("Connecting to MQTT broker: {}", args.broker);
 
@@ -270,8 +463,20 @@ async fn main() -> Result<()> {
 
     // Start MQTT monitoring
     let state_clone = state.clone();
+    let queue_capacity = args.queue_capacity.max(1);
+    let credentials = args.broker_args.credentials();
+    let tls = args.broker_args.tls();
     tokio::spawn(async move {
-        if let Err(e) = start_mqtt_monitoring(args.broker, users, state_clone).await {
+        if let Err(e) = start_mqtt_monitoring(
+            args.broker,
+            credentials,
+            tls,
+            users,
+            state_clone,
+            queue_capacity,
+        )
+        .await
+        {
             error!("MQTT monitoring error: {}", e);
         }
     });
@@ -285,6 +490,7 @@ async fn main() -> Result<()> {
     // Create router
     let app = Router::new()
         .route("/status", get(handle_status))
+        .route("/metrics", get(handle_metrics))
         .route("/users", get(handle_users))
         .route("/users/:user/stats", get(handle_user_stats))
         .route("/users/:user/chimes", get(handle_user_chimes))
@@ -293,7 +499,13 @@ async fn main() -> Result<()> {
             "/users/:user/chimes/:chime_id/status",
             get(handle_chime_status),
         )
+        .route(
+            "/users/:user/chimes/:chime_id/mode-durations",
+            get(handle_mode_durations),
+        )
         .route("/events", get(handle_events))
+        .route("/events/stream", get(handle_event_stream))
+        .route("/ws", get(handle_ws))
         .route(
             "/users/:user/chimes/:chime_id/ring",
             post(handle_ring_chime),
@@ -311,12 +523,16 @@ async fn main() -> Result<()> {
     info!("HTTP service listening on port {}", args.port);
     info!("Available endpoints:");
     info!("  GET /status - Service status");
+    info!("  GET /metrics - Prometheus metrics");
     info!("  GET /users - List monitored users");
     info!("  GET /users/:user/stats - User statistics");
     info!("  GET /users/:user/chimes - List user's chimes");
     info!("  GET /users/:user/chimes/:chime_id - Detailed chime information");
     info!("  GET /users/:user/chimes/:chime_id/status - Chime status");
+    info!("  GET /users/:user/chimes/:chime_id/mode-durations?window_secs=N - Time spent in each mode");
     info!("  GET /events - Recent events");
+    info!("  GET /events/stream?user=U&type=T - Live events (Server-Sent Events)");
+    info!("  GET /ws?user=U&type=T - Live events and ring/respond/mode commands (WebSocket)");
     info!("  POST /users/:user/chimes/:chime_id/ring - Ring a chime");
     info!("  POST /users/:user/chimes/:chime_id/respond - Respond to a chime");
     info!("  GET /custom-states - List custom LCGP states");
@@ -335,6 +551,121 @@ async fn handle_status(State(state): State<SharedState>) -> Json<ServiceStatus>
     Json(status)
 }
 
+async fn handle_ws(
+    ws: WebSocketUpgrade,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<SharedState>,
+) -> Response {
+    let events = state.read().await.event_tx.subscribe();
+    let filter = EventFilter::from_query(&params);
+    ws.on_upgrade(move |socket| ws_session(socket, state, events, filter))
+}
+
+async fn ws_session(
+    mut socket: WebSocket,
+    state: SharedState,
+    events: broadcast::Receiver<ChimeEvent>,
+    filter: EventFilter,
+) {
+    let events = event_stream(events, filter);
+    futures::pin_mut!(events);
+    // Commands run on their own tasks, since a ring can wait up to
+    // MAX_RING_WAIT_SECS for its answer. Events keep flowing meanwhile and
+    // the replies come back here to be sent.
+    let (replies_tx, mut replies) = mpsc::unbounded_channel();
+
+    loop {
+        let frame = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => WsFrame::Event(event),
+                None => break,
+            },
+            // Never None, replies_tx lives as long as the loop
+            Some(reply) = replies.recv() => reply,
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let state = state.clone();
+                    let replies_tx = replies_tx.clone();
+                    tokio::spawn(async move {
+                        // Only fails once the session is gone
+                        let _ = replies_tx.send(ws_command(&state, &text).await);
+                    });
+                    continue;
+                }
+                Some(Ok(Message::Binary(_))) => WsFrame::Error {
+                    error: "Commands are JSON text frames".to_string(),
+                },
+                // Pings are answered by axum
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+        };
+
+        let text = match serde_json::to_string(&frame) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to encode WebSocket frame: {}", e);
+                continue;
+            }
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Runs one command frame through the same code as the POST endpoints. A bad
+// frame gets an error frame back; the socket stays open.
+async fn ws_command(state: &SharedState, text: &str) -> WsFrame {
+    let command = match serde_json::from_str::<WsCommand>(text) {
+        Ok(command) => command,
+        Err(e) => {
+            return WsFrame::Error {
+                error: format!("Invalid command: {}", e),
+            }
+        }
+    };
+
+    let result = match command {
+        WsCommand::Ring {
+            user,
+            chime_id,
+            wait_secs,
+            request,
+        } => match ring_wait(wait_secs) {
+            Ok(wait) => ring_chime(state, user, chime_id, request, wait).await,
+            Err(error) => Err(error),
+        },
+        WsCommand::Respond {
+            user,
+            chime_id,
+            request,
+        } => respond_chime(state, user, chime_id, request).await,
+        WsCommand::Mode {
+            user,
+            chime_id,
+            request,
+        } => set_mode(state, user, chime_id, request).await,
+    };
+    match result {
+        Ok(Json(response)) => WsFrame::Ok {
+            message: response.message,
+            response: response.response,
+        },
+        Err((_, Json(response))) => WsFrame::Error {
+            error: response.error,
+        },
+    }
+}
+
+async fn handle_metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    let metrics = state.read().await.render_metrics();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics,
+    )
+}
+
 async fn handle_users(State(state): State<SharedState>) -> Json<Vec<UserStats>> {
     let state_guard = state.read().await;
     let users: Vec<UserStats> = state_guard
@@ -347,6 +678,7 @@ async fn handle_users(State(state): State<SharedState>) -> Json<Vec<UserStats>>
                 online_chimes: 0,
                 last_activity: None,
                 events_count: 0,
+                dropped_messages: state_guard.dropped_messages_for(user),
             })
         })
         .collect();
@@ -402,22 +734,49 @@ async fn handle_chime_status(
     Err(StatusCode::NOT_FOUND)
 }
 
+async fn handle_mode_durations(
+    Path((user, chime_id)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<SharedState>,
+) -> StdResult<Json<ModeDurations>, StatusCode> {
+    let window_secs = match params.get("window_secs") {
+        Some(secs) => secs
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or(StatusCode::BAD_REQUEST)?,
+        None => DEFAULT_MODE_WINDOW_SECS,
+    };
+
+    let state_guard = state.read().await;
+    let history = state_guard
+        .mode_histories
+        .get(&user)
+        .and_then(|histories| histories.get(&chime_id))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut modes: Vec<ModeDuration> = history
+        .durations(Duration::from_secs(window_secs), chrono::Utc::now())
+        .into_iter()
+        .map(|(mode, spent)| ModeDuration {
+            mode,
+            seconds: spent.as_secs(),
+            fraction: spent.as_secs_f64() / window_secs as f64,
+        })
+        .collect();
+    modes.sort_by_key(|mode| std::cmp::Reverse(mode.seconds));
+
+    Ok(Json(ModeDurations { window_secs, modes }))
+}
+
 async fn handle_events(
     Query(params): Query<HashMap<String, String>>,
     State(state): State<SharedState>,
 ) -> Json<Vec<ChimeEvent>> {
     let state_guard = state.read().await;
+    let filter = EventFilter::from_query(&params);
     let mut events = state_guard.events.clone();
-
-    // Filter by user if specified
-    if let Some(user) = params.get("user") {
-        events.retain(|e| e.user == *user);
-    }
-
-    // Filter by event type if specified
-    if let Some(event_type) = params.get("type") {
-        events.retain(|e| e.event_type == *event_type);
-    }
+    events.retain(|e| filter.matches(e));
 
     // Limit results
     let limit = params
@@ -430,16 +789,125 @@ async fn handle_events(
     Json(events)
 }
 
+// Optional ?user= and ?type= filters shared by /events and /events/stream
+#[derive(Debug, Clone, Default)]
+struct EventFilter {
+    user: Option<String>,
+    event_type: Option<String>,
+}
+
+impl EventFilter {
+    fn from_query(params: &HashMap<String, String>) -> Self {
+        Self {
+            user: params.get("user").cloned(),
+            event_type: params.get("type").cloned(),
+        }
+    }
+
+    fn matches(&self, event: &ChimeEvent) -> bool {
+        self.user.as_ref().is_none_or(|user| event.user == *user)
+            && self
+                .event_type
+                .as_ref()
+                .is_none_or(|event_type| event.event_type == *event_type)
+    }
+}
+
+// Each SSE event is named after the ChimeEvent's type and carries it as JSON
+async fn handle_event_stream(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<SharedState>,
+) -> Sse<impl Stream<Item = StdResult<Event, axum::Error>>> {
+    let events = state.read().await.event_tx.subscribe();
+    let stream = event_stream(events, EventFilter::from_query(&params)).map(|event| {
+        Event::default()
+            .event(event.event_type.as_str())
+            .json_data(&event)
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Events passing `filter` as they're added. A receiver that lagged behind the
+// channel skips what it missed; the stream ends when the service shuts down.
+fn event_stream(
+    events: broadcast::Receiver<ChimeEvent>,
+    filter: EventFilter,
+) -> impl Stream<Item = ChimeEvent> {
+    futures::stream::unfold((events, filter), |(mut events, filter)| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) if filter.matches(&event) => return Some((event, (events, filter))),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log::warn!(
+                        "Event stream client fell behind, skipped {} events",
+                        skipped
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+// Frames a WebSocket client sends, e.g. {"cmd":"ring","user":"alice","chime_id":"abc"};
+// the rest of each frame is the body of the matching POST endpoint
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum WsCommand {
+    Ring {
+        user: String,
+        chime_id: String,
+        // Like ?wait_secs on the POST endpoint
+        #[serde(default)]
+        wait_secs: Option<u64>,
+        #[serde(flatten)]
+        request: RingRequest,
+    },
+    Respond {
+        user: String,
+        chime_id: String,
+        #[serde(flatten)]
+        request: ResponseRequest,
+    },
+    Mode {
+        user: String,
+        chime_id: String,
+        #[serde(flatten)]
+        request: ModeRequest,
+    },
+}
+
+// Frames sent to a WebSocket client: live events, and one ok or error per command
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsFrame {
+    Event(ChimeEvent),
+    Ok {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        response: Option<ChimeResponseMessage>,
+    },
+    Error {
+        error: String,
+    },
+}
+
 #[derive(Deserialize)]
 struct RingRequest {
     notes: Option<Vec<String>>,
     chords: Option<Vec<String>>,
     duration_ms: Option<u64>,
+    note_durations: Option<Vec<u64>>,
+    preset: Option<String>,
+    #[serde(default)]
+    urgency: Urgency,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Deserialize)]
 struct ResponseRequest {
-    response: String, // "positive" or "negative"
+    response: String, // "positive", "negative", "snooze" or "snooze:<minutes>"
 }
 
 #[derive(Deserialize)]
@@ -451,6 +919,9 @@ struct ModeRequest {
 struct ApiResponse {
     success: bool,
     message: String,
+    // The chime's answer, for a ring that waited for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<ChimeResponseMessage>,
 }
 
 #[derive(Serialize)]
@@ -460,84 +931,158 @@ struct ErrorResponse {
 
 async fn handle_ring_chime(
     Path((user, chime_id)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<SharedState>,
     Json(ring_request): Json<RingRequest>,
 ) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let state_guard = state.read().await;
-    if let Some(_mqtt_client) = state_guard.mqtt_clients.get(&user) {
-        let ring_req = ChimeRingRequest {
-            chime_id: chime_id.clone(),
-            user: user.clone(),
-            notes: ring_request.notes,
-            chords: ring_request.chords,
-            duration_ms: ring_request.duration_ms,
-            timestamp: chrono::Utc::now(),
-        };
-
-        // This would need to be implemented - storing MQTT clients properly
-        info!(
-            "Would send ring request to {}/{}: {:?}",
-            user, chime_id, ring_req
-        );
+    let wait_secs = match params.get("wait_secs") {
+        Some(secs) => Some(secs.parse::<u64>().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("Invalid wait_secs '{}'", secs),
+                }),
+            )
+        })?),
+        None => None,
+    };
+    let wait = ring_wait(wait_secs)?;
+    ring_chime(&state, user, chime_id, ring_request, wait).await
+}
 
-        Ok(Json(ApiResponse {
-            success: true,
-            message: "Ring request sent".to_string(),
-        }))
-    } else {
-        Err((
-            StatusCode::NOT_FOUND,
+// How long a ring waits for its answer: not at all without wait_secs, at
+// most MAX_RING_WAIT_SECS
+fn ring_wait(
+    wait_secs: Option<u64>,
+) -> StdResult<Option<Duration>, (StatusCode, Json<ErrorResponse>)> {
+    match wait_secs {
+        Some(0) => Err((
+            StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
-                error: "User not found or not connected".to_string(),
+                error: "wait_secs must be at least 1".to_string(),
             }),
-        ))
+        )),
+        Some(secs) => Ok(Some(Duration::from_secs(secs.min(MAX_RING_WAIT_SECS)))),
+        None => Ok(None),
     }
 }
 
+// Shared by the POST handlers and WebSocket commands. With `wait`, the ring
+// carries a request_id and the chime's answer to it is returned, or a 504
+// once `wait` is over; without, it returns as soon as the ring is published.
+async fn ring_chime(
+    state: &SharedState,
+    user: String,
+    chime_id: String,
+    ring_request: RingRequest,
+    wait: Option<Duration>,
+) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let target = valid_chime_id(&chime_id)?;
+    let mqtt = state.read().await.mqtt_client(&user)?;
+    let ring_req = ChimeRingRequest {
+        chime_id: chime_id.clone(),
+        user: user.clone(),
+        notes: ring_request.notes,
+        chords: ring_request.chords,
+        duration_ms: ring_request.duration_ms,
+        note_durations: ring_request.note_durations,
+        timestamp: chrono::Utc::now(),
+        preset: ring_request.preset,
+        urgency: ring_request.urgency,
+        expires_at: ring_request.expires_at,
+        request_id: wait.map(|_| uuid::Uuid::new_v4().to_string()),
+    };
+    let owner = UserId::from(user.as_str());
+
+    // Listening before ringing, so a quick answer can't slip past
+    let answer = match (&ring_req.request_id, wait) {
+        (Some(request_id), Some(wait)) => Some((
+            mqtt.response_to(&owner, &target, request_id)
+                .await
+                .map_err(error_response)?,
+            wait,
+        )),
+        _ => None,
+    };
+
+    mqtt.publish_chime_ring_to_user(&owner, &target, &ring_req)
+        .await
+        .map_err(error_response)?;
+    info!("Sent ring request to {}/{}", user, chime_id);
+
+    let Some((answer, wait)) = answer else {
+        return Ok(Json(ApiResponse {
+            success: true,
+            message: "Ring request sent".to_string(),
+            response: None,
+        }));
+    };
+    let message = tokio::time::timeout(wait, answer)
+        .await
+        .map_err(|_| {
+            error_response(ChimeNetError::Timeout(format!(
+                "No response from {}/{} within {}s",
+                user,
+                chime_id,
+                wait.as_secs()
+            )))
+        })?
+        .map_err(error_response)?;
+    let response: ChimeResponseMessage =
+        serde_json::from_str(&message.payload).map_err(|e| error_response(e.into()))?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        message: format!("Chime answered {:?}", response.response),
+        response: Some(response),
+    }))
+}
+
 async fn handle_respond_chime(
     Path((user, chime_id)): Path<(String, String)>,
     State(state): State<SharedState>,
     Json(response_request): Json<ResponseRequest>,
 ) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let response = match response_request.response.to_lowercase().as_str() {
-        "positive" => ChimeResponse::Positive,
-        "negative" => ChimeResponse::Negative,
-        _ => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "Invalid response. Use 'positive' or 'negative'".to_string(),
-                }),
-            ));
+    respond_chime(&state, user, chime_id, response_request).await
+}
+
+async fn respond_chime(
+    state: &SharedState,
+    user: String,
+    chime_id: String,
+    response_request: ResponseRequest,
+) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let response = match response_request.response.parse::<ChimeResponse>() {
+        Ok(response) => response,
+        Err(error) => {
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error })));
         }
     };
 
-    let state_guard = state.read().await;
-    if let Some(_mqtt_client) = state_guard.mqtt_clients.get(&user) {
-        let response_msg = ChimeResponseMessage {
-            timestamp: chrono::Utc::now(),
-            response,
-            node_id: "http_service".to_string(),
-            original_chime_id: Some(chime_id.clone()),
-        };
+    let target = valid_chime_id(&chime_id)?;
+    let mqtt = state.read().await.mqtt_client(&user)?;
+    let response_msg = ChimeResponseMessage {
+        timestamp: chrono::Utc::now(),
+        response,
+        node_id: "http_service".to_string(),
+        original_chime_id: Some(chime_id.clone()),
+        message: None,
+        in_reply_to: None,
+    };
 
-        info!(
-            "Would send response to {}/{}: {:?}",
-            user, chime_id, response_msg
-        );
+    mqtt.publish_chime_response(&target, &response_msg)
+        .await
+        .map_err(error_response)?;
+    info!(
+        "Sent response to {}/{}: {:?}",
+        user, chime_id, response_msg.response
+    );
 
-        Ok(Json(ApiResponse {
-            success: true,
-            message: "Response sent".to_string(),
-        }))
-    } else {
-        Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "User not found or not connected".to_string(),
-            }),
-        ))
-    }
+    Ok(Json(ApiResponse {
+        success: true,
+        message: "Response sent".to_string(),
+        response: None,
+    }))
 }
 
 async fn handle_custom_states(State(state): State<SharedState>) -> Json<Vec<CustomLcgpState>> {
@@ -549,14 +1094,24 @@ async fn handle_custom_states(State(state): State<SharedState>) -> Json<Vec<Cust
 async fn handle_create_custom_state(
     State(state): State<SharedState>,
     Json(custom_state): Json<CustomLcgpState>,
-) -> Json<ApiResponse> {
+) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = custom_state.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid custom state: {}", errors.join("; ")),
+            }),
+        ));
+    }
+
     let mut state_guard = state.write().await;
     state_guard.add_custom_state(custom_state.clone());
 
-    Json(ApiResponse {
+    Ok(Json(ApiResponse {
         success: true,
         message: format!("Custom state '{}' created", custom_state.name),
-    })
+        response: None,
+    }))
 }
 
 async fn handle_set_mode(
@@ -564,87 +1119,159 @@ async fn handle_set_mode(
     State(state): State<SharedState>,
     Json(mode_request): Json<ModeRequest>,
 ) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let mode = match mode_request.mode.to_lowercase().as_str() {
-        "available" => LcgpMode::Available,
-        "donotdisturb" => LcgpMode::DoNotDisturb,
-        "grinding" => LcgpMode::Grinding,
-        "chillgrinding" => LcgpMode::ChillGrinding,
-        custom if custom.starts_with("custom:") => {
-            let name = custom.strip_prefix("custom:").unwrap_or("").to_string();
-            LcgpMode::Custom(name)
-        }
-        _ => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "Invalid mode".to_string(),
-                }),
-            ));
+    set_mode(&state, user, chime_id, mode_request).await
+}
+
+async fn set_mode(
+    state: &SharedState,
+    user: String,
+    chime_id: String,
+    mode_request: ModeRequest,
+) -> StdResult<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let mode = match mode_request.mode.parse::<LcgpMode>() {
+        Ok(mode) => mode,
+        Err(error) => {
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error })));
         }
     };
 
-    let state_guard = state.read().await;
-    if let Some(_mqtt_client) = state_guard.mqtt_clients.get(&user) {
-        info!("Would set mode for {}/{} to: {:?}", user, chime_id, mode);
+    let target = valid_chime_id(&chime_id)?;
+    let mqtt = state.read().await.mqtt_client(&user)?;
+    let request = ModeChangeRequest {
+        chime_id: chime_id.clone(),
+        requested_mode: mode.clone(),
+        requester_id: "http_service".to_string(),
+        timestamp: chrono::Utc::now(),
+    };
 
-        Ok(Json(ApiResponse {
-            success: true,
-            message: format!("Mode set to {:?}", mode),
-        }))
-    } else {
-        Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "User not found or not connected".to_string(),
-            }),
-        ))
+    // The chime applies it only if it allows remote mode changes
+    mqtt.publish_mode_request(&UserId::from(user.as_str()), &target, &request)
+        .await
+        .map_err(error_response)?;
+    info!("Requested mode {:?} for {}/{}", mode, user, chime_id);
+
+    Ok(Json(ApiResponse {
+        success: true,
+        message: format!("Mode change to {:?} requested", mode),
+        response: None,
+    }))
+}
+
+// Path ids go into topics: a bad one is refused rather than sanitized into
+// some other chime's topic
+fn valid_chime_id(chime_id: &str) -> StdResult<ChimeId, (StatusCode, Json<ErrorResponse>)> {
+    TopicBuilder::validate_segment(chime_id).map_err(error_response)?;
+    Ok(ChimeId::from(chime_id))
+}
+
+// Label values are quoted, so backslashes, quotes and newlines are escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Bad ids are the caller's fault; a broker that fails or doesn't answer
+// makes this a bad gateway
+fn error_response(e: ChimeNetError) -> (StatusCode, Json<ErrorResponse>) {
+    let (status, error) = match &e {
+        ChimeNetError::InvalidTopic(_) => (StatusCode::BAD_REQUEST, e.to_string()),
+        ChimeNetError::StateNotFound(_) => (StatusCode::NOT_FOUND, e.to_string()),
+        ChimeNetError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, e.to_string()),
+        ChimeNetError::Connection(_) | ChimeNetError::Mqtt(_) => (
+            StatusCode::BAD_GATEWAY,
+            format!("Failed to publish to the MQTT broker: {}", e),
+        ),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    if status.is_server_error() {
+        error!("{}", error);
     }
+    (status, Json(ErrorResponse { error }))
 }
 
 async fn start_mqtt_monitoring(
     broker_url: String,
+    credentials: Option<MqttCredentials>,
+    tls: Option<MqttTls>,
     users: Vec<String>,
     state: SharedState,
+    queue_capacity: usize,
 ) -> Result<()> {
     for user in users {
         let broker_url = broker_url.clone();
+        let credentials = credentials.clone();
+        let tls = tls.clone();
         let user = user.clone();
         let state = state.clone();
 
         tokio::spawn(async move {
             let client_id = format!("http_service_monitor_{}", user);
-            let mut mqtt = match ChimeNetMqtt::new(&broker_url, &user, &client_id).await {
+            let built = ChimeNetMqtt::builder()
+                .broker(&broker_url)
+                .user(&UserId::from(user.as_str()))
+                .client_id(client_id)
+                .credentials(credentials)
+                .tls(tls)
+                .build()
+                .await;
+            let mut mqtt = match built {
                 Ok(client) => client,
                 Err(e) => {
                     error!("Failed to create MQTT client for user {}: {}", user, e);
                     return;
                 }
             };
+            // monitor_connection reconnects itself so it can report progress
+            mqtt.set_reconnect(false);
 
-            if let Err(e) = mqtt.connect().await {
+            set_connection_state(&state, &user, ConnectionState::Connecting).await;
+            let mut attempts = 0;
+            while let Err(e) = mqtt.connect().await {
+                attempts += 1;
                 error!("Failed to connect MQTT client for user {}: {}", user, e);
-                return;
+                set_connection_state(
+                    &state,
+                    &user,
+                    ConnectionState::Reconnecting {
+                        attempts,
+                        last_error: Some(e.to_string()),
+                    },
+                )
+                .await;
+                tokio::time::sleep(ReconnectBackoff::default().delay(attempts)).await;
             }
+            set_connection_state(&state, &user, ConnectionState::Connected).await;
+
+            // Shared with the ring/respond/mode handlers, which publish through it
+            let mqtt = Arc::new(mqtt);
+            state
+                .write()
+                .await
+                .mqtt_clients
+                .insert(user.clone(), mqtt.clone());
 
             info!("Started monitoring user: {}", user);
 
+            // Each user gets a bounded queue drained by a single worker, so one
+            // noisy user can't spawn unbounded work or starve the others
+            let (queue_tx, queue_rx) = mpsc::channel::<(String, String)>(queue_capacity);
+            let dropped = Arc::new(AtomicU64::new(0));
+            state
+                .write()
+                .await
+                .dropped_messages
+                .insert(user.clone(), dropped.clone());
+
+            tokio::spawn(process_user_queue(user.clone(), queue_rx, state.clone()));
+
             // Subscribe to all chime topics for this user
-            let _topic = format!("/{}/chime/+/+", user);
             if let Err(e) = mqtt
-                .subscribe_to_user_chimes(&user, {
-                    let state = state.clone();
+                .subscribe_to_user_chimes(&UserId::from(user.as_str()), {
                     let user = user.clone();
                     move |topic, payload| {
-                        let state = state.clone();
-                        let user = user.clone();
-                        let topic = topic.clone();
-                        let payload = payload.clone();
-
-                        tokio::spawn(async move {
-                            if let Err(e) = handle_mqtt_message(topic, payload, user, state).await {
-                                error!("Error handling MQTT message: {}", e);
-                            }
-                        });
+                        enqueue_message(&queue_tx, &dropped, &user, topic, payload)
                     }
                 })
                 .await
@@ -655,14 +1282,103 @@ async fn start_mqtt_monitoring(
                 );
             }
 
-            // Keep the connection alive
-            tokio::time::sleep(tokio::time::Duration::from_secs(u64::MAX)).await;
+            monitor_connection(&mqtt, &user, &state).await;
         });
     }
 
     Ok(())
 }
 
+// Watches a user's connection forever, reconnecting (and resubscribing) with
+// exponential backoff whenever it drops
+async fn monitor_connection(mqtt: &ChimeNetMqtt, user: &str, state: &SharedState) {
+    loop {
+        tokio::time::sleep(CONNECTION_CHECK_INTERVAL).await;
+        if mqtt.is_connected() {
+            continue;
+        }
+
+        log::warn!("Lost MQTT connection for user {}, reconnecting", user);
+        set_connection_state(
+            state,
+            user,
+            ConnectionState::Reconnecting {
+                attempts: 0,
+                last_error: None,
+            },
+        )
+        .await;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match mqtt.reconnect().await {
+                Ok(()) => {
+                    info!("Reconnected MQTT client for user {}", user);
+                    set_connection_state(state, user, ConnectionState::Connected).await;
+                    break;
+                }
+                Err(e) => {
+                    error!(
+                        "Reconnect attempt {} failed for user {}: {}",
+                        attempts, user, e
+                    );
+                    set_connection_state(
+                        state,
+                        user,
+                        ConnectionState::Reconnecting {
+                            attempts,
+                            last_error: Some(e.to_string()),
+                        },
+                    )
+                    .await;
+                    tokio::time::sleep(ReconnectBackoff::default().delay(attempts)).await;
+                }
+            }
+        }
+    }
+}
+
+async fn set_connection_state(state: &SharedState, user: &str, connection: ConnectionState) {
+    state
+        .write()
+        .await
+        .connections
+        .insert(user.to_string(), connection);
+}
+
+// Never waits: a message that doesn't fit the user's full queue is dropped
+// and counted, so a noisy user can't hold up the MQTT client
+fn enqueue_message(
+    queue_tx: &mpsc::Sender<(String, String)>,
+    dropped: &AtomicU64,
+    user: &str,
+    topic: String,
+    payload: String,
+) {
+    if let Err(mpsc::error::TrySendError::Full(_)) = queue_tx.try_send((topic, payload)) {
+        let total = dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        if total % 100 == 1 {
+            log::warn!(
+                "Message queue full for user {}, {} messages dropped so far",
+                user,
+                total
+            );
+        }
+    }
+}
+
+async fn process_user_queue(
+    user: String,
+    mut queue_rx: mpsc::Receiver<(String, String)>,
+    state: SharedState,
+) {
+    while let Some((topic, payload)) = queue_rx.recv().await {
+        if let Err(e) = handle_mqtt_message(topic, payload, user.clone(), state.clone()).await {
+            error!("Error handling MQTT message: {}", e);
+        }
+    }
+}
+
 async fn handle_mqtt_message(
     topic: String,
     payload: String,
@@ -670,12 +1386,11 @@ async fn handle_mqtt_message(
     state: SharedState,
 ) -> Result<()> {
     let parts: Vec<&str> = topic.split('/').collect();
-    if parts.len() < 4 {
-        return Ok(());
-    }
-
-    let chime_id = parts[3];
-    let message_type = parts[4];
+    let (chime_id, message_type) = match parts.as_slice() {
+        [_, _, _, message_type] => ("", *message_type),
+        [_, _, _, chime_id, message_type] => (*chime_id, *message_type),
+        _ => return Ok(()),
+    };
 
     let event = ChimeEvent {
         timestamp: chrono::Utc::now(),
@@ -691,14 +1406,42 @@ async fn handle_mqtt_message(
 
     // Update internal state based on message type
     match message_type {
+        // An empty retained payload clears the topic: the user's list or
+        // chime is gone
+        "list" if payload.is_empty() => {
+            state_guard.chime_lists.remove(&user);
+            state_guard.update_user_stats(&user);
+        }
         "list" => {
             if let Ok(chime_list) = serde_json::from_str::<ChimeList>(&payload) {
                 state_guard.chime_lists.insert(user.clone(), chime_list);
                 state_guard.update_user_stats(&user);
             }
         }
+        "status" if payload.is_empty() => {
+            if let Some(statuses) = state_guard.chime_statuses.get_mut(&user) {
+                statuses.remove(chime_id);
+            }
+            if let Some(histories) = state_guard.mode_histories.get_mut(&user) {
+                histories.remove(chime_id);
+            }
+            state_guard.update_user_stats(&user);
+        }
         "status" => {
             if let Ok(status) = serde_json::from_str::<ChimeStatus>(&payload) {
+                // Time offline isn't attributed to whatever mode the chime left in
+                let history = state_guard
+                    .mode_histories
+                    .entry(user.clone())
+                    .or_default()
+                    .entry(chime_id.to_string())
+                    .or_default();
+                if status.online {
+                    history.record(status.mode.clone(), chrono::Utc::now());
+                } else {
+                    history.close(chrono::Utc::now());
+                }
+
                 state_guard
                     .chime_statuses
                     .entry(user.clone())
@@ -728,3 +1471,221 @@ async fn handle_mqtt_message(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(user: &str, event_type: &str) -> ChimeEvent {
+        ChimeEvent {
+            timestamp: chrono::Utc::now(),
+            event_type: event_type.to_string(),
+            user: user.to_string(),
+            chime_id: "abc".to_string(),
+            data: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn metrics_count_every_event_and_label_chimes_per_user() {
+        let mut state = ServiceState::new(vec!["alice".to_string(), "bob".to_string()]);
+        for _ in 0..MAX_EVENTS + 1 {
+            state.add_event(event("alice", "ring"));
+        }
+        state.add_event(event("bob", "mode \"quoted\""));
+        let chime = |id: &str| ChimeInfo {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: None,
+            notes: vec![],
+            chords: vec![],
+            created_at: chrono::Utc::now(),
+        };
+        state.chime_lists.insert(
+            "alice".to_string(),
+            ChimeList {
+                user: "alice".to_string(),
+                chimes: vec![chime("desk"), chime("kitchen")],
+                timestamp: chrono::Utc::now(),
+            },
+        );
+
+        let metrics = state.render_metrics();
+        for line in [
+            "# TYPE chimenet_events_total counter",
+            "chimenet_events_total 1002",
+            "chimenet_events_by_type_total{type=\"ring\"} 1001",
+            "chimenet_events_by_type_total{type=\"mode \\\"quoted\\\"\"} 1",
+            "# TYPE chimenet_online_chimes gauge",
+            "chimenet_online_chimes 0",
+            "chimenet_active_chimes 2",
+            "chimenet_user_chimes{user=\"alice\"} 2",
+            "chimenet_user_chimes{user=\"bob\"} 0",
+        ] {
+            assert!(metrics.lines().any(|l| l == line), "{}\n{}", line, metrics);
+        }
+    }
+
+    #[tokio::test]
+    async fn one_users_backlog_does_not_hold_up_another_users_rings() {
+        let state: SharedState = Arc::new(RwLock::new(ServiceState::new(vec![
+            "alice".to_string(),
+            "bob".to_string(),
+        ])));
+        let ring = |user: &str| (TopicBuilder::chime_ring(user, "desk"), "{}".to_string());
+
+        // Alice's worker is stuck, so her queue fills and overflows
+        let (alice_tx, _alice_rx) = mpsc::channel(4);
+        let alice_dropped = AtomicU64::new(0);
+        for _ in 0..10 {
+            let (topic, payload) = ring("alice");
+            enqueue_message(&alice_tx, &alice_dropped, "alice", topic, payload);
+        }
+        assert_eq!(alice_dropped.load(Ordering::Relaxed), 6);
+
+        let (bob_tx, bob_rx) = mpsc::channel(4);
+        tokio::spawn(process_user_queue("bob".to_string(), bob_rx, state.clone()));
+        let (topic, payload) = ring("bob");
+        enqueue_message(&bob_tx, &AtomicU64::new(0), "bob", topic, payload);
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while state.read().await.events.is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let state = state.read().await;
+        let users: Vec<&str> = state.events.iter().map(|e| e.user.as_str()).collect();
+        assert_eq!(users, vec!["bob"]);
+    }
+
+    #[tokio::test]
+    async fn cleared_retained_topics_drop_the_list_and_the_chime() {
+        let state: SharedState =
+            Arc::new(RwLock::new(ServiceState::new(vec!["alice".to_string()])));
+        let list = ChimeList {
+            user: "alice".to_string(),
+            chimes: vec![ChimeInfo {
+                id: "desk".to_string(),
+                name: "Desk".to_string(),
+                description: None,
+                notes: vec![],
+                chords: vec![],
+                created_at: chrono::Utc::now(),
+            }],
+            timestamp: chrono::Utc::now(),
+        };
+        let status = ChimeStatus {
+            chime_id: "desk".to_string(),
+            online: true,
+            mode: LcgpMode::Available,
+            last_seen: chrono::Utc::now(),
+            node_id: "node".to_string(),
+            following: None,
+            ring_rate_per_hour: 0.0,
+            status_message: None,
+            in_reply_to: None,
+        };
+        let receive = |topic: String, payload: String| {
+            handle_mqtt_message(topic, payload, "alice".to_string(), state.clone())
+        };
+        let status_topic = TopicBuilder::chime_status("alice", "desk");
+        receive(
+            TopicBuilder::chime_list("alice"),
+            serde_json::to_string(&list).unwrap(),
+        )
+        .await
+        .unwrap();
+        receive(
+            status_topic.clone(),
+            serde_json::to_string(&status).unwrap(),
+        )
+        .await
+        .unwrap();
+        let stats = state.read().await.user_stats["alice"].clone();
+        assert_eq!((stats.total_chimes, stats.online_chimes), (1, 1));
+
+        receive(status_topic, String::new()).await.unwrap();
+        receive(TopicBuilder::chime_list("alice"), String::new())
+            .await
+            .unwrap();
+        let state = state.read().await;
+        assert!(state.chime_statuses["alice"].is_empty());
+        assert!(!state.chime_lists.contains_key("alice"));
+        let stats = &state.user_stats["alice"];
+        assert_eq!((stats.total_chimes, stats.online_chimes), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn event_stream_filters_and_skips_what_a_slow_client_missed() {
+        let mut state = ServiceState::new(vec!["alice".to_string(), "bob".to_string()]);
+        let filter = EventFilter::from_query(&HashMap::from([
+            ("user".to_string(), "alice".to_string()),
+            ("type".to_string(), "ring".to_string()),
+        ]));
+        let stream = event_stream(state.event_tx.subscribe(), filter);
+        futures::pin_mut!(stream);
+
+        state.add_event(event("bob", "ring"));
+        state.add_event(event("alice", "status"));
+        state.add_event(event("alice", "ring"));
+        let next = stream.next().await.unwrap();
+        assert_eq!(
+            (next.user.as_str(), next.event_type.as_str()),
+            ("alice", "ring")
+        );
+
+        // Overflowing the channel doesn't block add_event; the client resumes
+        // with the newest events it still has
+        for _ in 0..EVENT_STREAM_CAPACITY + 10 {
+            state.add_event(event("alice", "ring"));
+        }
+        state.add_event(event("alice", "status"));
+        state.add_event(event("alice", "ring"));
+        drop(state);
+
+        let received: Vec<ChimeEvent> = stream.collect().await;
+        assert!(received.len() < EVENT_STREAM_CAPACITY + 11);
+        assert!(received
+            .iter()
+            .all(|e| e.user == "alice" && e.event_type == "ring"));
+    }
+
+    #[tokio::test]
+    async fn ws_commands_answer_bad_frames_with_an_error() {
+        let state: SharedState =
+            Arc::new(RwLock::new(ServiceState::new(vec!["alice".to_string()])));
+
+        let error = |frame: WsFrame| match frame {
+            WsFrame::Error { error } => error,
+            _ => panic!("expected an error frame"),
+        };
+        assert!(error(ws_command(&state, "not json").await).starts_with("Invalid command"));
+        assert!(
+            error(ws_command(&state, r#"{"cmd":"explode"}"#).await).starts_with("Invalid command")
+        );
+        // Parses, but nobody is monitoring mallory
+        let unknown = r#"{"cmd":"mode","user":"mallory","chime_id":"abc","mode":"Available"}"#;
+        assert!(!error(ws_command(&state, unknown).await).is_empty());
+        let no_wait = r#"{"cmd":"ring","user":"alice","chime_id":"abc","wait_secs":0}"#;
+        assert_eq!(
+            error(ws_command(&state, no_wait).await),
+            "wait_secs must be at least 1"
+        );
+    }
+
+    #[test]
+    fn ring_waits_are_opt_in_and_capped() {
+        assert_eq!(ring_wait(None).ok(), Some(None));
+        assert_eq!(ring_wait(Some(5)).ok(), Some(Some(Duration::from_secs(5))));
+        assert_eq!(
+            ring_wait(Some(3600)).ok(),
+            Some(Some(Duration::from_secs(MAX_RING_WAIT_SECS)))
+        );
+        assert_eq!(
+            ring_wait(Some(0)).err().map(|e| e.0),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+}
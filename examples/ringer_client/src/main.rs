@@ -1,8 +1,9 @@
 use chimenet::*;
 use clap::Parser;
 use log::{error, info};
-use serde_json;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::RwLock;
@@ -15,6 +16,9 @@ struct Args {
     #[arg(short, long, default_value = "tcp://localhost:1883")]
     broker: String,
 
+    #[command(flatten)]
+    broker_args: BrokerArgs,
+
     /// User name for this ringer
     #[arg(short, long, default_value = "ringer_user")]
     user: String,
@@ -22,9 +26,18 @@ struct Args {
     /// Auto-discovery interval in seconds
     #[arg(short, long, default_value = "30")]
     discovery_interval: u64,
+
+    /// Keep discovered chimes and custom states in this JSON file across
+    /// restarts. Restored chimes count as offline until a fresh status arrives.
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+
+    /// Demo mode: no broker (LAN transport) and sample chimes to ring
+    #[arg(long)]
+    demo: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct DiscoveredChime {
     user: String,
     chime_id: String,
@@ -33,15 +46,66 @@ struct DiscoveredChime {
     chords: Vec<String>,
     last_seen: chrono::DateTime<chrono::Utc>,
     status: Option<ChimeStatus>,
+    // Restored from --state-file and not confirmed by a status since
+    #[serde(skip)]
+    stale: bool,
+}
+
+impl DiscoveredChime {
+    // One of `chimenet::DiscoveredChime::sample_set()`, as if its status had
+    // just arrived
+    fn from_sample(sample: chimenet::DiscoveredChime) -> Self {
+        let status = ChimeStatus {
+            chime_id: sample.chime_id.clone(),
+            online: sample.online,
+            mode: sample.mode,
+            last_seen: sample.last_seen,
+            node_id: format!("demo_{}", sample.chime_id),
+            following: None,
+            ring_rate_per_hour: sample.ring_rate_per_hour,
+            status_message: sample.status_message,
+            in_reply_to: None,
+        };
+        Self {
+            user: sample.user,
+            chime_id: sample.chime_id,
+            name: sample.name,
+            notes: sample.notes,
+            chords: sample.chords,
+            last_seen: sample.last_seen,
+            status: Some(status),
+            stale: false,
+        }
+    }
+
+    fn is_online(&self) -> bool {
+        !self.stale && self.status.as_ref().is_some_and(|s| s.online)
+    }
+
+    // The mode from a status received this session
+    fn live_mode(&self) -> Option<&LcgpMode> {
+        self.status
+            .as_ref()
+            .filter(|_| !self.stale)
+            .map(|status| &status.mode)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct UserInfo {
+    #[allow(dead_code)]
     user: String,
     chimes: Vec<DiscoveredChime>,
     last_discovery: chrono::DateTime<chrono::Utc>,
 }
 
+// What --state-file holds. Users are rebuilt from the chimes on load.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedState {
+    discovered_chimes: Vec<DiscoveredChime>,
+    custom_states: Vec<CustomLcgpState>,
+}
+
 type SharedState = Arc<RwLock<RingerState>>;
 
 struct RingerState {
@@ -50,6 +114,7 @@ struct RingerState {
     user_info: HashMap<String, UserInfo>,
     mqtt: Option<Arc<ChimeNetMqtt>>,
     custom_states: HashMap<String, CustomLcgpState>,
+    state_file: Option<PathBuf>,
 }
 
 impl RingerState {
@@ -60,6 +125,47 @@ impl RingerState {
             user_info: HashMap::new(),
             mqtt: None,
             custom_states: HashMap::new(),
+            state_file: None,
+        }
+    }
+
+    // Restores what `save` wrote, returning how many chimes came back. They
+    // stay stale, so never online, until a status confirms them. A missing
+    // file is a first run, not an error.
+    fn load(&mut self, path: &Path) -> Result<usize> {
+        let saved: SavedState = match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let restored = saved.discovered_chimes.len();
+        for mut chime in saved.discovered_chimes {
+            chime.stale = true;
+            self.add_discovered_chime(chime);
+        }
+        for custom_state in saved.custom_states {
+            self.add_custom_state(custom_state);
+        }
+        Ok(restored)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+
+        let saved = SavedState {
+            discovered_chimes: self.get_all_chimes(),
+            custom_states: self.get_all_custom_states(),
+        };
+        write_atomically(path, serde_json::to_string_pretty(&saved)?)
+    }
+
+    // Saves if --state-file was given; failures are only logged
+    fn persist(&self) {
+        if let Err(e) = self.save() {
+            error!("Failed to save state: {}", e);
         }
     }
 
@@ -84,12 +190,29 @@ impl RingerState {
         self.discovered_chimes.insert(key, chime);
     }
 
+    // Forgets a chime, and its user once none of theirs are left
+    fn remove_discovered_chime(&mut self, user: &str, chime_id: &str) -> bool {
+        let key = format!("{}/{}", user, chime_id);
+        if self.discovered_chimes.remove(&key).is_none() {
+            return false;
+        }
+
+        if let Some(user_info) = self.user_info.get_mut(user) {
+            user_info.chimes.retain(|c| c.chime_id != chime_id);
+            if user_info.chimes.is_empty() {
+                self.user_info.remove(user);
+            }
+        }
+        true
+    }
+
     fn update_chime_status(&mut self, user: &str, chime_id: &str, status: ChimeStatus) {
         let key = format!("{}/{}", user, chime_id);
 
         if let Some(chime) = self.discovered_chimes.get_mut(&key) {
             chime.status = Some(status);
             chime.last_seen = chrono::Utc::now();
+            chime.stale = false;
         }
     }
 
@@ -108,11 +231,61 @@ impl RingerState {
     fn get_online_chimes(&self) -> Vec<DiscoveredChime> {
         self.discovered_chimes
             .values()
-            .filter(|chime| chime.status.as_ref().map_or(false, |s| s.online))
+            .filter(|chime| chime.is_online())
             .cloned()
             .collect()
     }
 
+    // By pitch, so "db4" finds a chime advertising "C#4"
+    fn find_chimes_by_note(&self, note: &str) -> Vec<DiscoveredChime> {
+        let wanted = [note.to_string()];
+        self.find_chimes(|chime| !notes::common_notes(&wanted, &chime.notes).is_empty())
+    }
+
+    fn find_chimes_by_chord(&self, chord: &str) -> Vec<DiscoveredChime> {
+        let wanted = [chord.to_string()];
+        self.find_chimes(|chime| !notes::common_chords(&wanted, &chime.chords).is_empty())
+    }
+
+    // Only the live mode counts: chimes restored from the state file or
+    // without a status yet are left out
+    fn find_chimes_by_mode(&self, mode: &LcgpMode) -> Vec<DiscoveredChime> {
+        self.find_chimes(|chime| chime.live_mode() == Some(mode))
+    }
+
+    // Sorted by user and name so listings are stable
+    fn find_chimes(&self, matches: impl Fn(&DiscoveredChime) -> bool) -> Vec<DiscoveredChime> {
+        let mut found: Vec<DiscoveredChime> = self
+            .discovered_chimes
+            .values()
+            .filter(|chime| matches(chime))
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| (&a.user, &a.name).cmp(&(&b.user, &b.name)));
+        found
+    }
+
+    // A random online chime that isn't in DoNotDisturb
+    fn pick_random(&self, weighting: PickWeighting) -> Option<DiscoveredChime> {
+        let mut candidates: Vec<&DiscoveredChime> = self
+            .discovered_chimes
+            .values()
+            .filter(|chime| {
+                chime.is_online()
+                    && chime
+                        .status
+                        .as_ref()
+                        .is_some_and(|s| s.mode != LcgpMode::DoNotDisturb)
+            })
+            .collect();
+        candidates.sort_by(|a, b| (&a.user, &a.chime_id).cmp(&(&b.user, &b.chime_id)));
+
+        pick_weighted(candidates, weighting, |chime| {
+            chime.status.as_ref().map_or(0.0, |s| s.ring_rate_per_hour)
+        })
+        .cloned()
+    }
+
     fn find_chime_by_name(&self, user: &str, name: &str) -> Option<DiscoveredChime> {
         self.discovered_chimes
             .values()
@@ -120,6 +293,24 @@ impl RingerState {
             .cloned()
     }
 
+    // The user's chimes whose name loosely matches, best match first
+    fn search_chimes(&self, user: &str, query: &str) -> Vec<DiscoveredChime> {
+        let mut matches: Vec<(u32, &DiscoveredChime)> = self
+            .discovered_chimes
+            .values()
+            .filter(|chime| chime.user == user)
+            .filter_map(|chime| Some((name_match_score(&chime.name, query)?, chime)))
+            .collect();
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then_with(|| a.name.cmp(&b.name))
+        });
+
+        matches
+            .into_iter()
+            .map(|(_, chime)| chime.clone())
+            .collect()
+    }
+
     fn get_user_info(&self, user: &str) -> Option<UserInfo> {
         self.user_info.get(user).cloned()
     }
@@ -132,6 +323,7 @@ impl RingerState {
         self.custom_states.insert(state.name.clone(), state);
     }
 
+    #[allow(dead_code)]
     fn get_custom_state(&self, name: &str) -> Option<CustomLcgpState> {
         self.custom_states.get(name).cloned()
     }
@@ -142,26 +334,59 @@ impl RingerState {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
 
-    let args = Args::parse();
+    let started = std::time::Instant::now();
+    let result = run(Args::parse()).await;
+    std::process::exit(ShutdownSummary::new("ringer_client", &result, started.elapsed()).report());
+}
 
+async fn run(args: Args) -> Result<()> {
     info!("Starting ChimeNet Ringer Client");
     info!("User: {}", args.user);
-    info!("Connecting to MQTT broker: {}", args.broker);
+    // Demo mode must work without any infrastructure, so it never uses the broker
+    let broker = if args.demo {
+        LAN_URL_SCHEME.to_string()
+    } else {
+        args.broker.clone()
+    };
+    info!("Connecting to MQTT broker: {}", broker);
 
     let state = Arc::new(RwLock::new(RingerState::new()));
+    if let Some(path) = &args.state_file {
+        let mut state_guard = state.write().await;
+        let restored = state_guard.load(path).map_err(|e| {
+            ChimeNetError::Config(format!("Can't read state file {}: {}", path.display(), e))
+        })?;
+        state_guard.state_file = Some(path.clone());
+        info!("Restored {} chimes from {}", restored, path.display());
+    }
 
     // Connect to MQTT
     let client_id = format!("ringer_{}_{}", args.user, state.read().await.ringer_id);
-    let mut mqtt = ChimeNetMqtt::new(&args.broker, &args.user, &client_id).await?;
+    let mut mqtt = ChimeNetMqtt::builder()
+        .broker(&broker)
+        .user(&args.user.as_str().into())
+        .client_id(client_id)
+        .credentials(args.broker_args.credentials())
+        .tls(args.broker_args.tls())
+        .build()
+        .await?;
     mqtt.connect().await?;
     let mqtt = Arc::new(mqtt);
 
     // Store MQTT client in state
     state.write().await.mqtt = Some(mqtt.clone());
 
+    if args.demo {
+        info!("Demo mode: adding sample chimes");
+        let mut state_guard = state.write().await;
+        for sample in chimenet::DiscoveredChime::sample_set() {
+            state_guard.add_discovered_chime(DiscoveredChime::from_sample(sample));
+        }
+    }
+
     // Start discovery process
     let state_clone = state.clone();
     let mqtt_clone = mqtt.clone();
@@ -190,7 +415,10 @@ async fn main() -> Result<()> {
     info!("  online [user] - List online chimes");
     info!("  status [user] [chime_name] - Show chime status");
     info!("  ring <user> <chime_name> [notes] [chords] - Ring a chime by name");
-    info!("  respond <user> <chime_name> <positive|negative> - Respond to a chime");
+    info!("  ring-random [activity|uniform] - Ring a random available chime");
+    info!(
+        "  respond <user> <chime_name> <positive|negative|snooze [minutes]> [\"message\"] - Respond to a chime"
+    );
     info!("  mode <user> <chime_name> <mode> - Set chime mode");
     info!("  custom-state <name> <should_chime> [auto_response] - Create custom state");
     info!("  states - List custom states");
@@ -206,6 +434,7 @@ async fn main() -> Result<()> {
     tokio::signal::ctrl_c().await?;
 
     info!("Shutting down ringer client...");
+    state.read().await.persist();
     // Note: In a real implementation, we'd need to properly handle MQTT disconnect
     // since the connect/disconnect methods require mutable access
 
@@ -242,7 +471,7 @@ async fn start_monitoring(state: SharedState, mqtt: Arc<ChimeNetMqtt>) -> Result
     // Subscribe to all chime lists and statuses
     let topic = "/+/chime/+/+";
 
-    mqtt.subscribe(topic, 1, {
+    mqtt.subscribe(topic, mqtt.any_qos(), {
         let state = state.clone();
         move |topic, payload| {
             let state = state.clone();
@@ -290,16 +519,30 @@ async fn handle_mqtt_message(topic: String, payload: String, state: SharedState)
                         chords: chime_info.chords,
                         last_seen: chrono::Utc::now(),
                         status: None,
+                        stale: false,
                     };
 
                     state_guard.add_discovered_chime(discovered_chime);
                 }
+                state_guard.persist();
 
                 info!("Updated chime list for user: {}", user);
             }
         }
+        // An emptied retained status: the chime was removed for good
+        "status" if payload.is_empty() => {
+            let mut state_guard = state.write().await;
+            if state_guard.remove_discovered_chime(user, chime_id) {
+                state_guard.persist();
+                info!("Chime removed: {}/{}", user, chime_id);
+            }
+        }
         "status" => {
-            if let Ok(status) = serde_json::from_str::<ChimeStatus>(&payload) {
+            let status = serde_json::from_str::<ChimeStatus>(&payload);
+            if let Err(e) = &status {
+                error!("Ignoring unreadable status on {}: {}", topic, e);
+            }
+            if let Ok(status) = status {
                 let mut state_guard = state.write().await;
                 state_guard.update_chime_status(user, chime_id, status);
                 info!(
@@ -309,17 +552,22 @@ async fn handle_mqtt_message(topic: String, payload: String, state: SharedState)
                     state_guard
                         .discovered_chimes
                         .get(&format!("{}/{}", user, chime_id))
-                        .map(|c| c.status.as_ref().map_or(false, |s| s.online))
-                        .unwrap_or(false)
+                        .is_some_and(|c| c.is_online())
                 );
             }
         }
         "response" => {
             if let Ok(response) = serde_json::from_str::<ChimeResponseMessage>(&payload) {
-                info!(
-                    "Received response from {}/{}: {:?}",
-                    user, chime_id, response.response
-                );
+                match &response.message {
+                    Some(message) => info!(
+                        "Received response from {}/{}: {:?} \"{}\"",
+                        user, chime_id, response.response, message
+                    ),
+                    None => info!(
+                        "Received response from {}/{}: {:?}",
+                        user, chime_id, response.response
+                    ),
+                }
             }
         }
         _ => {}
@@ -367,17 +615,32 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
 
     match parts[0] {
         "discover" => {
-            let state_guard = state.read().await;
-            if let Some(mqtt) = &state_guard.mqtt {
+            let requested_at = chrono::Utc::now();
+            {
+                let state_guard = state.read().await;
+                let Some(mqtt) = &state_guard.mqtt else {
+                    return Ok(());
+                };
                 let discovery = RingerDiscovery {
                     ringer_id: state_guard.ringer_id.clone(),
                     user: "discovery".to_string(),
-                    timestamp: chrono::Utc::now(),
+                    timestamp: requested_at,
                 };
 
                 mqtt.publish_ringer_discovery(&discovery).await?;
-                println!("Discovery request sent");
             }
+            println!("Discovery request sent, waiting for chimes to announce...");
+
+            // Chimes answer within their announce window
+            tokio::time::sleep(DEFAULT_ANNOUNCE_WINDOW * 2).await;
+            let answered = state
+                .read()
+                .await
+                .get_all_chimes()
+                .into_iter()
+                .filter(|chime| chime.last_seen >= requested_at)
+                .count();
+            println!("{} chime(s) answered", answered);
         }
 
         "users" => {
@@ -415,12 +678,23 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
                     println!("Chimes for user {}:", user);
                     for chime in chimes {
                         let status_str = match &chime.status {
+                            Some(status) if chime.stale => {
+                                format!("stale, last known mode={:?}", status.mode)
+                            }
                             Some(status) => {
                                 format!("online={}, mode={:?}", status.online, status.mode)
                             }
+                            None if chime.stale => "stale".to_string(),
                             None => "status=unknown".to_string(),
                         };
                         println!("  {} ({}) - {}", chime.name, chime.chime_id, status_str);
+                        if let Some(message) = chime
+                            .status
+                            .as_ref()
+                            .and_then(|s| s.status_message.as_ref())
+                        {
+                            println!("    Status: {}", message);
+                        }
                         println!("    Notes: {:?}", chime.notes);
                         println!("    Chords: {:?}", chime.chords);
                         println!(
@@ -448,9 +722,16 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
 
                         for chime in user_chimes {
                             let status_str = match &chime.status {
+                                _ if chime.stale => "stale".to_string(),
                                 Some(status) => {
                                     if status.online {
-                                        format!("online, mode={:?}", status.mode)
+                                        match &status.status_message {
+                                            Some(message) => format!(
+                                                "online, mode={:?}, \"{}\"",
+                                                status.mode, message
+                                            ),
+                                            None => format!("online, mode={:?}", status.mode),
+                                        }
                                     } else {
                                         "offline".to_string()
                                     }
@@ -471,7 +752,7 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
                 state_guard
                     .get_chimes_for_user(user)
                     .into_iter()
-                    .filter(|c| c.status.as_ref().map_or(false, |s| s.online))
+                    .filter(|c| c.is_online())
                     .collect()
             } else {
                 state_guard.get_online_chimes()
@@ -492,6 +773,40 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
             }
         }
 
+        "find-note" | "find-chord" | "find-mode" => {
+            let kind = parts[0].trim_start_matches("find-");
+            let Some(query) = parts.get(1) else {
+                println!("Usage: {} <{}>", parts[0], kind);
+                return Ok(());
+            };
+
+            let state_guard = state.read().await;
+            let chimes = match parts[0] {
+                "find-note" => state_guard.find_chimes_by_note(query),
+                "find-chord" => state_guard.find_chimes_by_chord(query),
+                _ => match query.parse::<LcgpMode>() {
+                    Ok(mode) => state_guard.find_chimes_by_mode(&mode),
+                    Err(e) => {
+                        println!("{}", e);
+                        return Ok(());
+                    }
+                },
+            };
+
+            if chimes.is_empty() {
+                println!("No chimes found for {} {}", kind, query);
+            } else {
+                for chime in chimes {
+                    let mode = match chime.live_mode() {
+                        Some(mode) => format!("{:?}", mode),
+                        None if chime.stale => "stale".to_string(),
+                        None => "unknown".to_string(),
+                    };
+                    println!("  {}/{} - mode: {}", chime.user, chime.name, mode);
+                }
+            }
+        }
+
         "status" => {
             let state_guard = state.read().await;
 
@@ -507,8 +822,11 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
                         chime.last_seen.format("%Y-%m-%d %H:%M:%S")
                     );
 
+                    if chime.stale {
+                        println!("  Restored from the state file, not confirmed yet");
+                    }
                     if let Some(status) = &chime.status {
-                        println!("  Online: {}", status.online);
+                        println!("  Online: {}", chime.is_online());
                         println!("  Mode: {:?}", status.mode);
                         println!("  Node ID: {}", status.node_id);
                     } else {
@@ -527,6 +845,41 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
             }
         }
 
+        "refresh" => {
+            if parts.len() < 3 {
+                println!("Usage: refresh <user> <chime_name>");
+                return Ok(());
+            }
+
+            let (user, chime_name) = (parts[1], parts[2]);
+            let (chime, mqtt) = {
+                let state_guard = state.read().await;
+                (
+                    state_guard.find_chime_by_name(user, chime_name),
+                    state_guard.mqtt.clone(),
+                )
+            };
+            let (Some(chime), Some(mqtt)) = (chime, mqtt) else {
+                println!("Chime '{}' not found for user '{}'", chime_name, user);
+                return Ok(());
+            };
+
+            // Lock released: the chime has up to STATUS_REQUEST_TIMEOUT to answer
+            match mqtt
+                .request_status(&user.into(), &chime.chime_id.as_str().into())
+                .await
+            {
+                Ok(status) => {
+                    println!("{}/{} is {:?}", user, chime.name, status.mode);
+                    state
+                        .write()
+                        .await
+                        .update_chime_status(user, &chime.chime_id, status);
+                }
+                Err(e) => println!("No answer from {}/{}: {}", user, chime.name, e),
+            }
+        }
+
         "ring" => {
             if parts.len() < 3 {
                 println!("Usage: ring <user> <chime_name> [notes] [chords]");
@@ -539,51 +892,147 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
             let state_guard = state.read().await;
             if let Some(chime) = state_guard.find_chime_by_name(user, chime_name) {
                 if let Some(mqtt) = &state_guard.mqtt {
-                    let notes = if parts.len() > 3 && !parts[3].is_empty() {
+                    let notes: Option<Vec<String>> = if parts.len() > 3 && !parts[3].is_empty() {
                         Some(parts[3].split(',').map(|s| s.trim().to_string()).collect())
                     } else {
                         None
                     };
 
-                    let chords = if parts.len() > 4 && !parts[4].is_empty() {
+                    let chords: Option<Vec<String>> = if parts.len() > 4 && !parts[4].is_empty() {
                         Some(parts[4].split(',').map(|s| s.trim().to_string()).collect())
                     } else {
                         None
                     };
 
-                    let ring_request = ChimeRingRequest {
-                        chime_id: chime.chime_id.clone(),
-                        user: user.to_string(),
-                        notes,
-                        chords,
-                        duration_ms: None,
-                        timestamp: chrono::Utc::now(),
-                    };
+                    // Rings still go out with unsupported sounds, so just point them out
+                    let warnings = [
+                        notes.as_deref().and_then(|requested| {
+                            notes::unsupported_notes_warning(requested, &chime.notes)
+                        }),
+                        chords.as_deref().and_then(|requested| {
+                            notes::unsupported_chords_warning(requested, &chime.chords)
+                        }),
+                    ];
+                    for warning in warnings.into_iter().flatten() {
+                        println!("⚠️  {}", warning);
+                    }
 
-                    mqtt.publish_chime_ring_to_user(user, &chime.chime_id, &ring_request)
-                        .await?;
+                    send_ring(mqtt, &chime, notes, chords).await?;
                     println!("Ring request sent to {} ({})", chime.name, chime.chime_id);
                 }
             } else {
                 println!("Chime '{}' not found for user '{}'", chime_name, user);
+
+                let candidates = state_guard.search_chimes(user, chime_name);
+                if !candidates.is_empty() {
+                    println!("Did you mean:");
+                    for candidate in candidates.iter().take(5) {
+                        println!("  {} ({})", candidate.name, candidate.chime_id);
+                    }
+                }
+            }
+        }
+
+        "ring-all" => {
+            if parts.len() < 2 {
+                println!("Usage: ring-all <user> [notes] [chords]");
+                return Ok(());
+            }
+
+            let user = parts[1];
+            let list = |index: usize| -> Option<Vec<String>> {
+                parts
+                    .get(index)
+                    .filter(|list| !list.is_empty())
+                    .map(|list| list.split(',').map(|s| s.trim().to_string()).collect())
+            };
+
+            let state_guard = state.read().await;
+            let chimes = state_guard.get_chimes_for_user(user);
+            if chimes.is_empty() {
+                println!("No chimes found for user: {} (try `discover`)", user);
+                return Ok(());
+            }
+            let Some(mqtt) = &state_guard.mqtt else {
+                return Ok(());
+            };
+
+            let ring_request = ChimeRingRequest {
+                chime_id: String::new(),
+                user: user.to_string(),
+                notes: list(2),
+                chords: list(3),
+                duration_ms: None,
+                note_durations: None,
+                timestamp: chrono::Utc::now(),
+                preset: None,
+                urgency: Urgency::Normal,
+                expires_at: None,
+                request_id: None,
+            };
+            let chime_ids: Vec<String> = chimes.iter().map(|c| c.chime_id.clone()).collect();
+            let results = mqtt
+                .publish_chime_ring_broadcast(&user.into(), &chime_ids, &ring_request)
+                .await;
+
+            let mut sent = 0;
+            for (chime, (_, result)) in chimes.iter().zip(&results) {
+                match result {
+                    Ok(()) => {
+                        sent += 1;
+                        println!("  Rang {} ({})", chime.name, chime.chime_id);
+                    }
+                    Err(e) => println!(
+                        "  Failed to ring {} ({}): {}",
+                        chime.name, chime.chime_id, e
+                    ),
+                }
+            }
+            println!(
+                "Ring request sent to {} of {} chimes for {}",
+                sent,
+                chimes.len(),
+                user
+            );
+        }
+
+        "ring-random" => {
+            let weighting = match parts.get(1).map(|w| w.parse::<PickWeighting>()) {
+                Some(Ok(weighting)) => weighting,
+                Some(Err(e)) => {
+                    println!("{}", e);
+                    return Ok(());
+                }
+                None => PickWeighting::default(),
+            };
+
+            let state_guard = state.read().await;
+            let Some(chime) = state_guard.pick_random(weighting) else {
+                println!("No online chimes to ring (try `discover`)");
+                return Ok(());
+            };
+            if let Some(mqtt) = &state_guard.mqtt {
+                send_ring(mqtt, &chime, None, None).await?;
+                println!(
+                    "Ring request sent to {}/{} ({})",
+                    chime.user, chime.name, chime.chime_id
+                );
             }
         }
 
         "respond" => {
             if parts.len() < 4 {
-                println!("Usage: respond <user> <chime_name> <positive|negative>");
+                println!("Usage: respond <user> <chime_name> <positive|negative|snooze [minutes]> [\"message\"]");
                 return Ok(());
             }
 
             let user = parts[1];
             let chime_name = parts[2];
-            let response_str = parts[3];
 
-            let response = match response_str.to_lowercase().as_str() {
-                "positive" | "pos" | "yes" | "y" => ChimeResponse::Positive,
-                "negative" | "neg" | "no" | "n" => ChimeResponse::Negative,
+            let (response, message) = match ChimeResponse::from_args(&parts[3..]) {
+                Ok((response, rest)) => (response, ChimeResponseMessage::message_from_args(rest)),
                 _ => {
-                    println!("Invalid response. Use 'positive' or 'negative'");
+                    println!("Invalid response. Use 'positive', 'negative' or 'snooze [minutes]'");
                     return Ok(());
                 }
             };
@@ -596,9 +1045,11 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
                         response: response.clone(),
                         node_id: state_guard.ringer_id.clone(),
                         original_chime_id: Some(chime.chime_id.clone()),
+                        message,
+                        in_reply_to: None,
                     };
 
-                    mqtt.publish_chime_response(&chime.chime_id, &response_msg)
+                    mqtt.publish_chime_response(&chime.chime_id.as_str().into(), &response_msg)
                         .await?;
                     println!(
                         "Response sent to {} ({}): {:?}",
@@ -620,29 +1071,35 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
             let chime_name = parts[2];
             let mode_str = parts[3];
 
-            let mode = match mode_str.to_lowercase().as_str() {
-                "available" => LcgpMode::Available,
-                "donotdisturb" | "dnd" => LcgpMode::DoNotDisturb,
-                "grinding" => LcgpMode::Grinding,
-                "chillgrinding" | "chill" => LcgpMode::ChillGrinding,
-                custom if custom.starts_with("custom:") => {
-                    let name = custom.strip_prefix("custom:").unwrap_or("").to_string();
-                    LcgpMode::Custom(name)
-                }
-                _ => {
-                    println!("Invalid mode. Use: Available, DoNotDisturb, Grinding, ChillGrinding, or Custom:name");
+            let mode = match mode_str.parse::<LcgpMode>() {
+                Ok(mode) => mode,
+                Err(e) => {
+                    println!("{}", e);
                     return Ok(());
                 }
             };
 
             let state_guard = state.read().await;
-            if let Some(_chime) = state_guard.find_chime_by_name(user, chime_name) {
-                println!(
-                    "Mode change requests are not implemented yet (would set {} to {:?})",
-                    chime_name, mode
-                );
-            } else {
+            let Some(chime) = state_guard.find_chime_by_name(user, chime_name) else {
                 println!("Chime '{}' not found for user '{}'", chime_name, user);
+                return Ok(());
+            };
+
+            if let Some(mqtt) = &state_guard.mqtt {
+                let request = ModeChangeRequest {
+                    chime_id: chime.chime_id.clone(),
+                    requested_mode: mode.clone(),
+                    requester_id: state_guard.ringer_id.clone(),
+                    timestamp: chrono::Utc::now(),
+                };
+                mqtt.publish_mode_request(
+                    &UserId::from(user),
+                    &ChimeId::from(chime.chime_id.as_str()),
+                    &request,
+                )
+                .await?;
+                // Chimes may refuse; their next status shows whether it took
+                println!("Requested mode {} for {}", mode, chime_name);
             }
         }
 
@@ -677,7 +1134,7 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
                 should_chime,
                 auto_response: auto_response.clone(),
                 auto_response_delay: auto_response.as_ref().map(|_| 5000), // 5 seconds default
-                description: Some(format!("Custom state created by ringer client")),
+                description: Some("Custom state created by ringer client".to_string()),
                 priority: Some(100),
                 active_hours: None,
                 conditions: Vec::new(),
@@ -685,6 +1142,7 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
 
             let mut state_guard = state.write().await;
             state_guard.add_custom_state(custom_state);
+            state_guard.persist();
             println!(
                 "Created custom state '{}' - should_chime: {}, auto_response: {:?}",
                 name, should_chime, auto_response
@@ -723,9 +1181,15 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
             println!("  users - List all discovered users");
             println!("  list [user] - List available chimes");
             println!("  online [user] - List online chimes");
+            println!("  find-note <note> - List chimes that can play a note");
+            println!("  find-chord <chord> - List chimes that can play a chord");
+            println!("  find-mode <mode> - List chimes currently in a mode");
             println!("  status [user] [chime_name] - Show chime status");
+            println!("  refresh <user> <chime_name> - Ask a chime for its current status");
             println!("  ring <user> <chime_name> [notes] [chords] - Ring a chime by name");
-            println!("  respond <user> <chime_name> <positive|negative> - Respond to a chime");
+            println!("  ring-all <user> [notes] [chords] - Ring every known chime of a user");
+            println!("  ring-random [activity|uniform] - Ring a random available chime, favoring quiet ones");
+            println!("  respond <user> <chime_name> <positive|negative|snooze [minutes]> [\"message\"] - Respond to a chime");
             println!("  mode <user> <chime_name> <mode> - Set chime mode");
             println!("  custom-state <name> <should_chime> [auto_response] - Create custom state");
             println!("  states - List custom states");
@@ -734,6 +1198,7 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
         }
 
         "quit" => {
+            state.read().await.persist();
             println!("Exiting...");
             return Ok(());
         }
@@ -748,3 +1213,220 @@ async fn handle_shell_command(command: &str, state: &SharedState) -> Result<()>
 
     Ok(())
 }
+
+// Rings `chime`, and again each time it snoozes this ring (up to
+// MAX_SNOOZE_FOLLOW_UPS times)
+async fn send_ring(
+    mqtt: &Arc<ChimeNetMqtt>,
+    chime: &DiscoveredChime,
+    notes: Option<Vec<String>>,
+    chords: Option<Vec<String>>,
+) -> Result<()> {
+    let Some(answer) = publish_ring(mqtt, chime, notes.clone(), chords.clone()).await? else {
+        return Ok(());
+    };
+
+    let (mqtt, chime) = (mqtt.clone(), chime.clone());
+    tokio::spawn(follow_snoozes(answer, move || {
+        let (mqtt, chime) = (mqtt.clone(), chime.clone());
+        let (notes, chords) = (notes.clone(), chords.clone());
+        async move {
+            match publish_ring(&mqtt, &chime, notes, chords).await {
+                Ok(answer) => {
+                    println!("Rang {} again after its snooze", chime.name);
+                    answer
+                }
+                Err(e) => {
+                    error!("Failed to ring {} after its snooze: {}", chime.name, e);
+                    None
+                }
+            }
+        }
+    }));
+    Ok(())
+}
+
+// Rings `chime` and returns the wait for the answer to this ring, None when
+// that answer can't be watched for
+async fn publish_ring(
+    mqtt: &ChimeNetMqtt,
+    chime: &DiscoveredChime,
+    notes: Option<Vec<String>>,
+    chords: Option<Vec<String>>,
+) -> Result<Option<NextMessage>> {
+    let (user, chime_id) = (chime.user.as_str().into(), chime.chime_id.as_str().into());
+    let ring_request = new_ring_request(&user, &chime_id, notes, chords, None);
+
+    let request_id = ring_request.request_id.as_deref().unwrap_or_default();
+    let answer = match mqtt.response_to(&user, &chime_id, request_id).await {
+        Ok(answer) => Some(answer),
+        Err(e) => {
+            error!("Not watching {} for a snooze: {}", chime.name, e);
+            None
+        }
+    };
+
+    mqtt.publish_chime_ring_to_user(&user, &chime_id, &ring_request)
+        .await?;
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_chimes_come_back_stale_until_a_status_arrives() {
+        let path =
+            std::env::temp_dir().join(format!("ringer_client_state_{}.json", std::process::id()));
+        let status = ChimeStatus {
+            chime_id: "desk".to_string(),
+            online: true,
+            mode: LcgpMode::Available,
+            last_seen: chrono::Utc::now(),
+            node_id: "node".to_string(),
+            following: None,
+            ring_rate_per_hour: 0.0,
+            status_message: None,
+            in_reply_to: None,
+        };
+
+        let mut state = RingerState::new();
+        state.state_file = Some(path.clone());
+        state.add_discovered_chime(DiscoveredChime {
+            user: "alice".to_string(),
+            chime_id: "desk".to_string(),
+            name: "Desk".to_string(),
+            notes: vec!["C4".to_string()],
+            chords: vec![],
+            last_seen: chrono::Utc::now(),
+            status: None,
+            stale: false,
+        });
+        state.update_chime_status("alice", "desk", status.clone());
+        state.add_custom_state(CustomLcgpState {
+            name: "Lunch".to_string(),
+            should_chime: false,
+            auto_response: None,
+            auto_response_delay: None,
+            description: None,
+            priority: None,
+            active_hours: None,
+            conditions: Vec::new(),
+        });
+        assert_eq!(state.get_online_chimes().len(), 1);
+        state.save().unwrap();
+
+        let mut restored = RingerState::new();
+        assert_eq!(restored.load(&path).unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.get_all_users(), vec!["alice".to_string()]);
+        assert!(restored.get_custom_state("Lunch").is_some());
+        let chime = restored.find_chime_by_name("alice", "Desk").unwrap();
+        assert!(chime.stale);
+        assert!(restored.get_online_chimes().is_empty());
+        assert!(restored.pick_random(PickWeighting::Uniform).is_none());
+
+        restored.update_chime_status("alice", "desk", status);
+        assert_eq!(restored.get_online_chimes().len(), 1);
+
+        // No file yet is a first run
+        assert_eq!(RingerState::new().load(&path).unwrap(), 0);
+    }
+
+    #[test]
+    fn finds_chimes_by_note_pitch_and_chord() {
+        let mut state = RingerState::new();
+        state.add_discovered_chime(DiscoveredChime {
+            user: "alice".to_string(),
+            chime_id: "desk".to_string(),
+            name: "Desk".to_string(),
+            notes: vec!["C#4".to_string()],
+            chords: vec!["Am".to_string()],
+            last_seen: chrono::Utc::now(),
+            status: None,
+            stale: false,
+        });
+
+        for note in ["C#4", "Db4", "db4"] {
+            assert_eq!(state.find_chimes_by_note(note).len(), 1, "{}", note);
+        }
+        assert!(state.find_chimes_by_note("D4").is_empty());
+        assert_eq!(state.find_chimes_by_chord(" Am").len(), 1);
+        assert!(state.find_chimes_by_chord("A").is_empty());
+    }
+
+    #[test]
+    fn chimes_are_found_by_note_chord_and_live_mode() {
+        let chime = |user: &str, name: &str, notes: &[&str], chords: &[&str]| DiscoveredChime {
+            user: user.to_string(),
+            chime_id: name.to_lowercase(),
+            name: name.to_string(),
+            notes: notes.iter().map(|n| n.to_string()).collect(),
+            chords: chords.iter().map(|c| c.to_string()).collect(),
+            last_seen: chrono::Utc::now(),
+            status: None,
+            stale: false,
+        };
+        let status = |chime_id: &str, mode: LcgpMode| ChimeStatus {
+            chime_id: chime_id.to_string(),
+            online: true,
+            mode,
+            last_seen: chrono::Utc::now(),
+            node_id: "node".to_string(),
+            following: None,
+            ring_rate_per_hour: 0.0,
+            status_message: None,
+            in_reply_to: None,
+        };
+
+        let mut state = RingerState::new();
+        state.add_discovered_chime(chime("bob", "Door", &["C4", "E4"], &["G"]));
+        state.add_discovered_chime(chime("alice", "Desk", &["C4"], &["C", "G"]));
+        state.add_discovered_chime(chime("alice", "Attic", &["A4"], &[]));
+        state.update_chime_status("alice", "desk", status("desk", LcgpMode::Available));
+        state.update_chime_status("bob", "door", status("door", LcgpMode::Grinding));
+        let names = |chimes: Vec<DiscoveredChime>| -> Vec<String> {
+            chimes
+                .into_iter()
+                .map(|c| format!("{}/{}", c.user, c.name))
+                .collect()
+        };
+
+        assert_eq!(
+            names(state.find_chimes_by_note("C4")),
+            vec!["alice/Desk", "bob/Door"]
+        );
+        assert_eq!(
+            names(state.find_chimes_by_chord("G")),
+            vec!["alice/Desk", "bob/Door"]
+        );
+        assert!(state.find_chimes_by_chord("Am").is_empty());
+        assert_eq!(
+            names(state.find_chimes_by_mode(&LcgpMode::Available)),
+            vec!["alice/Desk"]
+        );
+
+        // A status restored from the state file isn't live
+        state.discovered_chimes.get_mut("alice/desk").unwrap().stale = true;
+        assert!(state.find_chimes_by_mode(&LcgpMode::Available).is_empty());
+    }
+
+    #[test]
+    fn demo_samples_keep_their_presence() {
+        let samples = chimenet::DiscoveredChime::sample_set();
+        let mut state = RingerState::new();
+        for sample in samples.clone() {
+            state.add_discovered_chime(DiscoveredChime::from_sample(sample));
+        }
+
+        assert_eq!(state.get_all_chimes().len(), samples.len());
+        for sample in &samples {
+            let key = format!("{}/{}", sample.user, sample.chime_id);
+            let chime = &state.discovered_chimes[&key];
+            assert_eq!(chime.is_online(), sample.online, "{}", key);
+            assert_eq!(chime.live_mode(), Some(&sample.mode), "{}", key);
+        }
+    }
+}
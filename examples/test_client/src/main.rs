@@ -1,8 +1,8 @@
 use chimenet::*;
 use clap::Parser;
 use log::{error, info};
-use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 #[derive(Parser)]
@@ -12,6 +12,9 @@ struct Args {
     #[arg(short, long, default_value = "tcp://localhost:1883")]
     broker: String,
 
+    #[command(flatten)]
+    broker_args: BrokerArgs,
+
     /// Test client user name
     #[arg(short, long, default_value = "test_client")]
     user: String,
@@ -27,23 +30,30 @@ struct Args {
     /// Non-interactive mode - execute command and exit
     #[arg(long)]
     oneshot: bool,
-}
 
-#[derive(Debug, Clone)]
-struct DiscoveredChime {
-    user: String,
-    chime_id: String,
-    name: String,
-    description: Option<String>,
-    notes: Vec<String>,
-    chords: Vec<String>,
-    online: bool,
-    mode: LcgpMode,
-    last_seen: chrono::DateTime<chrono::Utc>,
+    /// Seconds between discovery cleanup passes
+    #[arg(long, default_value_t = 30)]
+    cleanup_interval: u64,
+
+    /// Seconds between the heartbeats chimes send (their --heartbeat-interval)
+    #[arg(long, default_value_t = 60)]
+    heartbeat_interval: u64,
+
+    /// Heartbeats a chime may miss before it is dropped from discovery
+    #[arg(long, default_value_t = 5)]
+    staleness_factor: u32,
+
+    /// Seconds a chime may go unseen before it is dropped from discovery;
+    /// overrides --heartbeat-interval times --staleness-factor
+    #[arg(long)]
+    stale_after: Option<u64>,
+
+    /// Demo mode: no broker (LAN transport) and sample chimes in discovery
+    #[arg(long)]
+    demo: bool,
 }
 
 type SharedState = Arc<RwLock<TestClientState>>;
-type DiscoveredChimes = Arc<RwLock<HashMap<String, DiscoveredChime>>>;
 
 #[derive(Clone)]
 struct TestClientState {
@@ -58,41 +68,89 @@ impl TestClientState {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
 
-    let args = Args::parse();
+    let started = std::time::Instant::now();
+    let result = run(Args::parse()).await;
+    std::process::exit(ShutdownSummary::new("test_client", &result, started.elapsed()).report());
+}
 
+async fn run(args: Args) -> Result<()> {
     info!("Starting ChimeNet Test Client");
     info!("Test client user: {}", args.user);
-    info!("Connecting to MQTT broker: {}", args.broker);
+    // Demo mode must work without any infrastructure, so it never uses the broker
+    let broker = if args.demo {
+        LAN_URL_SCHEME.to_string()
+    } else {
+        args.broker.clone()
+    };
+    info!("Connecting to MQTT broker: {}", broker);
 
     // Connect to MQTT
     let client_id = format!("test_client_{}", args.user);
-    let mut mqtt = ChimeNetMqtt::new(&args.broker, &args.user, &client_id).await?;
+    let mut mqtt = ChimeNetMqtt::builder()
+        .broker(&broker)
+        .user(&args.user.as_str().into())
+        .client_id(client_id)
+        .credentials(args.broker_args.credentials())
+        .tls(args.broker_args.tls())
+        .build()
+        .await?;
     mqtt.connect().await?;
 
     let state = Arc::new(RwLock::new(TestClientState::new(
         Arc::new(mqtt),
         args.user.clone(),
     )));
-    let discovered_chimes: DiscoveredChimes = Arc::new(RwLock::new(HashMap::new()));
+    // Create discovered chimes storage
+    let heartbeat = HeartbeatConfig {
+        interval: Duration::from_secs(args.heartbeat_interval),
+        staleness_factor: args.staleness_factor,
+    };
+    heartbeat.validate()?;
+    let discovery_config = DiscoveryConfig {
+        cleanup_interval: Duration::from_secs(args.cleanup_interval),
+        stale_after: args
+            .stale_after
+            .map_or(heartbeat.stale_after(), Duration::from_secs),
+    };
+    discovery_config.validate()?;
+    let discovery = ChimeDiscovery::new(discovery_config);
+    discovery.on_chime_removed(|chime| {
+        info!(
+            "Chime went away: {} ({}/{})",
+            chime.name, chime.user, chime.chime_id
+        );
+    });
+    let discovered_chimes = discovery.chimes();
+    let discovery_for_commands = discovery.clone();
 
     // Start discovery monitoring
-    let discovery_chimes = discovered_chimes.clone();
-    let discovery_user = args.user.clone();
+    let discovery_mqtt = state.read().await.mqtt.clone();
+    let discovery_broker = broker.clone();
     tokio::spawn(async move {
-        if let Err(e) = start_discovery_monitoring(discovery_chimes, discovery_user).await {
+        if let Err(e) =
+            start_discovery_monitoring(discovery, discovery_mqtt, &discovery_broker).await
+        {
             error!("Discovery monitoring error: {}", e);
         }
     });
 
+    if args.demo {
+        info!("Demo mode: injecting sample chimes");
+        tokio::spawn(refresh_demo_chimes(
+            discovery_for_commands.clone(),
+            Duration::from_secs(args.cleanup_interval.max(1)),
+        ));
+    }
+
     // Wait a bit for discovery
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
     // Execute command if provided
     if let Some(command) = args.command {
-        execute_command(&command, &state, &discovered_chimes).await?;
+        execute_command(&command, &state, &discovery_for_commands).await?;
 
         // If oneshot mode, exit after command
         if args.oneshot {
@@ -114,14 +172,14 @@ async fn main() -> Result<()> {
         info!("  discover - Show all discovered chimes with full details");
         info!("  list - List discovered chimes in simple format");
         info!("  ring <user> <chime_id> [notes] [chords] - Ring a chime by ID");
-        info!("  ring-name <chime_name> [notes] [chords] - Ring a chime by name");
+        info!("  ring-name [<user>/]<chime_name> [notes] [chords] - Ring a chime by name");
         info!("  test-all - Test all discovered chimes");
         info!("  monitor <user> [chime_id] - Monitor chime topics");
         info!("  status - Show client status");
         info!("  help - Show this help message");
         info!("  quit - Exit");
 
-        run_interactive_mode(&state, &discovered_chimes).await;
+        run_interactive_mode(&state, &discovery_for_commands).await;
     }
 
     let state_guard = state.read().await;
@@ -129,172 +187,44 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn start_discovery_monitoring(
-    discovered_chimes: DiscoveredChimes,
-    current_user: String,
-) -> Result<()> {
-    // Create a temporary MQTT client for discovery monitoring
-    let client_id = format!("test_discovery_{}", uuid::Uuid::new_v4());
-    let mut mqtt = ChimeNetMqtt::new("tcp://localhost:1883", &current_user, &client_id).await?;
-    mqtt.connect().await?;
-
-    info!("Starting discovery monitoring for user: {}", current_user);
-
-    // Subscribe to all chime lists, notes, chords, and status messages
-    let topics = vec![
-        "/+/chime/list",
-        "/+/chime/+/notes",
-        "/+/chime/+/chords",
-        "/+/chime/+/status",
-    ];
-
-    for topic in topics {
-        let discovered_clone = discovered_chimes.clone();
-        let current_user_clone = current_user.clone();
-
-        mqtt.subscribe(topic, 1, move |topic, payload| {
-            let discovered = discovered_clone.clone();
-            let user = current_user_clone.clone();
-            let topic = topic.clone();
-            let payload = payload.clone();
-
-            tokio::spawn(async move {
-                if let Err(e) = handle_discovery_message(topic, payload, discovered, user).await {
-                    error!("Error handling discovery message: {}", e);
-                }
-            });
-        })
-        .await?;
-    }
-
-    info!("Discovery monitoring started, listening for chime information...");
-
-    // Keep the discovery alive
+// Keeps the sample chimes fresh so cleanup never drops them
+async fn refresh_demo_chimes(discovery: ChimeDiscovery, every: Duration) {
+    let samples = DiscoveredChime::sample_set();
+    let mut interval = tokio::time::interval(every);
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
-
-        // Clean up old chimes (remove chimes not seen for 5 minutes)
-        let mut chimes = discovered_chimes.write().await;
-        let now = chrono::Utc::now();
-        let cutoff = now - chrono::Duration::minutes(5);
-
-        let old_count = chimes.len();
-        chimes.retain(|_, chime| chime.last_seen > cutoff);
-        let new_count = chimes.len();
-
-        if old_count != new_count {
-            info!(
-                "Cleaned up {} old chimes, {} chimes remaining",
-                old_count - new_count,
-                new_count
-            );
-        }
+        interval.tick().await;
+        discovery.inject(samples.clone()).await;
     }
 }
 
-async fn handle_discovery_message(
-    topic: String,
-    payload: String,
-    discovered_chimes: DiscoveredChimes,
-    current_user: String,
+async fn start_discovery_monitoring(
+    discovery: ChimeDiscovery,
+    mqtt: Arc<ChimeNetMqtt>,
+    broker: &str,
 ) -> Result<()> {
-    let parts: Vec<&str> = topic.split('/').collect();
-    if parts.len() < 3 {
-        return Ok(());
-    }
+    info!(
+        "Starting discovery monitoring for user: {} on broker: {}",
+        mqtt.user(),
+        broker
+    );
 
-    let user = parts[1];
+    // Discovery shares the client's connection rather than opening its own.
+    // Our own chimes are filtered out by the discovery subscription.
+    discovery.subscribe(&mqtt).await?;
 
-    // Skip our own messages
-    if user == current_user {
-        return Ok(());
-    }
-
-    match parts.get(2) {
-        Some(&"chime") => {
-            match parts.get(3) {
-                Some(&"list") => {
-                    // Handle chime list
-                    if let Ok(chime_list) = serde_json::from_str::<ChimeList>(&payload) {
-                        let mut chimes = discovered_chimes.write().await;
-                        let chime_count = chime_list.chimes.len();
-
-                        for chime_info in &chime_list.chimes {
-                            let key = format!("{}/{}", user, chime_info.id);
-                            let discovered_chime = DiscoveredChime {
-                                user: user.to_string(),
-                                chime_id: chime_info.id.clone(),
-                                name: chime_info.name.clone(),
-                                description: chime_info.description.clone(),
-                                notes: chime_info.notes.clone(),
-                                chords: chime_info.chords.clone(),
-                                online: true,
-                                mode: LcgpMode::Available, // Default, will be updated by status
-                                last_seen: chrono::Utc::now(),
-                            };
-
-                            chimes.insert(key, discovered_chime);
-                        }
-
-                        info!(
-                            "Updated chime list for user: {} ({} chimes)",
-                            user, chime_count
-                        );
-                    }
-                }
-                Some(chime_id) => {
-                    let key = format!("{}/{}", user, chime_id);
-
-                    match parts.get(4) {
-                        Some(&"notes") => {
-                            // Handle notes update
-                            if let Ok(notes) = serde_json::from_str::<Vec<String>>(&payload) {
-                                let mut chimes = discovered_chimes.write().await;
-                                if let Some(chime) = chimes.get_mut(&key) {
-                                    chime.notes = notes;
-                                    chime.last_seen = chrono::Utc::now();
-                                }
-                            }
-                        }
-                        Some(&"chords") => {
-                            // Handle chords update
-                            if let Ok(chords) = serde_json::from_str::<Vec<String>>(&payload) {
-                                let mut chimes = discovered_chimes.write().await;
-                                if let Some(chime) = chimes.get_mut(&key) {
-                                    chime.chords = chords;
-                                    chime.last_seen = chrono::Utc::now();
-                                }
-                            }
-                        }
-                        Some(&"status") => {
-                            // Handle status update
-                            if let Ok(status) = serde_json::from_str::<ChimeStatus>(&payload) {
-                                let mut chimes = discovered_chimes.write().await;
-                                if let Some(chime) = chimes.get_mut(&key) {
-                                    chime.online = status.online;
-                                    chime.mode = status.mode;
-                                    chime.last_seen = chrono::Utc::now();
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            }
-        }
-        _ => {}
-    }
+    info!("Discovery monitoring started, listening for chime information...");
 
-    Ok(())
+    // Keep pruning stale chimes for as long as the client runs
+    discovery.run_cleanup().await
 }
 
 async fn execute_command(
     command: &str,
     state: &SharedState,
-    discovered_chimes: &DiscoveredChimes,
+    discovery: &ChimeDiscovery,
 ) -> Result<()> {
     let parts: Vec<&str> = command.split_whitespace().collect();
+    let discovered_chimes = &discovery.chimes();
 
     if parts.is_empty() {
         return Ok(());
@@ -302,6 +232,16 @@ async fn execute_command(
 
     match parts[0] {
         "discover" => {
+            // Ask every chime to re-announce instead of relying on retained messages
+            let (mqtt, ringer_id) = {
+                let state_guard = state.read().await;
+                let ringer_id = format!("test_client_{}", state_guard.user);
+                (state_guard.mqtt.clone(), ringer_id)
+            };
+            let answered = discovery
+                .request_announce(&mqtt, &ringer_id, DEFAULT_ANNOUNCE_WINDOW * 2)
+                .await?;
+            println!("{} chime(s) answered the discovery request", answered.len());
             discover_chimes(discovered_chimes).await;
         }
 
@@ -333,7 +273,7 @@ async fn execute_command(
 
         "ring-name" => {
             if parts.len() < 2 {
-                println!("Usage: ring-name <chime_name> [notes] [chords]");
+                println!("Usage: ring-name [<user>/]<chime_name> [notes] [chords]");
                 return Ok(());
             }
 
@@ -369,7 +309,12 @@ async fn execute_command(
         }
 
         "test-all" => {
-            test_all_chimes(state, discovered_chimes).await?;
+            let results = test_all_chimes(state, discovered_chimes).await?;
+            // Fail the command (and a --oneshot run) if any ring couldn't be sent
+            let failed = results.iter().filter(|r| !r.success).count();
+            if failed > 0 {
+                return Err(format!("{} of {} test rings failed", failed, results.len()).into());
+            }
         }
 
         "status" => {
@@ -409,7 +354,7 @@ async fn discover_chimes(discovered_chimes: &DiscoveredChimes) {
         for chime in chimes.values() {
             users_chimes
                 .entry(chime.user.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(chime);
         }
 
@@ -447,7 +392,7 @@ async fn discover_chimes(discovered_chimes: &DiscoveredChimes) {
                 );
                 println!("    Test commands:");
                 println!("      ring {} {}", chime.user, chime.chime_id);
-                println!("      ring-name {}", chime.name);
+                println!("      ring-name {}/{}", chime.user, chime.name);
                 println!();
             }
         }
@@ -495,12 +440,17 @@ async fn ring_chime_by_id(
         notes,
         chords,
         duration_ms: Some(1000),
+        note_durations: None,
         timestamp: chrono::Utc::now(),
+        preset: None,
+        urgency: Urgency::Normal,
+        expires_at: None,
+        request_id: None,
     };
 
     match state_guard
         .mqtt
-        .publish_chime_ring_to_user(user, chime_id, &ring_request)
+        .publish_chime_ring_to_user(&user.into(), &chime_id.into(), &ring_request)
         .await
     {
         Ok(()) => println!("✓ Ring request sent successfully to {}/{}", user, chime_id),
@@ -519,16 +469,47 @@ async fn ring_chime_by_name(
 ) -> Result<()> {
     let chimes = discovered_chimes.read().await;
 
-    // Find chime by name
-    let chime = chimes
+    // Names are only unique per user, so "user/name" picks one explicitly
+    let (user, chime_name) = match chime_name.split_once('/') {
+        Some((user, name)) => (Some(user), name),
+        None => (None, chime_name),
+    };
+    let matches: Vec<&DiscoveredChime> = chimes
         .values()
-        .find(|c| c.name == chime_name)
-        .ok_or_else(|| anyhow::anyhow!("Chime '{}' not found", chime_name))?;
+        .filter(|c| c.name == chime_name && user.is_none_or(|user| c.user == user))
+        .collect();
+    let chime = match matches.as_slice() {
+        [chime] => *chime,
+        [] => return Err(format!("Chime '{}' not found", chime_name).into()),
+        _ => {
+            let owners: Vec<&str> = matches.iter().map(|c| c.user.as_str()).collect();
+            return Err(format!(
+                "Chime name '{}' is used by several users ({}); use <user>/{}",
+                chime_name,
+                owners.join(", "),
+                chime_name
+            )
+            .into());
+        }
+    };
 
     let chime_user = chime.user.clone();
     let chime_id = chime.chime_id.clone();
     let chime_name = chime.name.clone();
 
+    // Rings still go out with unsupported sounds, so just point them out
+    let warnings = [
+        notes
+            .as_deref()
+            .and_then(|requested| notes::unsupported_notes_warning(requested, &chime.notes)),
+        chords
+            .as_deref()
+            .and_then(|requested| notes::unsupported_chords_warning(requested, &chime.chords)),
+    ];
+    for warning in warnings.into_iter().flatten() {
+        println!("⚠️  {}", warning);
+    }
+
     drop(chimes);
 
     println!("🔔 Ringing chime: {} ({})", chime_name, chime_id);
@@ -541,12 +522,21 @@ async fn ring_chime_by_name(
         notes,
         chords,
         duration_ms: Some(1000),
+        note_durations: None,
         timestamp: chrono::Utc::now(),
+        preset: None,
+        urgency: Urgency::Normal,
+        expires_at: None,
+        request_id: None,
     };
 
     match state_guard
         .mqtt
-        .publish_chime_ring_to_user(&chime_user, &chime_id, &ring_request)
+        .publish_chime_ring_to_user(
+            &chime_user.as_str().into(),
+            &chime_id.as_str().into(),
+            &ring_request,
+        )
         .await
     {
         Ok(()) => println!("✓ Ring request sent successfully to {}", chime_name),
@@ -571,27 +561,39 @@ async fn monitor_chime_topics(
             let ring_topic = format!("/{}/chime/{}/ring", user, chime_id);
             state_guard
                 .mqtt
-                .subscribe(&ring_topic, 1, move |topic, payload| {
-                    println!("🔔 RING: {} -> {}", topic, payload);
-                })
+                .subscribe(
+                    &ring_topic,
+                    state_guard.mqtt.ring_qos(),
+                    move |topic, payload| {
+                        println!("🔔 RING: {} -> {}", topic, payload);
+                    },
+                )
                 .await?;
 
             // Monitor response topic
             let response_topic = format!("/{}/chime/{}/response", user, chime_id);
             state_guard
                 .mqtt
-                .subscribe(&response_topic, 1, move |topic, payload| {
-                    println!("💬 RESPONSE: {} -> {}", topic, payload);
-                })
+                .subscribe(
+                    &response_topic,
+                    state_guard.mqtt.ring_qos(),
+                    move |topic, payload| {
+                        println!("💬 RESPONSE: {} -> {}", topic, payload);
+                    },
+                )
                 .await?;
 
             // Monitor status topic
             let status_topic = format!("/{}/chime/{}/status", user, chime_id);
             state_guard
                 .mqtt
-                .subscribe(&status_topic, 1, move |topic, payload| {
-                    println!("📊 STATUS: {} -> {}", topic, payload);
-                })
+                .subscribe(
+                    &status_topic,
+                    state_guard.mqtt.state_qos(),
+                    move |topic, payload| {
+                        println!("📊 STATUS: {} -> {}", topic, payload);
+                    },
+                )
                 .await?;
         }
         None => {
@@ -601,9 +603,13 @@ async fn monitor_chime_topics(
             let all_topic = format!("/{}/chime/+/+", user);
             state_guard
                 .mqtt
-                .subscribe(&all_topic, 1, move |topic, payload| {
-                    println!("📨 ALL: {} -> {}", topic, payload);
-                })
+                .subscribe(
+                    &all_topic,
+                    state_guard.mqtt.any_qos(),
+                    move |topic, payload| {
+                        println!("📨 ALL: {} -> {}", topic, payload);
+                    },
+                )
                 .await?;
         }
     }
@@ -616,64 +622,44 @@ async fn monitor_chime_topics(
     }
 }
 
-async fn test_all_chimes(state: &SharedState, discovered_chimes: &DiscoveredChimes) -> Result<()> {
-    let chimes = discovered_chimes.read().await;
-    let chime_vec: Vec<&DiscoveredChime> = chimes.values().collect();
+async fn test_all_chimes(
+    state: &SharedState,
+    discovered_chimes: &DiscoveredChimes,
+) -> Result<Vec<RingTestResult>> {
+    let chimes: Vec<DiscoveredChime> = discovered_chimes.read().await.values().cloned().collect();
 
-    if chime_vec.is_empty() {
+    if chimes.is_empty() {
         println!("No chimes to test. Discovery runs automatically in the background.");
-        return Ok(());
+        return Ok(Vec::new());
     }
 
-    println!("🧪 Testing {} chimes...", chime_vec.len());
+    println!("🧪 Testing {} chimes...", chimes.len());
 
     let state_guard = state.read().await;
-
-    for chime in chime_vec {
+    let results = test_ring_chimes(
+        &state_guard.mqtt,
+        &state_guard.user.as_str().into(),
+        &chimes,
+        Duration::from_millis(100),
+    )
+    .await;
+
+    for chime in &chimes {
         println!("Testing: {} ({})", chime.name, chime.chime_id);
-
-        // Test with different combinations
-        let test_cases = vec![
-            ("Default", None, None),
-            ("With notes", Some(chime.notes.clone()), None),
-            ("With chords", None, Some(chime.chords.clone())),
-            (
-                "Notes and chords",
-                Some(chime.notes.clone()),
-                Some(chime.chords.clone()),
-            ),
-        ];
-
-        for (test_name, notes, chords) in test_cases {
-            println!("  {}: ", test_name);
-
-            let ring_request = ChimeRingRequest {
-                chime_id: chime.chime_id.clone(),
-                user: state_guard.user.clone(),
-                notes,
-                chords,
-                duration_ms: Some(500),
-                timestamp: chrono::Utc::now(),
-            };
-
-            match state_guard
-                .mqtt
-                .publish_chime_ring_to_user(&chime.user, &chime.chime_id, &ring_request)
-                .await
-            {
-                Ok(()) => println!("    ✓ Sent"),
-                Err(e) => println!("    ✗ Failed: {}", e),
+        for result in results
+            .iter()
+            .filter(|r| r.user == chime.user && r.chime_id == chime.chime_id)
+        {
+            match &result.error {
+                None => println!("  {}: ✓ Sent", result.case),
+                Some(e) => println!("  {}: ✗ Failed: {}", result.case, e),
             }
-
-            // Wait a bit between tests
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
         }
-
         println!();
     }
 
     println!("🎉 Test complete!");
-    Ok(())
+    Ok(results)
 }
 
 async fn show_status(discovered_chimes: &DiscoveredChimes) {
@@ -710,7 +696,7 @@ fn show_help() {
     );
     println!("  list                                  - List discovered chimes in simple format");
     println!("  ring <user> <chime_id> [notes] [chords] - Ring a chime by user and ID");
-    println!("  ring-name <chime_name> [notes] [chords] - Ring a chime by name");
+    println!("  ring-name [<user>/]<chime_name> [notes] [chords] - Ring a chime by name");
     println!("  test-all                              - Test all discovered chimes");
     println!("  monitor <user> [chime_id]             - Monitor chime topics (specific or all)");
     println!("  status                                - Show client status and statistics");
@@ -731,7 +717,7 @@ fn show_help() {
     println!("  monitor bob 87654321-4321-4321-4321-210987654321");
 }
 
-async fn run_interactive_mode(state: &SharedState, discovered_chimes: &DiscoveredChimes) {
+async fn run_interactive_mode(state: &SharedState, discovery: &ChimeDiscovery) {
     use std::io::{self, Write};
 
     loop {
@@ -752,7 +738,7 @@ async fn run_interactive_mode(state: &SharedState, discovered_chimes: &Discovere
             break;
         }
 
-        if let Err(e) = execute_command(command, state, discovered_chimes).await {
+        if let Err(e) = execute_command(command, state, discovery).await {
             error!("Command error: {}", e);
         }
     }
@@ -0,0 +1,169 @@
+// Line-oriented JSON control protocol for driving the chime from another process.
+// Each stdin line is one command, each reply is one JSON line on stdout.
+use chimenet::{ChimeId, Result, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum JsonCommand {
+    Ring {
+        user: UserId,
+        chime_id: ChimeId,
+        notes: Option<Vec<String>>,
+        chords: Option<Vec<String>>,
+    },
+    Respond {
+        response: String, // "positive", "negative" or "snooze:<minutes>"
+        chime_id: Option<ChimeId>,
+        message: Option<String>, // Why, e.g. "in a meeting"
+    },
+    Mode {
+        mode: String,
+    },
+    Away {
+        message: Option<String>,
+    },
+    Presence {
+        message: Option<String>,
+    },
+    Status,
+    Discover,
+    Snapshot,
+    Reset,
+    Quit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonReply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JsonReply {
+    fn ok(result: Value) -> Self {
+        Self {
+            ok: true,
+            result: (!result.is_null()).then_some(result),
+            error: None,
+        }
+    }
+
+    fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+// Reads commands until EOF or `quit`, handing each to `dispatch` and writing its reply
+pub async fn run<R, W, F, Fut>(reader: R, mut writer: W, mut dispatch: F) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnMut(JsonCommand) -> Fut,
+    Fut: Future<Output = Result<Value>>,
+{
+    let mut lines = reader.lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (reply, quit) = match serde_json::from_str::<JsonCommand>(line) {
+            Ok(JsonCommand::Quit) => (JsonReply::ok(Value::Null), true),
+            Ok(command) => match dispatch(command).await {
+                Ok(result) => (JsonReply::ok(result), false),
+                Err(e) => (JsonReply::error(e.to_string()), false),
+            },
+            Err(e) => (JsonReply::error(format!("Invalid command: {}", e)), false),
+        };
+
+        let mut out = serde_json::to_string(&reply)?;
+        out.push('\n');
+        writer.write_all(out.as_bytes()).await?;
+        writer.flush().await?;
+
+        if quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn piped_commands_get_json_replies() {
+        let input = concat!(
+            r#"{"cmd":"ring","user":"alice","chime_id":"abc"}"#,
+            "\n",
+            r#"{"cmd":"mode","mode":"Bogus"}"#,
+            "\n",
+            "not json\n",
+            "\n",
+            r#"{"cmd":"status"}"#,
+            "\n",
+            r#"{"cmd":"quit"}"#,
+            "\n",
+            r#"{"cmd":"status"}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        let mut seen = Vec::new();
+
+        run(input.as_bytes(), &mut output, |command| {
+            seen.push(command.clone());
+            async move {
+                match command {
+                    JsonCommand::Mode { mode } => Err(format!("Invalid mode: {}", mode).into()),
+                    JsonCommand::Status => Ok(json!({ "mode": "Available" })),
+                    _ => Ok(Value::Null),
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        let replies: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(
+            replies,
+            vec![
+                json!({ "ok": true }),
+                json!({ "ok": false, "error": "Invalid mode: Bogus" }),
+                replies[2].clone(),
+                json!({ "ok": true, "result": { "mode": "Available" } }),
+                json!({ "ok": true }),
+            ]
+        );
+        assert_eq!(replies[2]["ok"], json!(false));
+        assert_eq!(
+            seen[0],
+            JsonCommand::Ring {
+                user: "alice".into(),
+                chime_id: "abc".into(),
+                notes: None,
+                chords: None,
+            }
+        );
+        assert_eq!(seen.len(), 3);
+    }
+}
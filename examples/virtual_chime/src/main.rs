@@ -1,11 +1,14 @@
+mod json_mode;
+
+use chimenet::audio::{ChimePlayer, CooldownPolicy};
 use chimenet::*;
 use clap::Parser;
+use json_mode::JsonCommand;
 use log::{error, info};
-use std::collections::HashMap;
+use serde_json::{json, Value};
 use std::io::{self, Write};
-use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
-use tokio::sync::RwLock;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -14,6 +17,9 @@ struct Args {
     #[arg(short, long, default_value = "tcp://localhost:1883")]
     broker: String,
 
+    #[command(flatten)]
+    broker_args: BrokerArgs,
+
     /// User name
     #[arg(short, long, default_value = "default_user")]
     user: String,
@@ -33,31 +39,128 @@ struct Args {
     /// Available chords (comma-separated)
     #[arg(long, default_value = "C,Am,F,G,Dm,Em")]
     chords: String,
-}
 
-#[derive(Debug, Clone)]
-struct DiscoveredChime {
-    user: String,
-    chime_id: String,
-    name: String,
-    description: Option<String>,
-    notes: Vec<String>,
-    chords: Vec<String>,
-    online: bool,
-    mode: LcgpMode,
-    last_seen: chrono::DateTime<chrono::Utc>,
+    /// Where accepted rings go: audio, notification or both
+    #[arg(long, default_value = "audio")]
+    ring_output: RingOutput,
+
+    /// Ring the terminal bell instead of the audio device (the default when
+    /// there is no audio device)
+    #[arg(long)]
+    terminal_bell: bool,
+
+    /// Exit with an audio error (code 74) instead of falling back to the
+    /// terminal bell when there is no audio device
+    #[arg(long, conflicts_with = "terminal_bell")]
+    require_audio: bool,
+
+    /// Master volume from 0.0 to 1.0, to balance several chimes on one machine
+    #[arg(long, default_value_t = 1.0)]
+    volume: f32,
+
+    /// Minimum milliseconds between the starts of two rings
+    #[arg(long, default_value_t = 0)]
+    ring_cooldown_ms: u64,
+
+    /// What to do with a ring arriving during the cooldown: queue or drop
+    #[arg(long, default_value = "queue")]
+    on_cooldown: CooldownPolicy,
+
+    /// What Grinding mode auto-responds with: positive, negative or snooze:<minutes>
+    #[arg(long, default_value = "positive")]
+    grinding_response: ChimeResponse,
+
+    /// What ChillGrinding mode auto-responds with after its delay: positive,
+    /// negative or snooze:<minutes>
+    #[arg(long, default_value = "positive")]
+    chill_grinding_response: ChimeResponse,
+
+    /// Most delayed auto-responses waiting at once; past it the oldest is sent early
+    #[arg(long, default_value_t = DEFAULT_MAX_DELAYED_RESPONSES)]
+    max_delayed_responses: usize,
+
+    /// Encoding of published payloads: json or msgpack (receiving accepts both)
+    #[arg(long, default_value = "json")]
+    payload_format: PayloadFormat,
+
+    /// Keep this chime id instead of generating a new one each run
+    #[arg(long)]
+    chime_id: Option<String>,
+
+    /// Ask the broker to queue rings sent while this chime is offline and
+    /// deliver them on reconnect (expired rings are still dropped). Needs a
+    /// fixed --chime-id, since the session belongs to the client id.
+    #[arg(long, requires = "chime_id")]
+    persistent_session: bool,
+
+    /// Longest random delay in milliseconds before answering a ringer's
+    /// discovery request with this chime's info (0 ignores requests)
+    #[arg(long, default_value_t = DEFAULT_ANNOUNCE_WINDOW.as_millis() as u64)]
+    announce_window_ms: u64,
+
+    /// JSON file of custom LCGP states to load (an array, as custom_states
+    /// --states-file writes); select one with `mode Custom:<name>`
+    #[arg(long)]
+    states_file: Option<std::path::PathBuf>,
+
+    /// Ignore mode change requests from other clients (e.g. `mode` in the ringer client)
+    #[arg(long)]
+    no_remote_mode: bool,
+
+    /// Refuse (with a Negative response) rings whose notes and chords are all ones this
+    /// chime doesn't advertise, instead of playing the default sound
+    #[arg(long)]
+    strict_capabilities: bool,
+
+    /// Read JSON commands from stdin and write JSON replies to stdout
+    #[arg(long)]
+    json: bool,
+
+    /// Seconds between discovery cleanup passes
+    #[arg(long, default_value_t = 30)]
+    cleanup_interval: u64,
+
+    /// Seconds between status heartbeats, which tell discovery clients this chime is still there
+    #[arg(long, default_value_t = 60)]
+    heartbeat_interval: u64,
+
+    /// Heartbeats a chime may miss before it is dropped from discovery
+    #[arg(long, default_value_t = 5)]
+    staleness_factor: u32,
+
+    /// Seconds a chime may go unseen before it is dropped from discovery;
+    /// overrides --heartbeat-interval times --staleness-factor
+    #[arg(long)]
+    stale_after: Option<u64>,
+
+    /// Demo mode: no broker (LAN transport), sample chimes in discovery and
+    /// simulated incoming rings
+    #[arg(long)]
+    demo: bool,
+
+    /// Seconds between simulated rings in demo mode
+    #[arg(long, default_value_t = 20)]
+    demo_ring_interval: u64,
 }
 
-type DiscoveredChimes = Arc<RwLock<HashMap<String, DiscoveredChime>>>;
-
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
 
-    let args = Args::parse();
+    let started = std::time::Instant::now();
+    let result = run(Args::parse()).await;
+    std::process::exit(ShutdownSummary::new("virtual_chime", &result, started.elapsed()).report());
+}
 
+async fn run(args: Args) -> Result<()> {
     info!("Starting virtual chime: {}", args.name);
-    info!("Connecting to MQTT broker: {}", args.broker);
+    // Demo mode must work without any infrastructure, so it never uses the broker
+    let broker = if args.demo {
+        LAN_URL_SCHEME.to_string()
+    } else {
+        args.broker.clone()
+    };
+    info!("Connecting to MQTT broker: {}", broker);
 
     let notes: Vec<String> = args
         .notes
@@ -70,34 +173,153 @@ async fn main() -> Result<()> {
         .map(|s| s.trim().to_string())
         .collect();
 
-    let chime = ChimeInstance::new(
-        args.name.clone(),
-        args.description,
-        notes,
-        chords,
-        args.user.clone(),
-        &args.broker,
-    )
-    .await?;
+    let mut chime = match args.chime_id {
+        Some(chime_id) => {
+            ChimeInstance::with_chime_id(
+                args.name.clone(),
+                args.description,
+                notes,
+                chords,
+                args.user.clone().into(),
+                chime_id.into(),
+                &broker,
+            )
+            .await?
+        }
+        None => {
+            ChimeInstance::new(
+                args.name.clone(),
+                args.description,
+                notes,
+                chords,
+                args.user.clone().into(),
+                &broker,
+            )
+            .await?
+        }
+    };
+    if args.persistent_session {
+        chime.mqtt.lock().await.set_persistent_session(true)?;
+    }
+    let credentials = args.broker_args.credentials();
+    chime.mqtt.lock().await.set_credentials(credentials)?;
+    chime.mqtt.lock().await.set_tls(args.broker_args.tls())?;
+    if args.terminal_bell {
+        chime.player = ChimePlayer::terminal_bell();
+    }
+    if args.require_audio && chime.player.is_terminal_bell() {
+        // Try again for the error the fallback swallowed
+        chime.player = ChimePlayer::new()?;
+    }
+    chime.player.set_volume(args.volume)?;
+    chime
+        .mqtt
+        .lock()
+        .await
+        .set_payload_format(args.payload_format);
+    chime.set_ring_output(args.ring_output);
+    chime.set_allow_remote_mode(!args.no_remote_mode);
+    chime.set_strict_capabilities(args.strict_capabilities);
+    chime.set_announce_window(
+        (args.announce_window_ms > 0).then(|| Duration::from_millis(args.announce_window_ms)),
+    );
+    chime
+        .lcgp_handler
+        .set_max_delayed_responses(args.max_delayed_responses);
+    chime.lcgp_node.set_grinding_responses(GrindingResponses {
+        grinding: args.grinding_response,
+        chill_grinding: args.chill_grinding_response,
+    });
+    chime.set_ring_cooldown(
+        Duration::from_millis(args.ring_cooldown_ms),
+        args.on_cooldown,
+    );
+    if let Some(path) = &args.states_file {
+        let report = chime.lcgp_handler.load_states_from_file(path)?;
+        for (name, errors) in &report.invalid {
+            error!("Skipped invalid state '{}': {}", name, errors.join("; "));
+        }
+        info!(
+            "Loaded custom states from {}: {:?}",
+            path.display(),
+            report.imported
+        );
+    }
 
     // Create discovered chimes storage
-    let discovered_chimes: DiscoveredChimes = Arc::new(RwLock::new(HashMap::new()));
+    let heartbeat = HeartbeatConfig {
+        interval: Duration::from_secs(args.heartbeat_interval),
+        staleness_factor: args.staleness_factor,
+    };
+    let discovery_config = DiscoveryConfig {
+        cleanup_interval: Duration::from_secs(args.cleanup_interval),
+        stale_after: args
+            .stale_after
+            .map_or(heartbeat.stale_after(), Duration::from_secs),
+    };
+    discovery_config.validate()?;
+    let discovery = ChimeDiscovery::new(discovery_config);
+    chime.set_heartbeat(heartbeat)?;
+    discovery.on_chime_removed(|chime| {
+        info!(
+            "Chime went away: {} ({}/{})",
+            chime.name, chime.user, chime.chime_id
+        );
+    });
+    let discovered_chimes = discovery.chimes();
+    let discovery_for_input = discovery.clone();
 
     chime.start().await?;
 
     // Start discovery monitoring
-    let discovery_chimes = discovered_chimes.clone();
-    let discovery_user = args.user.clone();
+    if args.demo {
+        info!("Demo mode: injecting sample chimes and simulating rings");
+        tokio::spawn(run_demo(
+            chime.clone(),
+            discovery.clone(),
+            Duration::from_secs(args.demo_ring_interval.max(1)),
+        ));
+    }
+
+    let discovery_mqtt = chime.mqtt.clone();
+    let discovery_broker = broker.clone();
     tokio::spawn(async move {
-        if let Err(e) = start_discovery_monitoring(discovery_chimes, discovery_user).await {
+        if let Err(e) =
+            start_discovery_monitoring(discovery, discovery_mqtt, &discovery_broker).await
+        {
             error!("Discovery monitoring error: {}", e);
         }
     });
 
+    if args.json {
+        let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+        let session = json_mode::run(stdin, tokio::io::stdout(), |command| {
+            execute_json_command(&chime, &discovered_chimes, command)
+        });
+
+        tokio::select! {
+            result = session => result?,
+            _ = signal::ctrl_c() => {}
+        }
+
+        info!("Shutting down virtual chime...");
+        chime.shutdown().await?;
+        return Ok(());
+    }
+
     info!("Virtual chime started! Available commands:");
-    info!("  mode <mode>  - Set LCGP mode (DoNotDisturb, Available, ChillGrinding, Grinding)");
+    info!("  mode <mode> [minutes] - Set LCGP mode (DoNotDisturb, Available, ChillGrinding, Grinding), optionally for a while");
     info!("  ring <user> <chime_id> [notes] [chords] - Ring another chime");
-    info!("  respond <pos|neg> [chime_id] - Respond to a chime");
+    info!("  respond <pos|neg|snooze [minutes]> [chime_id] - Respond to a chime");
+    info!("  pending - List rings waiting for a response (answer with respond <number> <pos|neg>)");
+    info!("  schedule <HH:MM|+minutes> <user> <chime_id> [notes] [chords] - Ring later");
+    info!("  scheduled / cancel <id> - List or cancel scheduled rings (or a pending ring by chime id)");
+    info!("  away <message|off> - Set or clear the away auto-reply");
+    info!("  presence <message|off> - Set or clear the status message others see");
+    info!("  follow <user> <chime_id> / unfollow - Mirror another chime's mode");
+    info!("  output <audio|notification|both> - Choose how rings are delivered");
+    info!("  cooldown <ms> [queue|drop] - Space out rings that arrive in bursts");
+    info!("  reset - Clear pending rings and delayed work without reconnecting");
     info!("  status - Show current status");
     info!("  debug - Show debug information");
     info!("  discover - Discover and list available chimes");
@@ -106,7 +328,6 @@ async fn main() -> Result<()> {
 
     // Handle user input
     let chime_for_input = chime.clone();
-    let discovered_for_input = discovered_chimes.clone();
     tokio::spawn(async move {
         let stdin = io::stdin();
         let mut buffer = String::new();
@@ -126,7 +347,7 @@ async fn main() -> Result<()> {
             }
 
             if let Err(e) =
-                handle_command(&chime_for_input, command, &args.user, &discovered_for_input).await
+                handle_command(&chime_for_input, command, &args.user, &discovery_for_input).await
             {
                 error!("Command error: {}", e);
             }
@@ -150,9 +371,10 @@ async fn handle_command(
     chime: &ChimeInstance,
     command: &str,
     user: &str,
-    discovered_chimes: &DiscoveredChimes,
+    discovery: &ChimeDiscovery,
 ) -> Result<()> {
     let parts: Vec<&str> = command.split_whitespace().collect();
+    let discovered_chimes = discovery.chimes();
 
     if parts.is_empty() {
         return Ok(());
@@ -160,26 +382,35 @@ async fn handle_command(
 
     match parts[0] {
         "mode" => {
-            if parts.len() != 2 {
-                println!("Usage: mode <DoNotDisturb|Available|ChillGrinding|Grinding>");
+            if !(2..=3).contains(&parts.len()) {
+                println!(
+                    "Usage: mode <DoNotDisturb|Available|ChillGrinding|Grinding|Custom:name> [minutes]"
+                );
                 return Ok(());
             }
 
-            let mode = match parts[1] {
-                "DoNotDisturb" => LcgpMode::DoNotDisturb,
-                "Available" => LcgpMode::Available,
-                "ChillGrinding" => LcgpMode::ChillGrinding,
-                "Grinding" => LcgpMode::Grinding,
-                _ => {
-                    println!(
-                        "Invalid mode. Use: DoNotDisturb, Available, ChillGrinding, or Grinding"
-                    );
-                    return Ok(());
-                }
+            let Some(mode) = parse_mode(parts[1]) else {
+                println!("Invalid mode. Use: DoNotDisturb, Available, ChillGrinding, or Grinding");
+                return Ok(());
             };
 
-            chime.set_mode(mode).await?;
-            println!("Mode set to: {:?}", parts[1]);
+            match parts.get(2).map(|minutes| minutes.parse::<u32>()) {
+                None => {
+                    chime.set_mode(mode).await?;
+                    println!("Mode set to: {:?}", parts[1]);
+                }
+                Some(Ok(minutes)) if minutes > 0 => {
+                    let until = chrono::Utc::now() + chrono::Duration::minutes(minutes.into());
+                    chime.set_mode_until(mode, until).await?;
+                    if let Some(expiry) = chime.lcgp_node.mode_expiry() {
+                        println!(
+                            "Mode set to: {:?} for {} minutes, then back to {:?}",
+                            parts[1], minutes, expiry.previous
+                        );
+                    }
+                }
+                Some(_) => println!("Minutes must be a positive whole number"),
+            }
         }
 
         "ring" => {
@@ -213,7 +444,7 @@ async fn handle_command(
             }
 
             match chime
-                .ring_other_chime(user, chime_id, notes, chords, None)
+                .ring_other_chime(&user.into(), &chime_id.into(), notes, chords, None)
                 .await
             {
                 Ok(()) => {
@@ -225,39 +456,390 @@ async fn handle_command(
             }
         }
 
+        "ping" => {
+            if parts.len() < 3 {
+                println!("Usage: ping <user> <chime_id>");
+                return Ok(());
+            }
+
+            // Casual ring at QoS 0: cheaper for the broker, but may be lost
+            chime
+                .ring_other_chime_with_qos(&parts[1].into(), &parts[2].into(), None, None, None, 0)
+                .await?;
+            println!("✓ Ping sent (fire-and-forget)");
+        }
+
+        "ring-auto" => {
+            if parts.len() < 4 {
+                println!("Usage: ring-auto <user> <chime_id> <mode> [minutes]");
+                return Ok(());
+            }
+
+            let Some(mode) = parse_mode(parts[3]) else {
+                println!("Invalid mode: {}", parts[3]);
+                return Ok(());
+            };
+            let minutes = match parts.get(4).map(|m| m.parse::<u64>()) {
+                Some(Ok(minutes)) => minutes,
+                Some(Err(_)) => {
+                    println!("Invalid minutes: {}", parts[4]);
+                    return Ok(());
+                }
+                None => 5,
+            };
+
+            chime
+                .ring_with_auto_mode(
+                    &parts[1].into(),
+                    &parts[2].into(),
+                    None,
+                    None,
+                    mode.clone(),
+                    Duration::from_secs(minutes * 60),
+                )
+                .await?;
+            println!(
+                "✓ Ring sent; in {:?} until they respond or {} minute(s) pass",
+                mode, minutes
+            );
+        }
+
+        "schedule" => {
+            if parts.len() < 4 {
+                println!("Usage: schedule <HH:MM|+minutes> <user> <chime_id> [notes] [chords]");
+                return Ok(());
+            }
+
+            let Some(at) = parse_schedule_time(parts[1]) else {
+                println!("Invalid time '{}', use HH:MM or +minutes", parts[1]);
+                return Ok(());
+            };
+            let notes = parts
+                .get(4)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.split(',').map(|s| s.trim().to_string()).collect());
+            let chords = parts
+                .get(5)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.split(',').map(|s| s.trim().to_string()).collect());
+
+            let id = chime
+                .schedule_ring(&parts[2].into(), &parts[3].into(), at, notes, chords)
+                .await;
+            println!(
+                "✓ Scheduled ring {} for {}",
+                id,
+                at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+            );
+        }
+
+        "scheduled" => {
+            let rings = chime.list_scheduled().await;
+            if rings.is_empty() {
+                println!("No scheduled rings");
+            }
+            for ring in rings {
+                println!(
+                    "  {} -> {}/{} at {}",
+                    ring.id,
+                    ring.user,
+                    ring.chime_id,
+                    ring.at
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+
+        "cancel" => {
+            if parts.len() < 2 {
+                println!("Usage: cancel <scheduled id|pending chime_id>");
+                return Ok(());
+            }
+
+            if chime.cancel_scheduled(parts[1]).await {
+                println!("✓ Cancelled scheduled ring {}", parts[1]);
+            } else if chime.cancel_pending_response(parts[1]) {
+                println!("✓ Dropped pending ring {} without responding", parts[1]);
+            } else {
+                println!("No scheduled or pending ring with id {}", parts[1]);
+            }
+        }
+
+        "broadcast-emergency" => {
+            // Require an explicit confirmation word, this rings everyone
+            if parts.get(1) != Some(&"confirm") {
+                println!("This rings EVERY online chime of EVERY user.");
+                println!("Usage: broadcast-emergency confirm [notes] [chords]");
+                return Ok(());
+            }
+
+            let notes = parts
+                .get(2)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.split(',').map(|s| s.trim().to_string()).collect());
+            let chords = parts
+                .get(3)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.split(',').map(|s| s.trim().to_string()).collect());
+
+            let mqtt = chime.mqtt.lock().await;
+            let results = discovery
+                .broadcast_all(&mqtt, notes, chords, Urgency::Emergency)
+                .await;
+
+            let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+            println!(
+                "Emergency broadcast sent to {} chime(s), {} failed",
+                results.len() - failed,
+                failed
+            );
+            for (target, result) in results {
+                if let Err(e) = result {
+                    println!("  ✗ {}/{}: {}", target.user, target.chime_id, e);
+                }
+            }
+        }
+
+        "pending" => {
+            let pending = chime.pending_rings();
+            if pending.is_empty() {
+                println!("No rings waiting for a response");
+            } else {
+                println!("Rings waiting for a response (oldest first):");
+                for (i, ring) in pending.iter().enumerate() {
+                    println!(
+                        "  {}. {} from {} at {} ({}s ago)",
+                        i + 1,
+                        ring.chime_id,
+                        ring.from_node,
+                        ring.received_at.format("%H:%M:%S"),
+                        (chrono::Utc::now() - ring.received_at).num_seconds()
+                    );
+                }
+                println!("Answer one with: respond <number> <pos|neg|snooze [minutes]>");
+                println!("Drop one (and its auto-response) with: cancel <chime_id>");
+            }
+        }
+
         "respond" => {
             if parts.len() < 2 {
-                println!("Usage: respond <pos|neg> [chime_id]");
+                println!("Usage: respond <pos|neg|snooze [minutes]> [chime_id] [\"message\"] | respond <number> <pos|neg|snooze [minutes]> [\"message\"]");
                 return Ok(());
             }
 
-            let response = match parts[1] {
-                "pos" => ChimeResponse::Positive,
-                "neg" => ChimeResponse::Negative,
-                _ => {
-                    println!("Invalid response. Use: pos or neg");
+            // "respond 2 pos" answers the second entry of `pending`
+            if let Ok(number) = parts[1].parse::<usize>() {
+                let (response, message) = match ChimeResponse::from_args(&parts[2..]) {
+                    Ok((response, rest)) => {
+                        (response, ChimeResponseMessage::message_from_args(rest))
+                    }
+                    _ => {
+                        println!(
+                            "Usage: respond <number> <pos|neg|snooze [minutes]> [\"message\"]"
+                        );
+                        return Ok(());
+                    }
+                };
+
+                if number == 0 || number > chime.pending_rings().len() {
+                    println!("No pending ring #{} (see `pending`)", number);
                     return Ok(());
                 }
+
+                let ring = chime
+                    .respond_to_pending(number - 1, response.clone(), message)
+                    .await?;
+                println!("Sent {:?} to {}", response, ring.from_node);
+                return Ok(());
+            }
+
+            let (response, rest) = match ChimeResponse::from_args(&parts[1..]) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    println!("{}. Use: pos, neg or snooze [minutes]", e);
+                    return Ok(());
+                }
+            };
+
+            // A quoted first word starts the message rather than naming a chime
+            let (chime_id, rest) = match rest.split_first() {
+                Some((id, rest)) if !id.starts_with('"') => (Some(ChimeId::from(*id)), rest),
+                _ => (None, rest),
             };
+            let message = ChimeResponseMessage::message_from_args(rest);
+
+            chime
+                .respond_to_chime_with_message(response.clone(), chime_id, message.clone())
+                .await?;
+            match message {
+                Some(message) => println!("Sent response: {:?} ({})", response, message),
+                None => println!("Sent response: {:?}", response),
+            }
+        }
 
-            let chime_id = if parts.len() > 2 {
-                Some(parts[2].to_string())
+        "away" => {
+            if parts.len() < 2 {
+                println!("Usage: away <message...> | away off");
+                return Ok(());
+            }
+
+            if parts[1] == "off" {
+                chime.lcgp_handler.set_away_message(None);
+                println!("Away message cleared");
             } else {
-                None
+                let message = parts[1..].join(" ");
+                chime.lcgp_handler.set_away_message(Some(message.clone()));
+                println!("Away message set: {}", message);
+            }
+        }
+
+        "presence" => {
+            if parts.len() < 2 {
+                println!("Usage: presence <message...> | presence off");
+                return Ok(());
+            }
+
+            if parts[1] == "off" {
+                chime.set_status_message(None).await?;
+                println!("Status message cleared");
+            } else {
+                let message = parts[1..].join(" ");
+                chime.set_status_message(Some(message.clone())).await?;
+                println!("Status message set: {}", message);
+            }
+        }
+
+        "watch" => {
+            let mqtt = chime.mqtt.lock().await;
+            match parts.get(1) {
+                Some(&"all") if discovery.watched_users().is_none() => {
+                    println!("Already discovering chimes of all users");
+                }
+                Some(&"all") => {
+                    discovery.subscribe(&mqtt).await?;
+                    println!("Discovering chimes of all users");
+                }
+                Some(user) => {
+                    discovery.watch_user(&mqtt, &UserId::from(*user)).await?;
+                    println!("Watching chimes of {}", user);
+                }
+                None => match discovery.watched_users() {
+                    Some(users) if !users.is_empty() => {
+                        let users: Vec<String> = users.into_iter().map(String::from).collect();
+                        println!("Watching: {}", users.join(", "));
+                    }
+                    Some(_) => println!("Not watching anyone ('watch all' to discover everyone)"),
+                    None => println!("Discovering chimes of all users"),
+                },
+            }
+        }
+
+        "unwatch" => {
+            if parts.len() < 2 {
+                println!("Usage: unwatch <user>");
+                return Ok(());
+            }
+
+            let mqtt = chime.mqtt.lock().await;
+            discovery.unwatch_user(&mqtt, &parts[1].into()).await?;
+            println!("Stopped watching {}", parts[1]);
+        }
+
+        "follow" => {
+            if parts.len() < 3 {
+                println!("Usage: follow <user> <chime_id>");
+                return Ok(());
+            }
+
+            match chime.follow(&parts[1].into(), &parts[2].into()).await {
+                Ok(()) => println!("✓ Following mode of {}/{}", parts[1], parts[2]),
+                Err(e) => println!("✗ Failed to follow: {}", e),
+            }
+        }
+
+        "unfollow" => {
+            chime.unfollow().await?;
+            println!("Stopped following");
+        }
+
+        "output" => {
+            if parts.len() < 2 {
+                println!("Usage: output <audio|notification|both>");
+                return Ok(());
+            }
+
+            match parts[1].parse::<RingOutput>() {
+                Ok(output) => {
+                    chime.set_ring_output(output);
+                    println!("Ring output set to: {:?}", output);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        "cooldown" => {
+            if parts.len() < 2 {
+                println!("Usage: cooldown <ms> [queue|drop]");
+                return Ok(());
+            }
+
+            let Ok(ms) = parts[1].parse::<u64>() else {
+                println!("Invalid cooldown: {}", parts[1]);
+                return Ok(());
+            };
+            let policy = match parts.get(2).map(|p| p.parse::<CooldownPolicy>()) {
+                Some(Ok(policy)) => policy,
+                Some(Err(e)) => {
+                    println!("{}", e);
+                    return Ok(());
+                }
+                None => chime.get_ring_cooldown().1,
             };
 
-            chime.respond_to_chime(response, chime_id).await?;
-            println!("Sent response: {:?}", parts[1]);
+            chime.set_ring_cooldown(Duration::from_millis(ms), policy);
+            println!("Ring cooldown set to {}ms ({:?})", ms, policy);
+        }
+
+        "reset" => {
+            let summary = chime.reset().await?;
+            println!(
+                "Reset: cleared {} pending ring(s), {} delayed response(s), {} scheduled ring(s)",
+                summary.pending_rings, summary.delayed_responses, summary.scheduled_rings
+            );
         }
 
         "status" => {
             println!("Chime: {}", chime.info.name);
             println!("ID: {}", chime.info.id);
             println!("Mode: {:?}", chime.lcgp_node.get_mode());
+            println!("Ring output: {:?}", chime.get_ring_output());
+            if chime.player.is_terminal_bell() {
+                println!("Audio: terminal bell");
+            }
+            let (cooldown, policy) = chime.get_ring_cooldown();
+            println!("Ring cooldown: {:?} ({:?})", cooldown, policy);
+            println!(
+                "Activity: {:.1} rings/hour",
+                chime.lcgp_node.ring_rate_per_hour()
+            );
+            if let Some((user, chime_id)) = chime.following() {
+                println!("Following: {}/{}", user, chime_id);
+            }
+            if let Some(message) = chime.lcgp_node.get_away_message() {
+                println!("Away message: {}", message);
+            }
+            if let Some(message) = chime.status_message() {
+                println!("Status message: {}", message);
+            }
             println!("Notes: {:?}", chime.info.notes);
             println!("Chords: {:?}", chime.info.chords);
         }
 
+        "snapshot" => {
+            println!("{}", serde_json::to_string_pretty(&chime.snapshot().await)?);
+        }
+
         "debug" => {
             println!("=== Debug Information ===");
             println!("Chime ID: {}", chime.info.id);
@@ -266,6 +848,10 @@ async fn handle_command(
             println!("LCGP Mode: {:?}", chime.lcgp_node.get_mode());
             println!("Node ID: {}", chime.lcgp_node.node_id);
             println!("Subscribe Topic: /{}/chime/{}/ring", user, chime.info.id);
+            println!("Active Subscriptions:");
+            for topic in chime.mqtt.lock().await.active_subscriptions().await {
+                println!("  {}", topic);
+            }
             println!("Available Notes: {:?}", chime.info.notes);
             println!("Available Chords: {:?}", chime.info.chords);
             println!("Created: {}", chime.info.created_at);
@@ -279,6 +865,18 @@ async fn handle_command(
         "discover" => {
             println!("=== Discovering Chimes ===");
 
+            // Ask every chime to re-announce. The connection lock is released
+            // while waiting so rings keep being handled meanwhile.
+            let requested_at = {
+                let mqtt = chime.mqtt.lock().await;
+                discovery
+                    .send_announce_request(&mqtt, chime.node_id().as_str())
+                    .await?
+            };
+            tokio::time::sleep(DEFAULT_ANNOUNCE_WINDOW * 2).await;
+            let answered = discovery.heard_since(requested_at).await;
+            println!("{} chime(s) answered the discovery request", answered.len());
+
             let chimes = discovered_chimes.read().await;
 
             if chimes.is_empty() {
@@ -296,7 +894,7 @@ async fn handle_command(
                 for chime in chimes.values() {
                     users_chimes
                         .entry(chime.user.clone())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(chime);
                 }
 
@@ -326,6 +924,9 @@ async fn handle_command(
                             println!("    Description: {}", desc);
                         }
                         println!("    Mode: {:?}", chime.mode);
+                        if let Some(ref message) = chime.status_message {
+                            println!("    Status: {}", message);
+                        }
                         println!("    Notes: {:?}", chime.notes);
                         println!("    Chords: {:?}", chime.chords);
                         println!(
@@ -362,26 +963,75 @@ async fn handle_command(
 fn show_help() {
     println!("📚 ChimeNet Virtual Chime - Available Commands:");
     println!();
-    println!("  mode <mode>                           - Set LCGP mode");
+    println!("  mode <mode> [minutes]                 - Set LCGP mode, optionally for a while");
     println!("    Available modes: DoNotDisturb, Available, ChillGrinding, Grinding");
     println!();
     println!("  ring <user> <chime_id> [notes] [chords] - Ring another chime");
     println!("    Example: ring alice 12345678-1234-1234-1234-123456789012");
     println!("    Example: ring bob 87654321-4321-4321-4321-210987654321 C4,E4,G4 C,Am");
+    println!("  ping <user> <chime_id>                - Casual fire-and-forget ring (QoS 0, may be lost)");
+    println!(
+        "  ring-auto <user> <chime_id> <mode> [minutes] - Ring and switch mode until they respond"
+    );
+    println!("    Example: ring-auto alice 12345678-1234-1234-1234-123456789012 Grinding 10");
     println!();
     println!("  respond <pos|neg> [chime_id]          - Respond to incoming chimes");
+    println!("  pending                               - List rings waiting for a response");
+    println!("  respond <number> <pos|neg>            - Respond to one ring from `pending`");
     println!("    pos = positive response, neg = negative response");
+    println!("    snooze [minutes] = not now, the ringer rings again after that (default 10)");
     println!("    Example: respond pos");
+    println!("    Example: respond snooze 15");
     println!("    Example: respond neg 12345678-1234-1234-1234-123456789012");
     println!();
+    println!(
+        "  schedule <HH:MM|+minutes> <user> <chime_id> [notes] [chords] - Ring at a later time"
+    );
+    println!("    Example: schedule 15:00 alice 12345678-1234-1234-1234-123456789012");
+    println!("    Example: schedule +30 bob 87654321-4321-4321-4321-210987654321 C4,E4,G4");
+    println!("  scheduled                             - List pending scheduled rings");
+    println!(
+        "  cancel <id>                           - Cancel a scheduled ring, or drop a pending one"
+    );
+    println!();
+    println!("  away <message|off>                    - Set or clear the away auto-reply");
+    println!(
+        "    While not chiming (e.g. DoNotDisturb), rings get a Negative reply with this message"
+    );
+    println!("    Example: away Back at 3pm");
+    println!();
+    println!(
+        "  presence <message|off>                - Set or clear the status message others see"
+    );
+    println!("    Shown next to your mode when others discover this chime");
+    println!("    Example: presence Working from the cafe");
+    println!();
+    println!(
+        "  broadcast-emergency confirm [notes] [chords] - Ring every online chime of every user"
+    );
+    println!();
+    println!("  follow <user> <chime_id>              - Mirror another chime's mode");
+    println!("  unfollow                              - Stop mirroring");
+    println!();
+    println!("  output <audio|notification|both>      - Choose how accepted rings are delivered");
+    println!("  cooldown <ms> [queue|drop]            - Space out rings that arrive in bursts");
+    println!("    Example: output notification   (e.g. while on headphones in a call)");
+    println!();
     println!(
         "  discover                              - Show all discovered chimes with full details"
     );
     println!("    Shows users, chime IDs, status, modes, and ready-to-use ring commands");
+    println!("  watch [<user>|all]                    - Only discover the watched users (no argument lists them)");
+    println!("  unwatch <user>                        - Stop discovering a watched user");
     println!();
     println!("  status                                - Show current chime status");
     println!("    Shows your chime name, ID, mode, and capabilities");
     println!();
+    println!("  reset                                 - Clear stuck pending rings, delayed and scheduled");
+    println!("    work, and stop audio; stays connected");
+    println!();
+    println!("  snapshot                              - Print the chime's full state as JSON");
+    println!();
     println!("  debug                                 - Show debug information");
     println!("    Shows technical details like node ID, topics, and timestamps");
     println!();
@@ -398,8 +1048,8 @@ fn show_help() {
     println!("🎭 LCGP Modes:");
     println!("  DoNotDisturb  🔕 - Ignore all incoming rings");
     println!("  Available     🔔 - Ring and wait for manual response");
-    println!("  ChillGrinding 🟡 - Ring and auto-respond positive after 10 seconds");
-    println!("  Grinding      🟢 - Ring and immediately respond positive");
+    println!("  ChillGrinding 🟡 - Ring and auto-respond after 10 seconds (positive unless --chill-grinding-response negative)");
+    println!("  Grinding      🟢 - Ring and immediately respond (positive unless --grinding-response negative)");
     println!();
     println!("💡 Pro Tips:");
     println!("  - Use 'discover' to see what chimes are available");
@@ -409,163 +1059,199 @@ fn show_help() {
 }
 
 async fn start_discovery_monitoring(
-    discovered_chimes: DiscoveredChimes,
-    current_user: String,
+    discovery: ChimeDiscovery,
+    mqtt: std::sync::Arc<tokio::sync::Mutex<ChimeNetMqtt>>,
+    broker: &str,
 ) -> Result<()> {
-    use serde_json;
-
-    // Create a temporary MQTT client for discovery monitoring
-    let client_id = format!("discovery_monitor_{}", uuid::Uuid::new_v4());
-    let mut mqtt = ChimeNetMqtt::new("tcp://localhost:1883", &current_user, &client_id).await?;
-    mqtt.connect().await?;
-
-    info!("Starting discovery monitoring for user: {}", current_user);
-
-    // Subscribe to all chime lists, notes, chords, and status messages
-    let topics = vec![
-        "/+/chime/list",
-        "/+/chime/+/notes",
-        "/+/chime/+/chords",
-        "/+/chime/+/status",
-    ];
-
-    for topic in topics {
-        let discovered_clone = discovered_chimes.clone();
-        let current_user_clone = current_user.clone();
-
-        mqtt.subscribe(topic, 1, move |topic, payload| {
-            let discovered = discovered_clone.clone();
-            let user = current_user_clone.clone();
-            let topic = topic.clone();
-            let payload = payload.clone();
-
-            tokio::spawn(async move {
-                if let Err(e) = handle_discovery_message(topic, payload, discovered, user).await {
-                    error!("Error handling discovery message: {}", e);
-                }
-            });
-        })
-        .await?;
-    }
+    info!("Starting discovery monitoring on broker: {}", broker);
 
-    info!("Discovery monitoring started, listening for chime information...");
-
-    // Keep the discovery alive
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+    // Discovery shares the chime's connection so `watch`/`unwatch` can rescope it.
+    // Our own chimes are filtered out by the discovery subscription.
+    discovery.subscribe(&*mqtt.lock().await).await?;
 
-        // Clean up old chimes (remove chimes not seen for 5 minutes)
-        let mut chimes = discovered_chimes.write().await;
-        let now = chrono::Utc::now();
-        let cutoff = now - chrono::Duration::minutes(5);
+    info!("Discovery monitoring started, listening for chime information...");
 
-        let old_count = chimes.len();
-        chimes.retain(|_, chime| chime.last_seen > cutoff);
-        let new_count = chimes.len();
+    // Keep pruning stale chimes for as long as the chime runs
+    discovery.run_cleanup().await
+}
 
-        if old_count != new_count {
-            info!(
-                "Cleaned up {} old chimes, {} chimes remaining",
-                old_count - new_count,
-                new_count
-            );
+// Keeps the sample chimes fresh in discovery and rings this chime from each of
+// them in turn. Rings go through the transport, so the real handling path runs.
+async fn run_demo(chime: ChimeInstance, discovery: ChimeDiscovery, every: Duration) {
+    let samples = DiscoveredChime::sample_set();
+    discovery.inject(samples.clone()).await;
+
+    let mut interval = tokio::time::interval(every);
+    // The first tick completes immediately
+    interval.tick().await;
+
+    for sender in samples.iter().cycle() {
+        interval.tick().await;
+        discovery.inject(samples.clone()).await;
+
+        let ring_request = ChimeRingRequest {
+            chime_id: chime.info.id.clone(),
+            user: sender.user.clone(),
+            notes: Some(sender.notes.clone()),
+            chords: Some(sender.chords.clone()),
+            duration_ms: None,
+            note_durations: None,
+            timestamp: chrono::Utc::now(),
+            preset: None,
+            urgency: Urgency::Normal,
+            expires_at: None,
+            request_id: None,
+        };
+
+        info!(
+            "Demo: simulating a ring from {} ({})",
+            sender.name, sender.user
+        );
+        if let Err(e) = chime
+            .mqtt
+            .lock()
+            .await
+            .publish_chime_ring(&chime.chime_id(), &ring_request)
+            .await
+        {
+            error!("Demo: failed to simulate ring: {}", e);
         }
     }
 }
 
-async fn handle_discovery_message(
-    topic: String,
-    payload: String,
-    discovered_chimes: DiscoveredChimes,
-    current_user: String,
-) -> Result<()> {
-    let parts: Vec<&str> = topic.split('/').collect();
-    if parts.len() < 3 {
-        return Ok(());
+// Parses "HH:MM" (next occurrence, local time) or "+minutes" from now
+fn parse_schedule_time(input: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    if let Some(minutes) = input.strip_prefix('+') {
+        // Digits only: a second sign ("+-5", "++5") isn't a time from now
+        if !minutes.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let minutes: i64 = minutes.parse().ok()?;
+        return Some(chrono::Utc::now() + chrono::Duration::minutes(minutes));
     }
 
-    let user = parts[1];
-
-    // Skip our own messages
-    if user == current_user {
-        return Ok(());
+    let time = chrono::NaiveTime::parse_from_str(input, "%H:%M").ok()?;
+    let now = chrono::Local::now();
+    let mut at = now.date_naive().and_time(time);
+    if at <= now.naive_local() {
+        at += chrono::Duration::days(1);
     }
 
-    match parts.get(2) {
-        Some(&"chime") => {
-            match parts.get(3) {
-                Some(&"list") => {
-                    // Handle chime list
-                    if let Ok(chime_list) = serde_json::from_str::<ChimeList>(&payload) {
-                        let mut chimes = discovered_chimes.write().await;
-                        let chime_count = chime_list.chimes.len();
-
-                        for chime_info in &chime_list.chimes {
-                            let key = format!("{}/{}", user, chime_info.id);
-                            let discovered_chime = DiscoveredChime {
-                                user: user.to_string(),
-                                chime_id: chime_info.id.clone(),
-                                name: chime_info.name.clone(),
-                                description: chime_info.description.clone(),
-                                notes: chime_info.notes.clone(),
-                                chords: chime_info.chords.clone(),
-                                online: true,
-                                mode: LcgpMode::Available, // Default, will be updated by status
-                                last_seen: chrono::Utc::now(),
-                            };
-
-                            chimes.insert(key, discovered_chime);
-                        }
+    at.and_local_timezone(chrono::Local)
+        .earliest()
+        .map(|at| at.with_timezone(&chrono::Utc))
+}
 
-                        info!(
-                            "Updated chime list for user: {} ({} chimes)",
-                            user, chime_count
-                        );
-                    }
-                }
-                Some(chime_id) => {
-                    let key = format!("{}/{}", user, chime_id);
-
-                    match parts.get(4) {
-                        Some(&"notes") => {
-                            // Handle notes update
-                            if let Ok(notes) = serde_json::from_str::<Vec<String>>(&payload) {
-                                let mut chimes = discovered_chimes.write().await;
-                                if let Some(chime) = chimes.get_mut(&key) {
-                                    chime.notes = notes;
-                                    chime.last_seen = chrono::Utc::now();
-                                }
-                            }
-                        }
-                        Some(&"chords") => {
-                            // Handle chords update
-                            if let Ok(chords) = serde_json::from_str::<Vec<String>>(&payload) {
-                                let mut chimes = discovered_chimes.write().await;
-                                if let Some(chime) = chimes.get_mut(&key) {
-                                    chime.chords = chords;
-                                    chime.last_seen = chrono::Utc::now();
-                                }
-                            }
-                        }
-                        Some(&"status") => {
-                            // Handle status update
-                            if let Ok(status) = serde_json::from_str::<ChimeStatus>(&payload) {
-                                let mut chimes = discovered_chimes.write().await;
-                                if let Some(chime) = chimes.get_mut(&key) {
-                                    chime.online = status.online;
-                                    chime.mode = status.mode;
-                                    chime.last_seen = chrono::Utc::now();
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            }
+fn parse_mode(mode: &str) -> Option<LcgpMode> {
+    match mode {
+        "DoNotDisturb" => Some(LcgpMode::DoNotDisturb),
+        "Available" => Some(LcgpMode::Available),
+        "ChillGrinding" => Some(LcgpMode::ChillGrinding),
+        "Grinding" => Some(LcgpMode::Grinding),
+        // States from --states-file
+        custom if custom.starts_with("Custom:") => custom.parse().ok(),
+        _ => None,
+    }
+}
+
+// Runs a command from --json mode and returns its structured result
+async fn execute_json_command(
+    chime: &ChimeInstance,
+    discovered_chimes: &DiscoveredChimes,
+    command: JsonCommand,
+) -> Result<Value> {
+    match command {
+        JsonCommand::Ring {
+            user,
+            chime_id,
+            notes,
+            chords,
+        } => {
+            chime
+                .ring_other_chime(&user, &chime_id, notes, chords, None)
+                .await?;
+            Ok(Value::Null)
         }
-        _ => {}
+        JsonCommand::Respond {
+            response,
+            chime_id,
+            message,
+        } => {
+            let response = response
+                .parse::<ChimeResponse>()
+                .map_err(|e| format!("Invalid response: {}", e))?;
+            chime
+                .respond_to_chime_with_message(response, chime_id, message)
+                .await?;
+            Ok(Value::Null)
+        }
+        JsonCommand::Mode { mode } => {
+            let mode = parse_mode(&mode).ok_or_else(|| format!("Invalid mode: {}", mode))?;
+            chime.set_mode(mode).await?;
+            Ok(Value::Null)
+        }
+        JsonCommand::Away { message } => {
+            chime.lcgp_handler.set_away_message(message);
+            Ok(Value::Null)
+        }
+        JsonCommand::Presence { message } => {
+            chime.set_status_message(message).await?;
+            Ok(Value::Null)
+        }
+        JsonCommand::Status => Ok(json!({
+            "chime_id": chime.info.id,
+            "name": chime.info.name,
+            "mode": chime.lcgp_node.get_mode(),
+            "away_message": chime.lcgp_node.get_away_message(),
+            "status_message": chime.status_message(),
+            "ring_output": chime.get_ring_output(),
+            "notes": chime.info.notes,
+            "chords": chime.info.chords,
+        })),
+        JsonCommand::Discover => {
+            let chimes = discovered_chimes.read().await;
+            let chimes: Vec<Value> = chimes
+                .values()
+                .map(|chime| {
+                    json!({
+                        "user": chime.user,
+                        "chime_id": chime.chime_id,
+                        "name": chime.name,
+                        "description": chime.description,
+                        "online": chime.online,
+                        "mode": chime.mode,
+                        "status_message": chime.status_message,
+                        "notes": chime.notes,
+                        "chords": chime.chords,
+                        "last_seen": chime.last_seen,
+                    })
+                })
+                .collect();
+            Ok(Value::Array(chimes))
+        }
+        JsonCommand::Snapshot => Ok(serde_json::to_value(chime.snapshot().await)?),
+        JsonCommand::Reset => Ok(serde_json::to_value(chime.reset().await?)?),
+        // Handled by json_mode::run before dispatch
+        JsonCommand::Quit => Ok(Value::Null),
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_times_are_clock_times_or_minutes_ahead() {
+        let now = chrono::Utc::now();
+        let in_five = parse_schedule_time("+5").unwrap();
+        assert!(in_five > now + chrono::Duration::minutes(4));
+        assert!(in_five <= chrono::Utc::now() + chrono::Duration::minutes(5));
+
+        let at = parse_schedule_time("12:30").unwrap();
+        assert!(at > now && at <= now + chrono::Duration::days(1));
+
+        for bad in ["+-5", "++5", "+", "+5m", "25:00", "soon"] {
+            assert!(parse_schedule_time(bad).is_none(), "{}", bad);
+        }
+    }
 }
@@ -1,11 +1,29 @@
 use crate::types::notes::{chord_notes, frequency_for_note};
-use crate::types::Result;
+use crate::types::{ChimeNetError, ChimeRingRequest, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Device, Host, SampleFormat, Stream, StreamConfig};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+// Length of each note/chord when a ring doesn't give one
+pub const DEFAULT_NOTE_MS: u64 = 500;
+
+// Exponential decay of an A4 note per second, like a struck bell. Higher
+// notes die away faster, lower ones ring longer.
+const BELL_DECAY_PER_SEC: f32 = 3.0;
+
+// How quickly the chord normalization follows notes starting and ending,
+// per sample. Gradual, so a note ending doesn't make the others jump.
+const NORMALIZATION_SMOOTHING: f32 = 0.005;
+
+// Output up to this level passes unchanged; louder is compressed towards 1.0
+const SOFT_CLIP_KNEE: f32 = 0.5;
 
 unsafe impl Send for AudioPlayer {}
 unsafe impl Sync for AudioPlayer {}
@@ -14,74 +32,307 @@ pub struct AudioPlayer {
     _host: Host,
     _device: Device,
     _stream: Stream,
-    sender: mpsc::Sender<AudioCommand>,
+    engine: AudioEngine,
+    volume_curve: Mutex<VolumeCurve>,
+    waveform: Waveform,
+    envelope: Envelope,
 }
 
 #[derive(Debug, Clone)]
 enum AudioCommand {
-    PlayNote { frequency: f32, duration_ms: u64 },
+    PlayNote {
+        frequency: f32,
+        duration_ms: u64,
+        waveform: Waveform,
+        envelope: Envelope,
+    },
+    SetGain(f32),
     Stop,
 }
 
+// How a user-facing volume (0.0-1.0) maps to output gain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VolumeCurve {
+    Linear,
+    // Squares the volume so equal steps sound roughly equally loud
+    #[default]
+    Perceptual,
+}
+
+impl VolumeCurve {
+    pub fn gain_for(&self, volume: f32) -> f32 {
+        let volume = volume.clamp(0.0, 1.0);
+        match self {
+            VolumeCurve::Linear => volume,
+            VolumeCurve::Perceptual => volume * volume,
+        }
+    }
+}
+
+// Shape of each note's oscillator
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    // Value in -1.0..=1.0 at `phase`, the fraction (0.0-1.0) of the current cycle
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+// ADSR envelope applied to every note. The release happens within the note's
+// duration, so rings still last exactly as long as they ask for. Stages that
+// don't fit in a short note overlap rather than stretch it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub attack_ms: u64,
+    pub decay_ms: u64,
+    pub sustain_level: f32,
+    pub release_ms: u64,
+}
+
+impl Default for Envelope {
+    // Just enough attack and release to avoid clicks
+    fn default() -> Self {
+        Self {
+            attack_ms: 10,
+            decay_ms: 0,
+            sustain_level: 1.0,
+            release_ms: 50,
+        }
+    }
+}
+
+impl Envelope {
+    // Gain (0.0-1.0) `elapsed_ms` into a note lasting `duration_ms`
+    pub fn gain_at(&self, elapsed_ms: f32, duration_ms: f32) -> f32 {
+        let sustain = self.sustain_level.clamp(0.0, 1.0);
+        let attack = self.attack_ms as f32;
+        let decay = self.decay_ms as f32;
+
+        let level = if elapsed_ms < attack {
+            elapsed_ms / attack
+        } else if elapsed_ms < attack + decay {
+            1.0 - (1.0 - sustain) * (elapsed_ms - attack) / decay
+        } else {
+            sustain
+        };
+
+        let remaining = (duration_ms - elapsed_ms).max(0.0);
+        let release = if remaining < self.release_ms as f32 {
+            remaining / self.release_ms as f32
+        } else {
+            1.0
+        };
+
+        level * release
+    }
+}
+
+// Owns the command thread feeding the shared AudioState, restarting it if it dies
+struct AudioEngine {
+    audio_state: Arc<Mutex<AudioState>>,
+    sample_rate: u32,
+    worker: Mutex<CommandWorker>,
+}
+
+struct CommandWorker {
+    sender: mpsc::Sender<AudioCommand>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CommandWorker {
+    fn spawn(audio_state: Arc<Mutex<AudioState>>, sample_rate: u32) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<AudioCommand>();
+
+        let handle = thread::Builder::new()
+            .name("chimenet-audio".to_string())
+            .spawn(move || {
+                while let Ok(command) = receiver.recv() {
+                    let mut state = lock_audio_state(&audio_state);
+                    match command {
+                        AudioCommand::PlayNote {
+                            frequency,
+                            duration_ms,
+                            waveform,
+                            envelope,
+                        } => {
+                            state.add_note(frequency, duration_ms, waveform, envelope, sample_rate)
+                        }
+                        AudioCommand::SetGain(gain) => state.gain = gain,
+                        AudioCommand::Stop => state.stop(),
+                    }
+                }
+            })?;
+
+        Ok(Self { sender, handle })
+    }
+
+    fn is_alive(&self) -> bool {
+        !self.handle.is_finished()
+    }
+}
+
+impl AudioEngine {
+    fn new(audio_state: Arc<Mutex<AudioState>>, sample_rate: u32) -> Result<Self> {
+        let worker = CommandWorker::spawn(Arc::clone(&audio_state), sample_rate)?;
+
+        Ok(Self {
+            audio_state,
+            sample_rate,
+            worker: Mutex::new(worker),
+        })
+    }
+
+    fn send(&self, command: AudioCommand) -> Result<()> {
+        let mut worker = self.worker.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if worker.is_alive() && worker.sender.send(command.clone()).is_ok() {
+            return Ok(());
+        }
+
+        log::warn!("Audio command thread is not running, restarting it");
+        *worker = CommandWorker::spawn(Arc::clone(&self.audio_state), self.sample_rate)?;
+        worker
+            .sender
+            .send(command)
+            .map_err(|e| ChimeNetError::Audio(format!("Audio command thread unavailable: {}", e)))
+    }
+
+    fn is_playing(&self) -> bool {
+        !lock_audio_state(&self.audio_state).notes.is_empty()
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.worker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_alive()
+    }
+}
+
+// Locks the shared audio state, recovering it if a previous holder panicked.
+// The notes are cleared on recovery since they may have been left half-updated.
+fn lock_audio_state(audio_state: &Mutex<AudioState>) -> MutexGuard<'_, AudioState> {
+    match audio_state.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            log::warn!("Audio state lock was poisoned, resetting audio state");
+            let mut guard = poisoned.into_inner();
+            guard.stop();
+            audio_state.clear_poison();
+            guard
+        }
+    }
+}
+
 impl AudioPlayer {
     pub fn new() -> Result<Self> {
+        Self::with_config(Waveform::default(), Envelope::default())
+    }
+
+    // Every note played sounds with `waveform`, shaped by `envelope`
+    pub fn with_config(waveform: Waveform, envelope: Envelope) -> Result<Self> {
+        Self::open(waveform, envelope)
+            .map_err(|e| ChimeNetError::Audio(format!("Audio output unavailable: {}", e)))
+    }
+
+    fn open(waveform: Waveform, envelope: Envelope) -> Result<Self> {
         let host = cpal::default_host();
         let device = host
             .default_output_device()
-            .ok_or_else(|| anyhow::anyhow!("No output device available"))?;
+            .ok_or_else(|| ChimeNetError::Audio("No output device available".to_string()))?;
 
-        let config = device.default_output_config()?;
+        let config = device.default_output_config().map_err(audio_error)?;
         let sample_rate = config.sample_rate().0;
         let _channels = config.channels();
 
-        let (sender, receiver) = mpsc::channel::<AudioCommand>();
-
         // Shared state for the audio generator
         let audio_state = Arc::new(Mutex::new(AudioState::new()));
         let audio_state_clone = Arc::clone(&audio_state);
 
         // Spawn a thread to handle audio commands
-        let audio_state_cmd = Arc::clone(&audio_state);
-        thread::spawn(move || {
-            while let Ok(command) = receiver.recv() {
-                match command {
-                    AudioCommand::PlayNote {
-                        frequency,
-                        duration_ms,
-                    } => {
-                        let mut state = audio_state_cmd.lock().unwrap();
-                        state.add_note(frequency, duration_ms, sample_rate);
-                    }
-                    AudioCommand::Stop => {
-                        let mut state = audio_state_cmd.lock().unwrap();
-                        state.stop();
-                    }
-                }
-            }
-        });
+        let engine = AudioEngine::new(audio_state, sample_rate)?;
 
         let stream = match config.sample_format() {
             SampleFormat::F32 => build_stream::<f32>(&device, &config.into(), audio_state_clone)?,
             SampleFormat::I16 => build_stream::<i16>(&device, &config.into(), audio_state_clone)?,
             SampleFormat::U16 => build_stream::<u16>(&device, &config.into(), audio_state_clone)?,
-            _ => return Err(anyhow::anyhow!("Unsupported sample format").into()),
+            _ => {
+                return Err(ChimeNetError::Audio(
+                    "Unsupported sample format".to_string(),
+                ))
+            }
         };
 
-        stream.play()?;
+        stream.play().map_err(audio_error)?;
 
         Ok(Self {
             _host: host,
             _device: device,
             _stream: stream,
-            sender,
+            engine,
+            volume_curve: Mutex::new(VolumeCurve::default()),
+            waveform,
+            envelope,
         })
     }
 
+    // Sets the volume (0.0-1.0), mapped through the current volume curve
+    pub fn set_volume(&self, volume: f32) -> Result<()> {
+        let curve = *self
+            .volume_curve
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.set_raw_gain(curve.gain_for(volume))
+    }
+
+    // Sets the output gain directly, bypassing the volume curve
+    pub fn set_raw_gain(&self, gain: f32) -> Result<()> {
+        self.engine.send(AudioCommand::SetGain(gain.max(0.0)))
+    }
+
+    // Applies to subsequent set_volume calls
+    pub fn set_volume_curve(&self, curve: VolumeCurve) {
+        *self
+            .volume_curve
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = curve;
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.engine.is_healthy()
+    }
+
+    // True while any note is still sounding
+    pub fn is_playing(&self) -> bool {
+        self.engine.is_playing()
+    }
+
     pub fn play_note(&self, note: &str, duration_ms: u64) -> Result<()> {
         if let Some(frequency) = frequency_for_note(note) {
-            self.sender.send(AudioCommand::PlayNote {
+            self.engine.send(AudioCommand::PlayNote {
                 frequency,
                 duration_ms,
+                waveform: self.waveform,
+                envelope: self.envelope,
             })?;
         }
         Ok(())
@@ -110,7 +361,7 @@ impl AudioPlayer {
     }
 
     pub fn stop(&self) {
-        let _ = self.sender.send(AudioCommand::Stop);
+        let _ = self.engine.send(AudioCommand::Stop);
     }
 
     pub fn wait_for_completion(&self) {
@@ -123,6 +374,8 @@ impl AudioPlayer {
 struct AudioState {
     notes: Vec<Note>,
     current_sample: usize,
+    gain: f32,
+    normalization: f32, // Follows 1/sqrt(sounding notes)
 }
 
 struct Note {
@@ -130,6 +383,9 @@ struct Note {
     duration_samples: usize,
     current_sample: usize,
     amplitude: f32,
+    decay_rate: f32, // Per second, exponential
+    waveform: Waveform,
+    envelope: Envelope,
 }
 
 impl AudioState {
@@ -137,16 +393,28 @@ impl AudioState {
         Self {
             notes: Vec::new(),
             current_sample: 0,
+            gain: 1.0,
+            normalization: 1.0,
         }
     }
 
-    fn add_note(&mut self, frequency: f32, duration_ms: u64, sample_rate: u32) {
+    fn add_note(
+        &mut self,
+        frequency: f32,
+        duration_ms: u64,
+        waveform: Waveform,
+        envelope: Envelope,
+        sample_rate: u32,
+    ) {
         let duration_samples = (duration_ms as f32 * sample_rate as f32 / 1000.0) as usize;
         self.notes.push(Note {
             frequency,
             duration_samples,
             current_sample: 0,
             amplitude: 0.3, // Lower volume
+            decay_rate: BELL_DECAY_PER_SEC * (frequency / 440.0).sqrt(),
+            waveform,
+            envelope,
         });
     }
 
@@ -165,8 +433,11 @@ impl AudioState {
             }
 
             let t = note.current_sample as f32 / sample_rate as f32;
+            let duration = note.duration_samples as f32 / sample_rate as f32;
+            let envelope =
+                note.envelope.gain_at(t * 1000.0, duration * 1000.0) * (-note.decay_rate * t).exp();
             let note_sample =
-                (t * note.frequency * 2.0 * std::f32::consts::PI).sin() * note.amplitude;
+                note.waveform.sample((t * note.frequency).fract()) * note.amplitude * envelope;
             sample += note_sample;
             note.current_sample += 1;
         }
@@ -176,11 +447,26 @@ impl AudioState {
             self.notes.remove(i);
         }
 
+        // Equal-power scaling keeps chords about as loud as single notes
+        let sounding = self.notes.len().max(1) as f32;
+        self.normalization +=
+            (sounding.sqrt().recip() - self.normalization) * NORMALIZATION_SMOOTHING;
+
         self.current_sample += 1;
-        sample
+        soft_clip(sample * self.normalization * self.gain)
     }
 }
 
+// Linear up to the knee, then compressed so the result never reaches ±1.0
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_CLIP_KNEE;
+    (SOFT_CLIP_KNEE + headroom * ((level - SOFT_CLIP_KNEE) / headroom).tanh()).copysign(sample)
+}
+
 fn build_stream<T>(
     device: &Device,
     config: &StreamConfig,
@@ -192,76 +478,845 @@ where
     let sample_rate = config.sample_rate.0;
     let channels = config.channels as usize;
 
-    let stream = device.build_output_stream(
-        config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            let mut state = audio_state.lock().unwrap();
-            for frame in data.chunks_mut(channels) {
-                let sample = state.next_sample(sample_rate);
-                for channel in frame.iter_mut() {
-                    *channel = cpal::Sample::from_sample(sample);
+    let stream = device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut state = lock_audio_state(&audio_state);
+                for frame in data.chunks_mut(channels) {
+                    let sample = state.next_sample(sample_rate);
+                    for channel in frame.iter_mut() {
+                        *channel = cpal::Sample::from_sample(sample);
+                    }
                 }
-            }
-        },
-        move |err| {
-            eprintln!("Audio stream error: {}", err);
-        },
-        None,
-    )?;
+            },
+            move |err| {
+                eprintln!("Audio stream error: {}", err);
+            },
+            None,
+        )
+        .map_err(audio_error)?;
 
     Ok(stream)
 }
 
+fn audio_error(e: impl std::fmt::Display) -> ChimeNetError {
+    ChimeNetError::Audio(e.to_string())
+}
+
+#[derive(Clone)]
 pub struct ChimePlayer {
-    audio_player: Arc<AudioPlayer>,
+    backend: PlayerBackend,
+    // User-registered chords, looked up before the builtin ones
+    chords: Arc<Mutex<HashMap<String, Vec<String>>>>,
 }
 
-impl Clone for ChimePlayer {
-    fn clone(&self) -> Self {
-        Self {
-            audio_player: Arc::clone(&self.audio_player),
-        }
-    }
+#[derive(Clone)]
+enum PlayerBackend {
+    Device(Arc<AudioPlayer>),
+    // ASCII BEL on stdout, for headless machines without an audio device
+    TerminalBell,
 }
 
 impl ChimePlayer {
+    // Sine notes with a short attack and release
     pub fn new() -> Result<Self> {
+        Self::with_config(Waveform::default(), Envelope::default())
+    }
+
+    pub fn with_config(waveform: Waveform, envelope: Envelope) -> Result<Self> {
         Ok(Self {
-            audio_player: Arc::new(AudioPlayer::new()?),
+            backend: PlayerBackend::Device(Arc::new(AudioPlayer::with_config(waveform, envelope)?)),
+            chords: Arc::default(),
         })
     }
 
+    // Rings the terminal bell once per ring instead of synthesizing notes.
+    // Volume, notes and durations don't apply.
+    pub fn terminal_bell() -> Self {
+        Self {
+            backend: PlayerBackend::TerminalBell,
+            chords: Arc::default(),
+        }
+    }
+
+    pub fn is_terminal_bell(&self) -> bool {
+        matches!(self.backend, PlayerBackend::TerminalBell)
+    }
+
+    // Makes `name` playable as a chord, replacing a builtin or earlier chord
+    // of the same name. Every note must be one the synth can play.
+    pub fn register_chord(&self, name: impl Into<String>, notes: Vec<String>) -> Result<()> {
+        let name = name.into();
+        if notes.is_empty() {
+            return Err(ChimeNetError::Audio(format!(
+                "Chord '{}' has no notes",
+                name
+            )));
+        }
+        if let Some(note) = notes.iter().find(|note| frequency_for_note(note).is_none()) {
+            return Err(ChimeNetError::Audio(format!(
+                "Chord '{}' has an unknown note '{}'",
+                name, note
+            )));
+        }
+
+        self.chords
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, notes);
+        Ok(())
+    }
+
+    // The notes of a registered chord, or of a builtin one
+    pub fn chord_notes(&self, chord: &str) -> Vec<String> {
+        let chords = self.chords.lock().unwrap_or_else(PoisonError::into_inner);
+        match chords.get(chord) {
+            Some(notes) => notes.clone(),
+            None => chord_notes(chord),
+        }
+    }
+
+    // `note_durations` gives each note its own length; notes past its end use
+    // `duration_ms` like the chords do
     pub fn play_chime(
         &self,
         notes: Option<&[String]>,
+        note_durations: Option<&[u64]>,
         chords: Option<&[String]>,
         duration_ms: Option<u64>,
     ) -> Result<()> {
-        let duration = duration_ms.unwrap_or(500);
+        let audio_player = match &self.backend {
+            PlayerBackend::Device(audio_player) => audio_player,
+            PlayerBackend::TerminalBell => return ring_terminal_bell(),
+        };
 
-        if let Some(notes) = notes {
-            self.audio_player.play_notes(notes, duration)?;
+        let sounds = chime_sounds(notes, note_durations, chords, duration_ms, |chord| {
+            self.chord_notes(chord)
+        });
+        for (note, note_duration) in sounds {
+            audio_player.play_note(&note, note_duration)?;
         }
 
-        if let Some(chords) = chords {
-            self.audio_player.play_chords(chords, duration)?;
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let PlayerBackend::Device(audio_player) = &self.backend {
+            audio_player.stop();
         }
+    }
 
-        // If no notes or chords specified, play a default chime
-        if notes.is_none() && chords.is_none() {
-            self.audio_player.play_note("C4", duration)?;
-            self.audio_player.play_note("E4", duration)?;
-            self.audio_player.play_note("G4", duration)?;
+    pub fn set_volume(&self, volume: f32) -> Result<()> {
+        match &self.backend {
+            PlayerBackend::Device(audio_player) => audio_player.set_volume(volume),
+            PlayerBackend::TerminalBell => Ok(()),
         }
+    }
 
-        Ok(())
+    pub fn set_raw_gain(&self, gain: f32) -> Result<()> {
+        match &self.backend {
+            PlayerBackend::Device(audio_player) => audio_player.set_raw_gain(gain),
+            PlayerBackend::TerminalBell => Ok(()),
+        }
     }
 
-    pub fn stop(&self) {
-        self.audio_player.stop();
+    pub fn set_volume_curve(&self, curve: VolumeCurve) {
+        if let PlayerBackend::Device(audio_player) = &self.backend {
+            audio_player.set_volume_curve(curve);
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        match &self.backend {
+            PlayerBackend::Device(audio_player) => audio_player.is_healthy(),
+            PlayerBackend::TerminalBell => true,
+        }
     }
 
     pub fn wait_for_completion(&self) {
-        self.audio_player.wait_for_completion();
+        if let PlayerBackend::Device(audio_player) = &self.backend {
+            audio_player.wait_for_completion();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        match &self.backend {
+            PlayerBackend::Device(audio_player) => audio_player.is_playing(),
+            PlayerBackend::TerminalBell => false,
+        }
+    }
+}
+
+// The individual notes a ring plays, with their durations. Empty notes or
+// chords count as not given, so a ring asking for nothing still gets the
+// default chime instead of staying silent. Chords are resolved with `chord_notes`.
+fn chime_sounds(
+    notes: Option<&[String]>,
+    note_durations: Option<&[u64]>,
+    chords: Option<&[String]>,
+    duration_ms: Option<u64>,
+    chord_notes: impl Fn(&str) -> Vec<String>,
+) -> Vec<(String, u64)> {
+    let duration = duration_ms.unwrap_or(DEFAULT_NOTE_MS);
+    let notes = notes.filter(|notes| !notes.is_empty());
+    let chords = chords.filter(|chords| !chords.is_empty());
+
+    if notes.is_none() && chords.is_none() {
+        return ["C4", "E4", "G4"]
+            .iter()
+            .map(|note| (note.to_string(), duration))
+            .collect();
+    }
+
+    let mut sounds: Vec<(String, u64)> = notes
+        .map(|notes| {
+            timed_notes(notes, note_durations.unwrap_or_default(), duration)
+                .map(|(note, ms)| (note.to_string(), ms))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for chord in chords.unwrap_or_default() {
+        let chord_notes = chord_notes(chord);
+        if chord_notes.is_empty() {
+            log::warn!("Unknown chord '{}', not played", chord);
+        }
+        sounds.extend(chord_notes.into_iter().map(|note| (note, duration)));
+    }
+    sounds
+}
+
+// Synthesizes a ring offline, as mono 16-bit samples, through the same
+// AudioState the live stream plays from (default waveform and envelope, full
+// volume). Runs until the longest note is over.
+pub fn render_to_wav(
+    notes: Option<&[String]>,
+    note_durations: Option<&[u64]>,
+    chords: Option<&[String]>,
+    duration_ms: Option<u64>,
+    sample_rate: u32,
+) -> Vec<i16> {
+    let mut state = AudioState::new();
+    for (note, note_duration) in
+        chime_sounds(notes, note_durations, chords, duration_ms, chord_notes)
+    {
+        if let Some(frequency) = frequency_for_note(&note) {
+            state.add_note(
+                frequency,
+                note_duration,
+                Waveform::default(),
+                Envelope::default(),
+                sample_rate,
+            );
+        }
+    }
+
+    let length = state
+        .notes
+        .iter()
+        .map(|note| note.duration_samples)
+        .max()
+        .unwrap_or(0);
+    (0..length)
+        .map(|_| {
+            let sample = state.next_sample(sample_rate).clamp(-1.0, 1.0);
+            (sample * i16::MAX as f32) as i16
+        })
+        .collect()
+}
+
+// A mono 16-bit PCM WAV file holding `samples`
+pub fn wav_bytes(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + samples.len() * 2);
+
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // Bytes per second
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // Bytes per frame
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn write_wav(path: impl AsRef<Path>, samples: &[i16], sample_rate: u32) -> Result<()> {
+    std::fs::write(path, wav_bytes(samples, sample_rate))?;
+    Ok(())
+}
+
+// Only when stdout is a terminal: piped output (e.g. JSON mode) stays clean
+fn ring_terminal_bell() -> Result<()> {
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        stdout.write_all(b"\x07")?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+// How long a ring sounds. Notes and chords are mixed rather than played in
+// sequence, so it's the longest of them.
+pub fn ring_length(request: &ChimeRingRequest) -> Duration {
+    let default_ms = request.duration_ms.unwrap_or(DEFAULT_NOTE_MS);
+    let notes = request.notes.as_deref().unwrap_or_default();
+    let durations = request.note_durations.as_deref().unwrap_or_default();
+
+    let note_lengths = timed_notes(notes, durations, default_ms).map(|(_, ms)| ms);
+    let chord_lengths = request.chords.iter().flatten().map(|_| default_ms);
+
+    // No notes or chords plays the default chime
+    let longest_ms = note_lengths
+        .chain(chord_lengths)
+        .max()
+        .unwrap_or(default_ms);
+    Duration::from_millis(longest_ms)
+}
+
+// What to do with a ring that arrives while the previous one is still
+// sounding or inside its cooldown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CooldownPolicy {
+    #[default]
+    Queue, // Play it once the previous ring and cooldown are over
+    Drop,
+}
+
+impl FromStr for CooldownPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "queue" => Ok(CooldownPolicy::Queue),
+            "drop" => Ok(CooldownPolicy::Drop),
+            _ => Err(format!(
+                "Unknown cooldown policy '{}' (expected queue or drop)",
+                s
+            )),
+        }
+    }
+}
+
+// Spaces rings out so bursts don't overlap and clip: a ring may start once the
+// previous one has finished and at least `cooldown` after it started
+#[derive(Debug, Clone, Default)]
+pub struct RingCooldown {
+    cooldown: Duration,
+    policy: CooldownPolicy,
+    busy_until: Option<Instant>,
+    generation: u64,
+}
+
+impl RingCooldown {
+    pub fn new(cooldown: Duration, policy: CooldownPolicy) -> Self {
+        Self {
+            cooldown,
+            policy,
+            busy_until: None,
+            generation: 0,
+        }
+    }
+
+    // Bumped by `reset`; a queued ring only plays if it still matches the
+    // generation it was admitted in
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Forgets reserved slots and invalidates every queued ring
+    pub fn reset(&mut self) {
+        self.busy_until = None;
+        self.generation += 1;
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    pub fn policy(&self) -> CooldownPolicy {
+        self.policy
+    }
+
+    // Reserves a slot for a ring of `length`. Returns how long to wait before
+    // playing it, or None if it should be dropped. `sounding` is whether the
+    // player is still busy with audio this cooldown didn't schedule.
+    pub fn admit(&mut self, now: Instant, length: Duration, sounding: bool) -> Option<Duration> {
+        let scheduled_busy = self.busy_until.is_some_and(|until| until > now);
+
+        let wait = match self.policy {
+            _ if !sounding && !scheduled_busy => Duration::ZERO,
+            CooldownPolicy::Drop => return None,
+            CooldownPolicy::Queue => self
+                .busy_until
+                .map_or(Duration::ZERO, |until| until.saturating_duration_since(now)),
+        };
+
+        self.busy_until = Some(now + wait + length.max(self.cooldown));
+        Some(wait)
+    }
+}
+
+// Pairs each note with its duration, falling back to `default_ms`
+fn timed_notes<'a>(
+    notes: &'a [String],
+    durations: &'a [u64],
+    default_ms: u64,
+) -> impl Iterator<Item = (&'a str, u64)> + 'a {
+    notes.iter().enumerate().map(move |(i, note)| {
+        (
+            note.as_str(),
+            durations.get(i).copied().unwrap_or(default_ms),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn poison(audio_state: &Arc<Mutex<AudioState>>) {
+        let audio_state = Arc::clone(audio_state);
+        let _ = thread::spawn(move || {
+            let _guard = audio_state.lock().unwrap();
+            panic!("simulated audio thread panic");
+        })
+        .join();
+    }
+
+    #[test]
+    fn notes_without_a_duration_use_the_default() {
+        let notes: Vec<String> = ["C4", "E4", "G4"].iter().map(|n| n.to_string()).collect();
+
+        let timed: Vec<_> = timed_notes(&notes, &[750, 250], 500).collect();
+        assert_eq!(timed, vec![("C4", 750), ("E4", 250), ("G4", 500)]);
+
+        let timed: Vec<_> = timed_notes(&notes[..1], &[750, 250], 500).collect();
+        assert_eq!(timed, vec![("C4", 750)]);
+    }
+
+    #[test]
+    fn ring_length_is_the_longest_sound() {
+        let mut request: ChimeRingRequest = serde_json::from_str(
+            r#"{"chime_id":"abc","user":"alice","notes":["C4","E4"],"chords":null,"duration_ms":300,"timestamp":"2024-01-15T10:30:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(ring_length(&request), Duration::from_millis(300));
+
+        request.note_durations = Some(vec![1200]);
+        assert_eq!(ring_length(&request), Duration::from_millis(1200));
+
+        request.notes = None;
+        request.duration_ms = None;
+        assert_eq!(
+            ring_length(&request),
+            Duration::from_millis(DEFAULT_NOTE_MS)
+        );
+    }
+
+    #[test]
+    fn empty_notes_and_chords_play_the_default_chime() {
+        let default_chime: Vec<(String, u64)> = ["C4", "E4", "G4"]
+            .iter()
+            .map(|note| (note.to_string(), 200))
+            .collect();
+        let none: &[String] = &[];
+        let chord = ["C".to_string()];
+
+        assert_eq!(
+            chime_sounds(None, None, None, Some(200), chord_notes),
+            default_chime
+        );
+        assert_eq!(
+            chime_sounds(Some(none), None, Some(none), Some(200), chord_notes),
+            default_chime
+        );
+        assert_eq!(
+            chime_sounds(Some(none), None, None, Some(200), chord_notes),
+            default_chime
+        );
+        assert_eq!(
+            chime_sounds(None, None, Some(none), Some(200), chord_notes),
+            default_chime
+        );
+
+        // An empty side is skipped while the other one still plays
+        let chord_only = chime_sounds(Some(none), None, Some(&chord), Some(200), chord_notes);
+        assert_eq!(chord_only.len(), chord_notes("C").len());
+        assert!(chord_only.iter().all(|(_, ms)| *ms == 200));
+
+        let notes = ["A4".to_string(), "B4".to_string()];
+        assert_eq!(
+            chime_sounds(Some(&notes), Some(&[900]), Some(none), None, chord_notes),
+            vec![("A4".to_string(), 900), ("B4".to_string(), DEFAULT_NOTE_MS)]
+        );
+    }
+
+    #[test]
+    fn registered_chords_come_before_builtin_ones() {
+        let strings =
+            |items: &[&str]| -> Vec<String> { items.iter().map(|s| s.to_string()).collect() };
+        let player = ChimePlayer::terminal_bell();
+        player
+            .register_chord("Power", strings(&["C4", "G4"]))
+            .unwrap();
+        player
+            .register_chord("C", strings(&["C4", "Eb4", "G4"]))
+            .unwrap();
+        assert!(player.register_chord("Empty", vec![]).is_err());
+        assert!(player
+            .register_chord("Typo", strings(&["C4", "Q4"]))
+            .is_err());
+
+        assert_eq!(player.chord_notes("Power"), strings(&["C4", "G4"]));
+        assert_eq!(player.chord_notes("C"), strings(&["C4", "Eb4", "G4"]));
+        assert_eq!(player.chord_notes("Cmaj7"), chord_notes("Cmaj7"));
+        assert!(player.chord_notes("Typo").is_empty());
+        // Shared with clones, e.g. the player a ChimeInstance hands out
+        assert_eq!(player.clone().chord_notes("Power"), strings(&["C4", "G4"]));
+
+        let chords = strings(&["Power", "Nope", "G7"]);
+        let sounds = chime_sounds(None, None, Some(&chords), Some(200), |chord| {
+            player.chord_notes(chord)
+        });
+        assert_eq!(sounds.len(), 2 + 4);
+    }
+
+    #[test]
+    fn builtin_chords_are_playable() {
+        for chord in [
+            "C", "Am", "F", "G", "Dm", "Em", "D", "E", "A", "Bm", "G7", "Cmaj7",
+        ] {
+            let notes = chord_notes(chord);
+            assert!(notes.len() >= 3, "{}", chord);
+            assert!(
+                notes.iter().all(|n| frequency_for_note(n).is_some()),
+                "{}",
+                chord
+            );
+        }
+    }
+
+    #[test]
+    fn renders_a_chord_to_wav() {
+        let chord = ["C".to_string()];
+        let samples = render_to_wav(None, None, Some(&chord), Some(250), 8000);
+
+        assert_eq!(samples.len(), 2000);
+        let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak > i16::MAX as u16 / 4, "peak {}", peak);
+        // Faded in and out by the envelope
+        assert_eq!(samples[0], 0);
+        assert!(samples.last().unwrap().unsigned_abs() < 100);
+        assert_eq!(
+            samples,
+            render_to_wav(None, None, Some(&chord), Some(250), 8000)
+        );
+
+        let wav = wav_bytes(&samples, 8000);
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 8000);
+        assert_eq!(
+            i16::from_le_bytes(wav[44 + 200..46 + 200].try_into().unwrap()),
+            samples[100]
+        );
+    }
+
+    #[test]
+    fn queued_rings_wait_for_the_previous_one_and_cooldown() {
+        let start = Instant::now();
+        let mut cooldown = RingCooldown::new(Duration::from_millis(800), CooldownPolicy::Queue);
+        let ring = Duration::from_millis(500);
+
+        assert_eq!(cooldown.admit(start, ring, false), Some(Duration::ZERO));
+        // Still inside the 800ms cooldown of the first ring
+        let second = start + Duration::from_millis(100);
+        assert_eq!(
+            cooldown.admit(second, Duration::from_millis(1000), false),
+            Some(Duration::from_millis(700))
+        );
+        // The second ring starts at 800ms and lasts longer than the cooldown
+        let third = start + Duration::from_millis(200);
+        assert_eq!(
+            cooldown.admit(third, ring, false),
+            Some(Duration::from_millis(1600))
+        );
+        // Long after everything finished
+        let later = start + Duration::from_secs(10);
+        assert_eq!(cooldown.admit(later, ring, false), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn dropping_policy_refuses_overlapping_rings() {
+        let start = Instant::now();
+        let mut cooldown = RingCooldown::new(Duration::ZERO, CooldownPolicy::Drop);
+        let ring = Duration::from_millis(500);
+
+        assert_eq!(cooldown.admit(start, ring, false), Some(Duration::ZERO));
+        assert_eq!(
+            cooldown.admit(start + Duration::from_millis(499), ring, false),
+            None
+        );
+        let after = start + Duration::from_millis(500);
+        assert_eq!(cooldown.admit(after, ring, true), None);
+        assert_eq!(cooldown.admit(after, ring, false), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn reset_frees_the_queue() {
+        let start = Instant::now();
+        let mut cooldown = RingCooldown::new(Duration::from_secs(5), CooldownPolicy::Queue);
+        let ring = Duration::from_millis(500);
+
+        assert_eq!(cooldown.admit(start, ring, false), Some(Duration::ZERO));
+        assert_eq!(
+            cooldown.admit(start, ring, false),
+            Some(Duration::from_secs(5))
+        );
+        let queued_in = cooldown.generation();
+
+        cooldown.reset();
+        assert_ne!(cooldown.generation(), queued_in);
+        assert_eq!(cooldown.admit(start, ring, false), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn waveforms_span_a_full_cycle() {
+        for waveform in [
+            Waveform::Sine,
+            Waveform::Square,
+            Waveform::Triangle,
+            Waveform::Sawtooth,
+        ] {
+            let samples: Vec<f32> = (0..100)
+                .map(|i| waveform.sample(i as f32 / 100.0))
+                .collect();
+            let peak = samples.iter().copied().fold(f32::MIN, f32::max);
+            let trough = samples.iter().copied().fold(f32::MAX, f32::min);
+            assert!(
+                (peak - 1.0).abs() < 0.05,
+                "{:?} peaks at {}",
+                waveform,
+                peak
+            );
+            assert!(
+                (trough + 1.0).abs() < 0.05,
+                "{:?} dips to {}",
+                waveform,
+                trough
+            );
+        }
+
+        assert_eq!(Waveform::Square.sample(0.25), 1.0);
+        assert_eq!(Waveform::Square.sample(0.75), -1.0);
+        assert_eq!(Waveform::Triangle.sample(0.5), 1.0);
+        assert_eq!(Waveform::Sawtooth.sample(0.5), 0.0);
+    }
+
+    #[test]
+    fn envelope_moves_through_its_stages() {
+        let envelope = Envelope {
+            attack_ms: 100,
+            decay_ms: 100,
+            sustain_level: 0.5,
+            release_ms: 200,
+        };
+        let gain = |elapsed_ms: f32| envelope.gain_at(elapsed_ms, 1000.0);
+
+        assert_eq!(gain(0.0), 0.0);
+        assert_eq!(gain(50.0), 0.5);
+        assert_eq!(gain(100.0), 1.0);
+        assert_eq!(gain(150.0), 0.75);
+        assert_eq!(gain(500.0), 0.5);
+        assert_eq!(gain(900.0), 0.25);
+        assert_eq!(gain(1000.0), 0.0);
+
+        // A note shorter than attack plus release never reaches full volume
+        assert!(envelope.gain_at(100.0, 150.0) < 0.5);
+    }
+
+    #[test]
+    fn notes_fade_in_and_out() {
+        let mut state = AudioState::new();
+        state.add_note(
+            440.0,
+            100,
+            Waveform::Square,
+            Envelope::default(),
+            SAMPLE_RATE,
+        );
+        // Just the envelope, without the bell decay
+        state.notes[0].decay_rate = 0.0;
+        let samples: Vec<f32> = (0..SAMPLE_RATE as usize / 10)
+            .map(|_| state.next_sample(SAMPLE_RATE).abs())
+            .collect();
+
+        let first_ms = samples[..44].iter().copied().fold(0.0, f32::max);
+        let middle = samples[2000..2500].iter().copied().fold(0.0, f32::max);
+        let last = *samples.last().unwrap();
+        assert!(first_ms < 0.05, "starts at {}", first_ms);
+        assert!((middle - 0.3).abs() < 0.01, "sustains at {}", middle);
+        assert!(last < 0.01, "ends at {}", last);
+    }
+
+    #[test]
+    fn notes_decay_and_chords_stay_within_range() {
+        let mut state = AudioState::new();
+        state.add_note(
+            440.0,
+            1000,
+            Waveform::Sine,
+            Envelope::default(),
+            SAMPLE_RATE,
+        );
+        let mut window = |samples: usize| {
+            (0..samples)
+                .map(|_| state.next_sample(SAMPLE_RATE).abs())
+                .fold(0.0, f32::max)
+        };
+        let struck = window(2000);
+        window(SAMPLE_RATE as usize / 2);
+        let later = window(2000);
+        // exp(-3 * 0.5): under a quarter of the strike half a second on
+        assert!(later < struck * 0.25, "{} then {}", struck, later);
+
+        // Three chords at once, turned all the way up
+        let mut state = AudioState::new();
+        state.gain = 4.0;
+        for chord in ["C", "F", "G"] {
+            for note in chord_notes(chord) {
+                state.add_note(
+                    frequency_for_note(&note).unwrap(),
+                    500,
+                    Waveform::Square,
+                    Envelope::default(),
+                    SAMPLE_RATE,
+                );
+            }
+        }
+        let peak = (0..SAMPLE_RATE as usize / 2)
+            .map(|_| state.next_sample(SAMPLE_RATE).abs())
+            .fold(0.0, f32::max);
+        assert!(peak < 1.0, "peak {}", peak);
+
+        // Rendered the same way, a full chord never hits the i16 limit
+        let chords = ["C".to_string(), "F".to_string(), "G".to_string()];
+        let samples = render_to_wav(None, None, Some(&chords), Some(500), 8000);
+        let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak < i16::MAX as u16, "peak {}", peak);
+    }
+
+    #[test]
+    fn poisoned_audio_state_is_recovered() {
+        let audio_state = Arc::new(Mutex::new(AudioState::new()));
+        poison(&audio_state);
+        assert!(audio_state.is_poisoned());
+
+        let engine = AudioEngine::new(Arc::clone(&audio_state), SAMPLE_RATE).unwrap();
+        engine
+            .send(AudioCommand::PlayNote {
+                frequency: 440.0,
+                duration_ms: 100,
+                waveform: Waveform::default(),
+                envelope: Envelope::default(),
+            })
+            .unwrap();
+
+        // Wait for the command thread to apply the note
+        for _ in 0..100 {
+            if !lock_audio_state(&audio_state).notes.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(!audio_state.is_poisoned());
+        let mut state = lock_audio_state(&audio_state);
+        let peak = (0..1000)
+            .map(|_| state.next_sample(SAMPLE_RATE).abs())
+            .fold(0.0, f32::max);
+        assert!(peak > 0.0);
+    }
+
+    #[test]
+    fn volume_changes_apply_to_sounding_notes() {
+        let audio_state = Arc::new(Mutex::new(AudioState::new()));
+        let engine = AudioEngine::new(Arc::clone(&audio_state), SAMPLE_RATE).unwrap();
+        let peak = || {
+            let mut state = lock_audio_state(&audio_state);
+            (0..1000)
+                .map(|_| state.next_sample(SAMPLE_RATE).abs())
+                .fold(0.0, f32::max)
+        };
+        let set_gain = |gain: f32| {
+            engine.send(AudioCommand::SetGain(gain)).unwrap();
+            for _ in 0..100 {
+                if lock_audio_state(&audio_state).gain == gain {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        {
+            let mut state = lock_audio_state(&audio_state);
+            state.add_note(
+                440.0,
+                1000,
+                Waveform::Square,
+                Envelope::default(),
+                SAMPLE_RATE,
+            );
+            // Constant level, so only the gain changes the peak
+            state.notes[0].decay_rate = 0.0;
+        }
+        let full = peak();
+        assert!(full > 0.0);
+
+        set_gain(VolumeCurve::Linear.gain_for(0.5));
+        assert!((peak() - full * 0.5).abs() < 0.01);
+
+        // Silent, but the note keeps sounding underneath
+        set_gain(VolumeCurve::Perceptual.gain_for(0.0));
+        assert_eq!(peak(), 0.0);
+        assert!(engine.is_playing());
+
+        set_gain(VolumeCurve::Linear.gain_for(2.0));
+        assert!((peak() - full).abs() < 0.01);
+    }
+
+    #[test]
+    fn dead_command_thread_is_restarted() {
+        let audio_state = Arc::new(Mutex::new(AudioState::new()));
+        let engine = AudioEngine::new(Arc::clone(&audio_state), SAMPLE_RATE).unwrap();
+
+        // Replace the worker with one whose thread has already exited
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+        let handle = thread::spawn(|| {});
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        *engine.worker.lock().unwrap() = CommandWorker { sender, handle };
+        assert!(!engine.is_healthy());
+
+        engine.send(AudioCommand::Stop).unwrap();
+        assert!(engine.is_healthy());
     }
 }
@@ -1,19 +1,120 @@
-use crate::audio::ChimePlayer;
-use crate::lcgp::{LcgpHandler, LcgpNode};
+use crate::audio::{ring_length, ChimePlayer, CooldownPolicy, RingCooldown};
+use crate::discovery::HeartbeatConfig;
+use crate::lcgp::{LcgpHandler, LcgpNode, ModeUpdatePublisher, ResponsePublisher};
 use crate::mqtt::ChimeNetMqtt;
+use crate::notification::{NotificationSink, RingOutput};
+use crate::transport::{transport_for_url, NextMessage, Transport};
 use crate::types::*;
 use serde_json;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+// The chime whose mode we mirror; node_id is learned from its first status
+#[derive(Debug, Clone)]
+struct FollowTarget {
+    user: UserId,
+    chime_id: ChimeId,
+    node_id: Option<String>,
+}
+
+type ScheduledRings = Arc<Mutex<HashMap<String, (ScheduledRing, tokio::task::JoinHandle<()>)>>>;
+
+// How long after ringing a snooze reply still gets the ring repeated
+pub const SNOOZE_FOLLOW_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+// How many times a snoozed ring is rung again before the ringer gives up, so
+// two nodes that auto-snooze can't keep ringing each other
+pub const MAX_SNOOZE_FOLLOW_UPS: u32 = 3;
+
+// Follows the snoozes of one ring, given the wait for its answer: after each
+// snooze it sleeps the snoozed minutes and calls `ring_again`, which rings
+// once more and returns the wait for that ring's answer (None stops). Ends at
+// any other answer, at silence for SNOOZE_FOLLOW_WINDOW, or after
+// MAX_SNOOZE_FOLLOW_UPS follow-ups.
+pub async fn follow_snoozes<F, Fut>(mut answer: NextMessage, mut ring_again: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Option<NextMessage>>,
+{
+    let mut follow_ups = 0;
+    loop {
+        let Ok(Ok(message)) = tokio::time::timeout(SNOOZE_FOLLOW_WINDOW, answer).await else {
+            return;
+        };
+        let Some(minutes) = ChimeResponseMessage::snoozed_minutes(&message.payload) else {
+            return;
+        };
+        if follow_ups == MAX_SNOOZE_FOLLOW_UPS {
+            log::info!(
+                "{} snoozed again, giving up after {} follow-ups",
+                message.topic,
+                follow_ups
+            );
+            return;
+        }
+
+        log::info!(
+            "{} snoozed the ring, ringing again in {} min",
+            message.topic,
+            minutes
+        );
+        tokio::time::sleep(Duration::from_secs(u64::from(minutes) * 60)).await;
+        follow_ups += 1;
+        let Some(next) = ring_again().await else {
+            return;
+        };
+        answer = next;
+    }
+}
+
+// A normal-urgency ring for `user`'s chime under a fresh request_id, so the
+// answer to it can be told apart from answers to other rings
+pub fn new_ring_request(
+    user: &UserId,
+    chime_id: &ChimeId,
+    notes: Option<Vec<String>>,
+    chords: Option<Vec<String>>,
+    duration_ms: Option<u64>,
+) -> ChimeRingRequest {
+    ChimeRingRequest {
+        chime_id: chime_id.to_string(),
+        user: user.to_string(),
+        notes,
+        chords,
+        duration_ms,
+        note_durations: None,
+        timestamp: chrono::Utc::now(),
+        preset: None,
+        urgency: Urgency::Normal,
+        expires_at: None,
+        request_id: Some(Uuid::new_v4().to_string()),
+    }
+}
+
 pub struct ChimeInstance {
     pub info: ChimeInfo,
     pub player: ChimePlayer,
     pub lcgp_node: Arc<LcgpNode>,
     pub lcgp_handler: LcgpHandler,
     pub mqtt: Arc<Mutex<ChimeNetMqtt>>,
+    pub notifier: NotificationSink,
+    pub ring_output: Arc<std::sync::Mutex<RingOutput>>,
+    ring_cooldown: Arc<std::sync::Mutex<RingCooldown>>,
+    scheduled_rings: ScheduledRings,
+    following: Arc<std::sync::Mutex<Option<FollowTarget>>>,
+    status_message: Arc<std::sync::Mutex<Option<String>>>,
+    announce_window: Arc<std::sync::Mutex<Option<Duration>>>,
+    announce_scheduled: Arc<AtomicBool>,
+    heartbeat: Arc<std::sync::Mutex<HeartbeatConfig>>,
+    heartbeat_timer: Arc<std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    mode_revert: Arc<std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    snooze_follow_ups: Arc<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+    allow_remote_mode: Arc<AtomicBool>,
+    strict_capabilities: Arc<AtomicBool>,
 }
 
 impl Clone for ChimeInstance {
@@ -24,6 +125,20 @@ impl Clone for ChimeInstance {
             lcgp_node: Arc::clone(&self.lcgp_node),
             lcgp_handler: self.lcgp_handler.clone(),
             mqtt: Arc::clone(&self.mqtt),
+            notifier: self.notifier.clone(),
+            ring_output: Arc::clone(&self.ring_output),
+            ring_cooldown: Arc::clone(&self.ring_cooldown),
+            scheduled_rings: Arc::clone(&self.scheduled_rings),
+            following: Arc::clone(&self.following),
+            status_message: Arc::clone(&self.status_message),
+            announce_window: Arc::clone(&self.announce_window),
+            announce_scheduled: Arc::clone(&self.announce_scheduled),
+            heartbeat: Arc::clone(&self.heartbeat),
+            heartbeat_timer: Arc::clone(&self.heartbeat_timer),
+            mode_revert: Arc::clone(&self.mode_revert),
+            snooze_follow_ups: Arc::clone(&self.snooze_follow_ups),
+            allow_remote_mode: Arc::clone(&self.allow_remote_mode),
+            strict_capabilities: Arc::clone(&self.strict_capabilities),
         }
     }
 }
@@ -34,14 +149,63 @@ impl ChimeInstance {
         description: Option<String>,
         notes: Vec<String>,
         chords: Vec<String>,
-        user: String,
+        user: UserId,
         mqtt_broker: &str,
     ) -> Result<Self> {
-        let chime_id = Uuid::new_v4().to_string();
-        let node_id = format!("{}_{}", user, chime_id);
+        // Before the user ends up in a client id
+        user.validate()?;
+        let chime_id = ChimeId::from(Uuid::new_v4().to_string());
+        let node_id = NodeId::for_chime(&user, &chime_id);
+        let transport = transport_for_url(mqtt_broker, node_id.as_str()).await?;
+
+        Self::build(name, description, notes, chords, user, chime_id, transport)
+    }
+
+    // Like `new`, but keeps a known chime_id, e.g. from a previous run. The
+    // broker client id derives from it, which a persistent session needs.
+    pub async fn with_chime_id(
+        name: String,
+        description: Option<String>,
+        notes: Vec<String>,
+        chords: Vec<String>,
+        user: UserId,
+        chime_id: ChimeId,
+        mqtt_broker: &str,
+    ) -> Result<Self> {
+        user.validate()?;
+        chime_id.validate()?;
+        let node_id = NodeId::for_chime(&user, &chime_id);
+        let transport = transport_for_url(mqtt_broker, node_id.as_str()).await?;
+
+        Self::build(name, description, notes, chords, user, chime_id, transport)
+    }
+
+    // Like `new`, but over a caller-supplied transport (LAN, in-process bus, ...)
+    pub fn with_transport(
+        name: String,
+        description: Option<String>,
+        notes: Vec<String>,
+        chords: Vec<String>,
+        user: UserId,
+        transport: Box<dyn Transport>,
+    ) -> Result<Self> {
+        let chime_id = ChimeId::from(Uuid::new_v4().to_string());
+        Self::build(name, description, notes, chords, user, chime_id, transport)
+    }
+
+    fn build(
+        name: String,
+        description: Option<String>,
+        notes: Vec<String>,
+        chords: Vec<String>,
+        user: UserId,
+        chime_id: ChimeId,
+        transport: Box<dyn Transport>,
+    ) -> Result<Self> {
+        let node_id = NodeId::for_chime(&user, &chime_id);
 
         let info = ChimeInfo {
-            id: chime_id.clone(),
+            id: chime_id.to_string(),
             name,
             description,
             notes,
@@ -49,12 +213,16 @@ impl ChimeInstance {
             created_at: chrono::Utc::now(),
         };
 
-        let player = ChimePlayer::new()?;
-        let lcgp_node = Arc::new(LcgpNode::new(node_id.clone()));
-        let lcgp_handler = LcgpHandler::new(lcgp_node.clone());
-        let mqtt = Arc::new(Mutex::new(
-            ChimeNetMqtt::new(mqtt_broker, &user, &node_id).await?,
-        ));
+        // Headless machines still get an audible ring rather than no chime at all
+        let player = ChimePlayer::new().unwrap_or_else(|e| {
+            log::warn!("No audio output ({}), using the terminal bell", e);
+            ChimePlayer::terminal_bell()
+        });
+        let lcgp_node = Arc::new(LcgpNode::new(node_id.to_string()));
+        let mqtt = Arc::new(Mutex::new(ChimeNetMqtt::with_transport(transport, &user)?));
+        let lcgp_handler = LcgpHandler::new(lcgp_node.clone())
+            .with_mode_publisher(Self::mode_publisher(mqtt.clone(), chime_id.clone()))
+            .with_response_publisher(Self::response_publisher(mqtt.clone(), chime_id.clone()));
 
         Ok(Self {
             info,
@@ -62,33 +230,288 @@ impl ChimeInstance {
             lcgp_node,
             lcgp_handler,
             mqtt,
+            notifier: NotificationSink::default(),
+            ring_output: Arc::new(std::sync::Mutex::new(RingOutput::default())),
+            ring_cooldown: Arc::new(std::sync::Mutex::new(RingCooldown::default())),
+            scheduled_rings: Arc::new(Mutex::new(HashMap::new())),
+            following: Arc::new(std::sync::Mutex::new(None)),
+            status_message: Arc::new(std::sync::Mutex::new(None)),
+            announce_window: Arc::new(std::sync::Mutex::new(Some(
+                crate::discovery::DEFAULT_ANNOUNCE_WINDOW,
+            ))),
+            announce_scheduled: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(std::sync::Mutex::new(HeartbeatConfig::default())),
+            heartbeat_timer: Arc::new(std::sync::Mutex::new(None)),
+            mode_revert: Arc::new(std::sync::Mutex::new(None)),
+            snooze_follow_ups: Arc::new(std::sync::Mutex::new(Vec::new())),
+            allow_remote_mode: Arc::new(AtomicBool::new(true)),
+            strict_capabilities: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    // Same publishers as `handler`, over another node
+    fn rebuild_handler(handler: &LcgpHandler, node: Arc<LcgpNode>) -> LcgpHandler {
+        let mut rebuilt = LcgpHandler::new(node);
+        if let Some(publisher) = handler.mode_publisher() {
+            rebuilt = rebuilt.with_mode_publisher(publisher);
+        }
+        if let Some(publisher) = handler.response_publisher() {
+            rebuilt = rebuilt.with_response_publisher(publisher);
+        }
+        rebuilt.set_max_delayed_responses(handler.max_delayed_responses());
+        rebuilt
+    }
+
+    // Delayed auto-responses go where immediate ones do, this chime's
+    // response topic
+    fn response_publisher(mqtt: Arc<Mutex<ChimeNetMqtt>>, chime_id: ChimeId) -> ResponsePublisher {
+        Arc::new(move |response| {
+            let mqtt = mqtt.clone();
+            let chime_id = chime_id.clone();
+            tokio::spawn(async move {
+                let result = mqtt
+                    .lock()
+                    .await
+                    .publish_chime_response(&chime_id, &response)
+                    .await;
+                match result {
+                    Ok(()) => log::info!("Sent delayed response: {:?}", response.response),
+                    Err(e) => log::error!("Failed to send delayed response: {}", e),
+                }
+            });
+        })
+    }
+
+    // Publishes on the mode topic of whichever user the connection is for,
+    // so it keeps working after migrate_user
+    fn mode_publisher(mqtt: Arc<Mutex<ChimeNetMqtt>>, chime_id: ChimeId) -> ModeUpdatePublisher {
+        Arc::new(move |update| {
+            let mqtt = mqtt.clone();
+            let chime_id = chime_id.clone();
+            tokio::spawn(async move {
+                let result = mqtt
+                    .lock()
+                    .await
+                    .publish_mode_update(&chime_id, &update)
+                    .await;
+                if let Err(e) = result {
+                    log::warn!("Failed to publish mode update: {}", e);
+                }
+            });
+        })
+    }
+
+    pub fn chime_id(&self) -> ChimeId {
+        ChimeId::from(self.info.id.as_str())
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        NodeId::from(self.lcgp_node.node_id.as_str())
+    }
+
+    pub fn set_ring_output(&self, output: RingOutput) {
+        *self.ring_output.lock().unwrap() = output;
+    }
+
+    pub fn get_ring_output(&self) -> RingOutput {
+        *self.ring_output.lock().unwrap()
+    }
+
+    // Minimum time between the starts of two rings, and what happens to a
+    // ring arriving sooner (or while the last one still sounds)
+    pub fn set_ring_cooldown(&self, cooldown: Duration, policy: CooldownPolicy) {
+        *self.ring_cooldown.lock().unwrap() = RingCooldown::new(cooldown, policy);
+    }
+
+    pub fn get_ring_cooldown(&self) -> (Duration, CooldownPolicy) {
+        let ring_cooldown = self.ring_cooldown.lock().unwrap();
+        (ring_cooldown.cooldown(), ring_cooldown.policy())
+    }
+
+    // How long this chime may wait, at random, before answering a ringer's
+    // discovery request by re-publishing its info. None ignores requests.
+    pub fn set_announce_window(&self, window: Option<Duration>) {
+        *self.announce_window.lock().unwrap() = window;
+    }
+
+    pub fn announce_window(&self) -> Option<Duration> {
+        *self.announce_window.lock().unwrap()
+    }
+
+    // How often the status (and an unchanged mode) is re-published while
+    // online. Takes effect from the next beat.
+    // Refuses a zero interval (see HeartbeatConfig::validate)
+    pub fn set_heartbeat(&self, heartbeat: HeartbeatConfig) -> Result<()> {
+        heartbeat.validate()?;
+        *self.heartbeat.lock().unwrap() = heartbeat;
+        Ok(())
+    }
+
+    pub fn heartbeat(&self) -> HeartbeatConfig {
+        *self.heartbeat.lock().unwrap()
+    }
+
+    // Whether ModeChangeRequests from other clients are applied (the
+    // default). Owners who want to be the only one setting the mode turn it off.
+    pub fn set_allow_remote_mode(&self, allow: bool) {
+        self.allow_remote_mode.store(allow, Ordering::Relaxed);
+    }
+
+    pub fn allow_remote_mode(&self) -> bool {
+        self.allow_remote_mode.load(Ordering::Relaxed)
+    }
+
+    // Rings asking for notes/chords this chime doesn't advertise always have
+    // those dropped. When strict, a ring left with nothing to play is refused
+    // with a Negative response instead of playing the default sound.
+    pub fn set_strict_capabilities(&self, strict: bool) {
+        self.strict_capabilities.store(strict, Ordering::Relaxed);
+    }
+
+    pub fn strict_capabilities(&self) -> bool {
+        self.strict_capabilities.load(Ordering::Relaxed)
+    }
+
     pub async fn start(&self) -> Result<()> {
-        // Connect to MQTT
-        self.mqtt.lock().await.connect().await?;
+        // Connect to MQTT, with an offline status as the will in case this
+        // process dies without going offline properly
+        let mut mqtt = self.mqtt.lock().await;
+        mqtt.set_offline_will(&self.chime_id(), &self.current_status(false))?;
+        mqtt.connect().await?;
+        drop(mqtt);
+
+        self.go_online().await?;
+
+        log::info!("Chime instance '{}' started", self.info.name);
+        Ok(())
+    }
+
+    // Moves this chime to another user, keeping its chime_id, mode and
+    // settings. The old user's retained topics are cleared (offline status,
+    // empty list) and everything is re-published under the new user over the
+    // same connection. Consumes the instance: clones taken earlier keep the
+    // old identity and should be dropped.
+    pub async fn migrate_user(self, new_user: UserId) -> Result<Self> {
+        let old_user = self.mqtt.lock().await.user().clone();
+        if old_user == new_user {
+            return Ok(self);
+        }
+        // Refuse before touching any of the old user's topics
+        new_user.validate()?;
+
+        let chime_id = self.chime_id();
+        let following = self.following();
+        self.unfollow().await?;
+
+        {
+            let mqtt = self.mqtt.lock().await;
+            mqtt.publish_chime_status(&chime_id, &self.current_status(false))
+                .await?;
+            mqtt.publish_chime_list(&[]).await?;
+            mqtt.clear_chime_details(&chime_id).await?;
+            mqtt.unsubscribe(&TopicBuilder::chime_ring(
+                old_user.as_str(),
+                chime_id.as_str(),
+            ))
+            .await?;
+            mqtt.unsubscribe(&TopicBuilder::chime_mode_request(
+                old_user.as_str(),
+                chime_id.as_str(),
+            ))
+            .await?;
+            mqtt.unsubscribe(&TopicBuilder::chime_status_request(
+                old_user.as_str(),
+                chime_id.as_str(),
+            ))
+            .await?;
+            // Its handler announces the old identity; go_online adds a new one
+            mqtt.unsubscribe(&TopicBuilder::ringer_discover_all())
+                .await?;
+        }
 
+        let node_id = NodeId::for_chime(&new_user, &chime_id);
+        let lcgp_node = Arc::new(self.lcgp_node.with_node_id(node_id.to_string()));
+        self.mqtt.lock().await.set_user(&new_user)?;
+
+        let migrated = Self {
+            lcgp_handler: Self::rebuild_handler(&self.lcgp_handler, lcgp_node.clone()),
+            lcgp_node,
+            following: Arc::new(std::sync::Mutex::new(None)),
+            ..self
+        };
+        // For the next connect; the current one keeps its will on the old
+        // user's status, which is offline by now anyway
+        migrated
+            .mqtt
+            .lock()
+            .await
+            .set_offline_will(&chime_id, &migrated.current_status(false))?;
+        migrated.go_online().await?;
+
+        if let Some((user, chime_id)) = following {
+            migrated.follow(&user, &chime_id).await?;
+        }
+
+        log::info!(
+            "Migrated chime '{}' from {} to {}",
+            migrated.info.name,
+            old_user,
+            new_user
+        );
+        Ok(migrated)
+    }
+
+    // Re-publishes the status every heartbeat so discovery clients know this
+    // chime is still around, and the mode once it has gone unannounced as long
+    fn start_heartbeat(&self) -> tokio::task::JoinHandle<()> {
+        let chime = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let interval = chime.heartbeat().interval;
+                tokio::time::sleep(interval).await;
+
+                if let Err(e) = chime.publish_status().await {
+                    log::warn!("Failed to publish heartbeat status: {}", e);
+                }
+                chime.lcgp_handler.refresh_mode_update(interval);
+            }
+        })
+    }
+
+    // Publishes this chime's info and starts listening for rings
+    async fn go_online(&self) -> Result<()> {
         // Publish initial chime information
         self.publish_chime_info().await?;
 
-        // Start LCGP mode update timer
-        self.lcgp_handler.start_mode_update_timer().await;
+        // Start the heartbeat, replacing the one of a previous identity
+        let timer = self.start_heartbeat();
+        if let Some(previous) = self.heartbeat_timer.lock().unwrap().replace(timer) {
+            previous.abort();
+        }
 
         // Subscribe to ring requests
-        let chime_id = self.info.id.clone();
+        let chime_id = self.chime_id();
         let mqtt_clone = self.mqtt.clone();
         let lcgp_handler_clone = self.lcgp_handler.clone();
         let player_clone = self.player.clone();
+        let notifier_clone = self.notifier.clone();
+        let ring_output_clone = self.ring_output.clone();
+        let ring_cooldown_clone = self.ring_cooldown.clone();
+        let chime_info = self.info.clone();
+        let strict_capabilities_clone = self.strict_capabilities.clone();
 
         self.mqtt
             .lock()
             .await
-            .subscribe_to_chime_rings(&chime_id.clone(), move |topic, payload| {
+            .subscribe_to_chime_rings(&chime_id, move |topic, payload| {
                 let mqtt = mqtt_clone.clone();
                 let lcgp_handler = lcgp_handler_clone.clone();
                 let player = player_clone.clone();
-                let chime_id = chime_id.clone();
+                let notifier = notifier_clone.clone();
+                let ring_output = *ring_output_clone.lock().unwrap();
+                let ring_cooldown = ring_cooldown_clone.clone();
+                let chime_info = chime_info.clone();
+                let strict_capabilities = strict_capabilities_clone.load(Ordering::Relaxed);
 
                 tokio::spawn(async move {
                     if let Err(e) = Self::handle_ring_request(
@@ -97,7 +520,11 @@ impl ChimeInstance {
                         mqtt,
                         lcgp_handler,
                         player,
-                        chime_id,
+                        notifier,
+                        ring_output,
+                        ring_cooldown,
+                        chime_info,
+                        strict_capabilities,
                     )
                     .await
                     {
@@ -107,22 +534,123 @@ impl ChimeInstance {
             })
             .await?;
 
-        log::info!("Chime instance '{}' started", self.info.name);
+        let chime = self.clone();
+        self.mqtt
+            .lock()
+            .await
+            .subscribe_to_mode_requests(&chime_id, move |_, payload| {
+                let chime = chime.clone();
+                tokio::spawn(async move { chime.handle_mode_request(&payload).await });
+            })
+            .await?;
+
+        let chime = self.clone();
+        self.mqtt
+            .lock()
+            .await
+            .subscribe_to_status_requests(&chime_id, move |_, payload| {
+                let chime = chime.clone();
+                tokio::spawn(async move { chime.answer_status_request(&payload).await });
+            })
+            .await?;
+
+        let chime = self.clone();
+        self.mqtt
+            .lock()
+            .await
+            .subscribe_to_announce_requests(move |_, _| {
+                let chime = chime.clone();
+                tokio::spawn(async move { chime.announce_on_request().await });
+            })
+            .await?;
+
         Ok(())
     }
 
+    async fn handle_mode_request(&self, payload: &str) {
+        let request: ModeChangeRequest = match serde_json::from_str(payload) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!("Ignoring unreadable mode request: {}", e);
+                return;
+            }
+        };
+        if request.chime_id != self.info.id {
+            return;
+        }
+        if !self.allow_remote_mode() {
+            log::info!(
+                "Refusing mode change to {} from {}: remote mode changes are off",
+                request.requested_mode,
+                request.requester_id
+            );
+            return;
+        }
+
+        log::info!(
+            "Mode changed to {} at the request of {}",
+            request.requested_mode,
+            request.requester_id
+        );
+        if let Err(e) = self.set_mode(request.requested_mode).await {
+            log::warn!("Failed to apply requested mode: {}", e);
+        }
+    }
+
+    async fn answer_status_request(&self, payload: &str) {
+        let request: ChimeStatusRequest = match serde_json::from_str(payload) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!("Ignoring unreadable status request: {}", e);
+                return;
+            }
+        };
+
+        let status = ChimeStatus {
+            in_reply_to: Some(request.request_id),
+            ..self.current_status(true)
+        };
+        let mqtt = self.mqtt.lock().await;
+        if let Err(e) = mqtt.publish_status_reply(&self.chime_id(), &status).await {
+            log::warn!("Failed to answer status request: {}", e);
+        }
+    }
+
+    // Re-publishes info at a random point within the announce window.
+    // Requests arriving while an announce is scheduled share it.
+    async fn announce_on_request(&self) {
+        let Some(window) = self.announce_window() else {
+            return;
+        };
+        if self.announce_scheduled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        tokio::time::sleep(window.mul_f64(fastrand::f64())).await;
+        self.announce_scheduled.store(false, Ordering::SeqCst);
+
+        if let Err(e) = self.publish_chime_info().await {
+            log::warn!("Failed to answer discovery request: {}", e);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn handle_ring_request(
         topic: String,
         payload: String,
         mqtt: Arc<Mutex<ChimeNetMqtt>>,
         lcgp_handler: LcgpHandler,
         player: ChimePlayer,
-        chime_id: String,
+        notifier: NotificationSink,
+        ring_output: RingOutput,
+        ring_cooldown: Arc<std::sync::Mutex<RingCooldown>>,
+        chime_info: ChimeInfo,
+        strict_capabilities: bool,
     ) -> Result<()> {
         log::info!("Received ring request on topic '{}': {}", topic, payload);
 
         // Parse ring request
-        let ring_request: ChimeRingRequest = match serde_json::from_str(&payload) {
+        let mut ring_request: ChimeRingRequest = match serde_json::from_str(&payload) {
             Ok(req) => req,
             Err(e) => {
                 log::error!("Failed to parse ring request JSON: {}", e);
@@ -130,6 +658,47 @@ impl ChimeInstance {
             }
         };
 
+        // Late deliveries (persistent sessions, QoS>0) shouldn't ring a stale alarm
+        if ring_request.is_expired(chrono::Utc::now()) {
+            log::warn!(
+                "Dropping expired ring request from {} (expired at {:?})",
+                ring_request.user,
+                ring_request.expires_at
+            );
+            return Ok(());
+        }
+
+        if let Err(e) = ring_request.expand_preset() {
+            log::warn!("Ignoring preset: {}", e);
+        }
+
+        let had_sounds = ring_request.has_sounds();
+        let rejected = ring_request.restrict_to(&chime_info);
+        if !rejected.is_empty() {
+            log::warn!(
+                "Ring from {} asked for sounds this chime doesn't have: {:?}",
+                ring_request.user,
+                rejected
+            );
+        }
+        if strict_capabilities && had_sounds && !ring_request.has_sounds() {
+            let mqtt = mqtt.lock().await;
+            let chime_id = ChimeId::from(chime_info.id.as_str());
+            let refusal = ChimeResponseMessage {
+                timestamp: chrono::Utc::now(),
+                response: ChimeResponse::Negative,
+                node_id: NodeId::for_chime(mqtt.user(), &chime_id).to_string(),
+                original_chime_id: Some(ring_request.chime_id.clone()),
+                message: Some(format!(
+                    "Nothing playable: unsupported {}",
+                    rejected.join(", ")
+                )),
+                in_reply_to: ring_request.request_id.clone(),
+            };
+            log::info!("Refusing ring from {}: nothing playable", ring_request.user);
+            return mqtt.publish_chime_response(&chime_id, &refusal).await;
+        }
+
         log::info!(
             "Ring request details: user={}, chime_id={}, notes={:?}, chords={:?}",
             ring_request.user,
@@ -141,38 +710,64 @@ impl ChimeInstance {
         // Convert to chime message for LCGP handling
         let chime_message = ChimeMessage {
             timestamp: ring_request.timestamp,
-            from_node: ring_request.user,
+            from_node: ring_request.user.clone(),
             message: None,
             chime_id: Some(ring_request.chime_id.clone()),
             notes: ring_request.notes.clone(),
             chords: ring_request.chords.clone(),
+            request_id: ring_request.request_id.clone(),
         };
 
         // Handle via LCGP
-        let response = lcgp_handler
-            .handle_incoming_chime(chime_message.clone())
+        let RingOutcome {
+            should_chime: should_play,
+            response,
+        } = lcgp_handler
+            .handle_incoming_ring(chime_message.clone())
             .await;
 
-        // Check if the chime should be played (all modes except DoNotDisturb)
-        let should_play = lcgp_handler.should_chime(&chime_message);
-
         log::info!("LCGP decision: should_play={}", should_play);
 
-        if should_play {
-            let notes = ring_request.notes.as_deref();
-            let chords = ring_request.chords.as_deref();
-            let duration = ring_request.duration_ms;
+        if should_play && ring_output.shows_notification() {
+            let message = chime_message.clone();
+            let chime_name = chime_info.name.clone();
+            let result =
+                tokio::task::spawn_blocking(move || notifier.notify_ring(&chime_name, &message))
+                    .await;
 
-            log::info!(
-                "Playing chime with notes: {:?}, chords: {:?}, duration: {:?}ms",
-                notes,
-                chords,
-                duration
+            match result {
+                Ok(Ok(())) => log::info!("Ring notification shown"),
+                Ok(Err(e)) => log::error!("Failed to show ring notification: {}", e),
+                Err(e) => log::error!("Notification task failed: {}", e),
+            }
+        }
+
+        if should_play && ring_output.plays_audio() {
+            let slot = ring_cooldown.lock().unwrap().admit(
+                Instant::now(),
+                ring_length(&ring_request),
+                player.is_playing(),
             );
 
-            match player.play_chime(notes, chords, duration) {
-                Ok(()) => log::info!("Chime played successfully"),
-                Err(e) => log::error!("Failed to play chime: {}", e),
+            match slot {
+                None => log::info!(
+                    "Dropping ring from {}: previous ring still sounding",
+                    ring_request.user
+                ),
+                Some(wait) if wait.is_zero() => Self::play_ring(&player, &ring_request),
+                Some(wait) => {
+                    log::info!("Queueing ring from {} for {:?}", ring_request.user, wait);
+                    let player = player.clone();
+                    let generation = ring_cooldown.lock().unwrap().generation();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(wait).await;
+                        if ring_cooldown.lock().unwrap().generation() != generation {
+                            log::info!("Queued ring from {} cleared by reset", ring_request.user);
+                            return;
+                        }
+                        Self::play_ring(&player, &ring_request);
+                    });
+                }
             }
         } else {
             log::info!("Chime blocked by LCGP mode");
@@ -183,7 +778,7 @@ impl ChimeInstance {
             match mqtt
                 .lock()
                 .await
-                .publish_chime_response(&chime_id, &response)
+                .publish_chime_response(&ChimeId::from(chime_info.id.as_str()), &response)
                 .await
             {
                 Ok(()) => log::info!("Sent automatic response: {:?}", response.response),
@@ -194,90 +789,317 @@ impl ChimeInstance {
         Ok(())
     }
 
-    pub async fn publish_chime_info(&self) -> Result<()> {
-        // Publish to chime list
-        self.mqtt
-            .lock()
-            .await
-            .publish_chime_list(&[self.info.clone()])
-            .await?;
+    fn play_ring(player: &ChimePlayer, ring_request: &ChimeRingRequest) {
+        let notes = ring_request.notes.as_deref();
+        let note_durations = ring_request.note_durations.as_deref();
+        let chords = ring_request.chords.as_deref();
+        let duration = ring_request.duration_ms;
 
-        // Publish notes and chords
-        self.mqtt
-            .lock()
-            .await
-            .publish_chime_notes(&self.info.id, &self.info.notes)
-            .await?;
+        log::info!(
+            "Playing chime with notes: {:?} ({:?}ms), chords: {:?}, duration: {:?}ms",
+            notes,
+            note_durations,
+            chords,
+            duration
+        );
+
+        match player.play_chime(notes, note_durations, chords, duration) {
+            Ok(()) => log::info!("Chime played successfully"),
+            Err(e) => log::error!("Failed to play chime: {}", e),
+        }
+    }
+
+    pub async fn publish_chime_info(&self) -> Result<()> {
+        // The list carries notes and chords too
         self.mqtt
             .lock()
             .await
-            .publish_chime_chords(&self.info.id, &self.info.chords)
+            .publish_chime_list(std::slice::from_ref(&self.info))
             .await?;
 
         // Publish status
-        let status = ChimeStatus {
+        self.publish_status().await
+    }
+
+    fn current_status(&self, online: bool) -> ChimeStatus {
+        ChimeStatus {
             chime_id: self.info.id.clone(),
-            online: true,
+            online,
             mode: self.lcgp_node.get_mode(),
             last_seen: chrono::Utc::now(),
             node_id: self.lcgp_node.node_id.clone(),
+            following: self
+                .following
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|target| target.node_id.clone()),
+            ring_rate_per_hour: self.lcgp_node.ring_rate_per_hour(),
+            status_message: self.status_message(),
+            in_reply_to: None,
+        }
+    }
+
+    // Mirror another chime's mode whenever its status changes. Replaces any
+    // existing follow.
+    pub async fn follow(&self, user: &UserId, chime_id: &ChimeId) -> Result<()> {
+        if *chime_id == self.chime_id() {
+            return Err(ChimeNetError::FollowSelf);
+        }
+
+        self.unfollow().await?;
+
+        *self.following.lock().unwrap() = Some(FollowTarget {
+            user: user.clone(),
+            chime_id: chime_id.clone(),
+            node_id: None,
+        });
+
+        let chime = self.clone();
+        let topic = TopicBuilder::chime_status(user.as_str(), chime_id.as_str());
+        let mqtt = self.mqtt.lock().await;
+        mqtt.subscribe(&topic, mqtt.state_qos(), move |_topic, payload| {
+            let chime = chime.clone();
+            tokio::spawn(async move {
+                if let Err(e) = chime.apply_followed_status(&payload).await {
+                    log::error!("Failed to follow mode update: {}", e);
+                }
+            });
+        })
+        .await?;
+
+        log::info!("Following mode of {}/{}", user, chime_id);
+        Ok(())
+    }
+
+    pub async fn unfollow(&self) -> Result<()> {
+        let Some(target) = self.following.lock().unwrap().take() else {
+            return Ok(());
         };
 
         self.mqtt
             .lock()
             .await
-            .publish_chime_status(&self.info.id, &status)
+            .unsubscribe(&TopicBuilder::chime_status(
+                target.user.as_str(),
+                target.chime_id.as_str(),
+            ))
             .await?;
 
-        Ok(())
+        log::info!("Stopped following {}/{}", target.user, target.chime_id);
+        self.publish_status().await
+    }
+
+    async fn publish_status(&self) -> Result<()> {
+        let status = self.current_status(true);
+        self.mqtt
+            .lock()
+            .await
+            .publish_chime_status(&self.chime_id(), &status)
+            .await
+    }
+
+    // (user, chime_id) of the chime being followed
+    pub fn following(&self) -> Option<(UserId, ChimeId)> {
+        self.following
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|target| (target.user.clone(), target.chime_id.clone()))
+    }
+
+    async fn apply_followed_status(&self, payload: &str) -> Result<()> {
+        let status: ChimeStatus = serde_json::from_str(payload)?;
+
+        let learned_target = {
+            let mut following = self.following.lock().unwrap();
+            match following.as_mut() {
+                Some(target) if target.chime_id.as_str() == status.chime_id => {
+                    target.node_id.replace(status.node_id.clone()).is_none()
+                }
+                // Stale message from a chime we no longer follow
+                _ => return Ok(()),
+            }
+        };
+
+        // Guard against A follows B follows A: ignore a target that mirrors us
+        if status.following.as_deref() == Some(self.lcgp_node.node_id.as_str()) {
+            log::warn!(
+                "{} is following this chime; ignoring its mode to avoid a follow loop",
+                status.node_id
+            );
+            return Ok(());
+        }
+
+        // Equal modes are skipped too, so longer cycles settle instead of echoing forever
+        if !status.online || status.mode == self.lcgp_node.get_mode() {
+            // Still advertise who we follow so the target can detect a loop
+            if learned_target {
+                self.publish_status().await?;
+            }
+            return Ok(());
+        }
+
+        log::info!("Following {} into mode {:?}", status.node_id, status.mode);
+        self.set_mode(status.mode).await
+    }
+
+    // Free-text presence shown next to the mode by discoverers. Blank clears
+    // it. Unlike the away message it is public and doesn't affect rings.
+    pub async fn set_status_message(&self, message: Option<String>) -> Result<()> {
+        let message = message
+            .map(|message| message.trim().to_string())
+            .filter(|message| !message.is_empty());
+        *self.status_message.lock().unwrap() = message;
+        self.publish_status().await
+    }
+
+    pub fn status_message(&self) -> Option<String> {
+        self.status_message.lock().unwrap().clone()
     }
 
     pub async fn set_mode(&self, mode: LcgpMode) -> Result<()> {
+        if let Some(revert) = self.mode_revert.lock().unwrap().take() {
+            revert.abort();
+        }
         self.lcgp_node.set_mode(mode);
+        self.lcgp_handler.publish_mode_update();
 
         // Update status
-        let status = ChimeStatus {
-            chime_id: self.info.id.clone(),
-            online: true,
-            mode: self.lcgp_node.get_mode(),
-            last_seen: chrono::Utc::now(),
-            node_id: self.lcgp_node.node_id.clone(),
-        };
+        self.publish_status().await
+    }
 
-        self.mqtt
-            .lock()
-            .await
-            .publish_chime_status(&self.info.id, &status)
-            .await?;
+    // Switches to `mode` until `until`, then back to the mode from before (see
+    // `LcgpNode::set_mode_until`). A later `set_mode` cancels the revert.
+    pub async fn set_mode_until(
+        &self,
+        mode: LcgpMode,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        self.lcgp_node.set_mode_until(mode, until);
+        self.lcgp_handler.publish_mode_update();
 
-        Ok(())
+        let chime = self.clone();
+        let revert = tokio::spawn(async move {
+            let delay = (until - chrono::Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(delay).await;
+
+            let Some(previous) = chime.lcgp_node.expire_mode(chrono::Utc::now()) else {
+                return;
+            };
+            log::info!("Timed mode is over, back to {:?}", previous);
+            chime.lcgp_handler.publish_mode_update();
+            if let Err(e) = chime.publish_status().await {
+                log::error!("Failed to publish status after timed mode: {}", e);
+            }
+        });
+        if let Some(previous) = self.mode_revert.lock().unwrap().replace(revert) {
+            previous.abort();
+        }
+
+        self.publish_status().await
     }
 
     pub async fn ring_other_chime(
         &self,
-        user: &str,
-        chime_id: &str,
+        user: &UserId,
+        chime_id: &ChimeId,
         notes: Option<Vec<String>>,
         chords: Option<Vec<String>>,
         duration_ms: Option<u64>,
     ) -> Result<()> {
-        log::info!("Attempting to ring chime {} for user {}", chime_id, user);
+        let qos = self.mqtt.lock().await.ring_qos();
+        self.ring_other_chime_with_qos(user, chime_id, notes, chords, duration_ms, qos)
+            .await
+    }
+
+    // Like `ring_other_chime` with an explicit MQTT QoS. Use 0 for casual,
+    // high-volume pings where losing one is fine, 1 or 2 for rings that matter.
+    pub async fn ring_other_chime_with_qos(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        notes: Option<Vec<String>>,
+        chords: Option<Vec<String>>,
+        duration_ms: Option<u64>,
+        qos: i32,
+    ) -> Result<()> {
+        log::info!(
+            "Attempting to ring chime {} for user {} (QoS {})",
+            chime_id,
+            user,
+            qos
+        );
+
+        let ring_request = new_ring_request(user, chime_id, notes, chords, duration_ms);
+        self.ring_following_snoozes(ring_request, qos).await
+    }
+
+    // Sends `ring_request` and, when the answer is a snooze, rings again once
+    // it's up. Follow-ups ring with the same notes, each under a new request_id.
+    async fn ring_following_snoozes(&self, ring_request: ChimeRingRequest, qos: i32) -> Result<()> {
+        let Some(answer) = self.send_ring(&ring_request, qos).await? else {
+            return Ok(());
+        };
 
-        let ring_request = ChimeRingRequest {
-            chime_id: chime_id.to_string(),
-            user: user.to_string(),
+        let chime = self.clone();
+        let user = UserId::from(ring_request.user.as_str());
+        let chime_id = ChimeId::from(ring_request.chime_id.as_str());
+        let ChimeRingRequest {
             notes,
             chords,
             duration_ms,
-            timestamp: chrono::Utc::now(),
+            ..
+        } = ring_request;
+        let follow_up = tokio::spawn(follow_snoozes(answer, move || {
+            let (chime, user, chime_id) = (chime.clone(), user.clone(), chime_id.clone());
+            let (notes, chords) = (notes.clone(), chords.clone());
+            async move {
+                let ring_request = new_ring_request(&user, &chime_id, notes, chords, duration_ms);
+                match chime.send_ring(&ring_request, qos).await {
+                    Ok(answer) => answer,
+                    Err(e) => {
+                        log::error!(
+                            "Failed to ring {}/{} after its snooze: {}",
+                            user,
+                            chime_id,
+                            e
+                        );
+                        None
+                    }
+                }
+            }
+        }));
+
+        let mut follow_ups = self.snooze_follow_ups.lock().unwrap();
+        follow_ups.retain(|task| !task.is_finished());
+        follow_ups.push(follow_up);
+        Ok(())
+    }
+
+    // Publishes `ring_request` and returns the wait for the answer to it, None
+    // when that answer can't be watched for
+    async fn send_ring(
+        &self,
+        ring_request: &ChimeRingRequest,
+        qos: i32,
+    ) -> Result<Option<NextMessage>> {
+        let user = UserId::from(ring_request.user.as_str());
+        let chime_id = ChimeId::from(ring_request.chime_id.as_str());
+        let request_id = ring_request.request_id.as_deref().unwrap_or_default();
+
+        let mqtt = self.mqtt.lock().await;
+        let answer = match mqtt.response_to(&user, &chime_id, request_id).await {
+            Ok(answer) => Some(answer),
+            Err(e) => {
+                log::debug!("Not watching {}/{} for a snooze: {}", user, chime_id, e);
+                None
+            }
         };
 
         // CRITICAL FIX: Use publish_chime_ring_to_user to publish to the target user's topic
-        match self
-            .mqtt
-            .lock()
-            .await
-            .publish_chime_ring_to_user(user, chime_id, &ring_request)
+        match mqtt
+            .publish_chime_ring_to_user_with_qos(&user, &chime_id, ring_request, qos)
             .await
         {
             Ok(()) => {
@@ -286,7 +1108,7 @@ impl ChimeInstance {
                     user,
                     chime_id
                 );
-                Ok(())
+                Ok(answer)
             }
             Err(e) => {
                 log::error!(
@@ -300,16 +1122,217 @@ impl ChimeInstance {
         }
     }
 
+    // Rings another chime and waits up to `timeout` for its answer to this very
+    // ring, matched by request_id, so answers to other ringers are passed over.
+    // Timing out is a Timeout error: the ring may not have arrived, or
+    // nobody answered (e.g. in DoNotDisturb without an away message).
+    pub async fn ring_other_chime_awaiting(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        notes: Option<Vec<String>>,
+        chords: Option<Vec<String>>,
+        timeout: Duration,
+    ) -> Result<ChimeResponseMessage> {
+        let ring_request = new_ring_request(user, chime_id, notes, chords, None);
+        let request_id = ring_request.request_id.as_deref().unwrap_or_default();
+
+        // Listening before ringing, so a quick response can't slip past. The
+        // lock is released before waiting.
+        let response = {
+            let mqtt = self.mqtt.lock().await;
+            let response = mqtt.response_to(user, chime_id, request_id).await?;
+            mqtt.publish_chime_ring_to_user(user, chime_id, &ring_request)
+                .await?;
+            response
+        };
+
+        let message = tokio::time::timeout(timeout, response)
+            .await
+            .map_err(|_| {
+                ChimeNetError::Timeout(format!(
+                    "No response from {}/{} within {:?}",
+                    user, chime_id, timeout
+                ))
+            })??;
+        Ok(serde_json::from_str(&message.payload)?)
+    }
+
+    // Rings another chime and switches to `temp_mode` while awaiting the reply.
+    // The previous mode is restored when the target responds or after
+    // `revert_after`, unless the mode was changed by other means meanwhile.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn ring_with_auto_mode(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        notes: Option<Vec<String>>,
+        chords: Option<Vec<String>>,
+        temp_mode: LcgpMode,
+        revert_after: std::time::Duration,
+    ) -> Result<()> {
+        let previous = self.lcgp_node.get_mode();
+        let ring_request = new_ring_request(user, chime_id, notes, chords, None);
+        let request_id = ring_request.request_id.as_deref().unwrap_or_default();
+
+        // Waiting before ringing, so a quick response can't slip past. Only the
+        // answer to this ring counts, not one another ringer got.
+        let (responded, qos) = {
+            let mqtt = self.mqtt.lock().await;
+            (
+                mqtt.response_to(user, chime_id, request_id).await?,
+                mqtt.ring_qos(),
+            )
+        };
+
+        self.set_mode(temp_mode.clone()).await?;
+
+        if let Err(e) = self.ring_following_snoozes(ring_request, qos).await {
+            self.end_auto_mode(&temp_mode, previous).await;
+            return Err(e);
+        }
+
+        let chime = self.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(revert_after, responded).await {
+                Ok(_) => log::info!("Response received, ending auto mode"),
+                Err(_) => log::info!("No response within {:?}, ending auto mode", revert_after),
+            }
+            chime.end_auto_mode(&temp_mode, previous).await;
+        });
+
+        Ok(())
+    }
+
+    async fn end_auto_mode(&self, temp_mode: &LcgpMode, previous: LcgpMode) {
+        if !self
+            .lcgp_node
+            .compare_and_set_mode(temp_mode, previous.clone())
+        {
+            log::info!("Mode changed while awaiting a response, not reverting");
+            return;
+        }
+
+        log::info!("Reverted to {:?} after auto mode", previous);
+        if let Err(e) = self.publish_status().await {
+            log::error!("Failed to publish status after auto mode: {}", e);
+        }
+    }
+
+    // Rings another chime at `at`; returns the id of the scheduled ring
+    pub async fn schedule_ring(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        at: chrono::DateTime<chrono::Utc>,
+        notes: Option<Vec<String>>,
+        chords: Option<Vec<String>>,
+    ) -> String {
+        let ring = ScheduledRing {
+            id: Uuid::new_v4().to_string(),
+            user: user.clone(),
+            chime_id: chime_id.clone(),
+            at,
+            notes,
+            chords,
+        };
+
+        // Hold the lock while spawning so a ring that is already due can't
+        // try to remove itself before it has been inserted
+        let mut scheduled = self.scheduled_rings.lock().await;
+
+        let chime = self.clone();
+        let pending = ring.clone();
+        let handle = tokio::spawn(async move {
+            let delay = (pending.at - chrono::Utc::now())
+                .to_std()
+                .unwrap_or_default();
+            tokio::time::sleep(delay).await;
+
+            log::info!(
+                "Firing scheduled ring {} to {}/{}",
+                pending.id,
+                pending.user,
+                pending.chime_id
+            );
+            if let Err(e) = chime
+                .ring_other_chime(
+                    &pending.user,
+                    &pending.chime_id,
+                    pending.notes.clone(),
+                    pending.chords.clone(),
+                    None,
+                )
+                .await
+            {
+                log::error!("Scheduled ring {} failed: {}", pending.id, e);
+            }
+
+            chime.scheduled_rings.lock().await.remove(&pending.id);
+        });
+
+        log::info!(
+            "Scheduled ring {} to {}/{} at {}",
+            ring.id,
+            user,
+            chime_id,
+            at
+        );
+        let id = ring.id.clone();
+        scheduled.insert(id.clone(), (ring, handle));
+        id
+    }
+
+    // Returns false if no pending ring has this id
+    pub async fn cancel_scheduled(&self, id: &str) -> bool {
+        match self.scheduled_rings.lock().await.remove(id) {
+            Some((_, handle)) => {
+                handle.abort();
+                log::info!("Cancelled scheduled ring {}", id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Pending rings, soonest first
+    pub async fn list_scheduled(&self) -> Vec<ScheduledRing> {
+        let mut rings: Vec<ScheduledRing> = self
+            .scheduled_rings
+            .lock()
+            .await
+            .values()
+            .map(|(ring, _)| ring.clone())
+            .collect();
+        rings.sort_by_key(|ring| ring.at);
+        rings
+    }
+
     pub async fn respond_to_chime(
         &self,
         response: ChimeResponse,
-        original_chime_id: Option<String>,
+        original_chime_id: Option<ChimeId>,
+    ) -> Result<()> {
+        self.respond_to_chime_with_message(response, original_chime_id, None)
+            .await
+    }
+
+    // Like `respond_to_chime`, telling the ringer why, e.g. "busy until 3".
+    // Without a message, a custom behavior's note (if any) is sent instead.
+    pub async fn respond_to_chime_with_message(
+        &self,
+        response: ChimeResponse,
+        original_chime_id: Option<ChimeId>,
+        message: Option<String>,
     ) -> Result<()> {
         let response_msg = self
             .lcgp_handler
-            .handle_user_response(response, original_chime_id.clone());
+            .handle_user_response(response, original_chime_id.clone().map(String::from));
 
-        if let Some(response_msg) = response_msg {
+        if let Some(mut response_msg) = response_msg {
+            if message.is_some() {
+                response_msg.message = message;
+            }
             if let Some(chime_id) = &original_chime_id {
                 self.mqtt
                     .lock()
@@ -322,22 +1345,140 @@ impl ChimeInstance {
         Ok(())
     }
 
-    pub async fn shutdown(&self) -> Result<()> {
-        // Update status to offline
-        let status = ChimeStatus {
-            chime_id: self.info.id.clone(),
-            online: false,
-            mode: self.lcgp_node.get_mode(),
-            last_seen: chrono::Utc::now(),
-            node_id: self.lcgp_node.node_id.clone(),
-        };
+    // Responds to one ring from `pending_rings()` by position, for triaging
+    // several rings that arrived before the user got to them
+    pub async fn respond_to_pending(
+        &self,
+        index: usize,
+        response: ChimeResponse,
+        message: Option<String>,
+    ) -> Result<PendingRing> {
+        let (ring, mut response_msg) =
+            self.lcgp_handler
+                .handle_pending_response(index, response)
+                .ok_or_else(|| format!("No pending ring at position {}", index))?;
+        if message.is_some() {
+            response_msg.message = message;
+        }
 
         self.mqtt
             .lock()
             .await
-            .publish_chime_status(&self.info.id, &status)
+            .publish_chime_response(&ChimeId::from(ring.chime_id.as_str()), &response_msg)
             .await?;
 
+        Ok(ring)
+    }
+
+    pub fn pending_rings(&self) -> Vec<PendingRing> {
+        self.lcgp_handler.pending_rings()
+    }
+
+    // Drops a pending ring and its delayed auto-response, if any
+    pub fn cancel_pending_response(&self, chime_id: &str) -> bool {
+        self.lcgp_handler.cancel_pending(chime_id)
+    }
+
+    pub async fn snapshot(&self) -> ChimeSnapshot {
+        let status = self.current_status(true);
+        ChimeSnapshot {
+            info: self.info.clone(),
+            user: self.mqtt.lock().await.user().to_string(),
+            node_id: status.node_id,
+            mode: status.mode,
+            online: self.mqtt.lock().await.is_connected(),
+            following: self
+                .following()
+                .map(|(user, chime_id)| format!("{}/{}", user, chime_id)),
+            status_message: status.status_message,
+            pending_responses: self.pending_rings().len(),
+            recent_rings: self.lcgp_node.ring_summary(),
+            responses: self.lcgp_node.response_stats(),
+            taken_at: chrono::Utc::now(),
+        }
+    }
+
+    // Recovers from a wedged state without reconnecting: drops pending rings,
+    // cancels delayed and scheduled work, silences audio and re-announces status
+    pub async fn reset(&self) -> Result<ResetSummary> {
+        let (pending_rings, delayed_responses) = self.lcgp_handler.reset();
+
+        let scheduled_rings = {
+            let mut scheduled = self.scheduled_rings.lock().await;
+            for (_, handle) in scheduled.values() {
+                handle.abort();
+            }
+            let count = scheduled.len();
+            scheduled.clear();
+
+            // Rings waiting to follow a snooze count as scheduled too
+            let follow_ups = self
+                .snooze_follow_ups
+                .lock()
+                .unwrap()
+                .drain(..)
+                .filter(|task| !task.is_finished())
+                .inspect(|task| task.abort())
+                .count();
+            count + follow_ups
+        };
+
+        self.ring_cooldown.lock().unwrap().reset();
+        self.player.stop();
+
+        self.publish_status().await?;
+
+        let summary = ResetSummary {
+            pending_rings,
+            delayed_responses,
+            scheduled_rings,
+        };
+        log::info!("Chime instance '{}' reset: {:?}", self.info.name, summary);
+        Ok(summary)
+    }
+
+    // Removes this chime's retained list, notes, chords and status from the
+    // broker (an empty retained payload clears retention), so discovery
+    // clients that connect later don't see it at all
+    pub async fn purge_retained(&self) -> Result<()> {
+        self.mqtt
+            .lock()
+            .await
+            .clear_chime_retained(&self.chime_id())
+            .await
+    }
+
+    // Leaves a retained offline status behind, for a chime that comes back
+    pub async fn shutdown(&self) -> Result<()> {
+        self.shut_down(false).await
+    }
+
+    // Shuts down for good, purging the retained topics instead
+    pub async fn shutdown_and_purge(&self) -> Result<()> {
+        self.shut_down(true).await
+    }
+
+    async fn shut_down(&self, purge: bool) -> Result<()> {
+        if let Some(timer) = self.heartbeat_timer.lock().unwrap().take() {
+            timer.abort();
+        }
+        if let Some(revert) = self.mode_revert.lock().unwrap().take() {
+            revert.abort();
+        }
+
+        if purge {
+            self.purge_retained().await?;
+        } else {
+            // Update status to offline
+            let status = self.current_status(false);
+
+            self.mqtt
+                .lock()
+                .await
+                .publish_chime_status(&self.chime_id(), &status)
+                .await?;
+        }
+
         // Disconnect from MQTT
         self.mqtt.lock().await.disconnect().await?;
 
@@ -347,12 +1488,13 @@ impl ChimeInstance {
 }
 
 pub struct ChimeManager {
-    chimes: Arc<Mutex<HashMap<String, ChimeInstance>>>,
+    chimes: Arc<Mutex<HashMap<ChimeId, ChimeInstance>>>,
+    #[allow(dead_code)]
     mqtt: Arc<Mutex<ChimeNetMqtt>>,
 }
 
 impl ChimeManager {
-    pub async fn new(user: &str, mqtt_broker: &str) -> Result<Self> {
+    pub async fn new(user: &UserId, mqtt_broker: &str) -> Result<Self> {
         let client_id = format!("chime_manager_{}", user);
         let mqtt = Arc::new(Mutex::new(
             ChimeNetMqtt::new(mqtt_broker, user, &client_id).await?,
@@ -365,7 +1507,7 @@ impl ChimeManager {
     }
 
     pub async fn add_chime(&self, chime: ChimeInstance) -> Result<()> {
-        let chime_id = chime.info.id.clone();
+        let chime_id = chime.chime_id();
         chime.start().await?;
 
         self.chimes.lock().await.insert(chime_id, chime);
@@ -373,9 +1515,31 @@ impl ChimeManager {
         Ok(())
     }
 
-    pub async fn remove_chime(&self, chime_id: &str) -> Result<()> {
-        if let Some(chime) = self.chimes.lock().await.remove(chime_id) {
-            chime.shutdown().await?;
+    // Purging clears the user's whole list, so the chimes of that user that
+    // remain publish it again without the removed one
+    pub async fn remove_chime(&self, chime_id: &ChimeId) -> Result<()> {
+        let chimes = self.chimes.lock().await.remove(chime_id);
+        let Some(chime) = chimes else {
+            return Ok(());
+        };
+        let user = chime.mqtt.lock().await.user().clone();
+        chime.shutdown_and_purge().await?;
+
+        let chimes = self.chimes.lock().await;
+        let mut remaining = Vec::new();
+        for other in chimes.values() {
+            if *other.mqtt.lock().await.user() == user {
+                remaining.push(other);
+            }
+        }
+        if let Some(publisher) = remaining.first() {
+            let infos: Vec<ChimeInfo> = remaining.iter().map(|c| c.info.clone()).collect();
+            publisher
+                .mqtt
+                .lock()
+                .await
+                .publish_chime_list(&infos)
+                .await?;
         }
 
         Ok(())
@@ -386,7 +1550,7 @@ impl ChimeManager {
         chimes.values().map(|chime| chime.info.clone()).collect()
     }
 
-    pub async fn set_chime_mode(&self, chime_id: &str, mode: LcgpMode) -> Result<()> {
+    pub async fn set_chime_mode(&self, chime_id: &ChimeId, mode: LcgpMode) -> Result<()> {
         let chimes = self.chimes.lock().await;
         if let Some(chime) = chimes.get(chime_id) {
             chime.set_mode(mode).await?;
@@ -395,29 +1559,30 @@ impl ChimeManager {
         Ok(())
     }
 
+    // Rings `chime_id` of `user` from the managed chime `from_chime_id`
     pub async fn ring_chime(
         &self,
-        user: &str,
-        chime_id: &str,
+        from_chime_id: &ChimeId,
+        user: &UserId,
+        chime_id: &ChimeId,
         notes: Option<Vec<String>>,
         chords: Option<Vec<String>>,
         duration_ms: Option<u64>,
     ) -> Result<()> {
         let chimes = self.chimes.lock().await;
-        if let Some(chime) = chimes.values().next() {
-            chime
-                .ring_other_chime(user, chime_id, notes, chords, duration_ms)
-                .await?;
-        }
-
-        Ok(())
+        let chime = chimes.get(from_chime_id).ok_or_else(|| {
+            ChimeNetError::Config(format!("Chime {} is not managed here", from_chime_id))
+        })?;
+        chime
+            .ring_other_chime(user, chime_id, notes, chords, duration_ms)
+            .await
     }
 
     pub async fn respond_to_chime(
         &self,
-        chime_id: &str,
+        chime_id: &ChimeId,
         response: ChimeResponse,
-        original_chime_id: Option<String>,
+        original_chime_id: Option<ChimeId>,
     ) -> Result<()> {
         let chimes = self.chimes.lock().await;
         if let Some(chime) = chimes.get(chime_id) {
@@ -427,12 +1592,403 @@ impl ChimeManager {
         Ok(())
     }
 
+    // Shuts down every chime, even if some fail, so none is left advertised as online
     pub async fn shutdown(&self) -> Result<()> {
         let chimes = self.chimes.lock().await;
+        let mut errors = Vec::new();
+
         for chime in chimes.values() {
-            chime.shutdown().await?;
+            if let Err(e) = chime.shutdown().await {
+                log::error!("Failed to shut down chime '{}': {}", chime.info.name, e);
+                errors.push(format!("{} ({}): {}", chime.info.name, chime.info.id, e));
+            }
         }
 
-        Ok(())
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Failed to shut down {} of {} chimes: {}",
+                errors.len(),
+                chimes.len(),
+                errors.join("; ")
+            )
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::testing::TestBus;
+
+    #[tokio::test]
+    async fn manager_rings_from_the_requested_chime() {
+        let user = UserId::from("alice");
+        let bus = TestBus::new();
+        let manager = ChimeManager::new(&user, "tcp://localhost:1883")
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for name in ["kitchen", "office"] {
+            let transport = bus.client(name);
+            let chime = ChimeInstance::with_transport(
+                name.to_string(),
+                None,
+                vec!["C4".to_string()],
+                vec![],
+                user.clone(),
+                Box::new(transport),
+            )
+            .unwrap();
+            ids.push(chime.chime_id());
+            manager.chimes.lock().await.insert(chime.chime_id(), chime);
+        }
+
+        let target = ChimeId::from("door");
+        let bob = UserId::from("bob");
+        for (from, name) in ids.iter().zip(["kitchen", "office"]) {
+            bus.clear();
+            manager
+                .ring_chime(from, &bob, &target, None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                bus.published(),
+                vec![(name, "/bob/chime/door/ring".to_string())]
+            );
+        }
+
+        bus.clear();
+        let missing = ChimeId::from("missing");
+        assert!(manager
+            .ring_chime(&missing, &bob, &target, None, None, None)
+            .await
+            .is_err());
+        assert!(bus.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn remote_mode_requests_apply_only_while_allowed() {
+        let bus = TestBus::new();
+        let chime = ChimeInstance::with_transport(
+            "desk".to_string(),
+            None,
+            vec!["C4".to_string()],
+            vec![],
+            UserId::from("alice"),
+            Box::new(bus.client("desk")),
+        )
+        .unwrap();
+        let request = |mode: LcgpMode| {
+            serde_json::to_string(&ModeChangeRequest {
+                chime_id: chime.info.id.clone(),
+                requested_mode: mode,
+                requester_id: "bob".to_string(),
+                timestamp: chrono::Utc::now(),
+            })
+            .unwrap()
+        };
+        let chime_id = chime.info.id.as_str();
+
+        chime
+            .handle_mode_request(&request(LcgpMode::Grinding))
+            .await;
+        tokio::task::yield_now().await;
+        assert_eq!(chime.lcgp_node.get_mode(), LcgpMode::Grinding);
+        let topics: Vec<String> = bus
+            .published()
+            .into_iter()
+            .map(|(_, topic)| topic)
+            .collect();
+        assert!(topics.contains(&TopicBuilder::chime_mode("alice", chime_id)));
+        assert!(topics.contains(&TopicBuilder::chime_status("alice", chime_id)));
+
+        bus.clear();
+        chime.set_allow_remote_mode(false);
+        chime
+            .handle_mode_request(&request(LcgpMode::DoNotDisturb))
+            .await;
+        tokio::task::yield_now().await;
+        assert_eq!(chime.lcgp_node.get_mode(), LcgpMode::Grinding);
+        assert!(bus.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn removed_chimes_purge_their_retained_topics() {
+        let user = UserId::from("alice");
+        let bus = TestBus::new();
+        let manager = ChimeManager::new(&user, "tcp://localhost:1883")
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for name in ["kitchen", "office"] {
+            let transport = bus.client(name);
+            let chime = ChimeInstance::with_transport(
+                name.to_string(),
+                None,
+                vec!["C4".to_string()],
+                vec![],
+                user.clone(),
+                Box::new(transport),
+            )
+            .unwrap();
+            ids.push(chime.chime_id());
+            manager.chimes.lock().await.insert(chime.chime_id(), chime);
+        }
+
+        manager.remove_chime(&ids[0]).await.unwrap();
+        let (kitchen, office) = (ids[0].as_str(), ids[1].as_str());
+        let topic = |name, topic: String| (name, topic);
+        assert_eq!(
+            bus.published(),
+            vec![
+                topic("kitchen", TopicBuilder::chime_list("alice")),
+                topic("kitchen", TopicBuilder::chime_notes("alice", kitchen)),
+                topic("kitchen", TopicBuilder::chime_chords("alice", kitchen)),
+                topic("kitchen", TopicBuilder::chime_status("alice", kitchen)),
+                topic("office", TopicBuilder::chime_list("alice")),
+                topic("office", TopicBuilder::chime_notes("alice", office)),
+                topic("office", TopicBuilder::chime_chords("alice", office)),
+            ]
+        );
+        // An empty retained payload is what clears retention
+        let purged = bus.publishes().into_iter().rfind(|p| p.client == "kitchen");
+        let purged = purged.unwrap();
+        assert!(purged.retain && purged.payload.is_empty());
+        assert_eq!(manager.get_chime_list().await.len(), 1);
+
+        // Removing it again is a no-op
+        bus.clear();
+        manager.remove_chime(&ids[0]).await.unwrap();
+        assert!(bus.published().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn snoozed_rings_are_rung_again_a_limited_number_of_times() {
+        let bus = TestBus::new();
+        let transport = bus.client("kitchen");
+        let chime = ChimeInstance::with_transport(
+            "kitchen".to_string(),
+            None,
+            vec!["C4".to_string()],
+            vec![],
+            UserId::from("alice"),
+            Box::new(transport),
+        )
+        .unwrap();
+        let snooze = |in_reply_to: Option<String>| {
+            let reply = ChimeResponseMessage {
+                timestamp: chrono::Utc::now(),
+                response: ChimeResponse::Snooze { minutes: 1 },
+                node_id: "bob_door".to_string(),
+                original_chime_id: Some("door".to_string()),
+                message: None,
+                in_reply_to,
+            };
+            bus.deliver(
+                "/bob/chime/door/response",
+                &serde_json::to_string(&reply).unwrap(),
+            );
+        };
+        let last_request_id = || {
+            let ring: ChimeRingRequest =
+                serde_json::from_slice(&bus.last_publish().unwrap().payload).unwrap();
+            ring.request_id
+        };
+
+        let (bob, door) = (UserId::from("bob"), ChimeId::from("door"));
+        chime
+            .ring_other_chime(&bob, &door, Some(vec!["C4".to_string()]), None, None)
+            .await
+            .unwrap();
+        assert_eq!(bus.published().len(), 1);
+
+        // Snoozes answering other ringers' rings are not followed
+        snooze(None);
+        snooze(Some("someone else's ring".to_string()));
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(bus.published().len(), 1);
+
+        // A target that snoozes every ring is rung again only so many times
+        for rings in 2..=MAX_SNOOZE_FOLLOW_UPS as usize + 1 {
+            snooze(last_request_id());
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            assert_eq!(bus.published().len(), rings - 1);
+            tokio::time::sleep(Duration::from_secs(31)).await;
+            assert_eq!(bus.published().len(), rings);
+        }
+        snooze(last_request_id());
+        tokio::time::sleep(Duration::from_secs(600)).await;
+        assert_eq!(
+            bus.published(),
+            vec![
+                ("kitchen", "/bob/chime/door/ring".to_string());
+                MAX_SNOOZE_FOLLOW_UPS as usize + 1
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_republishes_status_on_its_interval() {
+        let bus = TestBus::new();
+        let chime = ChimeInstance::with_transport(
+            "kitchen".to_string(),
+            None,
+            vec!["C4".to_string()],
+            vec![],
+            UserId::from("alice"),
+            Box::new(bus.client("kitchen")),
+        )
+        .unwrap();
+        let heartbeat = HeartbeatConfig {
+            interval: Duration::from_secs(10),
+            staleness_factor: 3,
+        };
+        chime.set_heartbeat(heartbeat).unwrap();
+        let nonstop = HeartbeatConfig {
+            interval: Duration::ZERO,
+            ..heartbeat
+        };
+        let error = chime.set_heartbeat(nonstop).unwrap_err();
+        assert_eq!(error.kind(), Some("config"));
+        assert_eq!(chime.heartbeat(), heartbeat);
+        assert_eq!(heartbeat.stale_after(), Duration::from_secs(30));
+        assert_eq!(
+            crate::discovery::DiscoveryConfig::for_heartbeat(heartbeat).stale_after,
+            Duration::from_secs(30)
+        );
+
+        chime.start().await.unwrap();
+        let status_topic = TopicBuilder::chime_status("alice", chime.info.id.as_str());
+        let statuses = || {
+            bus.published()
+                .iter()
+                .filter(|(_, topic)| *topic == status_topic)
+                .count()
+        };
+        let at_start = statuses();
+
+        tokio::time::sleep(Duration::from_secs(25)).await;
+        assert_eq!(statuses(), at_start + 2);
+
+        chime.shutdown().await.unwrap();
+        let after_shutdown = statuses();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(statuses(), after_shutdown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn awaited_rings_take_only_the_answer_to_them() {
+        let bus = TestBus::new();
+        let transport = bus.client("kitchen");
+        let chime = ChimeInstance::with_transport(
+            "kitchen".to_string(),
+            None,
+            vec!["C4".to_string()],
+            vec![],
+            UserId::from("alice"),
+            Box::new(transport),
+        )
+        .unwrap();
+        let (bob, door) = (UserId::from("bob"), ChimeId::from("door"));
+
+        let ringing = {
+            let (chime, bob, door) = (chime.clone(), bob.clone(), door.clone());
+            tokio::spawn(async move {
+                chime
+                    .ring_other_chime_awaiting(&bob, &door, None, None, Duration::from_secs(5))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let ring: ChimeRingRequest =
+            serde_json::from_slice(&bus.last_publish().unwrap().payload).unwrap();
+
+        for (in_reply_to, response) in [
+            ("someone else's ring", ChimeResponse::Negative),
+            (ring.request_id.as_deref().unwrap(), ChimeResponse::Positive),
+        ] {
+            let reply = ChimeResponseMessage {
+                timestamp: chrono::Utc::now(),
+                response,
+                node_id: "bob_door".to_string(),
+                original_chime_id: Some("door".to_string()),
+                message: None,
+                in_reply_to: Some(in_reply_to.to_string()),
+            };
+            bus.deliver(
+                "/bob/chime/door/response",
+                &serde_json::to_string(&reply).unwrap(),
+            );
+        }
+        let response = ringing.await.unwrap().unwrap();
+        assert_eq!(response.response, ChimeResponse::Positive);
+        assert_eq!(response.in_reply_to, ring.request_id);
+
+        let started = tokio::time::Instant::now();
+        let unanswered = chime
+            .ring_other_chime_awaiting(&bob, &door, None, None, Duration::from_secs(2))
+            .await;
+        assert!(unanswered.is_err());
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn auto_mode_ends_only_on_the_answer_to_its_ring() {
+        let bus = TestBus::new();
+        let chime = ChimeInstance::with_transport(
+            "kitchen".to_string(),
+            None,
+            vec!["C4".to_string()],
+            vec![],
+            UserId::from("alice"),
+            Box::new(bus.client("kitchen")),
+        )
+        .unwrap();
+        let previous = chime.lcgp_node.get_mode();
+        let reply = |in_reply_to: &str| {
+            let reply = ChimeResponseMessage {
+                timestamp: chrono::Utc::now(),
+                response: ChimeResponse::Positive,
+                node_id: "bob_door".to_string(),
+                original_chime_id: Some("door".to_string()),
+                message: None,
+                in_reply_to: Some(in_reply_to.to_string()),
+            };
+            bus.deliver(
+                "/bob/chime/door/response",
+                &serde_json::to_string(&reply).unwrap(),
+            );
+        };
+
+        chime
+            .ring_with_auto_mode(
+                &UserId::from("bob"),
+                &ChimeId::from("door"),
+                None,
+                None,
+                LcgpMode::DoNotDisturb,
+                Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+        let ring = bus
+            .publishes()
+            .into_iter()
+            .find(|published| published.topic == "/bob/chime/door/ring")
+            .unwrap();
+        let ring: ChimeRingRequest = serde_json::from_slice(&ring.payload).unwrap();
+
+        reply("someone else's ring");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(chime.lcgp_node.get_mode(), LcgpMode::DoNotDisturb);
+
+        reply(ring.request_id.as_deref().unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(chime.lcgp_node.get_mode(), previous);
     }
 }
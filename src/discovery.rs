@@ -0,0 +1,1336 @@
+use crate::mqtt::ChimeNetMqtt;
+use crate::transport::SubscriptionId;
+use crate::types::*;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredChime {
+    pub user: String,
+    pub chime_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub notes: Vec<String>,
+    pub chords: Vec<String>,
+    pub online: bool,
+    pub mode: LcgpMode,
+    pub last_seen: chrono::DateTime<chrono::Utc>,
+    pub ring_rate_per_hour: f64,        // From the latest status
+    pub status_message: Option<String>, // From the latest status
+}
+
+impl DiscoveredChime {
+    // A handful of realistic chimes across several users, for demos and UI work
+    pub fn sample_set() -> Vec<DiscoveredChime> {
+        let sample = |user: &str,
+                      chime_id: &str,
+                      name: &str,
+                      mode: LcgpMode,
+                      online: bool,
+                      ring_rate_per_hour: f64| {
+            DiscoveredChime {
+                user: user.to_string(),
+                chime_id: chime_id.to_string(),
+                name: name.to_string(),
+                description: Some(format!("{}'s {}", user, name.to_lowercase())),
+                notes: vec!["C4".to_string(), "E4".to_string(), "G4".to_string()],
+                chords: vec!["C".to_string(), "Am".to_string()],
+                online,
+                mode,
+                last_seen: chrono::Utc::now(),
+                ring_rate_per_hour,
+                status_message: None,
+            }
+        };
+
+        vec![
+            sample(
+                "alice",
+                "demo-alice-office",
+                "Office Chime",
+                LcgpMode::Available,
+                true,
+                1.5,
+            ),
+            sample(
+                "alice",
+                "demo-alice-kitchen",
+                "Kitchen",
+                LcgpMode::DoNotDisturb,
+                true,
+                0.0,
+            ),
+            sample(
+                "bob",
+                "demo-bob-desk",
+                "Desk Bell",
+                LcgpMode::Grinding,
+                true,
+                12.0,
+            ),
+            sample(
+                "carol",
+                "demo-carol-studio",
+                "Studio",
+                LcgpMode::ChillGrinding,
+                true,
+                3.0,
+            ),
+            sample(
+                "dave",
+                "demo-dave-door",
+                "Front Door",
+                LcgpMode::Custom("Meeting".to_string()),
+                false,
+                0.0,
+            ),
+        ]
+    }
+}
+
+// Discovered chimes keyed by "{user}/{chime_id}" (see `discovery_key`). A
+// chime_id is only unique per user, so lookups must always include the user.
+pub type DiscoveredChimes = Arc<RwLock<HashMap<String, DiscoveredChime>>>;
+
+type ChimeRemovedHandler = Box<dyn Fn(&DiscoveredChime) + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    // How often stale chimes are pruned
+    pub cleanup_interval: Duration,
+    // How long a chime may go unseen before it is pruned
+    pub stale_after: Duration,
+}
+
+// How often a chime re-publishes its status, and how many of those beats a
+// discovery client lets it miss before treating it as gone. Short intervals
+// notice departures sooner, long ones save bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub staleness_factor: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            staleness_factor: 5,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn stale_after(&self) -> Duration {
+        self.interval * self.staleness_factor.max(1)
+    }
+
+    // A zero interval would republish status nonstop and make every chime
+    // stale at once
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.interval.is_zero() {
+            return Err(ChimeNetError::Config(
+                "Heartbeat interval must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl DiscoveryConfig {
+    // A zero cleanup interval can't be ticked on
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.cleanup_interval.is_zero() {
+            return Err(ChimeNetError::Config(
+                "Discovery cleanup interval must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Prunes chimes that missed `staleness_factor` heartbeats
+    pub fn for_heartbeat(heartbeat: HeartbeatConfig) -> Self {
+        Self {
+            stale_after: heartbeat.stale_after(),
+            ..Self::default()
+        }
+    }
+}
+
+pub fn discovery_key(user: &str, chime_id: &str) -> String {
+    format!("{}/{}", user, chime_id)
+}
+
+// How `pick_random` spreads picks across candidates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PickWeighting {
+    Uniform,
+    #[default]
+    InverseActivity, // Chimes ringing often are picked less, so busy people aren't bugged again
+}
+
+impl PickWeighting {
+    pub fn weight(&self, ring_rate_per_hour: f64) -> f64 {
+        match self {
+            PickWeighting::Uniform => 1.0,
+            PickWeighting::InverseActivity => 1.0 / (1.0 + ring_rate_per_hour.max(0.0)),
+        }
+    }
+}
+
+impl std::str::FromStr for PickWeighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uniform" => Ok(PickWeighting::Uniform),
+            "activity" | "inverse-activity" => Ok(PickWeighting::InverseActivity),
+            _ => Err(format!(
+                "Unknown weighting '{}' (expected uniform or activity)",
+                s
+            )),
+        }
+    }
+}
+
+// A random candidate, weighted by `weighting` on each one's ring rate. Give
+// the candidates in a stable order, so the same roll always picks the same one.
+pub fn pick_weighted<T>(
+    mut candidates: Vec<T>,
+    weighting: PickWeighting,
+    ring_rate_per_hour: impl Fn(&T) -> f64,
+) -> Option<T> {
+    let weights: Vec<f64> = candidates
+        .iter()
+        .map(|candidate| weighting.weight(ring_rate_per_hour(candidate)))
+        .collect();
+    let index = weighted_index(&weights, fastrand::f64())?;
+    Some(candidates.swap_remove(index))
+}
+
+// Index chosen with probability proportional to its weight, `roll` being a
+// uniform draw from [0, 1). None if nothing has a positive weight.
+pub fn weighted_index(weights: &[f64], roll: f64) -> Option<usize> {
+    let total: f64 = weights.iter().filter(|w| **w > 0.0).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut target = roll.clamp(0.0, 1.0) * total;
+    let mut last = None;
+    for (i, weight) in weights.iter().enumerate().filter(|(_, w)| **w > 0.0) {
+        if target < *weight {
+            return Some(i);
+        }
+        target -= weight;
+        last = Some(i);
+    }
+    // Only reachable through float rounding with roll close to 1
+    last
+}
+
+// Chimes answer a discovery request at a random point within this window,
+// so a ringer starting up doesn't get every reply at once
+pub const DEFAULT_ANNOUNCE_WINDOW: Duration = Duration::from_secs(1);
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            cleanup_interval: Duration::from_secs(30),
+            stale_after: HeartbeatConfig::default().stale_after(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ChimeDiscovery {
+    chimes: DiscoveredChimes,
+    config: DiscoveryConfig,
+    removed_handlers: Arc<Mutex<Vec<ChimeRemovedHandler>>>,
+    // None while discovering every user, otherwise the users being watched
+    watched: Arc<Mutex<Option<HashSet<UserId>>>>,
+    // Handlers per watched user ("+" for all), so unwatching leaves other
+    // listeners on the same topics (e.g. the chime's own) in place
+    handles: Arc<Mutex<HashMap<String, Vec<SubscriptionId>>>>,
+}
+
+impl ChimeDiscovery {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            chimes: Arc::new(RwLock::new(HashMap::new())),
+            config,
+            removed_handlers: Arc::new(Mutex::new(Vec::new())),
+            watched: Arc::new(Mutex::new(None)),
+            handles: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+
+    pub fn chimes(&self) -> DiscoveredChimes {
+        self.chimes.clone()
+    }
+
+    // Called with each chime pruned for going stale
+    pub fn on_chime_removed<F>(&self, handler: F)
+    where
+        F: Fn(&DiscoveredChime) + Send + Sync + 'static,
+    {
+        self.removed_handlers
+            .lock()
+            .unwrap()
+            .push(Box::new(handler));
+    }
+
+    // Subscribe to chime lists, notes, chords and status from all other users,
+    // replacing any per-user watches
+    pub async fn subscribe(&self, mqtt: &ChimeNetMqtt) -> crate::Result<()> {
+        // Already discovering everyone: subscribing again would double every handler
+        if self.handles.lock().unwrap().contains_key("+") {
+            return Ok(());
+        }
+
+        let watched = self.watched.lock().unwrap().take();
+        for user in watched.into_iter().flatten() {
+            self.unsubscribe_topics(mqtt, user.as_str()).await?;
+        }
+
+        self.subscribe_topics(mqtt, "+").await
+    }
+
+    // Narrow discovery to a set of users. The first watch drops the all-users
+    // subscription, so only watched users are discovered from then on.
+    pub async fn watch_user(&self, mqtt: &ChimeNetMqtt, user: &UserId) -> crate::Result<()> {
+        let (was_watching_all, newly_watched) = {
+            let mut watched = self.watched.lock().unwrap();
+            let was_watching_all = watched.is_none();
+            let newly_watched = watched
+                .get_or_insert_with(HashSet::new)
+                .insert(user.clone());
+            (was_watching_all, newly_watched)
+        };
+
+        if was_watching_all {
+            self.unsubscribe_topics(mqtt, "+").await?;
+            // Drop everyone discovered through the firehose except this user
+            self.chimes
+                .write()
+                .await
+                .retain(|_, chime| chime.user == user.as_str());
+        }
+        if newly_watched {
+            self.subscribe_topics(mqtt, user.as_str()).await?;
+            info!("Watching chimes of user: {}", user);
+        }
+
+        Ok(())
+    }
+
+    // Stop watching a user and forget their chimes. Unwatching the last user
+    // leaves discovery idle; call `subscribe` to go back to all users.
+    pub async fn unwatch_user(&self, mqtt: &ChimeNetMqtt, user: &UserId) -> crate::Result<()> {
+        let was_watched = self
+            .watched
+            .lock()
+            .unwrap()
+            .as_mut()
+            .is_some_and(|watched| watched.remove(user));
+        if !was_watched {
+            return Err(crate::ChimeNetError::NotWatching(user.to_string()));
+        }
+
+        self.unsubscribe_topics(mqtt, user.as_str()).await?;
+        self.chimes
+            .write()
+            .await
+            .retain(|_, chime| chime.user != user.as_str());
+
+        info!("Stopped watching chimes of user: {}", user);
+        Ok(())
+    }
+
+    // Asks every chime to re-publish its list and status, then waits `window`
+    // (longer than the chimes' announce window) for them. Returns the chimes
+    // heard from meanwhile, by user and name; only those this discovery is
+    // subscribed to can show up.
+    pub async fn request_announce(
+        &self,
+        mqtt: &ChimeNetMqtt,
+        ringer_id: &str,
+        window: Duration,
+    ) -> crate::Result<Vec<DiscoveredChime>> {
+        let requested_at = self.send_announce_request(mqtt, ringer_id).await?;
+        tokio::time::sleep(window).await;
+        Ok(self.heard_since(requested_at).await)
+    }
+
+    // The halves of `request_announce`, for a connection shared behind a
+    // lock that mustn't be held while waiting. Returns when it was sent.
+    pub async fn send_announce_request(
+        &self,
+        mqtt: &ChimeNetMqtt,
+        ringer_id: &str,
+    ) -> crate::Result<chrono::DateTime<chrono::Utc>> {
+        let requested_at = chrono::Utc::now();
+        mqtt.publish_ringer_discovery(&RingerDiscovery {
+            ringer_id: ringer_id.to_string(),
+            user: mqtt.user().to_string(),
+            timestamp: requested_at,
+        })
+        .await?;
+        Ok(requested_at)
+    }
+
+    // Chimes whose list or status arrived since `since`, by user and name
+    pub async fn heard_since(&self, since: chrono::DateTime<chrono::Utc>) -> Vec<DiscoveredChime> {
+        let mut heard: Vec<DiscoveredChime> = self
+            .chimes
+            .read()
+            .await
+            .values()
+            .filter(|chime| chime.last_seen >= since)
+            .cloned()
+            .collect();
+        heard.sort_by(|a, b| (&a.user, &a.name).cmp(&(&b.user, &b.name)));
+        heard
+    }
+
+    // Adds or refreshes chimes without any network traffic, e.g.
+    // `DiscoveredChime::sample_set()` in demo mode
+    pub async fn inject(&self, chimes: Vec<DiscoveredChime>) {
+        let mut known = self.chimes.write().await;
+        for chime in chimes {
+            known.insert(discovery_key(&chime.user, &chime.chime_id), chime);
+        }
+    }
+
+    pub async fn get(&self, user: &UserId, chime_id: &ChimeId) -> Option<DiscoveredChime> {
+        self.chimes
+            .read()
+            .await
+            .get(&discovery_key(user.as_str(), chime_id.as_str()))
+            .cloned()
+    }
+
+    // A random online chime passing `filter`, e.g. for "ring someone"
+    pub async fn pick_random<F>(
+        &self,
+        filter: F,
+        weighting: PickWeighting,
+    ) -> Option<DiscoveredChime>
+    where
+        F: Fn(&DiscoveredChime) -> bool,
+    {
+        let chimes = self.chimes.read().await;
+        let mut candidates: Vec<&DiscoveredChime> = chimes
+            .values()
+            .filter(|chime| chime.online && filter(chime))
+            .collect();
+        candidates.sort_by(|a, b| (&a.user, &a.chime_id).cmp(&(&b.user, &b.chime_id)));
+
+        pick_weighted(candidates, weighting, |chime| chime.ring_rate_per_hour).cloned()
+    }
+
+    pub async fn chimes_for_user(&self, user: &UserId) -> Vec<DiscoveredChime> {
+        self.chimes
+            .read()
+            .await
+            .values()
+            .filter(|chime| chime.user == user.as_str())
+            .cloned()
+            .collect()
+    }
+
+    // Exact (case-sensitive) name lookup within one user's chimes; names, like
+    // chime_ids, are only meaningful per user
+    pub async fn find_by_name(&self, user: &UserId, name: &str) -> Option<DiscoveredChime> {
+        self.chimes
+            .read()
+            .await
+            .values()
+            .find(|chime| chime.user == user.as_str() && chime.name == name)
+            .cloned()
+    }
+
+    // Chimes whose name matches `query`, best match first
+    pub async fn search(&self, query: &str) -> Vec<DiscoveredChime> {
+        let chimes = self.chimes.read().await;
+        let mut matches: Vec<(u32, &DiscoveredChime)> = chimes
+            .values()
+            .filter_map(|chime| Some((name_match_score(&chime.name, query)?, chime)))
+            .collect();
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then_with(|| a.name.cmp(&b.name))
+        });
+
+        matches
+            .into_iter()
+            .map(|(_, chime)| chime.clone())
+            .collect()
+    }
+
+    // None when discovering all users
+    pub fn watched_users(&self) -> Option<Vec<UserId>> {
+        self.watched.lock().unwrap().as_ref().map(|watched| {
+            let mut users: Vec<UserId> = watched.iter().cloned().collect();
+            users.sort();
+            users
+        })
+    }
+
+    fn is_in_scope(&self, user: &str) -> bool {
+        match self.watched.lock().unwrap().as_ref() {
+            Some(watched) => watched.contains(&UserId::from(user)),
+            None => true,
+        }
+    }
+
+    // Notes and chords come from the list; their own topics are only
+    // mirrors kept for older clients
+    fn discovery_topics(user: &str) -> [String; 3] {
+        [
+            TopicBuilder::chime_list_filter(user),
+            TopicBuilder::chime_status_filter(user, "+"),
+            TopicBuilder::chime_mode_filter(user, "+"),
+        ]
+    }
+
+    async fn subscribe_topics(&self, mqtt: &ChimeNetMqtt, user: &str) -> crate::Result<()> {
+        for topic in Self::discovery_topics(user) {
+            let discovery = self.clone();
+
+            let id = mqtt
+                .subscribe_foreign(&topic, mqtt.any_qos(), move |topic, payload| {
+                    let discovery = discovery.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(e) = discovery.handle_message(&topic, &payload).await {
+                            error!("Error handling discovery message: {}", e);
+                        }
+                    });
+                })
+                .await?;
+            self.handles
+                .lock()
+                .unwrap()
+                .entry(user.to_string())
+                .or_default()
+                .push(id);
+        }
+
+        Ok(())
+    }
+
+    async fn unsubscribe_topics(&self, mqtt: &ChimeNetMqtt, user: &str) -> crate::Result<()> {
+        let ids = self.handles.lock().unwrap().remove(user);
+        for id in ids.into_iter().flatten() {
+            mqtt.unsubscribe_handle(&id).await?;
+        }
+
+        Ok(())
+    }
+
+    // Rings every online discovered chime of every user. Meant for emergencies
+    // only, hence the loud logging. Returns the outcome for each target.
+    pub async fn broadcast_all(
+        &self,
+        mqtt: &ChimeNetMqtt,
+        notes: Option<Vec<String>>,
+        chords: Option<Vec<String>>,
+        urgency: Urgency,
+    ) -> Vec<(DiscoveredChime, crate::Result<()>)> {
+        let targets: Vec<DiscoveredChime> = self
+            .chimes
+            .read()
+            .await
+            .values()
+            .filter(|chime| chime.online)
+            .cloned()
+            .collect();
+
+        warn!(
+            "BROADCAST: ringing all {} online chimes with {:?} urgency",
+            targets.len(),
+            urgency
+        );
+
+        let mut results = Vec::with_capacity(targets.len());
+        for target in targets {
+            let ring_request = ChimeRingRequest {
+                chime_id: target.chime_id.clone(),
+                user: target.user.clone(),
+                notes: notes.clone(),
+                chords: chords.clone(),
+                duration_ms: None,
+                note_durations: None,
+                timestamp: chrono::Utc::now(),
+                preset: None,
+                urgency,
+                expires_at: None,
+                request_id: None,
+            };
+
+            let user = UserId::from(target.user.as_str());
+            let chime_id = ChimeId::from(target.chime_id.as_str());
+            let result = mqtt
+                .publish_chime_ring_to_user(&user, &chime_id, &ring_request)
+                .await;
+            match &result {
+                Ok(()) => warn!("BROADCAST: rang {}/{}", target.user, target.chime_id),
+                Err(e) => error!(
+                    "BROADCAST: failed to ring {}/{}: {}",
+                    target.user, target.chime_id, e
+                ),
+            }
+            results.push((target, result));
+        }
+
+        results
+    }
+
+    // Prunes stale chimes every cleanup interval; returns only with an error for an invalid config
+    pub async fn run_cleanup(&self) -> crate::Result<()> {
+        self.config.validate()?;
+        let mut interval = tokio::time::interval(self.config.cleanup_interval);
+        // The first tick completes immediately
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let removed = self.prune_stale().await;
+            if !removed.is_empty() {
+                info!(
+                    "Cleaned up {} old chimes, {} chimes remaining",
+                    removed.len(),
+                    self.chimes.read().await.len()
+                );
+            }
+        }
+    }
+
+    // Remove chimes not seen within the staleness threshold and notify listeners
+    pub async fn prune_stale(&self) -> Vec<DiscoveredChime> {
+        let stale_after =
+            chrono::Duration::from_std(self.config.stale_after).unwrap_or(chrono::Duration::MAX);
+        let cutoff = chrono::Utc::now() - stale_after;
+
+        let removed: Vec<DiscoveredChime> = {
+            let mut chimes = self.chimes.write().await;
+            let stale_keys: Vec<String> = chimes
+                .iter()
+                .filter(|(_, chime)| chime.last_seen <= cutoff)
+                .map(|(key, _)| key.clone())
+                .collect();
+            stale_keys
+                .iter()
+                .filter_map(|key| chimes.remove(key))
+                .collect()
+        };
+
+        self.notify_removed(&removed);
+        removed
+    }
+
+    fn notify_removed(&self, removed: &[DiscoveredChime]) {
+        let handlers = self.removed_handlers.lock().unwrap();
+        for chime in removed {
+            for handler in handlers.iter() {
+                handler(chime);
+            }
+        }
+    }
+
+    pub async fn handle_message(&self, topic: &str, payload: &str) -> crate::Result<()> {
+        let parts: Vec<&str> = topic.split('/').collect();
+        if parts.len() < 3 {
+            return Ok(());
+        }
+
+        let user = parts[1];
+
+        if parts.get(2) != Some(&"chime") {
+            return Ok(());
+        }
+
+        // Late messages for a user that was just unwatched
+        if !self.is_in_scope(user) {
+            return Ok(());
+        }
+
+        match parts.get(3) {
+            Some(&"list") => {
+                // Handle chime list
+                if let Ok(chime_list) = serde_json::from_str::<ChimeList>(payload) {
+                    let mut chimes = self.chimes.write().await;
+                    let chime_count = chime_list.chimes.len();
+
+                    for chime_info in &chime_list.chimes {
+                        let key = discovery_key(user, &chime_info.id);
+                        let chime = chimes.entry(key).or_insert_with(|| DiscoveredChime {
+                            user: user.to_string(),
+                            chime_id: chime_info.id.clone(),
+                            name: String::new(),
+                            description: None,
+                            notes: Vec::new(),
+                            chords: Vec::new(),
+                            online: true,
+                            mode: LcgpMode::Available, // Default, will be updated by status
+                            last_seen: chrono::Utc::now(),
+                            ring_rate_per_hour: 0.0,
+                            status_message: None,
+                        });
+
+                        // Status owns online/mode, so a republished list keeps them
+                        chime.name = chime_info.name.clone();
+                        chime.description = chime_info.description.clone();
+                        chime.notes = chime_info.notes.clone();
+                        chime.chords = chime_info.chords.clone();
+                        chime.last_seen = chrono::Utc::now();
+                    }
+
+                    info!(
+                        "Updated chime list for user: {} ({} chimes)",
+                        user, chime_count
+                    );
+                }
+            }
+            // An emptied retained status: the chime was removed for good
+            Some(chime_id) if parts.get(4) == Some(&"status") && payload.is_empty() => {
+                let key = discovery_key(user, chime_id);
+                let removed = self.chimes.write().await.remove(&key);
+                if let Some(chime) = removed {
+                    info!("Chime removed: {}/{}", user, chime_id);
+                    self.notify_removed(&[chime]);
+                }
+            }
+            Some(chime_id) if parts.get(4) == Some(&"status") => {
+                let key = discovery_key(user, chime_id);
+
+                match serde_json::from_str::<ChimeStatus>(payload) {
+                    Ok(status) => {
+                        let mut chimes = self.chimes.write().await;
+                        if let Some(chime) = chimes.get_mut(&key) {
+                            chime.online = status.online;
+                            chime.mode = status.mode;
+                            chime.ring_rate_per_hour = status.ring_rate_per_hour;
+                            chime.status_message = status.status_message;
+                            chime.last_seen = chrono::Utc::now();
+                        }
+                    }
+                    Err(e) => warn!("Ignoring unreadable status on {}: {}", topic, e),
+                }
+            }
+            // Live mode changes between statuses
+            Some(chime_id) if parts.get(4) == Some(&"mode") => {
+                let key = discovery_key(user, chime_id);
+
+                match serde_json::from_str::<ModeUpdate>(payload) {
+                    Ok(update) => {
+                        if let Some(chime) = self.chimes.write().await.get_mut(&key) {
+                            chime.mode = update.mode;
+                            chime.last_seen = chrono::Utc::now();
+                        }
+                    }
+                    Err(e) => warn!("Ignoring unreadable mode update on {}: {}", topic, e),
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+// How well a chime name matches a search query, higher is better, None for no
+// match. Case-insensitive; exact > prefix > word prefix > substring > fuzzy,
+// where fuzzy is the query's letters in order or a word within a typo or two.
+pub fn name_match_score(name: &str, query: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+
+    if name == query {
+        return Some(500);
+    }
+    if name.starts_with(&query) {
+        return Some(400);
+    }
+    if name.split_whitespace().any(|word| word.starts_with(&query)) {
+        return Some(300);
+    }
+    if name.contains(&query) {
+        return Some(200);
+    }
+
+    // Letters in order, e.g. "aoc" for "Alice Office Chime"; tighter spans rank higher
+    if let Some(span) = subsequence_span(&name, &query) {
+        let slack = (span - query.chars().count()) as u32;
+        return Some(100 - slack.min(50));
+    }
+
+    let max_typos = if query.chars().count() >= 6 { 2 } else { 1 };
+    name.split_whitespace()
+        .map(|word| edit_distance(word, &query))
+        .min()
+        .filter(|distance| *distance <= max_typos)
+        .map(|distance| 50 - 10 * distance as u32)
+}
+
+// Length of the shortest stretch of `haystack` containing `needle`'s chars in order
+fn subsequence_span(haystack: &str, needle: &str) -> Option<usize> {
+    let haystack: Vec<char> = haystack.chars().collect();
+    let needle: Vec<char> = needle.chars().collect();
+
+    (0..haystack.len())
+        .filter(|&start| haystack[start] == needle[0])
+        .filter_map(|start| {
+            let mut matched = 0;
+            for (offset, c) in haystack[start..].iter().enumerate() {
+                if *c == needle[matched] {
+                    matched += 1;
+                    if matched == needle.len() {
+                        return Some(offset + 1);
+                    }
+                }
+            }
+            None
+        })
+        .min()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+impl Default for ChimeDiscovery {
+    fn default() -> Self {
+        Self::new(DiscoveryConfig::default())
+    }
+}
+
+// Outcome of one ring sent by `test_ring_chimes`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RingTestResult {
+    pub user: String,
+    pub chime_id: String,
+    pub case: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+// Rings each chime once per case (default sound, its notes, its chords, both)
+// as `from_user`, pausing `gap` between rings. Success means the ring was
+// published, not that anyone heard it.
+pub async fn test_ring_chimes(
+    mqtt: &ChimeNetMqtt,
+    from_user: &UserId,
+    chimes: &[DiscoveredChime],
+    gap: Duration,
+) -> Vec<RingTestResult> {
+    let mut results = Vec::new();
+
+    for chime in chimes {
+        let cases = [
+            ("Default", None, None),
+            ("With notes", Some(chime.notes.clone()), None),
+            ("With chords", None, Some(chime.chords.clone())),
+            (
+                "Notes and chords",
+                Some(chime.notes.clone()),
+                Some(chime.chords.clone()),
+            ),
+        ];
+
+        for (case, notes, chords) in cases {
+            let ring_request = ChimeRingRequest {
+                chime_id: chime.chime_id.clone(),
+                user: from_user.to_string(),
+                notes,
+                chords,
+                duration_ms: Some(500),
+                note_durations: None,
+                timestamp: chrono::Utc::now(),
+                preset: None,
+                urgency: Urgency::Normal,
+                expires_at: None,
+                request_id: None,
+            };
+
+            let result = mqtt
+                .publish_chime_ring_to_user(
+                    &UserId::from(chime.user.as_str()),
+                    &ChimeId::from(chime.chime_id.as_str()),
+                    &ring_request,
+                )
+                .await;
+
+            results.push(RingTestResult {
+                user: chime.user.clone(),
+                chime_id: chime.chime_id.clone(),
+                case: case.to_string(),
+                success: result.is_ok(),
+                error: result.err().map(|e| e.to_string()),
+            });
+
+            tokio::time::sleep(gap).await;
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::testing::{TestBus, TestTransport};
+
+    #[test]
+    fn all_users_discovery_subscribes_to_wildcards() {
+        assert_eq!(
+            ChimeDiscovery::discovery_topics("+"),
+            [
+                "/+/chime/list".to_string(),
+                "/+/chime/+/status".to_string(),
+                "/+/chime/+/mode".to_string(),
+            ]
+        );
+        assert_eq!(
+            ChimeDiscovery::discovery_topics("alice")[1],
+            "/alice/chime/+/status"
+        );
+    }
+
+    fn chime_list(user: &str, chime_id: &str, name: &str) -> String {
+        chime_list_with_notes(user, chime_id, name, &[])
+    }
+
+    fn chime_list_with_notes(user: &str, chime_id: &str, name: &str, notes: &[&str]) -> String {
+        serde_json::to_string(&ChimeList {
+            user: user.to_string(),
+            chimes: vec![ChimeInfo {
+                id: chime_id.to_string(),
+                name: name.to_string(),
+                description: None,
+                notes: notes.iter().map(|note| note.to_string()).collect(),
+                chords: vec![],
+                created_at: chrono::Utc::now(),
+            }],
+            timestamp: chrono::Utc::now(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn colliding_chime_ids_are_addressed_per_user() {
+        let discovery = ChimeDiscovery::default();
+        for (user, name) in [("alice", "Desk"), ("bob", "Kitchen")] {
+            discovery
+                .handle_message(
+                    &TopicBuilder::chime_list(user),
+                    &chime_list(user, "shared", name),
+                )
+                .await
+                .unwrap();
+        }
+
+        // Only alice's chime goes offline and republishes its list with new notes
+        let status = ChimeStatus {
+            chime_id: "shared".to_string(),
+            online: false,
+            mode: LcgpMode::DoNotDisturb,
+            last_seen: chrono::Utc::now(),
+            node_id: "alice_shared".to_string(),
+            following: None,
+            ring_rate_per_hour: 0.0,
+            status_message: Some("back at 2pm".to_string()),
+            in_reply_to: None,
+        };
+        discovery
+            .handle_message(
+                &TopicBuilder::chime_status("alice", "shared"),
+                &serde_json::to_string(&status).unwrap(),
+            )
+            .await
+            .unwrap();
+        discovery
+            .handle_message(
+                &TopicBuilder::chime_list("alice"),
+                &chime_list_with_notes("alice", "shared", "Desk", &["C4"]),
+            )
+            .await
+            .unwrap();
+        // The deprecated per-chime topic no longer feeds discovery
+        discovery
+            .handle_message(&TopicBuilder::chime_notes("alice", "shared"), r#"["D4"]"#)
+            .await
+            .unwrap();
+
+        let shared = ChimeId::from("shared");
+        let alice = discovery.get(&"alice".into(), &shared).await.unwrap();
+        let bob = discovery.get(&"bob".into(), &shared).await.unwrap();
+        assert_eq!((alice.name.as_str(), alice.online), ("Desk", false));
+        assert_eq!(alice.notes, vec!["C4"]);
+        assert_eq!(alice.status_message.as_deref(), Some("back at 2pm"));
+        assert_eq!((bob.name.as_str(), bob.online), ("Kitchen", true));
+        assert_eq!(bob.status_message, None);
+        assert!(bob.notes.is_empty());
+
+        assert_eq!(discovery.chimes_for_user(&"alice".into()).await.len(), 1);
+        assert!(discovery
+            .find_by_name(&"bob".into(), "Desk")
+            .await
+            .is_none());
+        assert_eq!(
+            discovery
+                .find_by_name(&"bob".into(), "Kitchen")
+                .await
+                .unwrap()
+                .user,
+            "bob"
+        );
+    }
+
+    #[test]
+    fn weighted_index_follows_weights() {
+        let weights = [1.0, 0.0, 3.0];
+        assert_eq!(weighted_index(&weights, 0.0), Some(0));
+        assert_eq!(weighted_index(&weights, 0.24), Some(0));
+        assert_eq!(weighted_index(&weights, 0.25), Some(2));
+        assert_eq!(weighted_index(&weights, 0.999), Some(2));
+        assert_eq!(weighted_index(&[0.0, 0.0], 0.5), None);
+        assert_eq!(weighted_index(&[], 0.5), None);
+
+        // A chime ringing 12 times an hour is much less likely than an idle one
+        let busy = PickWeighting::InverseActivity.weight(12.0);
+        let idle = PickWeighting::InverseActivity.weight(0.0);
+        assert!(idle > 10.0 * busy);
+        assert_eq!(PickWeighting::Uniform.weight(12.0), 1.0);
+
+        let rate = |rate: &f64| *rate;
+        assert_eq!(
+            pick_weighted(vec![3.0], PickWeighting::Uniform, rate),
+            Some(3.0)
+        );
+        assert_eq!(
+            pick_weighted(Vec::new(), PickWeighting::InverseActivity, rate),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn pick_random_only_returns_online_matches() {
+        let discovery = ChimeDiscovery::default();
+        discovery.inject(DiscoveredChime::sample_set()).await;
+
+        for _ in 0..20 {
+            let chime = discovery
+                .pick_random(
+                    |chime| chime.mode != LcgpMode::DoNotDisturb,
+                    PickWeighting::InverseActivity,
+                )
+                .await
+                .unwrap();
+            assert!(chime.online);
+            assert_ne!(chime.chime_id, "demo-alice-kitchen");
+        }
+        assert!(discovery
+            .pick_random(|chime| chime.user == "dave", PickWeighting::Uniform)
+            .await
+            .is_none());
+    }
+
+    // Accepts every publish except to users in `unreachable`
+    fn flaky_transport(unreachable: &'static [&'static str]) -> TestTransport {
+        let bus = TestBus::new();
+        bus.on_publish(
+            move |_, publish| match TopicBuilder::user_from_topic(&publish.topic) {
+                Some(user) if unreachable.contains(&user) => {
+                    Err(format!("{} is unreachable", user).into())
+                }
+                _ => Ok(()),
+            },
+        );
+        bus.client("ci")
+    }
+
+    #[tokio::test]
+    async fn ring_test_reports_each_case() {
+        let transport = flaky_transport(&["bob"]);
+        let mqtt = ChimeNetMqtt::with_transport(Box::new(transport), &"ci".into()).unwrap();
+        let chimes: Vec<DiscoveredChime> = DiscoveredChime::sample_set()
+            .into_iter()
+            .filter(|chime| chime.user == "alice" || chime.user == "bob")
+            .collect();
+
+        let results = test_ring_chimes(&mqtt, &"ci".into(), &chimes, Duration::ZERO).await;
+
+        assert_eq!(results.len(), chimes.len() * 4);
+        for result in &results {
+            assert_eq!(result.success, result.user == "alice", "{:?}", result);
+            assert_eq!(result.error.is_some(), !result.success);
+        }
+        assert_eq!(
+            results
+                .iter()
+                .filter(|r| r.case == "Notes and chords")
+                .count(),
+            chimes.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn an_emptied_status_removes_the_chime() {
+        let discovery = ChimeDiscovery::default();
+        let removed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let removed_clone = removed.clone();
+        discovery.on_chime_removed(move |chime| {
+            removed_clone.lock().unwrap().push(chime.chime_id.clone());
+        });
+        for chime_id in ["c1", "c2"] {
+            discovery
+                .handle_message(
+                    &TopicBuilder::chime_list("alice"),
+                    &chime_list("alice", chime_id, "Desk"),
+                )
+                .await
+                .unwrap();
+        }
+
+        for chime_id in ["c1", "unknown"] {
+            discovery
+                .handle_message(&TopicBuilder::chime_status("alice", chime_id), "")
+                .await
+                .unwrap();
+        }
+
+        assert!(discovery.get(&"alice".into(), &"c1".into()).await.is_none());
+        assert!(discovery.get(&"alice".into(), &"c2".into()).await.is_some());
+        assert_eq!(*removed.lock().unwrap(), vec!["c1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn stale_chimes_are_pruned_and_reported() {
+        let discovery = ChimeDiscovery::new(DiscoveryConfig {
+            cleanup_interval: Duration::from_secs(30),
+            stale_after: Duration::from_secs(60),
+        });
+        let removed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for listener in ["first", "second"] {
+            let removed = removed.clone();
+            discovery.on_chime_removed(move |chime| {
+                removed
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}:{}", listener, chime.chime_id));
+            });
+        }
+        for chime_id in ["old", "fresh"] {
+            discovery
+                .handle_message(
+                    &TopicBuilder::chime_list("alice"),
+                    &chime_list("alice", chime_id, "Desk"),
+                )
+                .await
+                .unwrap();
+        }
+        let old = discovery_key("alice", "old");
+        discovery
+            .chimes()
+            .write()
+            .await
+            .get_mut(&old)
+            .unwrap()
+            .last_seen -= chrono::Duration::minutes(2);
+
+        let pruned = discovery.prune_stale().await;
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].chime_id, "old");
+        assert_eq!(*removed.lock().unwrap(), vec!["first:old", "second:old"]);
+        assert!(discovery
+            .get(&"alice".into(), &"fresh".into())
+            .await
+            .is_some());
+
+        // Nothing left to prune, nobody is told again
+        assert!(discovery.prune_stale().await.is_empty());
+        assert_eq!(removed.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_zero_cleanup_interval_is_refused() {
+        let config = DiscoveryConfig {
+            cleanup_interval: Duration::ZERO,
+            ..DiscoveryConfig::default()
+        };
+        assert_eq!(config.validate().unwrap_err().kind(), Some("config"));
+        let error = ChimeDiscovery::new(config).run_cleanup().await.unwrap_err();
+        assert_eq!(error.kind(), Some("config"));
+        assert!(DiscoveryConfig::default().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn mode_updates_change_the_discovered_mode() {
+        let discovery = ChimeDiscovery::default();
+        discovery
+            .handle_message(
+                &TopicBuilder::chime_list("alice"),
+                &chime_list("alice", "c1", "Desk"),
+            )
+            .await
+            .unwrap();
+
+        let update = ModeUpdate {
+            timestamp: chrono::Utc::now(),
+            mode: LcgpMode::Grinding,
+            node_id: "alice_c1".to_string(),
+            custom_state: None,
+        };
+        for chime_id in ["c1", "unknown"] {
+            discovery
+                .handle_message(
+                    &TopicBuilder::chime_mode("alice", chime_id),
+                    &serde_json::to_string(&update).unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let chime = discovery.get(&"alice".into(), &"c1".into()).await.unwrap();
+        assert_eq!(chime.mode, LcgpMode::Grinding);
+        assert_eq!(discovery.chimes().read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn watching_narrows_discovery_and_watch_all_restores_it() {
+        let bus = TestBus::new();
+        let mqtt = ChimeNetMqtt::with_transport(Box::new(bus.client("ci")), &"ci".into()).unwrap();
+        let discovery = ChimeDiscovery::default();
+        let wildcards = ChimeDiscovery::discovery_topics("+").to_vec();
+
+        discovery.subscribe(&mqtt).await.unwrap();
+        discovery.subscribe(&mqtt).await.unwrap();
+        assert_eq!(discovery.handles.lock().unwrap()["+"].len(), 3);
+
+        discovery.watch_user(&mqtt, &"alice".into()).await.unwrap();
+        assert_eq!(discovery.watched_users(), Some(vec!["alice".into()]));
+        let mut alice_topics = ChimeDiscovery::discovery_topics("alice").to_vec();
+        alice_topics.sort();
+        assert_eq!(mqtt.active_subscriptions().await, alice_topics);
+
+        for (user, name) in [("alice", "Desk"), ("bob", "Kitchen")] {
+            bus.deliver(
+                &TopicBuilder::chime_list(user),
+                &chime_list(user, "c1", name),
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let names: Vec<String> = discovery
+            .chimes()
+            .read()
+            .await
+            .values()
+            .map(|chime| chime.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Desk".to_string()]);
+
+        discovery
+            .unwatch_user(&mqtt, &"alice".into())
+            .await
+            .unwrap();
+        assert!(discovery.chimes().read().await.is_empty());
+        assert!(mqtt.active_subscriptions().await.is_empty());
+        assert!(discovery
+            .unwatch_user(&mqtt, &"alice".into())
+            .await
+            .is_err());
+
+        discovery.subscribe(&mqtt).await.unwrap();
+        assert_eq!(discovery.watched_users(), None);
+        let mut active = mqtt.active_subscriptions().await;
+        active.sort();
+        let mut expected = wildcards;
+        expected.sort();
+        assert_eq!(active, expected);
+    }
+
+    #[tokio::test]
+    async fn request_announce_collects_chimes_heard_within_the_window() {
+        let mqtt =
+            ChimeNetMqtt::with_transport(Box::new(flaky_transport(&[])), &"ci".into()).unwrap();
+        let discovery = ChimeDiscovery::default();
+        for (user, name) in [("alice", "Desk"), ("bob", "Kitchen")] {
+            discovery
+                .handle_message(
+                    &TopicBuilder::chime_list(user),
+                    &chime_list(user, "c1", name),
+                )
+                .await
+                .unwrap();
+        }
+        for chime in discovery.chimes().write().await.values_mut() {
+            chime.last_seen -= chrono::Duration::minutes(5);
+        }
+
+        // Only bob answers while the request is open
+        let answering = discovery.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            answering
+                .handle_message(
+                    &TopicBuilder::chime_list("bob"),
+                    &chime_list("bob", "c1", "Kitchen"),
+                )
+                .await
+                .unwrap();
+        });
+
+        let answered = discovery
+            .request_announce(&mqtt, "ringer", Duration::from_millis(100))
+            .await
+            .unwrap();
+        let names: Vec<&str> = answered.iter().map(|chime| chime.name.as_str()).collect();
+        assert_eq!(names, vec!["Kitchen"]);
+    }
+
+    #[test]
+    fn ranks_name_matches_by_quality() {
+        let score = |name| name_match_score(name, "office");
+
+        assert!(score("Office") > score("Office Chime"));
+        assert!(score("Office Chime") > score("Alice Office Chime"));
+        assert!(score("Alice Office Chime") > score("Backoffice"));
+        assert!(score("Backoffice").is_some());
+        assert_eq!(score("Kitchen"), None);
+    }
+
+    #[test]
+    fn fuzzy_matches_abbreviations_and_typos() {
+        assert!(name_match_score("Alice Office Chime", "aoc").is_some());
+        assert!(name_match_score("Alice Office Chime", "ofice").is_some());
+        assert!(name_match_score("Alice Office Chime", "offcie").is_some());
+        assert_eq!(name_match_score("Kitchen", "kitchem"), Some(40));
+        assert_eq!(name_match_score("Kitchen", "garage"), None);
+        assert_eq!(name_match_score("Kitchen", "  "), None);
+    }
+}
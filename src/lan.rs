@@ -0,0 +1,395 @@
+// Broker-less transport for chimes on the same LAN. Every message is a JSON
+// datagram sent to a UDP multicast group and each peer filters by topic
+// locally, so topics and payloads are exactly the same as over MQTT.
+use crate::mqtt::{MqttClient, MqttMessage};
+use crate::transport::{HandlerSet, MessagePredicate, NextMessage, SubscriptionId};
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+pub const LAN_URL_SCHEME: &str = "udp://";
+pub const DEFAULT_LAN_GROUP: SocketAddrV4 =
+    SocketAddrV4::new(Ipv4Addr::new(239, 255, 67, 78), 4267);
+
+// Largest payload a single IPv4 UDP datagram can carry
+const MAX_DATAGRAM: usize = 65_507;
+
+// Every handler per filter, as with MqttClient
+type HandlerMap = Arc<Mutex<HashMap<String, HandlerSet>>>;
+type RetainedMap = Arc<Mutex<HashMap<String, String>>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum LanFrame {
+    Publish { topic: String, payload: String },
+    // Sent on subscribe so peers replay their retained messages matching the
+    // filter, standing in for a broker's retained store
+    Sync { filter: String },
+}
+
+// Mirrors the MqttClient surface. QoS is accepted for compatibility but
+// delivery is best-effort, as with any UDP datagram.
+pub struct LanClient {
+    group: SocketAddrV4,
+    client_id: String,
+    socket: std::sync::Mutex<Option<Arc<UdpSocket>>>,
+    receiver: std::sync::Mutex<Option<JoinHandle<()>>>,
+    subscriptions: HandlerMap,
+    retained: RetainedMap,
+}
+
+impl LanClient {
+    pub fn new(group: SocketAddrV4, client_id: &str) -> Self {
+        Self {
+            group,
+            client_id: client_id.to_string(),
+            socket: std::sync::Mutex::new(None),
+            receiver: std::sync::Mutex::new(None),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            retained: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // "udp://239.255.67.78:4267" selects that group, a bare "udp://" the
+    // default one. Returns None for non-LAN (broker) URLs.
+    pub fn group_from_url(url: &str) -> Option<Result<SocketAddrV4>> {
+        let address = url.strip_prefix(LAN_URL_SCHEME)?.trim_end_matches('/');
+        if address.is_empty() {
+            return Some(Ok(DEFAULT_LAN_GROUP));
+        }
+
+        Some(match address.parse::<SocketAddrV4>() {
+            Ok(group) if group.ip().is_multicast() => Ok(group),
+            Ok(group) => Err(ChimeNetError::Config(format!(
+                "{} is not a multicast address",
+                group.ip()
+            ))),
+            Err(e) => Err(ChimeNetError::Config(format!(
+                "Invalid LAN group '{}': {}",
+                address, e
+            ))),
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<()> {
+        self.open().map_err(|e| {
+            ChimeNetError::Connection(format!("Failed to join LAN group {}: {}", self.group, e))
+        })
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        if let Some(receiver) = self.receiver.lock().unwrap().take() {
+            receiver.abort();
+        }
+        self.socket.lock().unwrap().take();
+        Ok(())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.socket.lock().unwrap().is_some()
+    }
+
+    // Rebinds if the socket was closed and asks peers to replay retained
+    // messages for every subscription
+    pub async fn reconnect(&self) -> Result<()> {
+        if !self.is_connected() {
+            self.open()?;
+        }
+
+        let filters: Vec<String> = self.subscriptions.lock().await.keys().cloned().collect();
+        for filter in filters {
+            self.send(&LanFrame::Sync { filter }).await?;
+        }
+
+        Ok(())
+    }
+
+    // LAN frames are JSON, so MessagePack payloads are sent as their JSON
+    // equivalent; bandwidth isn't scarce on a LAN
+    pub async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
+        let payload = PayloadFormat::decode_to_json(payload)?;
+        self.publish(topic, &payload, qos, retain).await
+    }
+
+    pub async fn publish(&self, topic: &str, payload: &str, _qos: i32, retain: bool) -> Result<()> {
+        if retain {
+            let mut retained = self.retained.lock().await;
+            // An empty retained payload clears the topic, as on a broker
+            if payload.is_empty() {
+                retained.remove(topic);
+            } else {
+                retained.insert(topic.to_string(), payload.to_string());
+            }
+        }
+
+        self.send(&LanFrame::Publish {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        })
+        .await
+    }
+
+    pub async fn publish_json<T: serde::Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        payload: &T,
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
+        let json = serde_json::to_string(payload)?;
+        self.publish(topic, &json, qos, retain).await
+    }
+
+    pub async fn subscribe<F>(&self, topic: &str, _qos: i32, handler: F) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let id = self
+            .subscriptions
+            .lock()
+            .await
+            .entry(topic.to_string())
+            .or_default()
+            .push(topic, Box::new(handler));
+
+        self.send(&LanFrame::Sync {
+            filter: topic.to_string(),
+        })
+        .await?;
+        Ok(id)
+    }
+
+    // See Transport::subscribe_once; registered before the retained replay is requested
+    pub async fn subscribe_once(
+        &self,
+        topic: &str,
+        _qos: i32,
+        predicate: MessagePredicate,
+    ) -> Result<NextMessage> {
+        let next = self
+            .subscriptions
+            .lock()
+            .await
+            .entry(topic.to_string())
+            .or_default()
+            .push_once(predicate);
+
+        self.send(&LanFrame::Sync {
+            filter: topic.to_string(),
+        })
+        .await?;
+        Ok(next)
+    }
+
+    // Drops every handler registered for `topic`
+    pub async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        self.subscriptions.lock().await.remove(topic);
+        Ok(())
+    }
+
+    // See Transport::unsubscribe_handle
+    pub async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        if let Some(handlers) = subscriptions.get_mut(id.topic()) {
+            if handlers.remove(id) && handlers.is_empty() {
+                subscriptions.remove(id.topic());
+            }
+        }
+        Ok(())
+    }
+
+    // Topic filters currently subscribed to, sorted
+    pub async fn active_subscriptions(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.subscriptions.lock().await.keys().cloned().collect();
+        topics.sort();
+        topics
+    }
+
+    // (handlers, one-shot waiters) per filter; test and "testing" builds only
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn subscription_handler_counts(&self) -> HashMap<String, (usize, usize)> {
+        self.subscriptions
+            .lock()
+            .await
+            .iter()
+            .map(|(topic, handlers)| (topic.clone(), handlers.counts()))
+            .collect()
+    }
+
+    fn open(&self) -> Result<()> {
+        // SO_REUSEADDR lets several chimes on one host share the group port
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.group.port())).into())?;
+        socket.join_multicast_v4(self.group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_nonblocking(true)?;
+
+        let socket = Arc::new(UdpSocket::from_std(socket.into())?);
+        let receiver = tokio::spawn(Self::handle_incoming_datagrams(
+            socket.clone(),
+            self.group,
+            self.subscriptions.clone(),
+            self.retained.clone(),
+        ));
+
+        *self.socket.lock().unwrap() = Some(socket);
+        if let Some(previous) = self.receiver.lock().unwrap().replace(receiver) {
+            previous.abort();
+        }
+
+        log::info!(
+            "{} joined LAN multicast group {}",
+            self.client_id,
+            self.group
+        );
+        Ok(())
+    }
+
+    async fn send(&self, frame: &LanFrame) -> Result<()> {
+        let socket = self
+            .socket
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or("LAN transport is not connected")?;
+        Self::send_frame(&socket, self.group, frame).await
+    }
+
+    async fn send_frame(socket: &UdpSocket, group: SocketAddrV4, frame: &LanFrame) -> Result<()> {
+        let datagram = serde_json::to_vec(frame)?;
+        if datagram.len() > MAX_DATAGRAM {
+            return Err(ChimeNetError::PayloadTooLarge {
+                size: datagram.len(),
+                limit: MAX_DATAGRAM,
+            });
+        }
+
+        socket.send_to(&datagram, group).await?;
+        Ok(())
+    }
+
+    async fn handle_incoming_datagrams(
+        socket: Arc<UdpSocket>,
+        group: SocketAddrV4,
+        subscriptions: HandlerMap,
+        retained: RetainedMap,
+    ) {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+
+        loop {
+            let len = match socket.recv_from(&mut buf).await {
+                Ok((len, _)) => len,
+                Err(e) => {
+                    log::error!("Failed to receive LAN datagram: {}", e);
+                    continue;
+                }
+            };
+
+            let frame: LanFrame = match serde_json::from_slice(&buf[..len]) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::debug!("Ignoring malformed LAN datagram: {}", e);
+                    continue;
+                }
+            };
+
+            match frame {
+                LanFrame::Publish { topic, payload } => {
+                    let message = MqttMessage {
+                        topic,
+                        payload,
+                        qos: 0,
+                        retain: false,
+                    };
+                    let mut subscriptions = subscriptions.lock().await;
+                    subscriptions.retain(|filter, handlers| {
+                        if MqttClient::topic_matches(filter, &message.topic) {
+                            handlers.dispatch(&message);
+                            return !handlers.is_empty();
+                        }
+                        true
+                    });
+                }
+                LanFrame::Sync { filter } => {
+                    let replay = Self::retained_matching(&*retained.lock().await, &filter);
+                    for (topic, payload) in replay {
+                        let frame = LanFrame::Publish { topic, payload };
+                        if let Err(e) = Self::send_frame(&socket, group, &frame).await {
+                            log::error!("Failed to replay retained LAN message: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn retained_matching(
+        retained: &HashMap<String, String>,
+        filter: &str,
+    ) -> Vec<(String, String)> {
+        retained
+            .iter()
+            .filter(|(topic, _)| MqttClient::topic_matches(filter, topic))
+            .map(|(topic, payload)| (topic.clone(), payload.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lan_urls() {
+        assert_eq!(
+            LanClient::group_from_url("udp://").unwrap().unwrap(),
+            DEFAULT_LAN_GROUP
+        );
+        assert_eq!(
+            LanClient::group_from_url("udp://239.1.2.3:5000")
+                .unwrap()
+                .unwrap(),
+            SocketAddrV4::new(Ipv4Addr::new(239, 1, 2, 3), 5000)
+        );
+        assert!(LanClient::group_from_url("udp://192.168.1.2:5000")
+            .unwrap()
+            .is_err());
+        assert!(LanClient::group_from_url("udp://nonsense")
+            .unwrap()
+            .is_err());
+        assert!(LanClient::group_from_url("tcp://localhost:1883").is_none());
+    }
+
+    #[test]
+    fn replays_only_matching_retained_topics() {
+        let retained = HashMap::from([
+            ("/alice/chime/abc/status".to_string(), "a".to_string()),
+            ("/alice/chime/list".to_string(), "b".to_string()),
+            ("/bob/chime/xyz/status".to_string(), "c".to_string()),
+        ]);
+
+        let mut replay = LanClient::retained_matching(&retained, "/+/chime/+/status");
+        replay.sort();
+        assert_eq!(
+            replay,
+            vec![
+                ("/alice/chime/abc/status".to_string(), "a".to_string()),
+                ("/bob/chime/xyz/status".to_string(), "c".to_string()),
+            ]
+        );
+        assert!(LanClient::retained_matching(&retained, "/carol/chime/list").is_empty());
+    }
+}
@@ -1,18 +1,164 @@
 use crate::types::*;
 use chrono::{DateTime, Datelike, Timelike, Utc};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::time;
 
+// Condition key start_system_monitor keeps the sampled load under
+pub const SYSTEM_LOAD_CONDITION: &str = "system_load";
+
+// How often start_system_monitor samples the system load
+const SYSTEM_LOAD_INTERVAL: Duration = Duration::from_secs(15);
+
+// An automatic answer to a ring: the response, its delay in ms and an
+// optional note from a custom behavior
+pub type AutoResponse = (ChimeResponse, Option<u64>, Option<String>);
+
+// Time constant of the ring activity average: a ring's weight falls to 1/e after this long
+const RING_ACTIVITY_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// Exponential moving average of rings received per hour, decaying continuously
+// between rings so an idle chime cools down without any background work
+#[derive(Debug, Clone, Copy)]
+pub struct RingActivity {
+    rate: f64,
+    updated: Instant,
+}
+
+impl RingActivity {
+    fn new(now: Instant) -> Self {
+        Self {
+            rate: 0.0,
+            updated: now,
+        }
+    }
+
+    fn record_at(&mut self, now: Instant) {
+        // Each ring adds 1/window-hours, so a steady rate of N/hour converges on N
+        let window_hours = RING_ACTIVITY_WINDOW.as_secs_f64() / 3600.0;
+        self.rate = self.rate_at(now) + 1.0 / window_hours;
+        self.updated = now;
+    }
+
+    fn rate_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.rate * (-elapsed / RING_ACTIVITY_WINDOW.as_secs_f64()).exp()
+    }
+}
+
+// Oldest transitions are dropped past this, so a long-running node can't grow forever
+const MAX_MODE_TRANSITIONS: usize = 10_000;
+
+// Delayed auto-responses waiting at once, by default; see
+// LcgpHandler::set_max_delayed_responses
+pub const DEFAULT_MAX_DELAYED_RESPONSES: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModeTransition {
+    pub mode: Option<LcgpMode>, // None = stopped tracking, e.g. the chime went offline
+    pub at: DateTime<Utc>,
+}
+
+// Log of mode changes, oldest first, for time-in-mode analytics
+#[derive(Debug, Clone, Default)]
+pub struct ModeHistory {
+    transitions: VecDeque<ModeTransition>,
+}
+
+impl ModeHistory {
+    pub fn record(&mut self, mode: LcgpMode, at: DateTime<Utc>) {
+        self.push(Some(mode), at);
+    }
+
+    // Ends the open interval without starting a new one
+    pub fn close(&mut self, at: DateTime<Utc>) {
+        self.push(None, at);
+    }
+
+    fn push(&mut self, mode: Option<LcgpMode>, at: DateTime<Utc>) {
+        if self.transitions.back().map(|last| &last.mode) == Some(&mode) {
+            return;
+        }
+
+        self.transitions.push_back(ModeTransition { mode, at });
+        if self.transitions.len() > MAX_MODE_TRANSITIONS {
+            self.transitions.pop_front();
+        }
+    }
+
+    pub fn transitions(&self) -> Vec<ModeTransition> {
+        self.transitions.iter().cloned().collect()
+    }
+
+    // Time spent in each mode during the `window` before `now`. The latest
+    // mode's interval is still open and counts up to `now`; time before the
+    // first recorded transition is unknown and not counted.
+    pub fn durations(&self, window: Duration, now: DateTime<Utc>) -> HashMap<LcgpMode, Duration> {
+        let window_start =
+            now - chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+        let mut durations = HashMap::new();
+
+        for (i, transition) in self.transitions.iter().enumerate() {
+            let Some(mode) = &transition.mode else {
+                continue;
+            };
+            let end = self.transitions.get(i + 1).map_or(now, |next| next.at);
+
+            let start = transition.at.max(window_start);
+            let end = end.min(now);
+            if end > start {
+                let spent = (end - start).to_std().unwrap_or_default();
+                *durations.entry(mode.clone()).or_insert(Duration::ZERO) += spent;
+            }
+        }
+
+        durations
+    }
+}
+
 pub struct LcgpNode {
     pub node_id: String,
     pub mode: Arc<Mutex<LcgpMode>>,
     pub custom_states: Arc<Mutex<HashMap<String, CustomLcgpState>>>,
     pub custom_behaviors: Arc<Mutex<HashMap<String, Box<dyn CustomBehavior>>>>,
     pub last_mode_update: Arc<Mutex<Instant>>,
-    pub pending_responses: Arc<Mutex<Vec<String>>>, // Pending chime IDs awaiting response
-    pub state_conditions: Arc<Mutex<HashMap<String, bool>>>, // For condition evaluation
+    pub pending_responses: Arc<Mutex<Vec<PendingRing>>>, // Awaiting a response, oldest first
+    pub state_conditions: Arc<Mutex<HashMap<String, ConditionValue>>>, // For condition evaluation
+    pub away_message: Arc<Mutex<Option<String>>>, // Sent with a Negative reply when not chiming
+    pub ring_activity: Arc<Mutex<RingActivity>>,
+    pub mode_history: Arc<Mutex<ModeHistory>>,
+    pub grinding_responses: Arc<Mutex<GrindingResponses>>,
+    pub last_ring_at: Arc<Mutex<Option<DateTime<Utc>>>>,
+    pub response_stats: Arc<Mutex<ResponseStats>>,
+    pub mode_expiry: Arc<Mutex<Option<ModeExpiry>>>, // Set by set_mode_until
+}
+
+// A mode set with `set_mode_until`, and the mode to go back to afterwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeExpiry {
+    pub mode: LcgpMode,
+    pub until: DateTime<Utc>,
+    pub previous: LcgpMode,
+}
+
+// What Grinding and ChillGrinding auto-respond with. "Grinding" means "accept
+// everything" to some people and "decline everything, don't wait on me" to others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrindingResponses {
+    pub grinding: ChimeResponse,
+    pub chill_grinding: ChimeResponse,
+}
+
+impl Default for GrindingResponses {
+    fn default() -> Self {
+        Self {
+            grinding: ChimeResponse::Positive,
+            chill_grinding: ChimeResponse::Positive,
+        }
+    }
 }
 
 impl LcgpNode {
@@ -25,23 +171,142 @@ impl LcgpNode {
             last_mode_update: Arc::new(Mutex::new(Instant::now())),
             pending_responses: Arc::new(Mutex::new(Vec::new())),
             state_conditions: Arc::new(Mutex::new(HashMap::new())),
+            away_message: Arc::new(Mutex::new(None)),
+            ring_activity: Arc::new(Mutex::new(RingActivity::new(Instant::now()))),
+            mode_history: Arc::new(Mutex::new({
+                let mut history = ModeHistory::default();
+                history.record(LcgpMode::Available, Utc::now());
+                history
+            })),
+            grinding_responses: Arc::new(Mutex::new(GrindingResponses::default())),
+            last_ring_at: Arc::new(Mutex::new(None)),
+            response_stats: Arc::new(Mutex::new(ResponseStats::default())),
+            mode_expiry: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // The same node under a new id. All state is shared, so tasks still holding
+    // the old node keep driving the mode of the new one.
+    pub fn with_node_id(&self, node_id: String) -> Self {
+        Self {
+            node_id,
+            mode: Arc::clone(&self.mode),
+            custom_states: Arc::clone(&self.custom_states),
+            custom_behaviors: Arc::clone(&self.custom_behaviors),
+            last_mode_update: Arc::clone(&self.last_mode_update),
+            pending_responses: Arc::clone(&self.pending_responses),
+            state_conditions: Arc::clone(&self.state_conditions),
+            away_message: Arc::clone(&self.away_message),
+            ring_activity: Arc::clone(&self.ring_activity),
+            mode_history: Arc::clone(&self.mode_history),
+            grinding_responses: Arc::clone(&self.grinding_responses),
+            last_ring_at: Arc::clone(&self.last_ring_at),
+            response_stats: Arc::clone(&self.response_stats),
+            mode_expiry: Arc::clone(&self.mode_expiry),
         }
     }
 
+    pub fn set_grinding_responses(&self, responses: GrindingResponses) {
+        *self.grinding_responses.lock().unwrap() = responses;
+    }
+
+    pub fn grinding_responses(&self) -> GrindingResponses {
+        self.grinding_responses.lock().unwrap().clone()
+    }
+
+    // Also cancels any pending revert from `set_mode_until`
     pub fn set_mode(&self, mode: LcgpMode) {
-        *self.mode.lock().unwrap() = mode;
+        let mut current = self.mode.lock().unwrap();
+        self.mode_expiry.lock().unwrap().take();
+        self.switch_mode(&mut current, mode);
+    }
+
+    // Switches to `mode` until `until`, when `expire_mode` reverts to the mode
+    // from before. Extending a timed mode keeps the original mode to revert to.
+    pub fn set_mode_until(&self, mode: LcgpMode, until: DateTime<Utc>) {
+        let mut current = self.mode.lock().unwrap();
+        let mut expiry = self.mode_expiry.lock().unwrap();
+        let previous = match expiry.take() {
+            Some(pending) => pending.previous,
+            None => current.clone(),
+        };
+        *expiry = Some(ModeExpiry {
+            mode: mode.clone(),
+            until,
+            previous,
+        });
+        self.switch_mode(&mut current, mode);
+    }
+
+    pub fn mode_expiry(&self) -> Option<ModeExpiry> {
+        self.mode_expiry.lock().unwrap().clone()
+    }
+
+    // Reverts a timed mode whose deadline has passed, returning the mode
+    // restored. Nothing happens before the deadline or once the mode was changed.
+    pub fn expire_mode(&self, now: DateTime<Utc>) -> Option<LcgpMode> {
+        let mut current = self.mode.lock().unwrap();
+        let mut expiry = self.mode_expiry.lock().unwrap();
+        if expiry.as_ref()?.until > now {
+            return None;
+        }
+
+        let expired = expiry.take()?;
+        if *current != expired.mode {
+            return None;
+        }
+        self.switch_mode(&mut current, expired.previous.clone());
+        Some(expired.previous)
+    }
+
+    fn switch_mode(&self, current: &mut LcgpMode, mode: LcgpMode) {
+        self.mode_history
+            .lock()
+            .unwrap()
+            .record(mode.clone(), Utc::now());
+        *current = mode;
         *self.last_mode_update.lock().unwrap() = Instant::now();
     }
 
+    // Time spent in each mode over the last `window`, up to now
+    pub fn mode_durations(&self, window: Duration) -> HashMap<LcgpMode, Duration> {
+        self.mode_history
+            .lock()
+            .unwrap()
+            .durations(window, Utc::now())
+    }
+
     pub fn get_mode(&self) -> LcgpMode {
         self.mode.lock().unwrap().clone()
     }
 
+    // Atomically switches to `new` only if the current mode is still `expected`.
+    // Returns false (leaving the mode untouched) if another writer got there first.
+    pub fn compare_and_set_mode(&self, expected: &LcgpMode, new: LcgpMode) -> bool {
+        let mut mode = self.mode.lock().unwrap();
+        if *mode != *expected {
+            return false;
+        }
+
+        self.mode_expiry.lock().unwrap().take();
+        self.switch_mode(&mut mode, new);
+        true
+    }
+
     pub fn register_custom_state(&self, state: CustomLcgpState) {
         let name = state.name.clone();
         self.custom_states.lock().unwrap().insert(name, state);
     }
 
+    // Forgets a custom state, falling back to Available if it was the current
+    // mode. A behavior registered under its name stays, for when the state is
+    // defined again.
+    pub fn remove_custom_state(&self, name: &str) -> Option<CustomLcgpState> {
+        let removed = self.custom_states.lock().unwrap().remove(name)?;
+        self.compare_and_set_mode(&LcgpMode::Custom(name.to_string()), LcgpMode::Available);
+        Some(removed)
+    }
+
     pub fn register_custom_behavior(&self, state_name: String, behavior: Box<dyn CustomBehavior>) {
         self.custom_behaviors
             .lock()
@@ -49,16 +314,77 @@ impl LcgpNode {
             .insert(state_name, behavior);
     }
 
+    // Adopts another node's custom states, e.g. loaded from a shared JSON file.
+    // States are handled in order, so duplicates within `states` collide with
+    // each other too. Invalid states are reported and never stored.
+    pub fn import_states(
+        &self,
+        states: Vec<CustomLcgpState>,
+        conflict: ImportPolicy,
+    ) -> StateImportReport {
+        let mut report = StateImportReport::default();
+        let mut custom_states = self.custom_states.lock().unwrap();
+        let behaviors = self.custom_behaviors.lock().unwrap();
+
+        for mut state in states {
+            if let Err(errors) = state.validate() {
+                report.invalid.push((state.name, errors));
+                continue;
+            }
+
+            if custom_states.contains_key(&state.name) {
+                match conflict {
+                    ImportPolicy::Skip => {
+                        report.skipped.push(state.name);
+                        continue;
+                    }
+                    ImportPolicy::Overwrite => report.overwritten.push(state.name.clone()),
+                    ImportPolicy::Rename => {
+                        let local_name = (2..)
+                            .map(|n| format!("{}_{}", state.name, n))
+                            .find(|name| !custom_states.contains_key(name))
+                            .unwrap();
+                        report.renamed.push((
+                            std::mem::replace(&mut state.name, local_name.clone()),
+                            local_name,
+                        ));
+                    }
+                }
+            }
+
+            if !behaviors.contains_key(&state.name) {
+                report.without_behavior.push(state.name.clone());
+            }
+            report.imported.push(state.name.clone());
+            custom_states.insert(state.name.clone(), state);
+        }
+
+        report
+    }
+
     pub fn get_custom_state(&self, name: &str) -> Option<CustomLcgpState> {
         self.custom_states.lock().unwrap().get(name).cloned()
     }
 
+    // Every custom state, by name, e.g. to share or save them
+    pub fn export_states(&self) -> Vec<CustomLcgpState> {
+        let mut states: Vec<CustomLcgpState> = self
+            .custom_states
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        states.sort_by(|a, b| a.name.cmp(&b.name));
+        states
+    }
+
     pub fn set_custom_mode(&self, state_name: String) -> Result<()> {
         if self.custom_states.lock().unwrap().contains_key(&state_name) {
             self.set_mode(LcgpMode::Custom(state_name));
             Ok(())
         } else {
-            Err(format!("Custom state '{}' not found", state_name).into())
+            Err(ChimeNetError::StateNotFound(state_name))
         }
     }
 
@@ -66,24 +392,125 @@ impl LcgpNode {
         self.custom_states.lock().unwrap().keys().cloned().collect()
     }
 
-    pub fn set_condition(&self, key: String, value: bool) {
-        self.state_conditions.lock().unwrap().insert(key, value);
+    pub fn registered_behaviors(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .custom_behaviors
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        names
     }
 
-    pub fn evaluate_auto_state_transitions(&self) -> Option<String> {
+    // Lists custom states and whether each one is backed by a registered behavior.
+    // Locks states before behaviors, like every other place that takes both.
+    pub fn describe_custom_states(&self) -> Vec<CustomStateInfo> {
         let states = self.custom_states.lock().unwrap();
-        let mut best_state: Option<(String, u8)> = None;
+        let behaviors = self.custom_behaviors.lock().unwrap();
+        let mut infos: Vec<CustomStateInfo> = states
+            .values()
+            .map(|state| CustomStateInfo {
+                name: state.name.clone(),
+                description: state.description.clone(),
+                has_behavior: behaviors.contains_key(&state.name),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
 
-        for (name, state) in states.iter() {
-            if self.evaluate_state_conditions(state) {
-                let priority = state.priority.unwrap_or(0);
-                if best_state.is_none() || priority > best_state.as_ref().unwrap().1 {
-                    best_state = Some((name.clone(), priority));
-                }
+    pub fn set_condition(&self, key: String, value: ConditionValue) {
+        self.state_conditions.lock().unwrap().insert(key, value);
+    }
+
+    pub fn set_condition_bool(&self, key: String, value: bool) {
+        self.set_condition(key, ConditionValue::Bool(value));
+    }
+
+    pub fn set_condition_float(&self, key: String, value: f64) {
+        self.set_condition(key, ConditionValue::Float(value));
+    }
+
+    pub fn set_condition_text(&self, key: String, value: String) {
+        self.set_condition(key, ConditionValue::Text(value));
+    }
+
+    // The "system_load" condition. None until sampled (or where it can't be);
+    // SystemLoad conditions are unmet meanwhile.
+    pub fn set_system_load(&self, load: Option<f32>) {
+        match load {
+            Some(load) => self.set_condition_float(SYSTEM_LOAD_CONDITION.to_string(), load.into()),
+            None => {
+                self.state_conditions
+                    .lock()
+                    .unwrap()
+                    .remove(SYSTEM_LOAD_CONDITION);
             }
         }
+    }
+
+    pub fn system_load(&self) -> Option<f32> {
+        match self
+            .state_conditions
+            .lock()
+            .unwrap()
+            .get(SYSTEM_LOAD_CONDITION)
+        {
+            Some(ConditionValue::Float(load)) => Some(*load as f32),
+            _ => None,
+        }
+    }
+
+    pub fn set_away_message(&self, message: Option<String>) {
+        *self.away_message.lock().unwrap() = message;
+    }
+
+    pub fn record_ring(&self) {
+        self.ring_activity.lock().unwrap().record_at(Instant::now());
+        *self.last_ring_at.lock().unwrap() = Some(Utc::now());
+    }
+
+    pub fn ring_summary(&self) -> RingSummary {
+        RingSummary {
+            rings_per_hour: self.ring_rate_per_hour(),
+            last_ring_at: *self.last_ring_at.lock().unwrap(),
+        }
+    }
+
+    pub fn record_response(&self, response: &ChimeResponse) {
+        self.response_stats
+            .lock()
+            .unwrap()
+            .record(response, Utc::now());
+    }
+
+    pub fn response_stats(&self) -> ResponseStats {
+        self.response_stats.lock().unwrap().clone()
+    }
+
+    // Smoothed rings received per hour, for "how busy is this chime" indicators
+    pub fn ring_rate_per_hour(&self) -> f64 {
+        self.ring_activity.lock().unwrap().rate_at(Instant::now())
+    }
+
+    pub fn get_away_message(&self) -> Option<String> {
+        self.away_message.lock().unwrap().clone()
+    }
+
+    // The state whose conditions hold, with its priority. The highest priority
+    // wins (an unset one counts as 0) and equal priorities go to the name that
+    // sorts first, so the pick never depends on map order.
+    pub fn evaluate_auto_state_transitions(&self) -> Option<(String, u8)> {
+        let states = self.custom_states.lock().unwrap();
 
-        best_state.map(|(name, _)| name)
+        states
+            .iter()
+            .filter(|(_, state)| self.evaluate_state_conditions(state))
+            .map(|(name, state)| (state.priority.unwrap_or(0), std::cmp::Reverse(name)))
+            .max()
+            .map(|(priority, std::cmp::Reverse(name))| (name.clone(), priority))
     }
 
     fn evaluate_state_conditions(&self, state: &CustomLcgpState) -> bool {
@@ -114,7 +541,7 @@ impl LcgpNode {
     }
 
     fn is_time_in_range(&self, time_range: &TimeRange, now: &DateTime<Utc>) -> bool {
-        let weekday = now.weekday().number_from_sunday() as u8;
+        let weekday = now.weekday().num_days_from_sunday() as u8;
 
         if !time_range.days_of_week.contains(&weekday) {
             return false;
@@ -134,37 +561,29 @@ impl LcgpNode {
 
     fn evaluate_condition(&self, condition: &StateCondition) -> bool {
         let conditions = self.state_conditions.lock().unwrap();
+        // Unset (or non-bool) flags count as false
+        let flag = |key: &str| matches!(conditions.get(key), Some(ConditionValue::Bool(true)));
 
         match condition {
-            StateCondition::UserPresence(required) => {
-                conditions.get("user_presence").unwrap_or(&false) == required
-            }
-            StateCondition::SystemLoad(threshold) => {
-                if let Some(load_str) = conditions.get("system_load") {
-                    // This is a simplified check - in reality you'd parse the load value
-                    *load_str == (*threshold > 0.5)
-                } else {
-                    false
-                }
-            }
-            StateCondition::NetworkActivity(required) => {
-                conditions.get("network_activity").unwrap_or(&false) == required
-            }
-            StateCondition::CalendarBusy(required) => {
-                conditions.get("calendar_busy").unwrap_or(&false) == required
-            }
-            StateCondition::Custom(key, expected_value) => {
-                // For custom conditions, we store them as string comparisons
-                // In a real implementation, you'd want more sophisticated comparison
-                conditions.get(key).unwrap_or(&false) == &(expected_value == "true")
-            }
+            StateCondition::UserPresence(required) => flag("user_presence") == *required,
+            // Met at or above the threshold, e.g. a "Busy" state at 0.8
+            StateCondition::SystemLoad(threshold) => matches!(
+                conditions.get(SYSTEM_LOAD_CONDITION),
+                Some(ConditionValue::Float(load)) if *load >= f64::from(*threshold)
+            ),
+            StateCondition::NetworkActivity(required) => flag("network_activity") == *required,
+            StateCondition::CalendarBusy(required) => flag("calendar_busy") == *required,
+            StateCondition::Custom(key, expected_value) => conditions
+                .get(key)
+                .is_some_and(|value| value.matches(expected_value)),
             StateCondition::TimeRange(time_range) => self.is_time_in_range(time_range, &Utc::now()),
         }
     }
 
-    pub fn should_send_mode_update(&self) -> bool {
+    // Whether the mode has gone unannounced for `interval`
+    pub fn should_send_mode_update(&self, interval: Duration) -> bool {
         let last_update = *self.last_mode_update.lock().unwrap();
-        last_update.elapsed() >= Duration::from_secs(300) // 5 minutes
+        last_update.elapsed() >= interval
     }
 
     pub fn create_mode_update(&self) -> ModeUpdate {
@@ -182,25 +601,65 @@ impl LcgpNode {
         }
     }
 
-    pub fn should_chime(&self, incoming_chime: &ChimeMessage) -> bool {
+    // Whether `incoming_chime` chimes, and the auto response it gets, both
+    // from one look at the mode. A custom behavior is asked once.
+    fn decide(&self, incoming_chime: &ChimeMessage) -> (bool, Option<AutoResponse>) {
         match self.get_mode() {
-            LcgpMode::DoNotDisturb => false,
-            LcgpMode::Available => true,
-            LcgpMode::ChillGrinding => true,
-            LcgpMode::Grinding => true,
-            LcgpMode::Custom(state_name) => {
-                if let Some(state) = self.get_custom_state(&state_name) {
-                    // Check if custom behavior override exists
-                    if let Some(behavior) = self.custom_behaviors.lock().unwrap().get(&state_name) {
+            LcgpMode::DoNotDisturb => (false, None),
+            LcgpMode::Available => (true, None), // Wait for user input
+            LcgpMode::ChillGrinding => (
+                true,
+                Some((self.grinding_responses().chill_grinding, Some(10000), None)), // 10 seconds
+            ),
+            LcgpMode::Grinding => (true, Some((self.grinding_responses().grinding, None, None))), // Immediate
+            LcgpMode::Custom(state_name) => match self.get_custom_state(&state_name) {
+                // A custom behavior overrides the state's config
+                Some(state) => match self.custom_behaviors.lock().unwrap().get(&state_name) {
+                    Some(behavior) => {
                         let result = behavior.on_incoming_chime(incoming_chime, &state);
-                        result.should_chime
-                    } else {
-                        state.should_chime
+                        let auto_response = result
+                            .auto_response
+                            .map(|resp| (resp, result.delay_ms, result.note));
+                        (result.should_chime, auto_response)
                     }
-                } else {
-                    false // State not found, default to not chiming
-                }
-            }
+                    None => (
+                        state.should_chime,
+                        state
+                            .auto_response
+                            .map(|resp| (resp, state.auto_response_delay, None)),
+                    ),
+                },
+                None => (false, None), // State not found, default to not chiming
+            },
+        }
+    }
+
+    pub fn should_chime(&self, incoming_chime: &ChimeMessage) -> bool {
+        self.decide(incoming_chime).0
+    }
+
+    // What `incoming_chime` would get if it rang now, decided like a real
+    // ring but without recording it, responding or leaving it pending. Rings
+    // that don't chime and have no auto response get the away message if one
+    // is set.
+    pub fn preview_reaction(&self, incoming_chime: &ChimeMessage) -> ReactionPreview {
+        let mode = self.get_mode();
+        let (would_chime, auto_response) = self.decide(incoming_chime);
+
+        let (auto_response, note) = match auto_response {
+            Some((response, delay, note)) => (Some((response, delay)), note),
+            None if !would_chime => match self.get_away_message() {
+                Some(message) => (Some((ChimeResponse::Negative, None)), Some(message)),
+                None => (None, None),
+            },
+            None => (None, None),
+        };
+
+        ReactionPreview {
+            would_chime,
+            auto_response,
+            note,
+            active_state: mode.to_string(),
         }
     }
 
@@ -208,45 +667,80 @@ impl LcgpNode {
         &self,
         incoming_chime: &ChimeMessage,
     ) -> Option<(ChimeResponse, Option<u64>)> {
-        match self.get_mode() {
-            LcgpMode::DoNotDisturb => None,
-            LcgpMode::Available => None, // Wait for user input
-            LcgpMode::ChillGrinding => Some((ChimeResponse::Positive, Some(10000))), // 10 seconds
-            LcgpMode::Grinding => Some((ChimeResponse::Positive, None)), // Immediate
-            LcgpMode::Custom(state_name) => {
-                if let Some(state) = self.get_custom_state(&state_name) {
-                    // Check if custom behavior override exists
-                    if let Some(behavior) = self.custom_behaviors.lock().unwrap().get(&state_name) {
-                        let result = behavior.on_incoming_chime(incoming_chime, &state);
-                        result.auto_response.map(|resp| (resp, result.delay_ms))
-                    } else {
-                        state
-                            .auto_response
-                            .map(|resp| (resp, state.auto_response_delay))
-                    }
-                } else {
-                    None
-                }
-            }
-        }
+        self.auto_response(incoming_chime)
+            .map(|(response, delay, _)| (response, delay))
+    }
+
+    // Like `should_auto_respond`, plus the note a custom behavior gave with it
+    pub fn auto_response(&self, incoming_chime: &ChimeMessage) -> Option<AutoResponse> {
+        self.decide(incoming_chime).1
+    }
+
+    pub fn add_pending_response(&self, chime_id: String, from_node: String) {
+        self.add_pending_ring(PendingRing {
+            chime_id,
+            from_node,
+            received_at: Utc::now(),
+            request_id: None,
+        });
+    }
+
+    pub fn add_pending_ring(&self, ring: PendingRing) {
+        self.pending_responses.lock().unwrap().push(ring);
     }
 
-    pub fn add_pending_response(&self, chime_id: String) {
-        self.pending_responses.lock().unwrap().push(chime_id);
+    // A response answers one ring, so only the oldest one for `chime_id` goes
+    pub fn remove_pending_response(&self, chime_id: &str) -> Option<PendingRing> {
+        let mut pending = self.pending_responses.lock().unwrap();
+        let index = pending.iter().position(|ring| ring.chime_id == chime_id)?;
+        Some(pending.remove(index))
     }
 
-    pub fn remove_pending_response(&self, chime_id: &str) {
+    // Removes one particular ring: the one with `request_id`, or without one
+    // the oldest ring for `chime_id` that has no request_id either
+    pub fn remove_pending_ring(
+        &self,
+        chime_id: &str,
+        request_id: Option<&str>,
+    ) -> Option<PendingRing> {
+        let mut pending = self.pending_responses.lock().unwrap();
+        let index = pending
+            .iter()
+            .position(|ring| is_same_ring(ring, chime_id, request_id))?;
+        Some(pending.remove(index))
+    }
+
+    pub fn has_pending_response(&self, chime_id: &str) -> bool {
         self.pending_responses
             .lock()
             .unwrap()
-            .retain(|id| id != chime_id);
+            .iter()
+            .any(|ring| ring.chime_id == chime_id)
     }
 
-    pub fn has_pending_response(&self, chime_id: &str) -> bool {
+    pub fn pending_rings(&self) -> Vec<PendingRing> {
+        self.pending_responses.lock().unwrap().clone()
+    }
+
+    // Chime ids still waiting for a response, oldest first
+    pub fn pending_response_ids(&self) -> Vec<String> {
         self.pending_responses
             .lock()
             .unwrap()
-            .contains(&chime_id.to_string())
+            .iter()
+            .map(|ring| ring.chime_id.clone())
+            .collect()
+    }
+
+    // Drops every pending ring without responding; returns how many there were
+    pub fn clear_pending_responses(&self) -> usize {
+        self.pending_responses.lock().unwrap().drain(..).count()
+    }
+
+    // Removes the ring at `index` in `pending_rings()` order
+    pub fn take_pending_ring(&self, index: usize) -> Option<PendingRing> {
+        let mut pending = self.pending_responses.lock().unwrap();
+        (index < pending.len()).then(|| pending.remove(index))
     }
 
     pub fn create_chime_message(
@@ -256,9 +750,8 @@ impl LcgpNode {
         notes: Option<Vec<String>>,
         chords: Option<Vec<String>>,
     ) -> ChimeMessage {
-        // When sending a chime, switch to grinding mode
-        self.set_mode(LcgpMode::Grinding);
-
+        // Sending a chime leaves the mode alone; see ChimeInstance::ring_with_auto_mode
+        // for an explicit temporary mode while awaiting a response
         ChimeMessage {
             timestamp: Utc::now(),
             from_node: self.node_id.clone(),
@@ -266,6 +759,7 @@ impl LcgpNode {
             chime_id,
             notes,
             chords,
+            request_id: None,
         }
     }
 
@@ -274,19 +768,45 @@ impl LcgpNode {
         response: ChimeResponse,
         original_chime_id: Option<String>,
     ) -> ChimeResponseMessage {
+        self.record_response(&response);
         ChimeResponseMessage {
             timestamp: Utc::now(),
             response,
             node_id: self.node_id.clone(),
             original_chime_id,
+            message: None,
+            in_reply_to: None,
         }
     }
+
+    // Builds the Negative auto-reply sent while not chiming, if an away message is set
+    pub fn create_away_response(
+        &self,
+        original_chime_id: Option<String>,
+    ) -> Option<ChimeResponseMessage> {
+        let message = self.get_away_message()?;
+        let mut response = self.create_response(ChimeResponse::Negative, original_chime_id);
+        response.message = Some(message);
+        Some(response)
+    }
 }
 
+// Sends a ModeUpdate on its way, e.g. over MQTT. Called from sync code, so
+// anything async has to be spawned.
+pub type ModeUpdatePublisher = Arc<dyn Fn(ModeUpdate) + Send + Sync>;
+
+// Sends a delayed auto-response once its delay is up, like ModeUpdatePublisher
+pub type ResponsePublisher = Arc<dyn Fn(ChimeResponseMessage) + Send + Sync>;
+
 #[derive(Clone)]
 pub struct LcgpHandler {
     node: Arc<LcgpNode>,
-    chill_grinding_tasks: Arc<Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+    mode_publisher: Option<ModeUpdatePublisher>,
+    response_publisher: Option<ResponsePublisher>,
+    // Delayed auto-responses still running, oldest first, with their chime
+    chill_grinding_tasks: Arc<Mutex<VecDeque<DelayedResponse>>>,
+    max_delayed_responses: Arc<std::sync::atomic::AtomicUsize>,
+    #[allow(dead_code)]
     condition_monitors: Arc<Mutex<Vec<tokio::task::JoinHandle<()>>>>,
 }
 
@@ -294,60 +814,191 @@ impl LcgpHandler {
     pub fn new(node: Arc<LcgpNode>) -> Self {
         Self {
             node,
-            chill_grinding_tasks: Arc::new(Mutex::new(Vec::new())),
+            mode_publisher: None,
+            response_publisher: None,
+            chill_grinding_tasks: Arc::new(Mutex::new(VecDeque::new())),
+            max_delayed_responses: Arc::new(std::sync::atomic::AtomicUsize::new(
+                DEFAULT_MAX_DELAYED_RESPONSES,
+            )),
             condition_monitors: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
+    // Mode updates (periodic and on change) go to `publisher`
+    pub fn with_mode_publisher(self, publisher: ModeUpdatePublisher) -> Self {
+        Self {
+            mode_publisher: Some(publisher),
+            ..self
+        }
+    }
+
+    pub fn mode_publisher(&self) -> Option<ModeUpdatePublisher> {
+        self.mode_publisher.clone()
+    }
+
+    // Delayed auto-responses (ChillGrinding, delayed custom states) go to
+    // `publisher`; immediate ones are returned to the caller as before.
+    // Without one they are only logged.
+    pub fn with_response_publisher(self, publisher: ResponsePublisher) -> Self {
+        Self {
+            response_publisher: Some(publisher),
+            ..self
+        }
+    }
+
+    pub fn response_publisher(&self) -> Option<ResponsePublisher> {
+        self.response_publisher.clone()
+    }
+
+    // Sends the node's current mode now; a no-op without a publisher
+    pub fn publish_mode_update(&self) {
+        if let Some(publisher) = &self.mode_publisher {
+            publisher(self.node.create_mode_update());
+        }
+    }
+
+    // Beyond this many, the oldest delayed auto-response is resolved right
+    // away to make room (at least 1)
+    pub fn set_max_delayed_responses(&self, max: usize) {
+        self.max_delayed_responses
+            .store(max.max(1), std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn max_delayed_responses(&self) -> usize {
+        self.max_delayed_responses
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    // Tracks a new delayed auto-response, dropping finished ones and
+    // resolving the oldest early while over the limit
+    fn track_delayed_response(&self, delayed: DelayedResponse) {
+        let mut tasks = self.chill_grinding_tasks.lock().unwrap();
+        tasks.retain(|task| !task.handle.is_finished());
+        tasks.push_back(delayed);
+
+        while tasks.len() > self.max_delayed_responses() {
+            let Some(oldest) = tasks.pop_front() else {
+                break;
+            };
+            oldest.handle.abort();
+            log::warn!(
+                "Too many delayed responses, resolving the one for {} now",
+                oldest.scheduled.chime_id
+            );
+            // Resolved like a timeout, so the state's behavior still gets `on_timeout`
+            if let Some(message) = oldest.scheduled.resolve(&self.node) {
+                send_delayed_response(self.response_publisher.as_ref(), message);
+            }
+        }
+    }
+
+    // Aborts the delayed auto-response scheduled for `ring`, if there is one,
+    // once the ring is answered or dropped some other way
+    fn drop_delayed_response(&self, ring: &PendingRing) {
+        let mut tasks = self.chill_grinding_tasks.lock().unwrap();
+        tasks.retain(|task| !task.handle.is_finished());
+        let index = tasks.iter().position(|task| {
+            is_same_ring(
+                ring,
+                &task.scheduled.chime_id,
+                task.scheduled.request_id.as_deref(),
+            )
+        });
+        if let Some(task) = index.and_then(|index| tasks.remove(index)) {
+            task.handle.abort();
+        }
+    }
+
     pub async fn handle_incoming_chime(&self, chime: ChimeMessage) -> Option<ChimeResponseMessage> {
+        self.handle_incoming_ring(chime).await.response
+    }
+
+    // Handles a ring like `handle_incoming_chime`, also returning whether it
+    // should play. Both come from one decision, so a custom behavior is asked
+    // once per ring and can't contradict itself.
+    pub async fn handle_incoming_ring(&self, chime: ChimeMessage) -> RingOutcome {
+        // Counted whether or not it gets through, it's demand on this chime either way
+        self.node.record_ring();
+
+        let (should_chime, auto_response) = self.node.decide(&chime);
+        RingOutcome {
+            should_chime,
+            response: self.respond(chime, should_chime, auto_response),
+        }
+    }
+
+    fn respond(
+        &self,
+        chime: ChimeMessage,
+        should_chime: bool,
+        auto_response: Option<AutoResponse>,
+    ) -> Option<ChimeResponseMessage> {
         let node = self.node.clone();
 
-        if !node.should_chime(&chime) {
-            return None;
+        // A ring that doesn't chime still gets its auto response. Without one,
+        // give the ringer closure instead of silence when an away message is set.
+        if !should_chime && auto_response.is_none() {
+            let mut away = node.create_away_response(chime.chime_id)?;
+            away.in_reply_to = chime.request_id;
+            return Some(away);
         }
 
+        // Left for a user or delayed response, which echoes its request_id
+        let wait_for_response = || {
+            if let Some(chime_id) = &chime.chime_id {
+                node.add_pending_ring(PendingRing {
+                    chime_id: chime_id.clone(),
+                    from_node: chime.from_node.clone(),
+                    received_at: Utc::now(),
+                    request_id: chime.request_id.clone(),
+                });
+            }
+        };
+
         // Check for automatic response
-        if let Some((response, delay)) = node.should_auto_respond(&chime) {
+        if let Some((response, delay, note)) = auto_response {
             if let Some(delay_ms) = delay {
-                // Schedule delayed response
-                let chime_id = chime.chime_id.clone();
-                let node_clone = node.clone();
-                let response_clone = response.clone();
-
-                let task = tokio::spawn(async move {
+                // Only a ring left pending under its chime id can be answered later
+                let chime_id = chime.chime_id.clone()?;
+                let scheduled = ScheduledResponse {
+                    chime_id,
+                    request_id: chime.request_id.clone(),
+                    response,
+                    note,
+                    // The state whose behavior gets the timeout, even if the mode changes meanwhile
+                    timeout_state: match node.get_mode() {
+                        LcgpMode::Custom(state_name) => Some(state_name),
+                        _ => None,
+                    },
+                    delay_ms,
+                };
+
+                let task_node = node.clone();
+                let task_scheduled = scheduled.clone();
+                let publisher = self.response_publisher.clone();
+                let handle = tokio::spawn(async move {
                     tokio::time::sleep(Duration::from_millis(delay_ms)).await;
 
-                    // Check if user hasn't responded manually
-                    if let Some(chime_id) = &chime_id {
-                        if node_clone.has_pending_response(chime_id) {
-                            // Auto-respond
-                            node_clone.remove_pending_response(chime_id);
-                            log::info!(
-                                "Auto-responding {:?} to chime {} after {} ms",
-                                response_clone,
-                                chime_id,
-                                delay_ms
-                            );
-                        }
+                    if let Some(message) = task_scheduled.resolve(&task_node) {
+                        send_delayed_response(publisher.as_ref(), message);
                     }
                 });
 
-                if let Some(chime_id) = &chime.chime_id {
-                    node.add_pending_response(chime_id.clone());
-                }
+                wait_for_response();
 
-                self.chill_grinding_tasks.lock().unwrap().push(task);
+                self.track_delayed_response(DelayedResponse { scheduled, handle });
                 return None; // Will respond later
             } else {
                 // Immediate response
-                return Some(node.create_response(response, chime.chime_id));
+                let mut message = node.create_response(response, chime.chime_id);
+                message.message = note;
+                message.in_reply_to = chime.request_id;
+                return Some(message);
             }
         }
 
         // No automatic response - waiting for user input
-        if let Some(chime_id) = &chime.chime_id {
-            node.add_pending_response(chime_id.clone());
-        }
+        wait_for_response();
 
         None
     }
@@ -357,34 +1008,99 @@ impl LcgpHandler {
         response: ChimeResponse,
         chime_id: Option<String>,
     ) -> Option<ChimeResponseMessage> {
-        if let Some(chime_id) = &chime_id {
-            self.node.remove_pending_response(chime_id);
+        let ring = chime_id
+            .as_deref()
+            .and_then(|chime_id| self.node.remove_pending_response(chime_id));
+        if let Some(ring) = &ring {
+            self.drop_delayed_response(ring);
         }
 
-        // Check for custom behavior response handling
-        if let LcgpMode::Custom(state_name) = self.node.get_mode() {
-            if let Some(state) = self.node.get_custom_state(&state_name) {
-                if let Some(behavior) = self.node.custom_behaviors.lock().unwrap().get(&state_name)
-                {
-                    let result = behavior.on_user_response(&response, &state);
+        let mut message = self.apply_user_response(response, chime_id);
+        message.in_reply_to = ring.and_then(|ring| ring.request_id);
+        Some(message)
+    }
 
-                    // Handle state transition if specified
-                    if let Some(next_state) = result.next_state {
-                        if let Err(e) = self.node.set_custom_mode(next_state) {
-                            log::error!("Failed to transition to next state: {}", e);
-                        }
-                    }
+    // Responds to the ring at `index` in `pending_rings()` order, leaving the
+    // other pending rings queued
+    pub fn handle_pending_response(
+        &self,
+        index: usize,
+        response: ChimeResponse,
+    ) -> Option<(PendingRing, ChimeResponseMessage)> {
+        let ring = self.node.take_pending_ring(index)?;
+        self.drop_delayed_response(&ring);
+        let mut message = self.apply_user_response(response, Some(ring.chime_id.clone()));
+        message.in_reply_to = ring.request_id.clone();
+        Some((ring, message))
+    }
+
+    pub fn pending_rings(&self) -> Vec<PendingRing> {
+        self.node.pending_rings()
+    }
+
+    // Forgets the oldest pending ring for `chime_id` without responding and
+    // aborts its delayed auto-response, if one is scheduled. False when no
+    // ring for it was pending.
+    pub fn cancel_pending(&self, chime_id: &str) -> bool {
+        let Some(ring) = self.node.remove_pending_response(chime_id) else {
+            return false;
+        };
+        self.drop_delayed_response(&ring);
+        true
+    }
+
+    // Cancels delayed auto-responses and forgets pending rings, leaving mode
+    // and custom states alone. Returns (pending rings, delayed responses) dropped.
+    pub fn reset(&self) -> (usize, usize) {
+        let mut delayed = 0;
+        for task in self.chill_grinding_tasks.lock().unwrap().drain(..) {
+            if !task.handle.is_finished() {
+                task.handle.abort();
+                delayed += 1;
+            }
+        }
+
+        (self.node.clear_pending_responses(), delayed)
+    }
+
+    fn apply_user_response(
+        &self,
+        response: ChimeResponse,
+        chime_id: Option<String>,
+    ) -> ChimeResponseMessage {
+        let mut note = None;
+
+        // Check for custom behavior response handling
+        if let LcgpMode::Custom(state_name) = self.node.get_mode() {
+            if let Some(state) = self.node.get_custom_state(&state_name) {
+                if let Some(behavior) = self.node.custom_behaviors.lock().unwrap().get(&state_name)
+                {
+                    let result = behavior.on_user_response(&response, &state);
+                    note = result.note;
+
+                    // Handle state transition if specified
+                    if let Some(next_state) = result.next_state {
+                        if let Err(e) = self.node.set_custom_mode(next_state) {
+                            log::error!("Failed to transition to next state: {}", e);
+                        }
+                    }
                 }
             }
         }
 
-        Some(self.node.create_response(response, chime_id))
+        let mut message = self.node.create_response(response, chime_id);
+        message.message = note;
+        message
     }
 
     pub fn should_chime(&self, chime_message: &ChimeMessage) -> bool {
         self.node.should_chime(chime_message)
     }
 
+    pub fn preview_reaction(&self, chime_message: &ChimeMessage) -> ReactionPreview {
+        self.node.preview_reaction(chime_message)
+    }
+
     pub fn start_auto_state_monitor(&self) -> tokio::task::JoinHandle<()> {
         let node = self.node.clone();
 
@@ -395,14 +1111,24 @@ impl LcgpHandler {
                 interval.tick().await;
 
                 // Check if any custom states should be activated
-                if let Some(best_state) = node.evaluate_auto_state_transitions() {
+                if let Some((best_state, priority)) = node.evaluate_auto_state_transitions() {
                     let current_mode = node.get_mode();
 
                     // Only transition if we're not already in this state
                     if !matches!(current_mode, LcgpMode::Custom(ref name) if name == &best_state) {
-                        log::info!("Auto-transitioning to state: {}", best_state);
-                        if let Err(e) = node.set_custom_mode(best_state) {
-                            log::error!("Failed to auto-transition state: {}", e);
+                        // Don't clobber a mode that was changed since we read it
+                        let target = LcgpMode::Custom(best_state.clone());
+                        if node.compare_and_set_mode(&current_mode, target) {
+                            log::info!(
+                                "Auto-transitioned to state: {} (priority {})",
+                                best_state,
+                                priority
+                            );
+                        } else {
+                            log::debug!(
+                                "Mode changed concurrently, skipping auto-transition to {}",
+                                best_state
+                            );
                         }
                     }
                 }
@@ -410,41 +1136,1567 @@ impl LcgpHandler {
         })
     }
 
-    pub async fn start_mode_update_timer(&self) -> tokio::task::JoinHandle<()> {
+    // Keeps the node's system load current for SystemLoad conditions
+    pub fn start_system_monitor(&self) -> tokio::task::JoinHandle<()> {
+        self.start_system_monitor_with(SYSTEM_LOAD_INTERVAL, sample_system_load)
+    }
+
+    // Like `start_system_monitor` with another load source, e.g. in tests
+    pub fn start_system_monitor_with<F>(
+        &self,
+        every: Duration,
+        sampler: F,
+    ) -> tokio::task::JoinHandle<()>
+    where
+        F: Fn() -> Option<f32> + Send + 'static,
+    {
         let node = self.node.clone();
 
         tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(300)); // 5 minutes
+            let mut interval = tokio::time::interval(every);
+            let mut warned = false;
 
             loop {
                 interval.tick().await;
 
-                if node.should_send_mode_update() {
-                    let mode_update = node.create_mode_update();
-                    // In a real implementation, this would send via MQTT
-                    log::info!("Would send mode update: {:?}", mode_update);
+                let load = sampler();
+                if load.is_none() && !warned {
+                    log::warn!("System load unavailable, SystemLoad conditions stay unmet");
+                    warned = true;
                 }
+                node.set_system_load(load);
             }
         })
     }
 
+    // Re-sends the mode every `every` while it hasn't changed (changes are
+    // published as they happen). ChimeInstance does this in its heartbeat.
+    pub async fn start_mode_update_timer(
+        &self,
+        every: Duration,
+    ) -> Result<tokio::task::JoinHandle<()>> {
+        if every.is_zero() {
+            return Err(ChimeNetError::Config(
+                "Mode update interval must be greater than zero".to_string(),
+            ));
+        }
+        let handler = self.clone();
+
+        Ok(tokio::spawn(async move {
+            let mut interval = time::interval(every);
+
+            loop {
+                interval.tick().await;
+
+                if handler.node.should_send_mode_update(every) {
+                    handler.publish_mode_update();
+                }
+            }
+        }))
+    }
+
+    // Announces the mode if it hasn't been for `interval`
+    pub fn refresh_mode_update(&self, interval: Duration) {
+        if self.node.should_send_mode_update(interval) {
+            self.publish_mode_update();
+        }
+    }
+
     pub fn register_custom_state(&self, state: CustomLcgpState) {
         self.node.register_custom_state(state);
     }
 
+    // Publishes the mode update when the removal fell back to Available
+    pub fn remove_custom_state(&self, name: &str) -> Option<CustomLcgpState> {
+        let was_current = self.node.get_mode() == LcgpMode::Custom(name.to_string());
+        let removed = self.node.remove_custom_state(name)?;
+        if was_current && self.node.get_mode() == LcgpMode::Available {
+            self.publish_mode_update();
+        }
+        Some(removed)
+    }
+
     pub fn register_custom_behavior(&self, state_name: String, behavior: Box<dyn CustomBehavior>) {
         self.node.register_custom_behavior(state_name, behavior);
     }
 
-    pub fn set_condition(&self, key: String, value: bool) {
+    pub fn import_states(
+        &self,
+        states: Vec<CustomLcgpState>,
+        conflict: ImportPolicy,
+    ) -> StateImportReport {
+        self.node.import_states(states, conflict)
+    }
+
+    // Registers the states in a JSON array of CustomLcgpState, replacing any of
+    // the same name. Only the config is in the file: a state gets custom
+    // behavior only if one is registered under its name.
+    pub fn load_states_from_file(&self, path: impl AsRef<Path>) -> Result<StateImportReport> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|e| {
+            ChimeNetError::Config(format!("Can't read states file {}: {}", path.display(), e))
+        })?;
+        let states: Vec<CustomLcgpState> = serde_json::from_str(&json).map_err(|e| {
+            ChimeNetError::Config(format!("Invalid states file {}: {}", path.display(), e))
+        })?;
+        Ok(self.import_states(states, ImportPolicy::Overwrite))
+    }
+
+    // Writes every custom state as `load_states_from_file` reads them;
+    // returns how many
+    pub fn save_states_to_file(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let states = self.node.export_states();
+        write_atomically(path, serde_json::to_string_pretty(&states)?)?;
+        Ok(states.len())
+    }
+
+    pub fn set_condition(&self, key: String, value: ConditionValue) {
         self.node.set_condition(key, value);
     }
 
+    pub fn set_condition_bool(&self, key: String, value: bool) {
+        self.node.set_condition_bool(key, value);
+    }
+
+    pub fn set_condition_float(&self, key: String, value: f64) {
+        self.node.set_condition_float(key, value);
+    }
+
+    pub fn set_condition_text(&self, key: String, value: String) {
+        self.node.set_condition_text(key, value);
+    }
+
+    pub fn set_away_message(&self, message: Option<String>) {
+        self.node.set_away_message(message);
+    }
+
+    pub fn compare_and_set_mode(&self, expected: &LcgpMode, new: LcgpMode) -> bool {
+        self.node.compare_and_set_mode(expected, new)
+    }
+
+    pub fn ring_rate_per_hour(&self) -> f64 {
+        self.node.ring_rate_per_hour()
+    }
+
     pub fn get_available_custom_states(&self) -> Vec<String> {
         self.node.get_available_custom_states()
     }
 
+    pub fn registered_behaviors(&self) -> Vec<String> {
+        self.node.registered_behaviors()
+    }
+
+    pub fn describe_custom_states(&self) -> Vec<CustomStateInfo> {
+        self.node.describe_custom_states()
+    }
+
     pub fn set_custom_mode(&self, state_name: String) -> Result<()> {
         self.node.set_custom_mode(state_name)
     }
 }
+
+// Views of internal state for test assertions; not built into release APIs
+#[cfg(any(test, feature = "testing"))]
+impl LcgpNode {
+    pub fn pending_response_count(&self) -> usize {
+        self.pending_responses.lock().unwrap().len()
+    }
+
+    pub fn state_conditions_snapshot(&self) -> HashMap<String, ConditionValue> {
+        self.state_conditions.lock().unwrap().clone()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl LcgpHandler {
+    // Delayed auto-responses still tracked, finished ones not yet pruned included
+    pub fn delayed_response_count(&self) -> usize {
+        self.chill_grinding_tasks.lock().unwrap().len()
+    }
+
+    // The chimes those delayed responses answer, oldest first
+    pub fn delayed_response_chime_ids(&self) -> Vec<String> {
+        self.chill_grinding_tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|task| task.scheduled.chime_id.clone())
+            .collect()
+    }
+}
+
+// One-minute load average per CPU from /proc/loadavg: 1.0 means every CPU
+// was busy on average, above it work was queueing. None off Linux.
+pub fn sample_system_load() -> Option<f32> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    parse_load_average(&loadavg, cpus)
+}
+
+fn parse_load_average(loadavg: &str, cpus: usize) -> Option<f32> {
+    let one_minute: f32 = loadavg.split_whitespace().next()?.parse().ok()?;
+    Some(one_minute / cpus.max(1) as f32)
+}
+
+// Rings are told apart by request_id. Only rings without one fall back to
+// their chime id, so answering one never takes another ring's place.
+fn is_same_ring(ring: &PendingRing, chime_id: &str, request_id: Option<&str>) -> bool {
+    match request_id {
+        Some(request_id) => ring.request_id.as_deref() == Some(request_id),
+        None => ring.request_id.is_none() && ring.chime_id == chime_id,
+    }
+}
+
+// A delayed auto-response for one pending ring
+#[derive(Clone)]
+struct ScheduledResponse {
+    chime_id: String,
+    request_id: Option<String>,
+    response: ChimeResponse,
+    note: Option<String>,
+    timeout_state: Option<String>,
+    delay_ms: u64,
+}
+
+impl ScheduledResponse {
+    // The auto-response to send, unless the ring was already answered manually.
+    // When it was scheduled in a custom state, that state's behavior gets
+    // `on_timeout`: its response (if any) replaces the scheduled one, then its
+    // next state applies.
+    fn resolve(&self, node: &LcgpNode) -> Option<ChimeResponseMessage> {
+        let ring = node.remove_pending_ring(&self.chime_id, self.request_id.as_deref())?;
+
+        let timeout = self.timeout_state.as_deref().and_then(|state_name| {
+            let state = node.get_custom_state(state_name)?;
+            let behaviors = node.custom_behaviors.lock().unwrap();
+            Some(behaviors.get(state_name)?.on_timeout(&state))
+        });
+        let (response, note) = match timeout.as_ref() {
+            Some(BehaviorResult {
+                auto_response: Some(response),
+                note: timeout_note,
+                ..
+            }) => (response.clone(), timeout_note.clone()),
+            _ => (self.response.clone(), self.note.clone()),
+        };
+
+        log::info!(
+            "Auto-responding {:?} to chime {} (scheduled after {} ms)",
+            response,
+            self.chime_id,
+            self.delay_ms
+        );
+        let mut message = node.create_response(response, Some(self.chime_id.clone()));
+        message.message = note;
+        message.in_reply_to = ring.request_id;
+
+        if let Some(next_state) = timeout.and_then(|result| result.next_state) {
+            if let Err(e) = node.set_custom_mode(next_state) {
+                log::error!("Failed to transition to next state: {}", e);
+            }
+        }
+
+        Some(message)
+    }
+}
+
+struct DelayedResponse {
+    scheduled: ScheduledResponse,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+fn send_delayed_response(publisher: Option<&ResponsePublisher>, message: ChimeResponseMessage) {
+    match publisher {
+        Some(publisher) => publisher(message),
+        None => log::warn!(
+            "No response publisher, auto-response to {:?} not sent",
+            message.original_chime_id
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn state(name: &str, description: &str) -> CustomLcgpState {
+        CustomLcgpState {
+            name: name.to_string(),
+            should_chime: true,
+            auto_response: None,
+            auto_response_delay: None,
+            description: Some(description.to_string()),
+            priority: None,
+            active_hours: None,
+            conditions: vec![],
+        }
+    }
+
+    #[test]
+    fn import_states_resolves_name_conflicts() {
+        let import = |policy| {
+            let node = LcgpNode::new("node".to_string());
+            node.register_custom_state(state("Focus", "local"));
+            node.register_custom_state(state("Focus_2", "local"));
+            let report = node.import_states(
+                vec![
+                    state("Focus", "theirs"),
+                    state("Lunch", "theirs"),
+                    state("", "broken"),
+                ],
+                policy,
+            );
+            (node, report)
+        };
+
+        let (node, report) = import(ImportPolicy::Skip);
+        assert_eq!(report.imported, vec!["Lunch"]);
+        assert_eq!(report.skipped, vec!["Focus"]);
+        assert_eq!(report.invalid.len(), 1);
+        assert_eq!(
+            node.get_custom_state("Focus")
+                .unwrap()
+                .description
+                .as_deref(),
+            Some("local")
+        );
+
+        let (node, report) = import(ImportPolicy::Overwrite);
+        assert_eq!(report.overwritten, vec!["Focus"]);
+        assert_eq!(
+            node.get_custom_state("Focus")
+                .unwrap()
+                .description
+                .as_deref(),
+            Some("theirs")
+        );
+
+        let (node, report) = import(ImportPolicy::Rename);
+        assert_eq!(
+            report.renamed,
+            vec![("Focus".to_string(), "Focus_3".to_string())]
+        );
+        assert_eq!(report.imported, vec!["Focus_3", "Lunch"]);
+        assert_eq!(
+            node.get_custom_state("Focus")
+                .unwrap()
+                .description
+                .as_deref(),
+            Some("local")
+        );
+        assert_eq!(
+            node.get_custom_state("Focus_3")
+                .unwrap()
+                .description
+                .as_deref(),
+            Some("theirs")
+        );
+    }
+
+    #[test]
+    fn imported_states_without_local_behavior_are_reported() {
+        struct Quiet;
+        impl CustomBehavior for Quiet {
+            fn on_incoming_chime(&self, _: &ChimeMessage, _: &CustomLcgpState) -> BehaviorResult {
+                self.on_timeout(&state("", ""))
+            }
+
+            fn on_user_response(&self, _: &ChimeResponse, _: &CustomLcgpState) -> BehaviorResult {
+                self.on_timeout(&state("", ""))
+            }
+
+            fn on_timeout(&self, _: &CustomLcgpState) -> BehaviorResult {
+                BehaviorResult {
+                    should_chime: false,
+                    auto_response: None,
+                    delay_ms: None,
+                    next_state: None,
+                    note: None,
+                }
+            }
+
+            fn evaluate_conditions(&self, _: &CustomLcgpState) -> bool {
+                false
+            }
+        }
+
+        let node = LcgpNode::new("node".to_string());
+        node.register_custom_behavior("Focus".to_string(), Box::new(Quiet));
+        let report = node.import_states(
+            vec![state("Focus", "theirs"), state("Lunch", "theirs")],
+            ImportPolicy::Skip,
+        );
+
+        assert_eq!(report.imported, vec!["Focus", "Lunch"]);
+        assert_eq!(report.without_behavior, vec!["Lunch"]);
+    }
+
+    #[test]
+    fn states_round_trip_through_a_file() {
+        let dir = std::env::temp_dir().join(format!("chimenet-states-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("states.json");
+
+        let saved = LcgpHandler::new(Arc::new(LcgpNode::new("node".to_string())));
+        saved.register_custom_state(state("Lunch", "saved"));
+        saved.register_custom_state(state("Focus", "saved"));
+        assert_eq!(saved.save_states_to_file(&path).unwrap(), 2);
+        assert!(!path.with_extension("tmp").exists());
+
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(state("Focus", "built in"));
+        let loaded = LcgpHandler::new(node.clone());
+        let report = loaded.load_states_from_file(&path).unwrap();
+        assert_eq!(report.overwritten, vec!["Focus"]);
+        assert_eq!(report.without_behavior, vec!["Focus", "Lunch"]);
+        let described: Vec<_> = node
+            .export_states()
+            .into_iter()
+            .map(|state| (state.name, state.description.unwrap()))
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                ("Focus".to_string(), "saved".to_string()),
+                ("Lunch".to_string(), "saved".to_string())
+            ]
+        );
+
+        std::fs::write(&path, "{not json").unwrap();
+        assert!(loaded.load_states_from_file(&path).is_err());
+        assert!(loaded
+            .load_states_from_file(dir.join("missing.json"))
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pending_rings_are_answered_individually() {
+        let node = LcgpNode::new("node".to_string());
+        node.add_pending_response("abc".to_string(), "alice".to_string());
+        node.add_pending_response("abc".to_string(), "bob".to_string());
+        node.add_pending_response("abc".to_string(), "carol".to_string());
+
+        let from = |rings: Vec<PendingRing>| -> Vec<String> {
+            rings.into_iter().map(|ring| ring.from_node).collect()
+        };
+
+        assert_eq!(node.take_pending_ring(1).unwrap().from_node, "bob");
+        assert!(node.take_pending_ring(2).is_none());
+        assert_eq!(from(node.pending_rings()), vec!["alice", "carol"]);
+
+        // Answering by chime id takes the oldest ring only
+        assert_eq!(
+            node.remove_pending_response("abc").unwrap().from_node,
+            "alice"
+        );
+        assert!(node.has_pending_response("abc"));
+        assert_eq!(from(node.pending_rings()), vec!["carol"]);
+    }
+
+    #[tokio::test]
+    async fn reset_cancels_delayed_responses() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.set_mode(LcgpMode::ChillGrinding);
+        let handler = LcgpHandler::new(node.clone());
+
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        assert!(handler.handle_incoming_chime(chime).await.is_none());
+        node.add_pending_response("def".to_string(), "bob".to_string());
+
+        assert_eq!(handler.reset(), (2, 1));
+        assert!(handler.pending_rings().is_empty());
+        assert_eq!(handler.delayed_response_count(), 0);
+        assert_eq!(handler.reset(), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_rings_never_get_their_auto_response() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.set_mode(LcgpMode::ChillGrinding);
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |message: ChimeResponseMessage| {
+                sink.lock().unwrap().push(message.original_chime_id)
+            },
+        ));
+
+        for id in ["abc", "def"] {
+            let chime = node.create_chime_message(None, Some(id.to_string()), None, None);
+            assert!(handler.handle_incoming_chime(chime).await.is_none());
+        }
+        assert_eq!(node.pending_response_ids(), vec!["abc", "def"]);
+
+        assert!(handler.cancel_pending("abc"));
+        assert!(!handler.cancel_pending("abc"));
+        assert_eq!(node.pending_response_ids(), vec!["def"]);
+        assert_eq!(
+            handler.delayed_response_chime_ids(),
+            vec!["def".to_string()]
+        );
+
+        tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+        assert_eq!(*sent.lock().unwrap(), vec![Some("def".to_string())]);
+        assert!(node.pending_response_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn manual_response_clears_the_pending_ring() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.set_mode(LcgpMode::Available);
+        node.set_condition_bool("present".to_string(), true);
+        let handler = LcgpHandler::new(node.clone());
+
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        assert!(handler.handle_incoming_chime(chime).await.is_none());
+        assert_eq!(node.pending_response_count(), 1);
+        assert_eq!(handler.delayed_response_count(), 0);
+
+        assert!(handler
+            .handle_user_response(ChimeResponse::Negative, Some("abc".to_string()))
+            .is_some());
+        assert_eq!(node.pending_response_count(), 0);
+        assert_eq!(
+            node.state_conditions_snapshot(),
+            HashMap::from([("present".to_string(), ConditionValue::Bool(true))])
+        );
+    }
+
+    #[tokio::test]
+    async fn mode_update_timer_refuses_a_zero_interval() {
+        let handler = LcgpHandler::new(Arc::new(LcgpNode::new("node".to_string())));
+        let error = handler
+            .start_mode_update_timer(Duration::ZERO)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), Some("config"));
+        handler
+            .start_mode_update_timer(Duration::from_secs(60))
+            .await
+            .unwrap()
+            .abort();
+    }
+
+    #[test]
+    fn mode_updates_go_to_the_publisher() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let handler = LcgpHandler::new(node.clone()).with_mode_publisher(Arc::new(
+            move |update: ModeUpdate| sink.lock().unwrap().push(update.mode),
+        ));
+
+        node.set_mode(LcgpMode::DoNotDisturb);
+        handler.publish_mode_update();
+        LcgpHandler::new(node).publish_mode_update();
+
+        assert_eq!(*sent.lock().unwrap(), vec![LcgpMode::DoNotDisturb]);
+    }
+
+    #[test]
+    fn removing_the_current_custom_state_publishes_available() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let handler = LcgpHandler::new(node.clone()).with_mode_publisher(Arc::new(
+            move |update: ModeUpdate| sink.lock().unwrap().push(update.mode),
+        ));
+        for name in ["Gym", "Lunch"] {
+            handler
+                .register_custom_state(CustomLcgpState::from_args(name, &["chime=true"]).unwrap());
+        }
+        node.set_custom_mode("Gym".to_string()).unwrap();
+
+        assert!(handler.remove_custom_state("Lunch").is_some());
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(handler.remove_custom_state("Gym").is_some());
+        assert_eq!(*sent.lock().unwrap(), vec![LcgpMode::Available]);
+    }
+
+    fn delayed_state(delay_ms: u64) -> CustomLcgpState {
+        CustomLcgpState {
+            auto_response: Some(ChimeResponse::Positive),
+            auto_response_delay: Some(delay_ms),
+            ..state("Delayed", "auto-responds later")
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn chill_grinding_publishes_its_response_after_the_delay() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.set_mode(LcgpMode::ChillGrinding);
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = published.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |response: ChimeResponseMessage| sink.lock().unwrap().push(response),
+        ));
+
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        assert!(handler.handle_incoming_chime(chime).await.is_none());
+        tokio::time::sleep(Duration::from_millis(9_000)).await;
+        assert!(published.lock().unwrap().is_empty());
+
+        tokio::time::sleep(Duration::from_millis(2_000)).await;
+        let published = published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].response, ChimeResponse::Positive);
+        assert_eq!(published[0].original_chime_id, Some("abc".to_string()));
+        assert!(!node.has_pending_response("abc"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn manual_response_suppresses_the_delayed_one() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.set_mode(LcgpMode::ChillGrinding);
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = published.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |response: ChimeResponseMessage| sink.lock().unwrap().push(response),
+        ));
+
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        handler.handle_incoming_chime(chime).await;
+        assert!(handler
+            .handle_user_response(ChimeResponse::Negative, Some("abc".to_string()))
+            .is_some());
+        tokio::time::sleep(Duration::from_millis(11_000)).await;
+        assert!(published.lock().unwrap().is_empty());
+    }
+
+    // Declines whatever timed out and moves on to "After"
+    struct DeclineOnTimeout {
+        timeouts: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl CustomBehavior for DeclineOnTimeout {
+        fn on_incoming_chime(&self, _: &ChimeMessage, state: &CustomLcgpState) -> BehaviorResult {
+            BehaviorResult {
+                should_chime: true,
+                auto_response: state.auto_response.clone(),
+                delay_ms: state.auto_response_delay,
+                next_state: None,
+                note: None,
+            }
+        }
+
+        fn on_user_response(&self, _: &ChimeResponse, _: &CustomLcgpState) -> BehaviorResult {
+            BehaviorResult {
+                should_chime: true,
+                auto_response: None,
+                delay_ms: None,
+                next_state: None,
+                note: None,
+            }
+        }
+
+        fn on_timeout(&self, _: &CustomLcgpState) -> BehaviorResult {
+            self.timeouts
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            BehaviorResult {
+                should_chime: false,
+                auto_response: Some(ChimeResponse::Negative),
+                delay_ms: None,
+                next_state: Some("After".to_string()),
+                note: None,
+            }
+        }
+
+        fn evaluate_conditions(&self, _: &CustomLcgpState) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn removing_the_current_custom_state_falls_back_to_available() {
+        let node = LcgpNode::new("node".to_string());
+        node.register_custom_state(
+            CustomLcgpState::from_args("Gym", &["chime=false", "auto=neg"]).unwrap(),
+        );
+        node.set_custom_mode("Gym".to_string()).unwrap();
+
+        assert!(node.remove_custom_state("Gym").is_some());
+        assert_eq!(node.get_mode(), LcgpMode::Available);
+        assert!(node.get_custom_state("Gym").is_none());
+        assert!(node.remove_custom_state("Gym").is_none());
+        assert!(node.set_custom_mode("Gym".to_string()).is_err());
+
+        // Other modes are left alone
+        node.register_custom_state(CustomLcgpState::from_args("Gym", &["chime=true"]).unwrap());
+        node.set_mode(LcgpMode::Grinding);
+        assert!(node.remove_custom_state("Gym").is_some());
+        assert_eq!(node.get_mode(), LcgpMode::Grinding);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out_custom_states_run_on_timeout() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(delayed_state(5_000));
+        node.register_custom_state(state("After", "where the timeout leads"));
+        let timeouts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        node.register_custom_behavior(
+            "Delayed".to_string(),
+            Box::new(DeclineOnTimeout {
+                timeouts: timeouts.clone(),
+            }),
+        );
+        node.set_custom_mode("Delayed".to_string()).unwrap();
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = published.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |response: ChimeResponseMessage| sink.lock().unwrap().push(response),
+        ));
+
+        // Answered in time: no timeout
+        let chime = node.create_chime_message(None, Some("early".to_string()), None, None);
+        handler.handle_incoming_chime(chime).await;
+        handler.handle_user_response(ChimeResponse::Positive, Some("early".to_string()));
+        tokio::time::sleep(Duration::from_millis(6_000)).await;
+        assert_eq!(timeouts.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        let chime = node.create_chime_message(None, Some("late".to_string()), None, None);
+        handler.handle_incoming_chime(chime).await;
+        tokio::time::sleep(Duration::from_millis(6_000)).await;
+
+        assert_eq!(timeouts.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let published = published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].response, ChimeResponse::Negative);
+        assert_eq!(published[0].original_chime_id, Some("late".to_string()));
+        assert_eq!(node.get_mode(), LcgpMode::Custom("After".to_string()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicted_delayed_responses_still_run_on_timeout() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(delayed_state(60_000));
+        node.register_custom_state(state("After", "where the timeout leads"));
+        let timeouts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        node.register_custom_behavior(
+            "Delayed".to_string(),
+            Box::new(DeclineOnTimeout {
+                timeouts: timeouts.clone(),
+            }),
+        );
+        node.set_custom_mode("Delayed".to_string()).unwrap();
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = published.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |response: ChimeResponseMessage| sink.lock().unwrap().push(response),
+        ));
+        handler.set_max_delayed_responses(1);
+
+        for chime_id in ["a", "b"] {
+            let chime = node.create_chime_message(None, Some(chime_id.to_string()), None, None);
+            handler.handle_incoming_chime(chime).await;
+        }
+
+        assert_eq!(timeouts.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let published = published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].response, ChimeResponse::Negative);
+        assert_eq!(published[0].original_chime_id, Some("a".to_string()));
+        assert_eq!(node.get_mode(), LcgpMode::Custom("After".to_string()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delayed_responses_answer_their_own_ring() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = published.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |response: ChimeResponseMessage| sink.lock().unwrap().push(response.in_reply_to),
+        ));
+        let ring = |request_id: &str| ChimeMessage {
+            request_id: Some(request_id.to_string()),
+            ..node.create_chime_message(None, Some("abc".to_string()), None, None)
+        };
+        let pending = || -> Vec<Option<String>> {
+            node.pending_rings()
+                .into_iter()
+                .map(|ring| ring.request_id)
+                .collect()
+        };
+
+        // An older ring for the same chime waits for the user meanwhile
+        node.set_mode(LcgpMode::Available);
+        handler.handle_incoming_chime(ring("manual")).await;
+        node.set_mode(LcgpMode::ChillGrinding);
+        handler.handle_incoming_chime(ring("answered")).await;
+        handler.handle_incoming_chime(ring("delayed")).await;
+
+        // Answering a ring by hand drops its delayed response, not another's
+        let (answered, _) = handler
+            .handle_pending_response(1, ChimeResponse::Negative)
+            .unwrap();
+        assert_eq!(answered.request_id.as_deref(), Some("answered"));
+        assert_eq!(handler.delayed_response_count(), 1);
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(
+            *published.lock().unwrap(),
+            vec![Some("delayed".to_string())]
+        );
+        assert_eq!(pending(), vec![Some("manual".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn finished_delayed_responses_are_pruned() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(delayed_state(1));
+        node.set_custom_mode("Delayed".to_string()).unwrap();
+        let handler = LcgpHandler::new(node.clone());
+
+        for i in 0..10 {
+            let chime = node.create_chime_message(None, Some(format!("c{}", i)), None, None);
+            handler.handle_incoming_chime(chime).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let chime = node.create_chime_message(None, Some("last".to_string()), None, None);
+        handler.handle_incoming_chime(chime).await;
+        assert_eq!(handler.delayed_response_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn oldest_delayed_response_resolves_when_over_the_limit() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(delayed_state(60_000));
+        node.set_custom_mode("Delayed".to_string()).unwrap();
+        let handler = LcgpHandler::new(node.clone());
+        handler.set_max_delayed_responses(2);
+
+        for chime_id in ["a", "b", "c"] {
+            let chime = node.create_chime_message(None, Some(chime_id.to_string()), None, None);
+            assert!(handler.handle_incoming_chime(chime).await.is_none());
+        }
+
+        assert_eq!(
+            handler.delayed_response_chime_ids(),
+            vec!["b".to_string(), "c".to_string()]
+        );
+        assert!(!node.has_pending_response("a"));
+        assert!(node.has_pending_response("b"));
+        assert!(node.has_pending_response("c"));
+    }
+
+    #[test]
+    fn grinding_response_polarity_is_configurable() {
+        let node = LcgpNode::new("node".to_string());
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+
+        node.set_mode(LcgpMode::Grinding);
+        assert_eq!(
+            node.should_auto_respond(&chime),
+            Some((ChimeResponse::Positive, None))
+        );
+
+        node.set_grinding_responses(GrindingResponses {
+            grinding: ChimeResponse::Negative,
+            ..GrindingResponses::default()
+        });
+        assert_eq!(
+            node.should_auto_respond(&chime),
+            Some((ChimeResponse::Negative, None))
+        );
+
+        node.set_mode(LcgpMode::ChillGrinding);
+        assert_eq!(
+            node.should_auto_respond(&chime),
+            Some((ChimeResponse::Positive, Some(10000)))
+        );
+    }
+
+    // Chimes only for rings that carry notes, declining the rest after 250ms,
+    // whatever its state's config says
+    struct NotesOnly;
+
+    impl CustomBehavior for NotesOnly {
+        fn on_incoming_chime(&self, chime: &ChimeMessage, _: &CustomLcgpState) -> BehaviorResult {
+            let has_notes = chime.notes.is_some();
+            BehaviorResult {
+                should_chime: has_notes,
+                auto_response: (!has_notes).then_some(ChimeResponse::Negative),
+                delay_ms: Some(250),
+                next_state: None,
+                note: None,
+            }
+        }
+
+        fn on_user_response(&self, _: &ChimeResponse, _: &CustomLcgpState) -> BehaviorResult {
+            BehaviorResult {
+                should_chime: true,
+                auto_response: None,
+                delay_ms: None,
+                next_state: None,
+                note: None,
+            }
+        }
+
+        fn on_timeout(&self, state: &CustomLcgpState) -> BehaviorResult {
+            self.on_user_response(&ChimeResponse::Positive, state)
+        }
+
+        fn evaluate_conditions(&self, _: &CustomLcgpState) -> bool {
+            true
+        }
+    }
+
+    // Declines straight away, and explains itself whichever way it answers
+    struct Meeting;
+
+    impl CustomBehavior for Meeting {
+        fn on_incoming_chime(&self, _: &ChimeMessage, _: &CustomLcgpState) -> BehaviorResult {
+            BehaviorResult {
+                should_chime: true,
+                auto_response: Some(ChimeResponse::Negative),
+                delay_ms: None,
+                next_state: None,
+                note: Some("in a meeting".to_string()),
+            }
+        }
+
+        fn on_user_response(&self, _: &ChimeResponse, _: &CustomLcgpState) -> BehaviorResult {
+            BehaviorResult {
+                should_chime: true,
+                auto_response: None,
+                delay_ms: None,
+                next_state: None,
+                note: Some("call you back".to_string()),
+            }
+        }
+
+        fn on_timeout(&self, state: &CustomLcgpState) -> BehaviorResult {
+            self.on_user_response(&ChimeResponse::Negative, state)
+        }
+
+        fn evaluate_conditions(&self, _: &CustomLcgpState) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn behavior_notes_become_response_messages() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let handler = LcgpHandler::new(node.clone());
+        node.register_custom_state(state("Meeting", "busy"));
+        node.register_custom_behavior("Meeting".to_string(), Box::new(Meeting));
+        node.set_custom_mode("Meeting".to_string()).unwrap();
+
+        let ring = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        let auto = handler.handle_incoming_chime(ring).await.unwrap();
+        assert_eq!(auto.response, ChimeResponse::Negative);
+        assert_eq!(auto.message.as_deref(), Some("in a meeting"));
+
+        let manual = handler
+            .handle_user_response(ChimeResponse::Negative, Some("abc".to_string()))
+            .unwrap();
+        assert_eq!(manual.message.as_deref(), Some("call you back"));
+
+        node.set_mode(LcgpMode::Available);
+        let plain = handler
+            .handle_user_response(ChimeResponse::Positive, Some("abc".to_string()))
+            .unwrap();
+        assert_eq!(plain.message, None);
+    }
+
+    #[tokio::test]
+    async fn responses_echo_the_request_id_of_their_ring() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let handler = LcgpHandler::new(node.clone());
+        let ring = |request_id: &str| ChimeMessage {
+            request_id: Some(request_id.to_string()),
+            ..node.create_chime_message(None, Some("abc".to_string()), None, None)
+        };
+
+        node.set_mode(LcgpMode::Grinding);
+        let immediate = handler.handle_incoming_chime(ring("r1")).await.unwrap();
+        assert_eq!(immediate.in_reply_to.as_deref(), Some("r1"));
+
+        node.set_mode(LcgpMode::Available);
+        assert!(handler.handle_incoming_chime(ring("r2")).await.is_none());
+        assert!(handler.handle_incoming_chime(ring("r3")).await.is_none());
+        let first = handler
+            .handle_user_response(ChimeResponse::Positive, Some("abc".to_string()))
+            .unwrap();
+        assert_eq!(first.in_reply_to.as_deref(), Some("r2"));
+        let (_, second) = handler
+            .handle_pending_response(0, ChimeResponse::Negative)
+            .unwrap();
+        assert_eq!(second.in_reply_to.as_deref(), Some("r3"));
+
+        // Answering with nothing pending has nothing to echo
+        let unprompted = handler
+            .handle_user_response(ChimeResponse::Positive, Some("abc".to_string()))
+            .unwrap();
+        assert_eq!(unprompted.in_reply_to, None);
+    }
+
+    // Drives each ring through the handler, so the auto responses checked are
+    // the ones the ringer actually gets
+    #[tokio::test(start_paused = true)]
+    async fn lcgp_decision_matrix() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(CustomLcgpState {
+            should_chime: false,
+            auto_response: Some(ChimeResponse::Negative),
+            auto_response_delay: Some(500),
+            ..state("Meeting", "config only")
+        });
+        node.register_custom_state(CustomLcgpState {
+            should_chime: false,
+            auto_response: Some(ChimeResponse::Positive),
+            ..state("Focus", "overridden by NotesOnly")
+        });
+        node.register_custom_behavior("Focus".to_string(), Box::new(NotesOnly));
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |message: ChimeResponseMessage| {
+                sink.lock()
+                    .unwrap()
+                    .push((message.response, tokio::time::Instant::now()))
+            },
+        ));
+
+        let plain = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        let with_notes = ChimeMessage {
+            notes: Some(vec!["C4".to_string()]),
+            ..plain.clone()
+        };
+
+        let custom = |name: &str| LcgpMode::Custom(name.to_string());
+        let cases = [
+            (LcgpMode::DoNotDisturb, &plain, false, None),
+            (LcgpMode::Available, &plain, true, None),
+            (
+                LcgpMode::ChillGrinding,
+                &plain,
+                true,
+                Some((ChimeResponse::Positive, Some(10000))),
+            ),
+            (
+                LcgpMode::Grinding,
+                &plain,
+                true,
+                Some((ChimeResponse::Positive, None)),
+            ),
+            (
+                custom("Meeting"),
+                &plain,
+                false,
+                Some((ChimeResponse::Negative, Some(500))),
+            ),
+            (
+                custom("Focus"),
+                &plain,
+                false,
+                Some((ChimeResponse::Negative, Some(250))),
+            ),
+            (custom("Focus"), &with_notes, true, None),
+            (custom("Unknown"), &plain, false, None),
+        ];
+
+        for (i, (mode, chime, should_chime, auto_response)) in cases.into_iter().enumerate() {
+            // Set directly: set_custom_mode refuses unknown states
+            *node.mode.lock().unwrap() = mode.clone();
+            // A chime id per case, so no ring is left pending under another's
+            let chime = ChimeMessage {
+                chime_id: Some(format!("ring{}", i)),
+                ..chime.clone()
+            };
+
+            let rung_at = tokio::time::Instant::now();
+            let outcome = handler.handle_incoming_ring(chime).await;
+            assert_eq!(outcome.should_chime, should_chime, "{:?}", mode);
+
+            let answered = match outcome.response {
+                Some(message) => Some((message.response, None)),
+                None => {
+                    tokio::time::sleep(Duration::from_secs(3600)).await;
+                    sent.lock().unwrap().pop().map(|(response, sent_at)| {
+                        let delay = sent_at.duration_since(rung_at).as_millis() as u64;
+                        (response, Some(delay))
+                    })
+                }
+            };
+            assert_eq!(answered, auto_response, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn previews_show_the_reaction_without_causing_it() {
+        let node = LcgpNode::new("node".to_string());
+        node.register_custom_state(CustomLcgpState {
+            should_chime: false,
+            auto_response: Some(ChimeResponse::Negative),
+            ..state("Meeting", "swallows rings")
+        });
+        node.register_custom_state(state("Focus", "NotesOnly decides"));
+        node.register_custom_behavior("Focus".to_string(), Box::new(NotesOnly));
+        let plain = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        let with_notes = ChimeMessage {
+            notes: Some(vec!["C4".to_string()]),
+            ..plain.clone()
+        };
+        let preview = |mode: LcgpMode, chime: &ChimeMessage| {
+            *node.mode.lock().unwrap() = mode;
+            let preview = node.preview_reaction(chime);
+            (preview.would_chime, preview.auto_response, preview.note)
+        };
+
+        assert_eq!(
+            preview(LcgpMode::ChillGrinding, &plain),
+            (true, Some((ChimeResponse::Positive, Some(10000))), None)
+        );
+        assert_eq!(preview(LcgpMode::DoNotDisturb, &plain), (false, None, None));
+        // A state that doesn't chime still answers with its auto response
+        let meeting = LcgpMode::Custom("Meeting".to_string());
+        let declined = (false, Some((ChimeResponse::Negative, None)), None);
+        assert_eq!(preview(meeting.clone(), &plain), declined);
+        let focus = LcgpMode::Custom("Focus".to_string());
+        assert_eq!(preview(focus, &with_notes), (true, None, None));
+
+        // The away message only stands in for a missing auto response
+        node.set_away_message(Some("back at 3".to_string()));
+        assert_eq!(preview(meeting, &plain), declined);
+        assert_eq!(node.preview_reaction(&plain).active_state, "Custom:Meeting");
+        assert_eq!(
+            preview(LcgpMode::DoNotDisturb, &plain),
+            (
+                false,
+                Some((ChimeResponse::Negative, None)),
+                Some("back at 3".to_string())
+            )
+        );
+
+        let stats = node.response_stats();
+        assert_eq!((stats.positive, stats.negative), (0, 0));
+        assert_eq!(node.ring_rate_per_hour(), 0.0);
+        assert!(node.pending_rings().is_empty());
+    }
+
+    #[tokio::test]
+    async fn handler_responds_per_mode() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let handler = LcgpHandler::new(node.clone());
+        let ring = |chime_id: &str| {
+            node.create_chime_message(None, Some(chime_id.to_string()), None, None)
+        };
+
+        // DND: blocked, nothing sent back and nothing left waiting
+        node.set_mode(LcgpMode::DoNotDisturb);
+        assert!(handler.handle_incoming_chime(ring("dnd")).await.is_none());
+        assert!(!node.has_pending_response("dnd"));
+
+        // Available: chimes and waits for the user
+        node.set_mode(LcgpMode::Available);
+        assert!(handler
+            .handle_incoming_chime(ring("available"))
+            .await
+            .is_none());
+        assert!(node.has_pending_response("available"));
+
+        // Grinding: answered on the spot
+        node.set_mode(LcgpMode::Grinding);
+        let response = handler
+            .handle_incoming_chime(ring("grinding"))
+            .await
+            .unwrap();
+        assert_eq!(response.response, ChimeResponse::Positive);
+        assert_eq!(response.original_chime_id.as_deref(), Some("grinding"));
+        assert!(!node.has_pending_response("grinding"));
+
+        // ChillGrinding: answered later, pending until then
+        node.set_mode(LcgpMode::ChillGrinding);
+        assert!(handler.handle_incoming_chime(ring("chill")).await.is_none());
+        assert!(node.has_pending_response("chill"));
+    }
+
+    #[tokio::test]
+    async fn rings_and_responses_are_summarized() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let handler = LcgpHandler::new(node.clone());
+        assert_eq!(node.ring_summary().last_ring_at, None);
+        assert_eq!(node.response_stats(), ResponseStats::default());
+
+        node.set_mode(LcgpMode::Grinding);
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        handler.handle_incoming_chime(chime.clone()).await;
+        node.set_mode(LcgpMode::Available);
+        handler.handle_incoming_chime(chime).await;
+        handler.handle_user_response(ChimeResponse::Negative, Some("abc".to_string()));
+
+        let rings = node.ring_summary();
+        assert!(rings.last_ring_at.is_some());
+        assert!(rings.rings_per_hour > 0.0);
+
+        let stats = node.response_stats();
+        assert_eq!((stats.positive, stats.negative), (1, 1));
+        assert!(stats.last_response_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn snoozing_answers_the_ring_manually_or_automatically() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let handler = LcgpHandler::new(node.clone());
+        let snooze = ChimeResponse::Snooze { minutes: 15 };
+        let chime = node.create_chime_message(None, Some("abc".to_string()), None, None);
+
+        handler.handle_incoming_chime(chime.clone()).await;
+        let reply = handler
+            .handle_user_response(snooze.clone(), Some("abc".to_string()))
+            .unwrap();
+        assert_eq!(reply.response, snooze);
+        assert_eq!(node.pending_response_count(), 0);
+
+        node.set_grinding_responses(GrindingResponses {
+            grinding: snooze.clone(),
+            ..GrindingResponses::default()
+        });
+        node.set_mode(LcgpMode::Grinding);
+        assert_eq!(
+            handler.handle_incoming_chime(chime).await.unwrap().response,
+            snooze
+        );
+
+        let stats = node.response_stats();
+        assert_eq!((stats.positive, stats.negative, stats.snoozed), (0, 0, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn system_load_conditions_follow_the_sampled_load() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(CustomLcgpState {
+            conditions: vec![StateCondition::SystemLoad(0.8)],
+            ..state("Busy", "heavy load")
+        });
+        assert_eq!(node.evaluate_auto_state_transitions(), None);
+
+        let samples = Arc::new(Mutex::new(vec![Some(0.5), Some(0.9), None]));
+        let source = samples.clone();
+        let monitor = LcgpHandler::new(node.clone())
+            .start_system_monitor_with(Duration::from_secs(15), move || {
+                source.lock().unwrap().remove(0)
+            });
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(node.system_load(), Some(0.5));
+        assert_eq!(node.evaluate_auto_state_transitions(), None);
+
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert_eq!(
+            node.evaluate_auto_state_transitions(),
+            Some(("Busy".to_string(), 0))
+        );
+
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert_eq!(node.system_load(), None);
+        assert_eq!(node.evaluate_auto_state_transitions(), None);
+        monitor.abort();
+
+        assert_eq!(
+            parse_load_average("3.00 2.10 1.50 2/345 6789\n", 4),
+            Some(0.75)
+        );
+        assert_eq!(parse_load_average("", 4), None);
+    }
+
+    #[test]
+    fn equal_priorities_go_to_the_first_name() {
+        let with_states = || {
+            let node = LcgpNode::new("node".to_string());
+            for (name, priority) in [("Zen", Some(50)), ("Focus", Some(50)), ("Idle", None)] {
+                node.register_custom_state(CustomLcgpState {
+                    priority,
+                    ..state(name, "always satisfied")
+                });
+            }
+            node
+        };
+
+        // Every node's map is seeded differently, so its order varies; the
+        // pick must not
+        for _ in 0..10 {
+            assert_eq!(
+                with_states().evaluate_auto_state_transitions(),
+                Some(("Focus".to_string(), 50))
+            );
+        }
+
+        let node = with_states();
+
+        node.remove_custom_state("Focus");
+        node.remove_custom_state("Zen");
+        node.register_custom_state(CustomLcgpState {
+            priority: Some(0),
+            ..state("Away", "always satisfied")
+        });
+        // An unset priority is 0, tied with an explicit one
+        assert_eq!(
+            node.evaluate_auto_state_transitions(),
+            Some(("Away".to_string(), 0))
+        );
+    }
+
+    #[test]
+    fn conditions_compare_typed_values() {
+        let node = LcgpNode::new("node".to_string());
+        node.register_custom_state(CustomLcgpState {
+            conditions: vec![
+                StateCondition::UserPresence(true),
+                StateCondition::Custom("location".to_string(), "home office".to_string()),
+                StateCondition::Custom("floor".to_string(), "2".to_string()),
+            ],
+            ..state("Home", "working from home")
+        });
+        let active = || node.evaluate_auto_state_transitions().is_some();
+
+        node.set_condition_bool("user_presence".to_string(), true);
+        node.set_condition_text("location".to_string(), "home office".to_string());
+        assert!(!active());
+
+        node.set_condition("floor".to_string(), "2".parse().unwrap());
+        assert!(active());
+
+        // Text that merely reads as true isn't a flag, and "office" isn't "home office"
+        node.set_condition_text("user_presence".to_string(), "true".to_string());
+        assert!(!active());
+        node.set_condition_bool("user_presence".to_string(), true);
+        node.set_condition_text("location".to_string(), "office".to_string());
+        assert!(!active());
+    }
+
+    #[test]
+    fn compare_and_set_mode_requires_expected_mode() {
+        let node = LcgpNode::new("node".to_string());
+
+        assert!(!node.compare_and_set_mode(&LcgpMode::Grinding, LcgpMode::DoNotDisturb));
+        assert_eq!(node.get_mode(), LcgpMode::Available);
+
+        assert!(node.compare_and_set_mode(&LcgpMode::Available, LcgpMode::DoNotDisturb));
+        assert_eq!(node.get_mode(), LcgpMode::DoNotDisturb);
+    }
+
+    #[test]
+    fn timed_modes_revert_once_unless_changed_meanwhile() {
+        let node = LcgpNode::new("alice_abc".to_string());
+        node.set_mode(LcgpMode::Grinding);
+        let start = Utc::now();
+        let later = |minutes| start + chrono::Duration::minutes(minutes);
+
+        node.set_mode_until(LcgpMode::DoNotDisturb, later(30));
+        assert_eq!(node.expire_mode(later(29)), None);
+        // Extending keeps the mode from before the first one
+        node.set_mode_until(LcgpMode::DoNotDisturb, later(60));
+        assert_eq!(node.mode_expiry().unwrap().previous, LcgpMode::Grinding);
+        assert_eq!(node.expire_mode(later(45)), None);
+        assert_eq!(node.expire_mode(later(60)), Some(LcgpMode::Grinding));
+        assert_eq!(node.get_mode(), LcgpMode::Grinding);
+        assert_eq!(node.mode_expiry(), None);
+        assert_eq!(node.expire_mode(later(90)), None);
+
+        // A manual change cancels the revert
+        node.set_mode_until(LcgpMode::DoNotDisturb, later(30));
+        node.set_mode(LcgpMode::Available);
+        assert_eq!(node.expire_mode(later(30)), None);
+        assert_eq!(node.get_mode(), LcgpMode::Available);
+    }
+
+    #[test]
+    fn concurrent_compare_and_set_has_single_winner() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let node = node.clone();
+                thread::spawn(move || {
+                    node.compare_and_set_mode(
+                        &LcgpMode::Available,
+                        LcgpMode::Custom(format!("state_{}", i)),
+                    )
+                })
+            })
+            .collect();
+
+        let winners = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|won| *won)
+            .count();
+
+        assert_eq!(winners, 1);
+        assert!(matches!(node.get_mode(), LcgpMode::Custom(_)));
+    }
+
+    #[test]
+    fn mode_durations_cover_window_and_open_interval() {
+        let start = Utc::now();
+        let minutes = |m| start + chrono::Duration::minutes(m);
+        let mut history = ModeHistory::default();
+        history.record(LcgpMode::Available, start);
+        history.record(LcgpMode::DoNotDisturb, minutes(60));
+        history.record(LcgpMode::DoNotDisturb, minutes(70)); // repeat is ignored
+        history.close(minutes(90));
+        history.record(LcgpMode::Grinding, minutes(100));
+
+        // Last 60 minutes: 30 of DND, a 10 minute gap, then 20 of the still-open Grinding
+        let durations = history.durations(Duration::from_secs(60 * 60), minutes(120));
+        assert_eq!(durations.len(), 2);
+        assert_eq!(
+            durations[&LcgpMode::DoNotDisturb],
+            Duration::from_secs(30 * 60)
+        );
+        assert_eq!(durations[&LcgpMode::Grinding], Duration::from_secs(20 * 60));
+
+        let all = history.durations(Duration::from_secs(24 * 60 * 60), minutes(120));
+        assert_eq!(all[&LcgpMode::Available], Duration::from_secs(60 * 60));
+        assert_eq!(all[&LcgpMode::DoNotDisturb], Duration::from_secs(30 * 60));
+    }
+
+    #[test]
+    fn weekend_days_match_their_time_range_numbers() {
+        let node = LcgpNode::new("node".to_string());
+        let all_day = |days: Vec<u8>| TimeRange {
+            start_hour: 0,
+            start_minute: 0,
+            end_hour: 23,
+            end_minute: 59,
+            days_of_week: days,
+        };
+        let noon = |date: &str| {
+            DateTime::parse_from_rfc3339(&format!("{}T12:00:00Z", date))
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let (saturday, sunday) = (noon("2024-01-06"), noon("2024-01-07"));
+
+        for (days, on_saturday, on_sunday) in [
+            (vec![0], false, true),
+            (vec![6], true, false),
+            (vec![0, 6], true, true),
+            (vec![1, 2, 3, 4, 5], false, false),
+        ] {
+            let range = all_day(days.clone());
+            let weekend = CustomLcgpState {
+                active_hours: Some(range.clone()),
+                ..state("Weekend", "valid days")
+            };
+            assert!(weekend.validate().is_ok(), "{:?}", days);
+            assert_eq!(
+                node.is_time_in_range(&range, &saturday),
+                on_saturday,
+                "{:?}",
+                days
+            );
+            assert_eq!(
+                node.is_time_in_range(&range, &sunday),
+                on_sunday,
+                "{:?}",
+                days
+            );
+        }
+    }
+
+    // The custom_states example's `define-state` help suggests this state
+    #[tokio::test(start_paused = true)]
+    async fn a_defined_state_that_never_chimes_declines_after_its_delay() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        let args = ["chime=false", "auto=neg", "delay=1000", "priority=60"];
+        node.register_custom_state(CustomLcgpState::from_args("Gym", &args).unwrap());
+        node.set_custom_mode("Gym".to_string()).unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let handler = LcgpHandler::new(node.clone()).with_response_publisher(Arc::new(
+            move |message: ChimeResponseMessage| sink.lock().unwrap().push(message.response),
+        ));
+
+        let ring = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        let outcome = handler.handle_incoming_ring(ring).await;
+        assert!(!outcome.should_chime);
+        assert!(outcome.response.is_none());
+
+        tokio::time::sleep(Duration::from_millis(999)).await;
+        assert!(sent.lock().unwrap().is_empty());
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(*sent.lock().unwrap(), vec![ChimeResponse::Negative]);
+    }
+
+    // Chimes for every other ring, so asking it twice per ring gives
+    // contradicting answers
+    struct Alternating(std::sync::atomic::AtomicUsize);
+
+    impl CustomBehavior for Alternating {
+        fn on_incoming_chime(&self, _: &ChimeMessage, _: &CustomLcgpState) -> BehaviorResult {
+            let asked = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let should_chime = asked.is_multiple_of(2);
+            BehaviorResult {
+                should_chime,
+                auto_response: Some(if should_chime {
+                    ChimeResponse::Positive
+                } else {
+                    ChimeResponse::Negative
+                }),
+                delay_ms: None,
+                next_state: None,
+                note: None,
+            }
+        }
+
+        fn on_user_response(&self, _: &ChimeResponse, _: &CustomLcgpState) -> BehaviorResult {
+            BehaviorResult {
+                should_chime: true,
+                auto_response: None,
+                delay_ms: None,
+                next_state: None,
+                note: None,
+            }
+        }
+
+        fn on_timeout(&self, state: &CustomLcgpState) -> BehaviorResult {
+            self.on_user_response(&ChimeResponse::Positive, state)
+        }
+
+        fn evaluate_conditions(&self, _: &CustomLcgpState) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn a_ring_is_decided_once() {
+        let node = Arc::new(LcgpNode::new("node".to_string()));
+        node.register_custom_state(state("Moody", "asks Alternating"));
+        node.register_custom_behavior(
+            "Moody".to_string(),
+            Box::new(Alternating(Default::default())),
+        );
+        node.set_custom_mode("Moody".to_string()).unwrap();
+        let handler = LcgpHandler::new(node.clone());
+
+        let ring = node.create_chime_message(None, Some("abc".to_string()), None, None);
+        let outcome = handler.handle_incoming_ring(ring).await;
+        assert!(outcome.should_chime);
+        assert_eq!(outcome.response.unwrap().response, ChimeResponse::Positive);
+    }
+
+    #[test]
+    fn ring_activity_tracks_steady_rate_and_decays() {
+        let start = Instant::now();
+        let mut activity = RingActivity::new(start);
+
+        // One ring every 6 minutes for 10 hours: 10 rings/hour
+        let mut now = start;
+        for _ in 0..100 {
+            now += Duration::from_secs(6 * 60);
+            activity.record_at(now);
+        }
+        assert!((activity.rate_at(now) - 10.0).abs() < 1.0);
+
+        // An idle window later it has cooled to about 1/e
+        let later = activity.rate_at(now + RING_ACTIVITY_WINDOW);
+        assert!((later - activity.rate_at(now) / std::f64::consts::E).abs() < 1e-9);
+    }
+}
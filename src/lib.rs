@@ -1,10 +1,18 @@
 pub mod audio;
 pub mod chime;
+pub mod discovery;
+pub mod lan;
 pub mod lcgp;
 pub mod mqtt;
+pub mod notification;
+pub mod transport;
 pub mod types;
 
 pub use chime::*;
+pub use discovery::*;
+pub use lan::*;
 pub use lcgp::*;
 pub use mqtt::*;
+pub use notification::*;
+pub use transport::*;
 pub use types::*;
@@ -1,5 +1,3 @@
-use chimenet::*;
-
 fn main() {
     println!("ChimeNet Library");
     println!("This is a library crate. Use the examples in the examples/ directory.");
@@ -1,16 +1,303 @@
+use crate::transport::{
+    transport_for_url, HandlerSet, MessageHandler, MessagePredicate, NextMessage, SubscriptionId,
+    Transport,
+};
 use crate::types::*;
+use async_trait::async_trait;
 use futures::StreamExt;
 use paho_mqtt as mqtt;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
+type SubscriptionMap = Arc<Mutex<HashMap<String, Subscription>>>;
+type UnclaimedMessages = Arc<std::sync::Mutex<VecDeque<MqttMessage>>>;
+
+// Most messages held for filters nobody has subscribed to yet; past it the
+// oldest is dropped
+pub const MAX_UNCLAIMED_MESSAGES: usize = 256;
+
+// Default QoS per kind of message. QOS_STATE is for the retained state
+// topics (lists, notes, chords, status, ringer availability), QOS_RING for
+// one-off messages (rings, responses, mode changes, discovery requests).
+// ChimeNetMqtt::set_default_qos overrides both.
+pub const QOS_STATE: i32 = 1;
+pub const QOS_RING: i32 = 1;
+
+pub fn validate_qos(qos: i32) -> Result<()> {
+    if !(0..=2).contains(&qos) {
+        return Err(ChimeNetError::Config(format!(
+            "Invalid QoS {}, expected 0, 1 or 2",
+            qos
+        )));
+    }
+    Ok(())
+}
+
+// How long request_status waits for the chime's reply
+pub const STATUS_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often the client pings an otherwise idle broker; the broker drops a
+// client it hasn't heard from in 1.5 times this
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(20);
+
+// Delay before each automatic reconnect attempt: `initial` doubling per
+// failed attempt, up to `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    // `attempt` counts from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max)
+    }
+}
+
+// Shared with the stream task, which notices a lost connection
+struct ReconnectSettings {
+    enabled: AtomicBool,
+    // Set by disconnect() so a deliberate disconnect isn't undone
+    closing: AtomicBool,
+    // A reconnect loop is underway
+    running: AtomicBool,
+    backoff: std::sync::Mutex<ReconnectBackoff>,
+}
+
+impl ReconnectSettings {
+    fn active(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed) && !self.closing.load(Ordering::Relaxed)
+    }
+}
+
+// Kept so subscriptions can be re-issued after a reconnect. Several parts of
+// a node (its own ring handling, discovery, ...) may subscribe to the same
+// filter over one connection, so each filter holds every handler.
+struct Subscription {
+    qos: i32,
+    handlers: HandlerSet,
+}
+
 pub struct MqttClient {
     client: mqtt::AsyncClient,
     message_tx: mpsc::UnboundedSender<MqttMessage>,
-    subscriptions: Arc<Mutex<HashMap<String, Box<dyn Fn(String, String) + Send + Sync>>>>,
+    subscriptions: SubscriptionMap,
+    persistent_session: Arc<AtomicBool>,
+    keep_alive: std::sync::Mutex<Duration>,
+    credentials: std::sync::Mutex<Option<MqttCredentials>>,
+    // Set only for ssl:// and mqtts:// brokers, which always connect over TLS
+    tls: Option<std::sync::Mutex<MqttTls>>,
+    will: SharedWill,
+    // A persistent session's backlog arrives right after connecting, before
+    // the node has subscribed its handlers; it waits here until they do
+    unclaimed: UnclaimedMessages,
+    reconnect: Arc<ReconnectSettings>,
+}
+
+// Username and optional password for a broker that requires authentication
+#[derive(Clone, PartialEq, Eq)]
+pub struct MqttCredentials {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+impl MqttCredentials {
+    pub fn new(username: impl Into<String>, password: Option<String>) -> Self {
+        Self {
+            username: username.into(),
+            password,
+        }
+    }
+}
+
+// Keeps the password out of logs
+impl std::fmt::Debug for MqttCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttCredentials")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+// Broker flags shared by the example binaries; flatten them into a clap
+// Parser with #[command(flatten)]
+#[cfg(feature = "cli")]
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct BrokerArgs {
+    /// Username for a broker that requires authentication
+    #[arg(long, env = "CHIMENET_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+
+    /// Password for --mqtt-username; prefer the environment variable, which
+    /// stays out of the process list
+    #[arg(
+        long,
+        env = "CHIMENET_MQTT_PASSWORD",
+        hide_env_values = true,
+        requires = "mqtt_username"
+    )]
+    pub mqtt_password: Option<String>,
+
+    /// PEM file of CA certificates to verify an ssl:// or mqtts:// broker
+    /// with, instead of the system's
+    #[arg(long)]
+    pub mqtt_ca_file: Option<PathBuf>,
+
+    /// PEM client certificate chain, for brokers that require one (may
+    /// include the private key)
+    #[arg(long)]
+    pub mqtt_cert: Option<PathBuf>,
+
+    /// PEM private key for --mqtt-cert, when it's in a separate file
+    #[arg(long, requires = "mqtt_cert")]
+    pub mqtt_key: Option<PathBuf>,
+
+    /// Don't verify the broker's certificate (self-signed test brokers only)
+    #[arg(long)]
+    pub mqtt_insecure: bool,
+}
+
+#[cfg(feature = "cli")]
+impl BrokerArgs {
+    pub fn credentials(&self) -> Option<MqttCredentials> {
+        let username = self.mqtt_username.clone()?;
+        Some(MqttCredentials::new(username, self.mqtt_password.clone()))
+    }
+
+    pub fn tls(&self) -> Option<MqttTls> {
+        MqttTls {
+            ca_file: self.mqtt_ca_file.clone(),
+            key_store: self.mqtt_cert.clone(),
+            private_key: self.mqtt_key.clone(),
+            insecure: self.mqtt_insecure,
+        }
+        .configured()
+    }
+}
+
+// Published by the broker on this client's behalf when the connection drops
+// without a clean disconnect, e.g. a crash or a lost network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastWill {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: i32,
+    pub retain: bool,
+}
+
+// The will for the next connect, and the last retained payload this client
+// published on its topic. A reconnect means the broker may have published the
+// will meanwhile, so that payload is restored afterwards.
+#[derive(Debug, Default)]
+struct WillState {
+    will: Option<LastWill>,
+    last_published: Option<Vec<u8>>,
+}
+
+impl WillState {
+    fn set(&mut self, will: Option<LastWill>) {
+        let same_topic = match (&self.will, &will) {
+            (Some(old), Some(new)) => old.topic == new.topic,
+            _ => false,
+        };
+        if !same_topic {
+            self.last_published = None;
+        }
+        self.will = will;
+    }
+
+    fn record(&mut self, topic: &str, payload: &[u8], retain: bool) {
+        if retain && self.will.as_ref().is_some_and(|will| will.topic == topic) {
+            self.last_published = Some(payload.to_vec());
+        }
+    }
+
+    // (topic, payload, qos) to re-publish after reconnecting
+    fn restore(&self) -> Option<(String, Vec<u8>, i32)> {
+        let will = self.will.as_ref()?;
+        Some((will.topic.clone(), self.last_published.clone()?, will.qos))
+    }
+}
+
+type SharedWill = Arc<std::sync::Mutex<WillState>>;
+
+// Broker URL schemes that connect over TLS. Both mean the same; "mqtts://"
+// is passed to the client library as "ssl://".
+pub const TLS_URL_SCHEMES: [&str; 2] = ["ssl://", "mqtts://"];
+
+pub fn is_tls_url(url: &str) -> bool {
+    TLS_URL_SCHEMES.iter().any(|scheme| url.starts_with(scheme))
+}
+
+// TLS settings for ssl:// and mqtts:// brokers. All files are PEM. Without
+// any, the server is verified against the system's trusted CAs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MqttTls {
+    pub ca_file: Option<PathBuf>, // CA certificates to trust instead of the system's
+    pub key_store: Option<PathBuf>, // Client certificate chain, may include its key
+    pub private_key: Option<PathBuf>, // Client key, when not in key_store
+    pub insecure: bool,           // Skip server verification, for self-signed test brokers
+}
+
+impl MqttTls {
+    // None when nothing differs from the defaults
+    pub fn configured(self) -> Option<Self> {
+        (self != Self::default()).then_some(self)
+    }
+
+    pub fn ssl_options(&self) -> Result<mqtt::SslOptions> {
+        let mut builder = mqtt::SslOptionsBuilder::new();
+        let files = [
+            ("CA file", &self.ca_file),
+            ("client certificate", &self.key_store),
+            ("client key", &self.private_key),
+        ];
+        for (what, file) in files {
+            if let Some(file) = file {
+                if !file.is_file() {
+                    return Err(ChimeNetError::Config(format!(
+                        "TLS {} {} not found",
+                        what,
+                        file.display()
+                    )));
+                }
+            }
+        }
+
+        let invalid = |e: mqtt::Error| ChimeNetError::Config(format!("Invalid TLS path: {}", e));
+        if let Some(ca_file) = &self.ca_file {
+            builder.trust_store(ca_file).map_err(invalid)?;
+        }
+        if let Some(key_store) = &self.key_store {
+            builder.key_store(key_store).map_err(invalid)?;
+        }
+        if let Some(private_key) = &self.private_key {
+            builder.private_key(private_key).map_err(invalid)?;
+        }
+        builder
+            .enable_server_cert_auth(!self.insecure)
+            .verify(!self.insecure);
+        Ok(builder.finalize())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -23,55 +310,227 @@ pub struct MqttMessage {
 
 impl MqttClient {
     pub async fn new(broker_url: &str, client_id: &str) -> Result<Self> {
+        let server_uri = match broker_url.strip_prefix("mqtts://") {
+            Some(address) => format!("ssl://{}", address),
+            None => broker_url.to_string(),
+        };
         let create_opts = mqtt::CreateOptionsBuilder::new()
-            .server_uri(broker_url)
+            .server_uri(server_uri)
             .client_id(client_id)
             .finalize();
 
-        let client = mqtt::AsyncClient::new(create_opts)?;
+        let client = mqtt::AsyncClient::new(create_opts).map_err(|e| {
+            ChimeNetError::Config(format!("Invalid MQTT broker '{}': {}", broker_url, e))
+        })?;
         let (message_tx, message_rx) = mpsc::unbounded_channel();
 
         let subscriptions = Arc::new(Mutex::new(HashMap::new()));
+        let persistent_session = Arc::new(AtomicBool::new(false));
+        let unclaimed = Arc::new(std::sync::Mutex::new(VecDeque::new()));
 
         // Start message handler
         let client_clone = client.clone();
         let subscriptions_clone = subscriptions.clone();
+        let persistent_clone = persistent_session.clone();
+        let unclaimed_clone = unclaimed.clone();
         tokio::spawn(async move {
-            Self::handle_incoming_messages(client_clone, message_rx, subscriptions_clone).await;
+            Self::handle_incoming_messages(
+                client_clone,
+                message_rx,
+                subscriptions_clone,
+                persistent_clone,
+                unclaimed_clone,
+            )
+            .await;
         });
 
         Ok(Self {
             client,
             message_tx,
             subscriptions,
+            persistent_session,
+            keep_alive: std::sync::Mutex::new(DEFAULT_KEEP_ALIVE),
+            credentials: std::sync::Mutex::new(None),
+            tls: is_tls_url(broker_url).then(|| std::sync::Mutex::new(MqttTls::default())),
+            will: SharedWill::default(),
+            unclaimed,
+            reconnect: Arc::new(ReconnectSettings {
+                enabled: AtomicBool::new(true),
+                closing: AtomicBool::new(false),
+                running: AtomicBool::new(false),
+                backoff: std::sync::Mutex::new(ReconnectBackoff::default()),
+            }),
         })
     }
 
-    pub async fn connect(&mut self) -> Result<()> {
-        let conn_opts = mqtt::ConnectOptionsBuilder::new()
-            .keep_alive_interval(std::time::Duration::from_secs(20))
-            .clean_session(true)
-            .finalize();
+    // On by default: a dropped connection is re-established in the
+    // background with backoff and every subscription re-issued
+    pub fn set_reconnect(&self, enabled: bool) {
+        self.reconnect.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn reconnect_enabled(&self) -> bool {
+        self.reconnect.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_reconnect_backoff(&self, backoff: ReconnectBackoff) {
+        *self.reconnect.backoff.lock().unwrap() = backoff;
+    }
+
+    pub fn reconnect_backoff(&self) -> ReconnectBackoff {
+        *self.reconnect.backoff.lock().unwrap()
+    }
+
+    // Off (clean session) by default. When on, the broker keeps this client
+    // id's subscriptions while it is away and queues QoS 1/2 messages for it,
+    // delivered on the next connect. Takes effect on the next connect.
+    pub fn set_persistent_session(&self, persistent: bool) {
+        self.persistent_session.store(persistent, Ordering::Relaxed);
+    }
+
+    pub fn persistent_session(&self) -> bool {
+        self.persistent_session.load(Ordering::Relaxed)
+    }
+
+    // DEFAULT_KEEP_ALIVE unless set. Takes effect on the next connect.
+    pub fn set_keep_alive(&self, interval: Duration) {
+        *self.keep_alive.lock().unwrap() = interval;
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        *self.keep_alive.lock().unwrap()
+    }
+
+    // None (the default) connects anonymously. Takes effect on the next connect.
+    pub fn set_credentials(&self, credentials: Option<MqttCredentials>) {
+        *self.credentials.lock().unwrap() = credentials;
+    }
+
+    pub fn has_credentials(&self) -> bool {
+        self.credentials.lock().unwrap().is_some()
+    }
+
+    // Only for ssl:// and mqtts:// brokers; None restores the defaults.
+    // Checked now, so a missing file fails here rather than on connect.
+    pub fn set_tls(&self, tls: Option<MqttTls>) -> Result<()> {
+        let tls = tls.unwrap_or_default();
+        let Some(current) = &self.tls else {
+            if tls == MqttTls::default() {
+                return Ok(());
+            }
+            return Err(ChimeNetError::Config(format!(
+                "TLS settings need a {} broker URL",
+                TLS_URL_SCHEMES.join(" or ")
+            )));
+        };
+
+        tls.ssl_options()?;
+        *current.lock().unwrap() = tls;
+        Ok(())
+    }
+
+    pub fn uses_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    // None (the default) registers no will. Takes effect on the next connect;
+    // reconnects keep the will of the last connect.
+    pub fn set_will(&self, will: Option<LastWill>) {
+        self.will.lock().unwrap().set(will);
+    }
+
+    pub fn will(&self) -> Option<LastWill> {
+        self.will.lock().unwrap().will.clone()
+    }
+
+    fn connect_options(&self) -> Result<mqtt::ConnectOptions> {
+        let mut builder = mqtt::ConnectOptionsBuilder::new();
+        if let Some(tls) = &self.tls {
+            builder.ssl_options(tls.lock().unwrap().ssl_options()?);
+        }
+        builder
+            .keep_alive_interval(self.keep_alive())
+            .clean_session(!self.persistent_session());
+        if let Some(credentials) = &*self.credentials.lock().unwrap() {
+            builder.user_name(credentials.username.as_str());
+            if let Some(password) = &credentials.password {
+                builder.password(password.as_str());
+            }
+        }
+        if let Some(will) = &self.will.lock().unwrap().will {
+            builder.will_message(
+                mqtt::MessageBuilder::new()
+                    .topic(will.topic.as_str())
+                    .payload(will.payload.clone())
+                    .qos(will.qos)
+                    .retained(will.retain)
+                    .finalize(),
+            );
+        }
+        Ok(builder.finalize())
+    }
 
-        self.client.connect(conn_opts).await?;
+    pub async fn connect(&mut self) -> Result<()> {
+        let conn_opts = self.connect_options()?;
 
-        // Set up message stream
+        // Set up the message stream first: a persistent session's queued
+        // messages are sent as soon as the broker accepts the connection
         let mut strm = self.client.get_stream(25);
+
+        self.reconnect.closing.store(false, Ordering::Relaxed);
+        self.client
+            .connect(conn_opts)
+            .await
+            .map_err(|e| ChimeNetError::Connection(format!("MQTT connection failed: {}", e)))?;
+
         let tx = self.message_tx.clone();
+        let client = self.client.clone();
+        let subscriptions = self.subscriptions.clone();
+        let will = self.will.clone();
+        let reconnect = self.reconnect.clone();
 
         tokio::spawn(async move {
             while let Some(msg_opt) = strm.next().await {
-                if let Some(msg) = msg_opt {
-                    let mqtt_msg = MqttMessage {
-                        topic: msg.topic().to_string(),
-                        payload: String::from_utf8_lossy(msg.payload()).to_string(),
-                        qos: msg.qos(),
-                        retain: msg.retained(),
-                    };
-
-                    if let Err(e) = tx.send(mqtt_msg) {
-                        log::error!("Failed to send MQTT message to handler: {}", e);
+                // None marks a lost connection. Reconnecting runs on its own
+                // so this loop keeps draining whatever arrives meanwhile.
+                let Some(msg) = msg_opt else {
+                    if reconnect.active() && !reconnect.running.swap(true, Ordering::SeqCst) {
+                        log::warn!("Lost connection to MQTT broker, reconnecting");
+                        let (client, subscriptions, will, reconnect) = (
+                            client.clone(),
+                            subscriptions.clone(),
+                            will.clone(),
+                            reconnect.clone(),
+                        );
+                        tokio::spawn(async move {
+                            Self::reconnect_with_backoff(
+                                &client,
+                                &subscriptions,
+                                &will,
+                                &reconnect,
+                            )
+                            .await;
+                            reconnect.running.store(false, Ordering::SeqCst);
+                        });
                     }
+                    continue;
+                };
+                let payload = match PayloadFormat::decode_to_json(msg.payload()) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        log::warn!("Dropping undecodable payload on {}: {}", msg.topic(), e);
+                        continue;
+                    }
+                };
+                let mqtt_msg = MqttMessage {
+                    topic: msg.topic().to_string(),
+                    payload,
+                    qos: msg.qos(),
+                    retain: msg.retained(),
+                };
+
+                if let Err(e) = tx.send(mqtt_msg) {
+                    log::error!("Failed to send MQTT message to handler: {}", e);
                 }
             }
         });
@@ -80,11 +539,23 @@ impl MqttClient {
     }
 
     pub async fn disconnect(&self) -> Result<()> {
+        self.reconnect.closing.store(true, Ordering::Relaxed);
         self.client.disconnect(None).await?;
         Ok(())
     }
 
     pub async fn publish(&self, topic: &str, payload: &str, qos: i32, retain: bool) -> Result<()> {
+        self.publish_bytes(topic, payload.as_bytes(), qos, retain)
+            .await
+    }
+
+    pub async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
         let msg = mqtt::MessageBuilder::new()
             .topic(topic)
             .payload(payload)
@@ -93,6 +564,7 @@ impl MqttClient {
             .finalize();
 
         self.client.publish(msg).await?;
+        self.will.lock().unwrap().record(topic, payload, retain);
         Ok(())
     }
 
@@ -107,18 +579,62 @@ impl MqttClient {
         self.publish(topic, &json, qos, retain).await
     }
 
-    pub async fn subscribe<F>(&self, topic: &str, qos: i32, handler: F) -> Result<()>
+    pub async fn subscribe<F>(&self, topic: &str, qos: i32, handler: F) -> Result<SubscriptionId>
     where
         F: Fn(String, String) + Send + Sync + 'static,
     {
-        self.client.subscribe(topic, qos).await?;
+        let mut subscriptions = self.subscriptions.lock().await;
+        let handlers = Self::subscribe_locked(&self.client, &mut subscriptions, topic, qos).await?;
+        let id = handlers.push(topic, Box::new(handler));
+
+        let backlog = Self::take_unclaimed(&mut self.unclaimed.lock().unwrap(), topic);
+        for msg in &backlog {
+            handlers.dispatch(msg);
+        }
+        Ok(id)
+    }
 
+    // See Transport::subscribe_once. The broker has acknowledged the filter
+    // and the waiter is registered before this returns; both happen under the
+    // subscriptions lock, which dispatch needs too. Unclaimed backlog isn't
+    // offered to it: a waiter is after the reply to something sent next.
+    pub async fn subscribe_once(
+        &self,
+        topic: &str,
+        qos: i32,
+        predicate: MessagePredicate,
+    ) -> Result<NextMessage> {
         let mut subscriptions = self.subscriptions.lock().await;
-        subscriptions.insert(topic.to_string(), Box::new(handler));
+        Ok(
+            Self::subscribe_locked(&self.client, &mut subscriptions, topic, qos)
+                .await?
+                .push_once(predicate),
+        )
+    }
 
-        Ok(())
+    async fn subscribe_locked<'a>(
+        client: &mqtt::AsyncClient,
+        subscriptions: &'a mut HashMap<String, Subscription>,
+        topic: &str,
+        qos: i32,
+    ) -> Result<&'a mut HandlerSet> {
+        let qos = subscriptions
+            .get(topic)
+            .map_or(qos, |existing| existing.qos.max(qos));
+        // Re-subscribing an existing filter is harmless and upgrades its QoS
+        client.subscribe(topic, qos).await?;
+
+        let subscription = subscriptions
+            .entry(topic.to_string())
+            .or_insert_with(|| Subscription {
+                qos,
+                handlers: HandlerSet::default(),
+            });
+        subscription.qos = qos;
+        Ok(&mut subscription.handlers)
     }
 
+    // Drops every handler registered for `topic`
     pub async fn unsubscribe(&self, topic: &str) -> Result<()> {
         self.client.unsubscribe(topic).await?;
 
@@ -128,24 +644,156 @@ impl MqttClient {
         Ok(())
     }
 
+    // See Transport::unsubscribe_handle. Under the subscriptions lock, so a
+    // concurrent subscribe to the same filter isn't undone.
+    pub async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let Some(subscription) = subscriptions.get_mut(id.topic()) else {
+            return Ok(());
+        };
+        if subscription.handlers.remove(id) && subscription.handlers.is_empty() {
+            self.client.unsubscribe(id.topic()).await?;
+            subscriptions.remove(id.topic());
+        }
+        Ok(())
+    }
+
+    // Topic filters currently subscribed to, sorted
+    pub async fn active_subscriptions(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.subscriptions.lock().await.keys().cloned().collect();
+        topics.sort();
+        topics
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    // Reconnects with the last connect options and re-issues every subscription,
+    // since a clean session drops them on the broker side
+    pub async fn reconnect(&self) -> Result<()> {
+        Self::reconnect_and_resubscribe(&self.client, &self.subscriptions, &self.will).await
+    }
+
+    async fn reconnect_and_resubscribe(
+        client: &mqtt::AsyncClient,
+        subscriptions: &SubscriptionMap,
+        will: &SharedWill,
+    ) -> Result<()> {
+        client.reconnect().await?;
+
+        {
+            let subscriptions = subscriptions.lock().await;
+            for (topic, subscription) in subscriptions.iter() {
+                client.subscribe(topic, subscription.qos).await?;
+            }
+        }
+
+        // Undo the will, e.g. an offline status, if the broker published it
+        let restore = will.lock().unwrap().restore();
+        if let Some((topic, payload, qos)) = restore {
+            client
+                .publish(mqtt::Message::new_retained(topic, payload, qos))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Retries until connected again, or until reconnecting is switched off
+    // or the client is disconnected on purpose
+    async fn reconnect_with_backoff(
+        client: &mqtt::AsyncClient,
+        subscriptions: &SubscriptionMap,
+        will: &SharedWill,
+        reconnect: &ReconnectSettings,
+    ) {
+        let mut attempt = 0;
+        while reconnect.active() {
+            attempt += 1;
+            let delay = reconnect.backoff.lock().unwrap().delay(attempt);
+            tokio::time::sleep(delay).await;
+            if client.is_connected() || !reconnect.active() {
+                return;
+            }
+
+            match Self::reconnect_and_resubscribe(client, subscriptions, will).await {
+                Ok(()) => {
+                    log::info!("Reconnected to MQTT broker after {} attempt(s)", attempt);
+                    return;
+                }
+                Err(e) => log::warn!("Reconnect attempt {} failed: {}", attempt, e),
+            }
+        }
+    }
+
     async fn handle_incoming_messages(
-        _client: mqtt::AsyncClient,
+        client: mqtt::AsyncClient,
         mut message_rx: mpsc::UnboundedReceiver<MqttMessage>,
-        subscriptions: Arc<Mutex<HashMap<String, Box<dyn Fn(String, String) + Send + Sync>>>>,
+        subscriptions: SubscriptionMap,
+        persistent_session: Arc<AtomicBool>,
+        unclaimed: UnclaimedMessages,
     ) {
         while let Some(msg) = message_rx.recv().await {
-            let subscriptions_guard = subscriptions.lock().await;
+            let mut subscriptions_guard = subscriptions.lock().await;
+            let claimed = subscriptions_guard
+                .keys()
+                .any(|pattern| Self::topic_matches(pattern, &msg.topic));
+            if !claimed && persistent_session.load(Ordering::Relaxed) {
+                Self::hold_unclaimed(&mut unclaimed.lock().unwrap(), msg);
+                continue;
+            }
+            // Still under the lock, so a new subscribe to the same filter
+            // can't be undone by this unsubscribe
+            for topic in Self::dispatch(&mut subscriptions_guard, &msg) {
+                if let Err(e) = client.unsubscribe(&topic).await {
+                    log::warn!("Failed to unsubscribe from {}: {}", topic, e);
+                }
+            }
+        }
+    }
 
-            // Find matching subscription handlers
-            for (topic_pattern, handler) in subscriptions_guard.iter() {
-                if Self::topic_matches(topic_pattern, &msg.topic) {
-                    handler(msg.topic.clone(), msg.payload.clone());
+    // Runs every handler whose filter matches the topic. Returns the filters
+    // left without handlers (their one-shot waiters fired), already removed.
+    fn dispatch(
+        subscriptions: &mut HashMap<String, Subscription>,
+        msg: &MqttMessage,
+    ) -> Vec<String> {
+        let mut emptied = Vec::new();
+        for (topic_pattern, subscription) in subscriptions.iter_mut() {
+            if Self::topic_matches(topic_pattern, &msg.topic) {
+                subscription.handlers.dispatch(msg);
+                if subscription.handlers.is_empty() {
+                    emptied.push(topic_pattern.clone());
                 }
             }
         }
+
+        for topic in &emptied {
+            subscriptions.remove(topic);
+        }
+        emptied
+    }
+
+    fn hold_unclaimed(unclaimed: &mut VecDeque<MqttMessage>, msg: MqttMessage) {
+        unclaimed.push_back(msg);
+        if unclaimed.len() > MAX_UNCLAIMED_MESSAGES {
+            if let Some(dropped) = unclaimed.pop_front() {
+                log::warn!("Dropping unclaimed message on {}", dropped.topic);
+            }
+        }
+    }
+
+    // Removes and returns the held messages matching `pattern`, oldest first
+    fn take_unclaimed(unclaimed: &mut VecDeque<MqttMessage>, pattern: &str) -> Vec<MqttMessage> {
+        let (matching, rest): (Vec<_>, Vec<_>) = unclaimed
+            .drain(..)
+            .partition(|msg| Self::topic_matches(pattern, &msg.topic));
+        *unclaimed = rest.into();
+        matching
     }
 
-    fn topic_matches(pattern: &str, topic: &str) -> bool {
+    pub(crate) fn topic_matches(pattern: &str, topic: &str) -> bool {
         // Simple wildcard matching for MQTT topics
         if pattern == topic {
             return true;
@@ -169,8 +817,7 @@ impl MqttClient {
         }
 
         // Handle multi-level wildcard (#)
-        if pattern.ends_with('#') {
-            let prefix = &pattern[..pattern.len() - 1];
+        if let Some(prefix) = pattern.strip_suffix('#') {
             return topic.starts_with(prefix);
         }
 
@@ -178,129 +825,1251 @@ impl MqttClient {
     }
 }
 
-pub struct ChimeNetMqtt {
-    client: MqttClient,
-    user: String,
+// Views of internal state for test assertions; not built into release APIs
+#[cfg(any(test, feature = "testing"))]
+impl MqttClient {
+    pub async fn subscription_handler_counts(&self) -> HashMap<String, (usize, usize)> {
+        self.subscriptions
+            .lock()
+            .await
+            .iter()
+            .map(|(topic, subscription)| (topic.clone(), subscription.handlers.counts()))
+            .collect()
+    }
+
+    // Backlog held until a matching subscription arrives
+    pub fn unclaimed_message_count(&self) -> usize {
+        self.unclaimed.lock().unwrap().len()
+    }
 }
 
-impl ChimeNetMqtt {
-    pub async fn new(broker_url: &str, user: &str, client_id: &str) -> Result<Self> {
-        let client = MqttClient::new(broker_url, client_id).await?;
+// Everything a ChimeNetMqtt is configured with before its first connect.
+// Broker and user are required; without a client id one is generated. Each
+// setting is checked by the transport in build(), e.g. TLS needs an ssl://
+// or mqtts:// broker and a kept session needs a broker at all.
+#[derive(Debug, Clone, Default)]
+pub struct ChimeNetMqttBuilder {
+    broker: Option<String>,
+    user: Option<UserId>,
+    client_id: Option<String>,
+    credentials: Option<MqttCredentials>,
+    tls: Option<MqttTls>,
+    keep_alive: Option<Duration>,
+    clean_session: Option<bool>,
+    default_qos: Option<i32>,
+}
 
-        Ok(Self {
-            client,
-            user: user.to_string(),
-        })
+impl ChimeNetMqttBuilder {
+    pub fn broker(mut self, url: impl Into<String>) -> Self {
+        self.broker = Some(url.into());
+        self
     }
 
-    pub async fn connect(&mut self) -> Result<()> {
-        self.client.connect().await
+    pub fn user(mut self, user: &UserId) -> Self {
+        self.user = Some(user.clone());
+        self
     }
 
-    pub async fn disconnect(&self) -> Result<()> {
-        self.client.disconnect().await
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
     }
 
-    // Chime list operations
-    pub async fn publish_chime_list(&self, chimes: &[ChimeInfo]) -> Result<()> {
-        let chime_list = ChimeList {
-            user: self.user.clone(),
-            chimes: chimes.to_vec(),
-            timestamp: chrono::Utc::now(),
-        };
+    // None connects anonymously
+    pub fn credentials(mut self, credentials: Option<MqttCredentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
 
-        let topic = TopicBuilder::chime_list(&self.user);
-        self.client.publish_json(&topic, &chime_list, 1, true).await
+    // None (or the defaults) verifies the broker against the system's CAs
+    pub fn tls(mut self, tls: Option<MqttTls>) -> Self {
+        self.tls = tls.and_then(MqttTls::configured);
+        self
     }
 
-    pub async fn publish_chime_notes(&self, chime_id: &str, notes: &[String]) -> Result<()> {
-        let topic = TopicBuilder::chime_notes(&self.user, chime_id);
-        self.client.publish_json(&topic, notes, 1, true).await
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
     }
 
-    pub async fn publish_chime_chords(&self, chime_id: &str, chords: &[String]) -> Result<()> {
-        let topic = TopicBuilder::chime_chords(&self.user, chime_id);
-        self.client.publish_json(&topic, chords, 1, true).await
+    // True (the default) starts fresh on every connect; false keeps the
+    // session, see Transport::set_persistent_session
+    pub fn clean_session(mut self, clean: bool) -> Self {
+        self.clean_session = Some(clean);
+        self
     }
 
-    pub async fn publish_chime_status(&self, chime_id: &str, status: &ChimeStatus) -> Result<()> {
-        let topic = TopicBuilder::chime_status(&self.user, chime_id);
-        self.client.publish_json(&topic, status, 1, true).await
+    // See ChimeNetMqtt::set_default_qos
+    pub fn default_qos(mut self, qos: i32) -> Self {
+        self.default_qos = Some(qos);
+        self
     }
 
-    pub async fn publish_chime_ring(
-        &self,
-        chime_id: &str,
-        ring_request: &ChimeRingRequest,
-    ) -> Result<()> {
-        let topic = TopicBuilder::chime_ring(&self.user, chime_id);
-        self.client
-            .publish_json(&topic, ring_request, 1, false)
-            .await
+    // Creates the client without connecting
+    pub async fn build(self) -> Result<ChimeNetMqtt> {
+        let broker = self
+            .broker
+            .ok_or_else(|| ChimeNetError::Config("No MQTT broker URL given".to_string()))?;
+        let user = self
+            .user
+            .ok_or_else(|| ChimeNetError::Config("No ChimeNet user given".to_string()))?;
+        user.validate()?;
+        let client_id = self
+            .client_id
+            .unwrap_or_else(|| format!("chimenet_{}_{}", user, uuid::Uuid::new_v4().simple()));
+
+        let mut mqtt =
+            ChimeNetMqtt::with_transport(transport_for_url(&broker, &client_id).await?, &user)?;
+        mqtt.set_credentials(self.credentials)?;
+        mqtt.set_tls(self.tls)?;
+        if let Some(interval) = self.keep_alive {
+            mqtt.set_keep_alive(interval);
+        }
+        if let Some(clean) = self.clean_session {
+            mqtt.set_persistent_session(!clean)?;
+        }
+        mqtt.set_default_qos(self.default_qos)?;
+        Ok(mqtt)
     }
+}
 
-    pub async fn publish_chime_ring_to_user(
-        &self,
-        user: &str,
-        chime_id: &str,
-        ring_request: &ChimeRingRequest,
-    ) -> Result<()> {
-        let topic = TopicBuilder::chime_ring(user, chime_id);
-        self.client
-            .publish_json(&topic, ring_request, 1, false)
+// ChimeNet operations on top of any Transport. Named for its default
+// (MQTT) transport; `new` also accepts udp:// URLs for the LAN transport.
+pub struct ChimeNetMqtt {
+    client: Box<dyn Transport>,
+    user: UserId,
+    format: PayloadFormat,
+    default_qos: Option<i32>,
+}
+
+impl ChimeNetMqtt {
+    pub async fn new(broker_url: &str, user: &UserId, client_id: &str) -> Result<Self> {
+        Self::builder()
+            .broker(broker_url)
+            .user(user)
+            .client_id(client_id)
+            .build()
             .await
     }
 
-    pub async fn publish_chime_response(
-        &self,
-        chime_id: &str,
-        response: &ChimeResponseMessage,
-    ) -> Result<()> {
-        let topic = TopicBuilder::chime_response(&self.user, chime_id);
-        self.client.publish_json(&topic, response, 1, false).await
+    pub fn builder() -> ChimeNetMqttBuilder {
+        ChimeNetMqttBuilder::default()
     }
 
-    // Ringer operations
-    pub async fn publish_ringer_discovery(&self, discovery: &RingerDiscovery) -> Result<()> {
-        let topic = TopicBuilder::ringer_discover(&self.user);
-        self.client.publish_json(&topic, discovery, 1, false).await
+    pub fn with_transport(client: Box<dyn Transport>, user: &UserId) -> Result<Self> {
+        user.validate()?;
+        Ok(Self {
+            client,
+            user: user.clone(),
+            format: PayloadFormat::default(),
+            default_qos: None,
+        })
     }
 
-    pub async fn publish_ringer_available(&self, available: &RingerAvailable) -> Result<()> {
-        let topic = TopicBuilder::ringer_available(&self.user);
-        self.client.publish_json(&topic, available, 1, true).await
+    pub fn user(&self) -> &UserId {
+        &self.user
     }
 
-    // Subscription helpers
-    pub async fn subscribe_to_chime_rings<F>(&self, chime_id: &str, handler: F) -> Result<()>
-    where
-        F: Fn(String, String) + Send + Sync + 'static,
-    {
-        let topic = TopicBuilder::chime_ring(&self.user, chime_id);
-        self.client.subscribe(&topic, 1, handler).await
+    // Encoding of everything this client publishes; receiving accepts both
+    pub fn set_payload_format(&mut self, format: PayloadFormat) {
+        self.format = format;
     }
 
-    pub async fn subscribe_to_user_chimes<F>(&self, user: &str, handler: F) -> Result<()>
-    where
-        F: Fn(String, String) + Send + Sync + 'static,
-    {
-        let topic = format!("/{}/chime/+/+", user);
-        self.client.subscribe(&topic, 1, handler).await
+    pub fn payload_format(&self) -> PayloadFormat {
+        self.format
     }
 
-    pub async fn subscribe_to_ringer_discovery<F>(&self, handler: F) -> Result<()>
-    where
-        F: Fn(String, String) + Send + Sync + 'static,
-    {
-        let topic = TopicBuilder::ringer_discover(&self.user);
-        self.client.subscribe(&topic, 1, handler).await
-    }
+    // QoS for everything the helpers below publish and subscribe to, in place
+    // of QOS_STATE and QOS_RING; e.g. 2 for exactly-once rings. None restores
+    // the per-kind defaults. Explicit QoS arguments are left alone.
+    pub fn set_default_qos(&mut self, qos: Option<i32>) -> Result<()> {
+        if let Some(qos) = qos {
+            validate_qos(qos)?;
+        }
+        self.default_qos = qos;
+        Ok(())
+    }
+
+    pub fn default_qos(&self) -> Option<i32> {
+        self.default_qos
+    }
+
+    pub fn state_qos(&self) -> i32 {
+        self.default_qos.unwrap_or(QOS_STATE)
+    }
+
+    pub fn ring_qos(&self) -> i32 {
+        self.default_qos.unwrap_or(QOS_RING)
+    }
+
+    // For wildcard subscriptions spanning both kinds, e.g. "/alice/chime/+/+"
+    pub fn any_qos(&self) -> i32 {
+        self.state_qos().max(self.ring_qos())
+    }
+
+    // Topics built after this use the new user; existing subscriptions are untouched
+    pub fn set_user(&mut self, user: &UserId) -> Result<()> {
+        user.validate()?;
+        self.user = user.clone();
+        Ok(())
+    }
+
+    pub async fn connect(&mut self) -> Result<()> {
+        self.client.connect().await
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        self.client.disconnect().await
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    // See Transport::set_persistent_session; fails on the LAN transport
+    pub fn set_persistent_session(&self, persistent: bool) -> Result<()> {
+        self.client.set_persistent_session(persistent)
+    }
+
+    // See Transport::set_credentials; fails on the LAN transport unless None
+    pub fn set_credentials(&self, credentials: Option<MqttCredentials>) -> Result<()> {
+        self.client.set_credentials(credentials)
+    }
+
+    // See Transport::set_tls; fails unless the broker URL is ssl:// or mqtts://
+    pub fn set_tls(&self, tls: Option<MqttTls>) -> Result<()> {
+        self.client.set_tls(tls)
+    }
+
+    // See Transport::set_keep_alive; ignored by the LAN transport
+    pub fn set_keep_alive(&self, interval: Duration) {
+        self.client.set_keep_alive(interval)
+    }
+
+    // Has the broker publish `status` (meant to be offline), retained, as the
+    // chime's status if this client drops without disconnecting, so others
+    // see it go offline right away instead of after their stale timeout.
+    // Takes effect on the next connect; ignored by the LAN transport.
+    pub fn set_offline_will(&self, chime_id: &ChimeId, status: &ChimeStatus) -> Result<()> {
+        self.client.set_will(Some(LastWill {
+            topic: TopicBuilder::chime_status(self.user.as_str(), chime_id.as_str()),
+            payload: self.format.encode(status)?,
+            qos: self.state_qos(),
+            retain: true,
+        }));
+        Ok(())
+    }
+
+    pub async fn reconnect(&self) -> Result<()> {
+        self.client.reconnect().await
+    }
+
+    // Automatic reconnection after the broker drops the connection (on by
+    // default over MQTT). Callers running their own reconnect loop turn it off.
+    pub fn set_reconnect(&self, enabled: bool) {
+        self.client.set_reconnect(enabled)
+    }
+
+    pub fn set_reconnect_backoff(&self, backoff: ReconnectBackoff) {
+        self.client.set_reconnect_backoff(backoff)
+    }
+
+    pub async fn active_subscriptions(&self) -> Vec<String> {
+        self.client.active_subscriptions().await
+    }
+
+    // (handlers, one-shot waiters) per filter; test and "testing" builds only
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn subscription_handler_counts(&self) -> HashMap<String, (usize, usize)> {
+        self.client.subscription_handler_counts().await
+    }
+
+    async fn publish_json<T: serde::Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        payload: &T,
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
+        let payload = self.format.encode(payload)?;
+        self.client
+            .publish_bytes(topic, &payload, qos, retain)
+            .await
+    }
+
+    // The list is the authoritative source of each chime's notes and chords.
+    // The per-chime notes/chords topics are deprecated mirrors for older
+    // clients, only ever written from here so they can't drift from it.
+    pub async fn publish_chime_list(&self, chimes: &[ChimeInfo]) -> Result<()> {
+        let chime_list = ChimeList {
+            user: self.user.to_string(),
+            chimes: chimes.to_vec(),
+            timestamp: chrono::Utc::now(),
+        };
+
+        let topic = TopicBuilder::chime_list(self.user.as_str());
+        self.publish_json(&topic, &chime_list, self.state_qos(), true)
+            .await?;
+
+        for chime in chimes {
+            self.publish_chime_mirrors(&chime.id, Some(&chime.notes), Some(&chime.chords))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Only writes the mirror topic; the list still has the old notes
+    #[deprecated(note = "publish_chime_list publishes notes and chords together")]
+    pub async fn publish_chime_notes(&self, chime_id: &ChimeId, notes: &[String]) -> Result<()> {
+        self.publish_chime_mirrors(chime_id.as_str(), Some(notes), None)
+            .await
+    }
+
+    // Only writes the mirror topic; the list still has the old chords
+    #[deprecated(note = "publish_chime_list publishes notes and chords together")]
+    pub async fn publish_chime_chords(&self, chime_id: &ChimeId, chords: &[String]) -> Result<()> {
+        self.publish_chime_mirrors(chime_id.as_str(), None, Some(chords))
+            .await
+    }
+
+    async fn publish_chime_mirrors(
+        &self,
+        chime_id: &str,
+        notes: Option<&[String]>,
+        chords: Option<&[String]>,
+    ) -> Result<()> {
+        let user = self.user.as_str();
+        if let Some(notes) = notes {
+            let topic = TopicBuilder::chime_notes(user, chime_id);
+            self.publish_json(&topic, notes, self.state_qos(), true)
+                .await?;
+        }
+        if let Some(chords) = chords {
+            let topic = TopicBuilder::chime_chords(user, chime_id);
+            self.publish_json(&topic, chords, self.state_qos(), true)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // An empty retained payload makes the broker drop the retained message
+    pub async fn clear_chime_details(&self, chime_id: &ChimeId) -> Result<()> {
+        let user = self.user.as_str();
+        for topic in [
+            TopicBuilder::chime_notes(user, chime_id.as_str()),
+            TopicBuilder::chime_chords(user, chime_id.as_str()),
+        ] {
+            self.client
+                .publish(&topic, "", self.state_qos(), true)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Clears the user's list and everything retained for `chime_id`, so new
+    // subscribers no longer see the chime. Other chimes sharing the list need
+    // to publish it again.
+    pub async fn clear_chime_retained(&self, chime_id: &ChimeId) -> Result<()> {
+        let list_topic = TopicBuilder::chime_list(self.user.as_str());
+        self.client
+            .publish(&list_topic, "", self.state_qos(), true)
+            .await?;
+        self.clear_chime_details(chime_id).await?;
+        let status_topic = TopicBuilder::chime_status(self.user.as_str(), chime_id.as_str());
+        self.client
+            .publish(&status_topic, "", self.state_qos(), true)
+            .await
+    }
+
+    // Not retained: the retained status carries the mode for late joiners
+    pub async fn publish_mode_update(&self, chime_id: &ChimeId, update: &ModeUpdate) -> Result<()> {
+        let topic = TopicBuilder::chime_mode(self.user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, update, self.ring_qos(), false)
+            .await
+    }
+
+    pub async fn publish_chime_status(
+        &self,
+        chime_id: &ChimeId,
+        status: &ChimeStatus,
+    ) -> Result<()> {
+        let topic = TopicBuilder::chime_status(self.user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, status, self.state_qos(), true)
+            .await
+    }
+
+    // Answer to a ChimeStatusRequest: not retained, so the retained status
+    // stays the plain one
+    pub async fn publish_status_reply(
+        &self,
+        chime_id: &ChimeId,
+        status: &ChimeStatus,
+    ) -> Result<()> {
+        let topic = TopicBuilder::chime_status(self.user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, status, self.state_qos(), false)
+            .await
+    }
+
+    // Asks `user`'s chime for its current status and waits for the reply, up
+    // to STATUS_REQUEST_TIMEOUT. Fails if the chime is offline or too old to
+    // answer status requests.
+    pub async fn request_status(&self, user: &UserId, chime_id: &ChimeId) -> Result<ChimeStatus> {
+        self.request_status_with_timeout(user, chime_id, STATUS_REQUEST_TIMEOUT)
+            .await
+    }
+
+    pub async fn request_status_with_timeout(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        timeout: Duration,
+    ) -> Result<ChimeStatus> {
+        let request = ChimeStatusRequest {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            timestamp: chrono::Utc::now(),
+        };
+
+        // Listening before asking, so a quick reply can't slip past. The
+        // retained status and replies to other requests are passed over.
+        let request_id = request.request_id.clone();
+        let status_topic = TopicBuilder::chime_status(user.as_str(), chime_id.as_str());
+        let reply = self
+            .subscribe_once(&status_topic, move |_, payload| {
+                serde_json::from_str::<ChimeStatus>(payload)
+                    .is_ok_and(|status| status.in_reply_to.as_deref() == Some(&request_id))
+            })
+            .await?;
+
+        let topic = TopicBuilder::chime_status_request(user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, &request, self.ring_qos(), false)
+            .await?;
+
+        let message = tokio::time::timeout(timeout, reply).await.map_err(|_| {
+            ChimeNetError::Timeout(format!(
+                "No status from {}/{} within {:?}",
+                user, chime_id, timeout
+            ))
+        })??;
+        Ok(serde_json::from_str(&message.payload)?)
+    }
+
+    pub async fn publish_chime_ring(
+        &self,
+        chime_id: &ChimeId,
+        ring_request: &ChimeRingRequest,
+    ) -> Result<()> {
+        let topic = TopicBuilder::chime_ring(self.user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, ring_request, self.ring_qos(), false)
+            .await
+    }
+
+    pub async fn publish_chime_ring_to_user(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        ring_request: &ChimeRingRequest,
+    ) -> Result<()> {
+        self.publish_chime_ring_to_user_with_qos(user, chime_id, ring_request, self.ring_qos())
+            .await
+    }
+
+    // QoS 0 is fire-and-forget: no broker storage or retries, so it can be lost
+    // (e.g. if the target is briefly disconnected). QoS 1 may deliver twice, QoS 2 exactly once.
+    pub async fn publish_chime_ring_to_user_with_qos(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        ring_request: &ChimeRingRequest,
+        qos: i32,
+    ) -> Result<()> {
+        validate_qos(qos)?;
+
+        let topic = TopicBuilder::chime_ring(user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, ring_request, qos, false).await
+    }
+
+    // MQTT has no wildcard publish, so this rings each of `chime_ids` in turn with a copy of
+    // `ring_request` addressed to it. One chime failing doesn't stop the rest; each
+    // id comes back with its own result, in order.
+    pub async fn publish_chime_ring_broadcast(
+        &self,
+        user: &UserId,
+        chime_ids: &[String],
+        ring_request: &ChimeRingRequest,
+    ) -> Vec<(String, Result<()>)> {
+        let mut results = Vec::with_capacity(chime_ids.len());
+        for chime_id in chime_ids {
+            let request = ChimeRingRequest {
+                chime_id: chime_id.clone(),
+                user: user.to_string(),
+                ..ring_request.clone()
+            };
+            let id = ChimeId::from(chime_id.as_str());
+            let result = match id.validate() {
+                Ok(()) => self.publish_chime_ring_to_user(user, &id, &request).await,
+                Err(e) => Err(e),
+            };
+            results.push((chime_id.clone(), result));
+        }
+        results
+    }
+
+    // Mode change for another user's chime; it applies it only if it allows remote changes
+    pub async fn publish_mode_request(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        request: &ModeChangeRequest,
+    ) -> Result<()> {
+        let topic = TopicBuilder::chime_mode_request(user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, request, self.ring_qos(), false)
+            .await
+    }
+
+    pub async fn publish_chime_response(
+        &self,
+        chime_id: &ChimeId,
+        response: &ChimeResponseMessage,
+    ) -> Result<()> {
+        let topic = TopicBuilder::chime_response(self.user.as_str(), chime_id.as_str());
+        self.publish_json(&topic, response, self.ring_qos(), false)
+            .await
+    }
+
+    // Ringer operations
+    pub async fn publish_ringer_discovery(&self, discovery: &RingerDiscovery) -> Result<()> {
+        let topic = TopicBuilder::ringer_discover(self.user.as_str());
+        self.publish_json(&topic, discovery, self.ring_qos(), false)
+            .await
+    }
+
+    pub async fn publish_ringer_available(&self, available: &RingerAvailable) -> Result<()> {
+        let topic = TopicBuilder::ringer_available(self.user.as_str());
+        self.publish_json(&topic, available, self.state_qos(), true)
+            .await
+    }
+
+    // Subscription helpers
+    pub async fn subscribe_to_chime_rings<F>(
+        &self,
+        chime_id: &ChimeId,
+        handler: F,
+    ) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let topic = TopicBuilder::chime_ring(self.user.as_str(), chime_id.as_str());
+        self.client
+            .subscribe(&topic, self.ring_qos(), Box::new(handler))
+            .await
+    }
+
+    pub async fn subscribe_to_mode_requests<F>(
+        &self,
+        chime_id: &ChimeId,
+        handler: F,
+    ) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let topic = TopicBuilder::chime_mode_request(self.user.as_str(), chime_id.as_str());
+        self.client
+            .subscribe(&topic, self.ring_qos(), Box::new(handler))
+            .await
+    }
+
+    pub async fn subscribe_to_status_requests<F>(
+        &self,
+        chime_id: &ChimeId,
+        handler: F,
+    ) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let topic = TopicBuilder::chime_status_request(self.user.as_str(), chime_id.as_str());
+        self.client
+            .subscribe(&topic, self.ring_qos(), Box::new(handler))
+            .await
+    }
+
+    // Every topic of a user's chimes, including their list
+    pub async fn subscribe_to_user_chimes<F>(
+        &self,
+        user: &UserId,
+        handler: F,
+    ) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let topic = format!("/{}/chime/#", user);
+        self.client
+            .subscribe(&topic, self.any_qos(), Box::new(handler))
+            .await
+    }
+
+    pub async fn subscribe_to_ringer_discovery<F>(&self, handler: F) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let topic = TopicBuilder::ringer_discover(self.user.as_str());
+        self.client
+            .subscribe(&topic, self.ring_qos(), Box::new(handler))
+            .await
+    }
+
+    // Every user's discovery requests, which chimes answer by re-announcing
+    pub async fn subscribe_to_announce_requests<F>(&self, handler: F) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let topic = TopicBuilder::ringer_discover_all();
+        self.client
+            .subscribe(&topic, self.ring_qos(), Box::new(handler))
+            .await
+    }
 
     // Generic subscription method
-    pub async fn subscribe<F>(&self, topic: &str, qos: i32, handler: F) -> Result<()>
+    pub async fn subscribe<F>(&self, topic: &str, qos: i32, handler: F) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        self.client.subscribe(topic, qos, Box::new(handler)).await
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        self.client.unsubscribe(topic).await
+    }
+
+    // Drops one handler returned by the subscribe methods, keeping the rest
+    pub async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+        self.client.unsubscribe_handle(id).await
+    }
+
+    // The first message on `topic` passing `predicate`; see Transport::subscribe_once
+    pub async fn subscribe_once<P>(&self, topic: &str, predicate: P) -> Result<NextMessage>
+    where
+        P: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.client
+            .subscribe_once(topic, self.ring_qos(), Box::new(predicate))
+            .await
+    }
+
+    // The response `user`'s chime gives to the ring with `request_id`, passing
+    // over answers to other ringers. Call before ringing it, so a quick reply
+    // can't slip past.
+    pub async fn response_to(
+        &self,
+        user: &UserId,
+        chime_id: &ChimeId,
+        request_id: &str,
+    ) -> Result<NextMessage> {
+        let topic = TopicBuilder::chime_response(user.as_str(), chime_id.as_str());
+        let request_id = request_id.to_string();
+        self.subscribe_once(&topic, move |_, payload| {
+            serde_json::from_str::<ChimeResponseMessage>(payload)
+                .is_ok_and(|response| response.in_reply_to.as_deref() == Some(request_id.as_str()))
+        })
+        .await
+    }
+
+    // Like `subscribe`, but skips messages published under this client's own user,
+    // so a node doesn't react to its own list/status publications
+    pub async fn subscribe_foreign<F>(
+        &self,
+        topic: &str,
+        qos: i32,
+        handler: F,
+    ) -> Result<SubscriptionId>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let handler = Self::foreign_only(self.user.to_string(), handler);
+        self.client.subscribe(topic, qos, Box::new(handler)).await
+    }
+
+    fn foreign_only<F>(own_user: String, handler: F) -> impl Fn(String, String) + Send + Sync
     where
         F: Fn(String, String) + Send + Sync + 'static,
     {
+        move |topic, payload| {
+            if TopicBuilder::user_from_topic(&topic) != Some(own_user.as_str()) {
+                handler(topic, payload);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for ChimeNetMqtt {
+    async fn connect(&mut self) -> Result<()> {
+        self.client.connect().await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.client.disconnect().await
+    }
+
+    async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
+        self.client.publish_bytes(topic, payload, qos, retain).await
+    }
+
+    async fn subscribe(
+        &self,
+        topic: &str,
+        qos: i32,
+        handler: MessageHandler,
+    ) -> Result<SubscriptionId> {
         self.client.subscribe(topic, qos, handler).await
     }
+
+    async fn subscribe_once(
+        &self,
+        topic: &str,
+        qos: i32,
+        predicate: MessagePredicate,
+    ) -> Result<NextMessage> {
+        self.client.subscribe_once(topic, qos, predicate).await
+    }
+
+    async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        self.client.unsubscribe(topic).await
+    }
+
+    async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+        self.client.unsubscribe_handle(id).await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    fn set_persistent_session(&self, persistent: bool) -> Result<()> {
+        self.client.set_persistent_session(persistent)
+    }
+
+    fn set_credentials(&self, credentials: Option<MqttCredentials>) -> Result<()> {
+        self.client.set_credentials(credentials)
+    }
+
+    fn set_tls(&self, tls: Option<MqttTls>) -> Result<()> {
+        self.client.set_tls(tls)
+    }
+
+    fn set_keep_alive(&self, interval: Duration) {
+        self.client.set_keep_alive(interval)
+    }
+
+    fn set_will(&self, will: Option<LastWill>) {
+        self.client.set_will(will)
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        self.client.reconnect().await
+    }
+
+    fn set_reconnect(&self, enabled: bool) {
+        self.client.set_reconnect(enabled)
+    }
+
+    fn set_reconnect_backoff(&self, backoff: ReconnectBackoff) {
+        self.client.set_reconnect_backoff(backoff)
+    }
+
+    async fn active_subscriptions(&self) -> Vec<String> {
+        ChimeNetMqtt::active_subscriptions(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::testing::{TestBus, TestTransport};
+    use std::sync::Mutex as StdMutex;
+
+    #[tokio::test]
+    async fn credentials_apply_to_the_next_connect_and_stay_out_of_logs() {
+        let client = MqttClient::new("tcp://localhost:1883", "credentials_test")
+            .await
+            .unwrap();
+        assert!(!client.has_credentials());
+
+        let credentials = MqttCredentials::new("alice", Some("hunter2".to_string()));
+        client.set_credentials(Some(credentials.clone()));
+        assert!(client.has_credentials());
+        let options = client.connect_options().unwrap();
+        assert!(options.clean_session());
+        let sent = format!("{:?}", options);
+        assert!(sent.contains("\"alice\""), "{}", sent);
+        assert!(sent.contains("\"hunter2\""), "{}", sent);
+
+        let logged = format!("{:?}", credentials);
+        assert!(logged.contains("alice"));
+        assert!(!logged.contains("hunter2"));
+
+        client.set_credentials(None);
+        assert!(!client.has_credentials());
+        let sent = format!("{:?}", client.connect_options().unwrap());
+        assert!(!sent.contains("\"alice\""), "{}", sent);
+    }
+
+    #[tokio::test]
+    async fn a_client_dropped_without_disconnecting_leaves_its_will() {
+        let bus = TestBus::new();
+        let watcher =
+            ChimeNetMqtt::with_transport(Box::new(bus.client("watcher")), &"bob".into()).unwrap();
+        let seen = Arc::new(StdMutex::new(Vec::new()));
+        let sink = seen.clone();
+        let status_topic = TopicBuilder::chime_status("alice", "desk");
+        watcher
+            .subscribe_foreign(&status_topic, 1, move |_, payload| {
+                sink.lock().unwrap().push(payload)
+            })
+            .await
+            .unwrap();
+        let offline = ChimeStatus {
+            chime_id: "desk".to_string(),
+            online: false,
+            mode: LcgpMode::Available,
+            last_seen: chrono::Utc::now(),
+            node_id: "node".to_string(),
+            following: None,
+            ring_rate_per_hour: 0.0,
+            status_message: None,
+            in_reply_to: None,
+        };
+
+        // A clean disconnect withdraws the will; only the dropped client leaves it
+        for disconnect_first in [true, false] {
+            let mut chime =
+                ChimeNetMqtt::with_transport(Box::new(bus.client("chime")), &"alice".into())
+                    .unwrap();
+            chime.set_offline_will(&"desk".into(), &offline).unwrap();
+            chime.connect().await.unwrap();
+            if disconnect_first {
+                chime.disconnect().await.unwrap();
+            }
+            drop(chime);
+        }
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        let status: ChimeStatus = serde_json::from_str(&seen[0]).unwrap();
+        assert!(!status.online);
+        let will = bus.last_publish().unwrap();
+        assert_eq!(
+            (will.client, will.topic.as_str()),
+            ("chime", status_topic.as_str())
+        );
+        assert!(will.retain);
+    }
+
+    // Answers status requests as a chime would, first with a reply to some
+    // other request. Chimes named "silent" never answer.
+    fn status_responder() -> TestTransport {
+        let bus = TestBus::new();
+        bus.on_publish(|bus, publish| {
+            let Some(status_topic) = publish.topic.strip_suffix("_request") else {
+                return Ok(());
+            };
+            if status_topic.contains("/silent/") {
+                return Ok(());
+            }
+
+            let request: ChimeStatusRequest = serde_json::from_slice(&publish.payload)?;
+            for in_reply_to in ["someone else".to_string(), request.request_id] {
+                let status = ChimeStatus {
+                    chime_id: "abc".to_string(),
+                    online: true,
+                    mode: LcgpMode::Available,
+                    last_seen: chrono::Utc::now(),
+                    node_id: "alice_abc".to_string(),
+                    following: None,
+                    ring_rate_per_hour: 0.0,
+                    status_message: None,
+                    in_reply_to: Some(in_reply_to),
+                };
+                bus.deliver(status_topic, &serde_json::to_string(&status)?);
+            }
+            Ok(())
+        });
+        bus.client("alice")
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn status_requests_take_only_their_own_reply() {
+        let alice = UserId::from("alice");
+        let mqtt = ChimeNetMqtt::with_transport(Box::new(status_responder()), &alice).unwrap();
+
+        let status = mqtt
+            .request_status(&alice, &ChimeId::from("abc"))
+            .await
+            .unwrap();
+        assert_eq!(status.chime_id, "abc");
+        assert_ne!(status.in_reply_to.as_deref(), Some("someone else"));
+
+        let started = tokio::time::Instant::now();
+        let silent = mqtt
+            .request_status_with_timeout(&alice, &ChimeId::from("silent"), Duration::from_secs(2))
+            .await;
+        assert!(silent.is_err());
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn broadcast_ring_reports_each_chime_and_carries_on_past_failures() {
+        // Refuses the ring for chime "broken"
+        let bus = TestBus::new();
+        bus.on_publish(|_, publish| {
+            if publish.topic.contains("/broken/") {
+                return Err(ChimeNetError::Connection("broker said no".to_string()));
+            }
+            Ok(())
+        });
+        let mqtt = ChimeNetMqtt::with_transport(Box::new(bus.client("bob")), &UserId::from("bob"))
+            .unwrap();
+        let ring_request = ChimeRingRequest {
+            chime_id: "ignored".to_string(),
+            user: "ignored".to_string(),
+            notes: Some(vec!["C4".to_string()]),
+            chords: None,
+            duration_ms: None,
+            note_durations: None,
+            timestamp: chrono::Utc::now(),
+            preset: None,
+            urgency: Urgency::Normal,
+            expires_at: None,
+            request_id: None,
+        };
+
+        let ids = ["abc", "broken", "a/b", "xyz"].map(String::from);
+        let results = mqtt
+            .publish_chime_ring_broadcast(&UserId::from("alice"), &ids, &ring_request)
+            .await;
+        let outcome: Vec<(&str, bool)> = results
+            .iter()
+            .map(|(id, result)| (id.as_str(), result.is_ok()))
+            .collect();
+        assert_eq!(
+            outcome,
+            [
+                ("abc", true),
+                ("broken", false),
+                ("a/b", false),
+                ("xyz", true)
+            ]
+        );
+
+        let rung: Vec<(String, ChimeRingRequest)> = bus
+            .publishes()
+            .into_iter()
+            .map(|publish| {
+                (
+                    publish.topic,
+                    serde_json::from_slice(&publish.payload).unwrap(),
+                )
+            })
+            .collect();
+        let topics: Vec<&str> = rung.iter().map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(topics, ["/alice/chime/abc/ring", "/alice/chime/xyz/ring"]);
+        assert!(rung.iter().all(|(topic, request)| request.user == "alice"
+            && topic.contains(&request.chime_id)
+            && request.notes == ring_request.notes));
+    }
+
+    #[tokio::test]
+    async fn default_qos_overrides_every_kind_of_message() {
+        let builder = || {
+            ChimeNetMqtt::builder()
+                .broker("tcp://localhost:1883")
+                .user(&UserId::from("alice"))
+        };
+
+        let mut mqtt = builder().build().await.unwrap();
+        assert_eq!(mqtt.default_qos(), None);
+        assert_eq!((mqtt.state_qos(), mqtt.ring_qos()), (QOS_STATE, QOS_RING));
+
+        mqtt.set_default_qos(Some(0)).unwrap();
+        assert_eq!(
+            (mqtt.state_qos(), mqtt.ring_qos(), mqtt.any_qos()),
+            (0, 0, 0)
+        );
+        assert!(mqtt.set_default_qos(Some(3)).is_err());
+        assert_eq!(mqtt.default_qos(), Some(0));
+        mqtt.set_default_qos(None).unwrap();
+        assert_eq!(mqtt.ring_qos(), QOS_RING);
+
+        let exactly_once = builder().default_qos(2).build().await.unwrap();
+        assert_eq!((exactly_once.state_qos(), exactly_once.ring_qos()), (2, 2));
+        assert!(builder().default_qos(-1).build().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn builder_checks_each_setting_against_the_transport() {
+        let alice = UserId::from("alice");
+        let builder = || {
+            ChimeNetMqtt::builder()
+                .broker("tcp://localhost:1883")
+                .user(&alice)
+        };
+
+        let mqtt = builder()
+            .credentials(Some(MqttCredentials::new("alice", None)))
+            .keep_alive(Duration::from_secs(5))
+            .clean_session(false)
+            .tls(Some(MqttTls::default()))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(mqtt.user(), &alice);
+        assert!(!mqtt.is_connected());
+
+        let missing = |builder: ChimeNetMqttBuilder| async { builder.build().await.is_err() };
+        assert!(missing(ChimeNetMqtt::builder().user(&alice)).await);
+        assert!(missing(ChimeNetMqtt::builder().broker("tcp://localhost:1883")).await);
+        assert!(missing(builder().user(&UserId::from("al/ice"))).await);
+
+        let tls = MqttTls {
+            insecure: true,
+            ..MqttTls::default()
+        };
+        assert!(builder().tls(Some(tls)).build().await.is_err());
+        let lan = || ChimeNetMqtt::builder().broker("udp://").user(&alice);
+        assert!(lan().clean_session(false).build().await.is_err());
+        assert!(lan().clean_session(true).build().await.is_ok());
+
+        let client = MqttClient::new("tcp://localhost:1883", "keep_alive_test")
+            .await
+            .unwrap();
+        assert_eq!(client.keep_alive(), DEFAULT_KEEP_ALIVE);
+        client.set_keep_alive(Duration::from_secs(5));
+        assert_eq!(client.keep_alive(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn retained_will_topic_payload_is_restored_after_reconnect() {
+        let will = |topic: &str| LastWill {
+            topic: topic.to_string(),
+            payload: b"offline".to_vec(),
+            qos: 1,
+            retain: true,
+        };
+        let mut state = WillState::default();
+        state.record("/alice/chime/abc/status", b"online", true);
+        assert_eq!(state.restore(), None);
+
+        state.set(Some(will("/alice/chime/abc/status")));
+        state.record("/alice/chime/abc/status", b"stale", false);
+        state.record("/alice/chime/abc/list", b"other", true);
+        assert_eq!(state.restore(), None);
+        state.record("/alice/chime/abc/status", b"online", true);
+        assert_eq!(
+            state.restore(),
+            Some(("/alice/chime/abc/status".to_string(), b"online".to_vec(), 1))
+        );
+
+        // Same topic keeps the payload; a new one forgets it
+        state.set(Some(will("/alice/chime/abc/status")));
+        assert!(state.restore().is_some());
+        state.set(Some(will("/alice/chime/xyz/status")));
+        assert_eq!(state.restore(), None);
+
+        let client = MqttClient::new("tcp://localhost:1883", "will_test")
+            .await
+            .unwrap();
+        assert_eq!(client.will(), None);
+        client.set_will(Some(will("/alice/chime/abc/status")));
+        assert_eq!(client.will(), Some(will("/alice/chime/abc/status")));
+        assert!(client.connect_options().is_ok());
+    }
+
+    #[tokio::test]
+    async fn tls_options_are_built_for_tls_brokers_only() {
+        let ca_file = std::env::temp_dir().join(format!("chimenet_ca_{}.pem", std::process::id()));
+        std::fs::write(&ca_file, "-----BEGIN CERTIFICATE-----\n").unwrap();
+        let tls = MqttTls {
+            ca_file: Some(ca_file.clone()),
+            insecure: true,
+            ..MqttTls::default()
+        };
+
+        let options = tls.ssl_options().unwrap();
+        assert_eq!(options.trust_store(), ca_file);
+        assert!(!options.enable_server_cert_auth());
+        assert!(MqttTls::default()
+            .ssl_options()
+            .unwrap()
+            .enable_server_cert_auth());
+        assert_eq!(MqttTls::default().configured(), None);
+
+        for url in ["ssl://broker:8883", "mqtts://broker:8883"] {
+            let client = MqttClient::new(url, "tls_test").await.unwrap();
+            assert!(client.uses_tls(), "{}", url);
+            client.set_tls(Some(tls.clone())).unwrap();
+            assert!(client.connect_options().is_ok());
+        }
+
+        let missing = MqttTls {
+            key_store: Some(ca_file.with_extension("missing")),
+            ..MqttTls::default()
+        };
+        let client = MqttClient::new("ssl://broker:8883", "tls_test")
+            .await
+            .unwrap();
+        assert!(client.set_tls(Some(missing)).is_err());
+
+        let plain = MqttClient::new("tcp://broker:1883", "tls_test")
+            .await
+            .unwrap();
+        assert!(!plain.uses_tls());
+        assert!(plain.set_tls(Some(tls)).is_err());
+        assert!(plain.set_tls(None).is_ok());
+
+        std::fs::remove_file(ca_file).unwrap();
+    }
+
+    #[test]
+    fn foreign_only_filters_own_messages() {
+        let received = Arc::new(StdMutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let handler = ChimeNetMqtt::foreign_only("alice".to_string(), move |topic, _| {
+            received_clone.lock().unwrap().push(topic);
+        });
+
+        handler("/alice/chime/list".to_string(), "{}".to_string());
+        handler("/alice/chime/abc/status".to_string(), "{}".to_string());
+        handler("/bob/chime/list".to_string(), "{}".to_string());
+        handler("/alice_work/chime/xyz/status".to_string(), "{}".to_string());
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec!["/bob/chime/list", "/alice_work/chime/xyz/status"]
+        );
+    }
+
+    #[test]
+    fn every_handler_on_a_filter_runs() {
+        let received = Arc::new(StdMutex::new(Vec::new()));
+        let handler = |name: &'static str| -> MessageHandler {
+            let received = received.clone();
+            Box::new(move |topic, _| received.lock().unwrap().push((name, topic)))
+        };
+
+        let subscription = |topic: &str, handlers: Vec<MessageHandler>| {
+            let mut set = HandlerSet::default();
+            let ids: Vec<SubscriptionId> = handlers
+                .into_iter()
+                .map(|handler| set.push(topic, handler))
+                .collect();
+            let subscription = Subscription {
+                qos: 1,
+                handlers: set,
+            };
+            (subscription, ids)
+        };
+        let (list, list_ids) =
+            subscription("/+/chime/list", vec![handler("node"), handler("discovery")]);
+        let (ring, _) = subscription("/alice/chime/abc/ring", vec![handler("ring")]);
+        let mut subscriptions = HashMap::from([
+            ("/+/chime/list".to_string(), list),
+            ("/alice/chime/abc/ring".to_string(), ring),
+        ]);
+        let (mut response, _) = subscription("/alice/chime/abc/response", vec![]);
+        let once = response.handlers.push_once(Box::new(|_, _| true));
+        subscriptions.insert("/alice/chime/abc/response".to_string(), response);
+
+        let message = |topic: &str| MqttMessage {
+            topic: topic.to_string(),
+            payload: "{}".to_string(),
+            qos: 1,
+            retain: false,
+        };
+        assert!(MqttClient::dispatch(&mut subscriptions, &message("/bob/chime/list")).is_empty());
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                ("node", "/bob/chime/list".to_string()),
+                ("discovery", "/bob/chime/list".to_string())
+            ]
+        );
+
+        // A filter only a one-shot waiter used is dropped once it fires
+        assert_eq!(
+            MqttClient::dispatch(&mut subscriptions, &message("/alice/chime/abc/response")),
+            vec!["/alice/chime/abc/response".to_string()]
+        );
+        assert!(!subscriptions.contains_key("/alice/chime/abc/response"));
+        let taken = futures::FutureExt::now_or_never(once).unwrap().unwrap();
+        assert_eq!(taken.topic, "/alice/chime/abc/response");
+
+        // Removing one handle keeps the other handler on the filter
+        assert_eq!(list_ids[0].topic(), "/+/chime/list");
+        assert_ne!(list_ids[0], list_ids[1]);
+        let list = &mut subscriptions.get_mut("/+/chime/list").unwrap().handlers;
+        assert!(list.remove(&list_ids[0]));
+        assert!(!list.remove(&list_ids[0]));
+        assert!(!list.is_empty());
+        received.lock().unwrap().clear();
+        MqttClient::dispatch(&mut subscriptions, &message("/carol/chime/list"));
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![("discovery", "/carol/chime/list".to_string())]
+        );
+
+        let list = &mut subscriptions.get_mut("/+/chime/list").unwrap().handlers;
+        assert!(list.remove(&list_ids[1]));
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn extracts_user_from_topic() {
+        assert_eq!(
+            TopicBuilder::user_from_topic("/alice/chime/list"),
+            Some("alice")
+        );
+        assert_eq!(TopicBuilder::user_from_topic("alice/chime/list"), None);
+    }
+
+    #[test]
+    fn reconnect_backoff_doubles_up_to_the_cap() {
+        let backoff = ReconnectBackoff::default();
+        let delays: Vec<u64> = (1..=7).map(|a| backoff.delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(backoff.delay(u32::MAX), backoff.max);
+    }
+
+    #[test]
+    fn unclaimed_backlog_waits_for_its_filter() {
+        let message = |topic: &str| MqttMessage {
+            topic: topic.to_string(),
+            payload: "{}".to_string(),
+            qos: 1,
+            retain: false,
+        };
+        let mut unclaimed = VecDeque::new();
+        MqttClient::hold_unclaimed(&mut unclaimed, message("/alice/chime/abc/ring"));
+        MqttClient::hold_unclaimed(&mut unclaimed, message("/bob/chime/list"));
+        MqttClient::hold_unclaimed(&mut unclaimed, message("/alice/chime/abc/ring"));
+
+        let rings = MqttClient::take_unclaimed(&mut unclaimed, "/alice/chime/abc/ring");
+        assert_eq!(rings.len(), 2);
+        assert_eq!(unclaimed.len(), 1);
+        assert_eq!(unclaimed[0].topic, "/bob/chime/list");
+
+        for i in 0..MAX_UNCLAIMED_MESSAGES {
+            MqttClient::hold_unclaimed(&mut unclaimed, message(&format!("/u{}/chime/list", i)));
+        }
+        assert_eq!(unclaimed.len(), MAX_UNCLAIMED_MESSAGES);
+        assert_eq!(unclaimed[0].topic, "/u0/chime/list");
+    }
 }
@@ -0,0 +1,75 @@
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Where an accepted ring is delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RingOutput {
+    #[default]
+    Audio,
+    Notification,
+    Both,
+}
+
+impl RingOutput {
+    pub fn plays_audio(&self) -> bool {
+        matches!(self, RingOutput::Audio | RingOutput::Both)
+    }
+
+    pub fn shows_notification(&self) -> bool {
+        matches!(self, RingOutput::Notification | RingOutput::Both)
+    }
+}
+
+impl FromStr for RingOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "audio" => Ok(RingOutput::Audio),
+            "notification" | "notify" => Ok(RingOutput::Notification),
+            "both" => Ok(RingOutput::Both),
+            _ => Err(format!(
+                "Unknown ring output '{}' (expected audio, notification or both)",
+                s
+            )),
+        }
+    }
+}
+
+// Raises a desktop notification for incoming rings
+#[derive(Debug, Clone)]
+pub struct NotificationSink {
+    app_name: String,
+}
+
+impl NotificationSink {
+    pub fn new(app_name: &str) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+        }
+    }
+
+    pub fn notify_ring(&self, chime_name: &str, chime_message: &ChimeMessage) -> Result<()> {
+        let summary = format!("{} from {}", chime_name, chime_message.from_node);
+        let body = chime_message
+            .message
+            .clone()
+            .unwrap_or_else(|| format!("{} is ringing", chime_name));
+
+        notify_rust::Notification::new()
+            .appname(&self.app_name)
+            .summary(&summary)
+            .body(&body)
+            .show()
+            .map_err(|e| format!("Desktop notification failed: {}", e))?;
+
+        Ok(())
+    }
+}
+
+impl Default for NotificationSink {
+    fn default() -> Self {
+        Self::new("ChimeNet")
+    }
+}
@@ -0,0 +1,676 @@
+// Common surface of everything ChimeNet can talk over: an MQTT broker, LAN
+// multicast, or an in-process bus in tests. Topics and payloads are always the
+// MQTT-style ones built by TopicBuilder, whatever carries them.
+use crate::lan::LanClient;
+use crate::mqtt::{LastWill, MqttClient, MqttCredentials, MqttMessage, MqttTls, ReconnectBackoff};
+use crate::types::*;
+use async_trait::async_trait;
+#[cfg(any(test, feature = "testing"))]
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+pub type MessageHandler = Box<dyn Fn(String, String) + Send + Sync>;
+
+// Decides from (topic, payload) whether a `subscribe_once` waiter takes a message
+pub type MessagePredicate = Box<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+// The first message accepted by a `subscribe_once` predicate. Fails if the
+// subscription goes away first, e.g. through `unsubscribe`.
+pub struct NextMessage(oneshot::Receiver<MqttMessage>);
+
+impl Future for NextMessage {
+    type Output = Result<MqttMessage>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map_err(|_| {
+            ChimeNetError::Connection(
+                "Subscription closed before a matching message arrived".to_string(),
+            )
+        })
+    }
+}
+
+// One handler registered through `subscribe`, for `unsubscribe_handle`.
+// Unique within the process, whichever transport issued it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionId {
+    topic: String,
+    serial: u64,
+}
+
+impl SubscriptionId {
+    pub(crate) fn new(topic: &str) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self {
+            topic: topic.to_string(),
+            serial: NEXT.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    // The filter the handler was subscribed with
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+// Everything registered on one topic filter. One-shot waiters are dropped
+// once they've taken a message or their NextMessage was dropped.
+#[derive(Default)]
+pub(crate) struct HandlerSet {
+    handlers: Vec<(SubscriptionId, MessageHandler)>,
+    once: Vec<(MessagePredicate, oneshot::Sender<MqttMessage>)>,
+}
+
+impl HandlerSet {
+    pub(crate) fn push(&mut self, topic: &str, handler: MessageHandler) -> SubscriptionId {
+        let id = SubscriptionId::new(topic);
+        self.handlers.push((id.clone(), handler));
+        id
+    }
+
+    // False if `id` wasn't registered here
+    pub(crate) fn remove(&mut self, id: &SubscriptionId) -> bool {
+        let before = self.handlers.len();
+        self.handlers.retain(|(registered, _)| registered != id);
+        self.handlers.len() < before
+    }
+
+    pub(crate) fn push_once(&mut self, predicate: MessagePredicate) -> NextMessage {
+        let (tx, rx) = oneshot::channel();
+        self.once.retain(|(_, tx)| !tx.is_closed());
+        self.once.push((predicate, tx));
+        NextMessage(rx)
+    }
+
+    // For a message whose topic matches this set's filter
+    pub(crate) fn dispatch(&mut self, message: &MqttMessage) {
+        for (_, handler) in &self.handlers {
+            handler(message.topic.clone(), message.payload.clone());
+        }
+
+        for (predicate, tx) in std::mem::take(&mut self.once) {
+            if tx.is_closed() {
+                continue;
+            }
+            if predicate(&message.topic, &message.payload) {
+                let _ = tx.send(message.clone());
+            } else {
+                self.once.push((predicate, tx));
+            }
+        }
+    }
+
+    // How many handlers and still-waiting one-shot waiters are registered
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn counts(&self) -> (usize, usize) {
+        let waiting = self.once.iter().filter(|(_, tx)| !tx.is_closed()).count();
+        (self.handlers.len(), waiting)
+    }
+
+    // True when no handler and no live one-shot waiter is left, so the filter can go
+    pub(crate) fn is_empty(&self) -> bool {
+        self.handlers.is_empty() && self.once.iter().all(|(_, tx)| tx.is_closed())
+    }
+}
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn connect(&mut self) -> Result<()>;
+
+    async fn disconnect(&self) -> Result<()>;
+
+    async fn publish(&self, topic: &str, payload: &str, qos: i32, retain: bool) -> Result<()> {
+        self.publish_bytes(topic, payload.as_bytes(), qos, retain)
+            .await
+    }
+
+    // Raw payload, e.g. from PayloadFormat::encode. Handlers always receive
+    // JSON text (see PayloadFormat::decode_to_json).
+    async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: i32,
+        retain: bool,
+    ) -> Result<()>;
+
+    // `topic` may use MQTT wildcards; the handler gets (topic, payload).
+    // Other handlers on the same filter are kept; each gets every message.
+    async fn subscribe(
+        &self,
+        topic: &str,
+        qos: i32,
+        handler: MessageHandler,
+    ) -> Result<SubscriptionId>;
+
+    // Registers a waiter for the first message on `topic` passing `predicate`.
+    // It's in place when this returns, so publish the request afterwards and
+    // then await the reply: nothing sent in between can be missed. The waiter
+    // is removed after it fires, as is the filter if nothing else uses it.
+    // Dropping the NextMessage abandons the wait; it's cleared on the next
+    // message to the filter.
+    async fn subscribe_once(
+        &self,
+        topic: &str,
+        qos: i32,
+        predicate: MessagePredicate,
+    ) -> Result<NextMessage>;
+
+    // Drops every handler and waiter registered for `topic`
+    async fn unsubscribe(&self, topic: &str) -> Result<()>;
+
+    // Drops just the handler `subscribe` returned `id` for; the filter goes
+    // once nothing else is registered on it. Unknown ids are ignored.
+    async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()>;
+
+    fn is_connected(&self) -> bool;
+
+    // Asks the broker to keep the session (subscriptions and queued QoS 1/2
+    // messages) across disconnects; takes effect on the next connect. Only
+    // transports with a broker can do this.
+    fn set_persistent_session(&self, persistent: bool) -> Result<()> {
+        if persistent {
+            return Err(ChimeNetError::Config(
+                "This transport has no broker to keep a session".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Credentials for brokers that require authentication; takes effect on
+    // the next connect. Only transports with a broker can use them.
+    fn set_credentials(&self, credentials: Option<MqttCredentials>) -> Result<()> {
+        if credentials.is_some() {
+            return Err(ChimeNetError::Config(
+                "This transport has no broker to authenticate with".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // TLS settings for the next connect; only brokers reached over TLS take them
+    fn set_tls(&self, tls: Option<MqttTls>) -> Result<()> {
+        if tls.is_some() {
+            return Err(ChimeNetError::Config(
+                "This transport doesn't connect over TLS".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // How often to ping an idle broker; takes effect on the next connect.
+    // Transports without a broker ignore it.
+    fn set_keep_alive(&self, _interval: Duration) {}
+
+    // Message the broker publishes if this client drops without
+    // disconnecting; takes effect on the next connect. Transports without a
+    // broker ignore it, their peers notice silence through stale timeouts.
+    fn set_will(&self, _will: Option<LastWill>) {}
+
+    // Restores the connection and re-issues every subscription
+    async fn reconnect(&self) -> Result<()>;
+
+    // Whether a dropped connection is restored in the background, and how
+    // long to wait between attempts. Connectionless transports ignore both.
+    fn set_reconnect(&self, _enabled: bool) {}
+
+    fn set_reconnect_backoff(&self, _backoff: ReconnectBackoff) {}
+
+    // Topic filters currently subscribed to, for debugging handlers that
+    // never fire
+    async fn active_subscriptions(&self) -> Vec<String>;
+
+    // (handlers, one-shot waiters) per subscribed filter, for test assertions
+    #[cfg(any(test, feature = "testing"))]
+    async fn subscription_handler_counts(&self) -> HashMap<String, (usize, usize)> {
+        HashMap::new()
+    }
+}
+
+// "udp://..." selects the broker-less LAN transport, anything else is an MQTT broker
+pub async fn transport_for_url(url: &str, client_id: &str) -> Result<Box<dyn Transport>> {
+    match LanClient::group_from_url(url) {
+        Some(group) => Ok(Box::new(LanClient::new(group?, client_id))),
+        None => Ok(Box::new(MqttClient::new(url, client_id).await?)),
+    }
+}
+
+#[async_trait]
+impl Transport for MqttClient {
+    async fn connect(&mut self) -> Result<()> {
+        MqttClient::connect(self).await
+    }
+
+    fn set_persistent_session(&self, persistent: bool) -> Result<()> {
+        MqttClient::set_persistent_session(self, persistent);
+        Ok(())
+    }
+
+    fn set_credentials(&self, credentials: Option<MqttCredentials>) -> Result<()> {
+        MqttClient::set_credentials(self, credentials);
+        Ok(())
+    }
+
+    fn set_tls(&self, tls: Option<MqttTls>) -> Result<()> {
+        MqttClient::set_tls(self, tls)
+    }
+
+    fn set_keep_alive(&self, interval: Duration) {
+        MqttClient::set_keep_alive(self, interval)
+    }
+
+    fn set_will(&self, will: Option<LastWill>) {
+        MqttClient::set_will(self, will)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        MqttClient::disconnect(self).await
+    }
+
+    async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
+        MqttClient::publish_bytes(self, topic, payload, qos, retain).await
+    }
+
+    async fn subscribe(
+        &self,
+        topic: &str,
+        qos: i32,
+        handler: MessageHandler,
+    ) -> Result<SubscriptionId> {
+        MqttClient::subscribe(self, topic, qos, handler).await
+    }
+
+    async fn subscribe_once(
+        &self,
+        topic: &str,
+        qos: i32,
+        predicate: MessagePredicate,
+    ) -> Result<NextMessage> {
+        MqttClient::subscribe_once(self, topic, qos, predicate).await
+    }
+
+    async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        MqttClient::unsubscribe(self, topic).await
+    }
+
+    async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+        MqttClient::unsubscribe_handle(self, id).await
+    }
+
+    fn is_connected(&self) -> bool {
+        MqttClient::is_connected(self)
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        MqttClient::reconnect(self).await
+    }
+
+    fn set_reconnect(&self, enabled: bool) {
+        MqttClient::set_reconnect(self, enabled)
+    }
+
+    fn set_reconnect_backoff(&self, backoff: ReconnectBackoff) {
+        MqttClient::set_reconnect_backoff(self, backoff)
+    }
+
+    async fn active_subscriptions(&self) -> Vec<String> {
+        MqttClient::active_subscriptions(self).await
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    async fn subscription_handler_counts(&self) -> HashMap<String, (usize, usize)> {
+        MqttClient::subscription_handler_counts(self).await
+    }
+}
+
+#[async_trait]
+impl Transport for LanClient {
+    async fn connect(&mut self) -> Result<()> {
+        LanClient::connect(self).await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        LanClient::disconnect(self).await
+    }
+
+    async fn publish_bytes(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: i32,
+        retain: bool,
+    ) -> Result<()> {
+        LanClient::publish_bytes(self, topic, payload, qos, retain).await
+    }
+
+    async fn subscribe(
+        &self,
+        topic: &str,
+        qos: i32,
+        handler: MessageHandler,
+    ) -> Result<SubscriptionId> {
+        LanClient::subscribe(self, topic, qos, handler).await
+    }
+
+    async fn subscribe_once(
+        &self,
+        topic: &str,
+        qos: i32,
+        predicate: MessagePredicate,
+    ) -> Result<NextMessage> {
+        LanClient::subscribe_once(self, topic, qos, predicate).await
+    }
+
+    async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        LanClient::unsubscribe(self, topic).await
+    }
+
+    async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+        LanClient::unsubscribe_handle(self, id).await
+    }
+
+    fn is_connected(&self) -> bool {
+        LanClient::is_connected(self)
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        LanClient::reconnect(self).await
+    }
+
+    async fn active_subscriptions(&self) -> Vec<String> {
+        LanClient::active_subscriptions(self).await
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    async fn subscription_handler_counts(&self) -> HashMap<String, (usize, usize)> {
+        LanClient::subscription_handler_counts(self).await
+    }
+}
+
+// In-process stand-in for a broker, for unit tests. Each TestTransport from
+// `TestBus::client` publishes to the bus, which logs the publish and delivers
+// it to every matching subscription on the bus. A publish hook can refuse
+// publishes or answer them, e.g. play the chime a request is addressed to. A
+// client dropped while connected has its will published, as a broker would.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::mqtt::MqttClient;
+    use std::sync::{Arc, Mutex};
+
+    type PublishHook = Box<dyn Fn(&TestBus, &Published) -> Result<()> + Send + Sync>;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct Published {
+        pub client: &'static str,
+        pub topic: String,
+        pub payload: Vec<u8>,
+        pub retain: bool,
+    }
+
+    #[derive(Default)]
+    pub(crate) struct TestBus {
+        // Per (client, filter), so one client unsubscribing leaves the others
+        subscriptions: Mutex<HashMap<(&'static str, String), HandlerSet>>,
+        published: Mutex<Vec<Published>>,
+        hook: Mutex<Option<PublishHook>>,
+    }
+
+    impl TestBus {
+        pub(crate) fn new() -> Arc<Self> {
+            Arc::default()
+        }
+
+        pub(crate) fn client(self: &Arc<Self>, name: &'static str) -> TestTransport {
+            TestTransport {
+                bus: self.clone(),
+                name,
+                will: Mutex::default(),
+                armed_will: Mutex::default(),
+            }
+        }
+
+        // Runs before each publish is logged and delivered; an error fails
+        // the publish instead
+        pub(crate) fn on_publish<F>(&self, hook: F)
+        where
+            F: Fn(&TestBus, &Published) -> Result<()> + Send + Sync + 'static,
+        {
+            *self.hook.lock().unwrap() = Some(Box::new(hook));
+        }
+
+        // Hands `payload` to every subscription matching `topic`, as if some
+        // node outside the test had published it. Not logged.
+        pub(crate) fn deliver(&self, topic: &str, payload: &str) {
+            let message = MqttMessage {
+                topic: topic.to_string(),
+                payload: payload.to_string(),
+                qos: 1,
+                retain: false,
+            };
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            for ((_, filter), handlers) in subscriptions.iter_mut() {
+                if MqttClient::topic_matches(filter, topic) {
+                    handlers.dispatch(&message);
+                }
+            }
+        }
+
+        fn broadcast(&self, publish: Published) -> Result<()> {
+            let payload = crate::types::PayloadFormat::decode_to_json(&publish.payload)?;
+            let topic = publish.topic.clone();
+            self.published.lock().unwrap().push(publish);
+            self.deliver(&topic, &payload);
+            Ok(())
+        }
+
+        // (client, topic) of every publish so far, oldest first
+        pub(crate) fn published(&self) -> Vec<(&'static str, String)> {
+            self.published
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|publish| (publish.client, publish.topic.clone()))
+                .collect()
+        }
+
+        pub(crate) fn publishes(&self) -> Vec<Published> {
+            self.published.lock().unwrap().clone()
+        }
+
+        pub(crate) fn last_publish(&self) -> Option<Published> {
+            self.published.lock().unwrap().last().cloned()
+        }
+
+        pub(crate) fn clear(&self) {
+            self.published.lock().unwrap().clear();
+        }
+    }
+
+    pub(crate) struct TestTransport {
+        bus: Arc<TestBus>,
+        name: &'static str,
+        will: Mutex<Option<LastWill>>,
+        // The will of the current connection, like a broker holds it
+        armed_will: Mutex<Option<LastWill>>,
+    }
+
+    impl Drop for TestTransport {
+        fn drop(&mut self) {
+            if let Some(will) = self.armed_will.lock().unwrap().take() {
+                let _ = self.bus.broadcast(Published {
+                    client: self.name,
+                    topic: will.topic,
+                    payload: will.payload,
+                    retain: will.retain,
+                });
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Transport for TestTransport {
+        async fn connect(&mut self) -> Result<()> {
+            *self.armed_will.lock().unwrap() = self.will.lock().unwrap().clone();
+            Ok(())
+        }
+
+        async fn disconnect(&self) -> Result<()> {
+            self.armed_will.lock().unwrap().take();
+            Ok(())
+        }
+
+        fn set_will(&self, will: Option<LastWill>) {
+            *self.will.lock().unwrap() = will;
+        }
+
+        async fn publish_bytes(
+            &self,
+            topic: &str,
+            payload: &[u8],
+            _qos: i32,
+            retain: bool,
+        ) -> Result<()> {
+            let publish = Published {
+                client: self.name,
+                topic: topic.to_string(),
+                payload: payload.to_vec(),
+                retain,
+            };
+            if let Some(hook) = self.bus.hook.lock().unwrap().as_ref() {
+                hook(&self.bus, &publish)?;
+            }
+
+            self.bus.broadcast(publish)
+        }
+
+        async fn subscribe(
+            &self,
+            topic: &str,
+            _qos: i32,
+            handler: MessageHandler,
+        ) -> Result<SubscriptionId> {
+            let mut subscriptions = self.bus.subscriptions.lock().unwrap();
+            Ok(subscriptions
+                .entry((self.name, topic.to_string()))
+                .or_default()
+                .push(topic, handler))
+        }
+
+        async fn subscribe_once(
+            &self,
+            topic: &str,
+            _qos: i32,
+            predicate: MessagePredicate,
+        ) -> Result<NextMessage> {
+            let mut subscriptions = self.bus.subscriptions.lock().unwrap();
+            Ok(subscriptions
+                .entry((self.name, topic.to_string()))
+                .or_default()
+                .push_once(predicate))
+        }
+
+        async fn unsubscribe(&self, topic: &str) -> Result<()> {
+            let key = (self.name, topic.to_string());
+            self.bus.subscriptions.lock().unwrap().remove(&key);
+            Ok(())
+        }
+
+        async fn unsubscribe_handle(&self, id: &SubscriptionId) -> Result<()> {
+            let key = (self.name, id.topic().to_string());
+            let mut subscriptions = self.bus.subscriptions.lock().unwrap();
+            if let Some(handlers) = subscriptions.get_mut(&key) {
+                handlers.remove(id);
+                if handlers.is_empty() {
+                    subscriptions.remove(&key);
+                }
+            }
+            Ok(())
+        }
+
+        fn is_connected(&self) -> bool {
+            true
+        }
+
+        async fn reconnect(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn active_subscriptions(&self) -> Vec<String> {
+            let mut filters: Vec<String> = self
+                .bus
+                .subscriptions
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|(client, _)| *client == self.name)
+                .map(|(_, filter)| filter.clone())
+                .collect();
+            filters.sort();
+            filters
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            qos: 1,
+            retain: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn once_waiters_take_one_matching_message() {
+        let seen = Arc::new(Mutex::new(0));
+        let mut set = HandlerSet::default();
+        let counter = seen.clone();
+        set.push(
+            "/a/chime/x/response",
+            Box::new(move |_, _| *counter.lock().unwrap() += 1),
+        );
+
+        let positive = set.push_once(Box::new(|_, payload| payload.contains("Positive")));
+        let abandoned = set.push_once(Box::new(|_, _| true));
+        drop(abandoned);
+        assert_eq!(set.counts(), (1, 1));
+
+        set.dispatch(&message("/a/chime/x/response", "Negative"));
+        set.dispatch(&message("/a/chime/x/response", "Positive 1"));
+        set.dispatch(&message("/a/chime/x/response", "Positive 2"));
+
+        assert_eq!(positive.await.unwrap().payload, "Positive 1");
+        assert_eq!(*seen.lock().unwrap(), 3);
+        assert_eq!(set.counts(), (1, 0));
+
+        let mut once_only = HandlerSet::default();
+        let next = once_only.push_once(Box::new(|_, _| true));
+        assert!(!once_only.is_empty());
+        once_only.dispatch(&message("/a/chime/x/response", "{}"));
+        assert!(once_only.is_empty());
+        assert!(next.await.is_ok());
+
+        // Dropping the set (e.g. unsubscribe) fails the waiter instead of hanging
+        let mut dropped = HandlerSet::default();
+        let next = dropped.push_once(Box::new(|_, _| true));
+        drop(dropped);
+        assert!(next.await.is_err());
+    }
+}
@@ -1,7 +1,90 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+// Newtypes for the different kinds of identifiers, so a user can't be passed
+// where a chime id is expected (and vice versa)
+macro_rules! id_type {
+    ($name:ident) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            // Rejects ids that could escape their topic namespace, see
+            // `is_valid_topic_segment`
+            pub fn validate(&self) -> Result<()> {
+                if is_valid_topic_segment(&self.0) {
+                    Ok(())
+                } else {
+                    Err(ChimeNetError::InvalidTopic(format!(
+                        "Invalid {} '{}': only letters, digits, '-' and '_' are allowed",
+                        stringify!($name),
+                        self.0
+                    )))
+                }
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_string())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+// Users and chime ids are interpolated into topics, so anything beyond
+// [A-Za-z0-9_-] is refused: '/' adds levels, '+' and '#' are wildcards and
+// ".." reads like a path escape.
+pub fn is_valid_topic_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+id_type!(UserId); // Owner of chimes, e.g. "alice"; the first segment of every topic
+id_type!(ChimeId); // A single chime, unique per user
+id_type!(NodeId); // An LCGP node, "{user}_{chime_id}" for chimes
+
+impl NodeId {
+    pub fn for_chime(user: &UserId, chime_id: &ChimeId) -> Self {
+        Self(format!("{}_{}", user, chime_id))
+    }
+}
+
+// Still serialized in serde's externally tagged form ({"Custom":"x"}) so older
+// nodes can read it; deserializing also accepts the "Custom:x" text form
+// (see FromStr) written by HTTP and shell clients.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
 pub enum LcgpMode {
     DoNotDisturb,
     Available,
@@ -10,6 +93,87 @@ pub enum LcgpMode {
     Custom(String), // Custom state name
 }
 
+impl std::fmt::Display for LcgpMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LcgpMode::DoNotDisturb => write!(f, "DoNotDisturb"),
+            LcgpMode::Available => write!(f, "Available"),
+            LcgpMode::ChillGrinding => write!(f, "ChillGrinding"),
+            LcgpMode::Grinding => write!(f, "Grinding"),
+            LcgpMode::Custom(name) => write!(f, "Custom:{}", name),
+        }
+    }
+}
+
+// Case-insensitive mode names plus "dnd" and "chill"; a custom state's name
+// keeps its case
+impl std::str::FromStr for LcgpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some((prefix, name)) = s.split_once(':') {
+            if prefix.eq_ignore_ascii_case("custom") && !name.is_empty() {
+                return Ok(LcgpMode::Custom(name.to_string()));
+            }
+        }
+
+        match s.to_lowercase().as_str() {
+            "donotdisturb" | "dnd" => Ok(LcgpMode::DoNotDisturb),
+            "available" => Ok(LcgpMode::Available),
+            "chillgrinding" | "chill" => Ok(LcgpMode::ChillGrinding),
+            "grinding" => Ok(LcgpMode::Grinding),
+            _ => Err(format!(
+                "Invalid mode '{}' (expected Available, DoNotDisturb, Grinding, ChillGrinding or Custom:name)",
+                s
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LcgpMode {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // Mirrors LcgpMode's derived serialization
+        #[derive(Deserialize)]
+        enum Tagged {
+            DoNotDisturb,
+            Available,
+            ChillGrinding,
+            Grinding,
+            Custom(String),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Wire {
+            Text(String),
+            Tagged(Tagged),
+        }
+
+        static SEEN_TAGGED_CUSTOM: std::sync::atomic::AtomicBool =
+            std::sync::atomic::AtomicBool::new(false);
+
+        match Wire::deserialize(deserializer)? {
+            Wire::Text(text) => text.parse().map_err(serde::de::Error::custom),
+            Wire::Tagged(Tagged::DoNotDisturb) => Ok(LcgpMode::DoNotDisturb),
+            Wire::Tagged(Tagged::Available) => Ok(LcgpMode::Available),
+            Wire::Tagged(Tagged::ChillGrinding) => Ok(LcgpMode::ChillGrinding),
+            Wire::Tagged(Tagged::Grinding) => Ok(LcgpMode::Grinding),
+            Wire::Tagged(Tagged::Custom(name)) => {
+                if !SEEN_TAGGED_CUSTOM.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                    log::info!(
+                        "Read custom mode '{}' in the legacy {{\"Custom\": ...}} form",
+                        name
+                    );
+                }
+                Ok(LcgpMode::Custom(name))
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CustomLcgpState {
     pub name: String,
@@ -22,6 +186,120 @@ pub struct CustomLcgpState {
     pub conditions: Vec<StateCondition>, // Conditions for auto-activation
 }
 
+impl CustomLcgpState {
+    // Builds a state from `key=value` command words, e.g. ["chime=false",
+    // "auto=neg", "delay=2000", "priority=100"]. Keys left out keep their
+    // default (no chime, no auto response, no delay or priority). The state
+    // must pass `validate`.
+    pub fn from_args(name: &str, args: &[&str]) -> Result<Self> {
+        let mut state = CustomLcgpState {
+            name: name.to_string(),
+            should_chime: false,
+            auto_response: None,
+            auto_response_delay: None,
+            description: None,
+            priority: None,
+            active_hours: None,
+            conditions: Vec::new(),
+        };
+
+        for arg in args {
+            let (key, value) = arg.split_once('=').ok_or_else(|| {
+                ChimeNetError::Config(format!("Expected key=value, got '{}'", arg))
+            })?;
+            let invalid = |expected: &str| {
+                ChimeNetError::Config(format!("Invalid {} '{}' ({})", key, value, expected))
+            };
+            match key {
+                "chime" => {
+                    state.should_chime = match value.to_lowercase().as_str() {
+                        "true" | "yes" | "y" => true,
+                        "false" | "no" | "n" => false,
+                        _ => return Err(invalid("expected true or false")),
+                    }
+                }
+                "auto" => {
+                    state.auto_response = match value.to_lowercase().as_str() {
+                        "none" => None,
+                        response => Some(response.parse().map_err(ChimeNetError::Config)?),
+                    }
+                }
+                "delay" => {
+                    state.auto_response_delay =
+                        Some(value.parse().map_err(|_| invalid("milliseconds"))?)
+                }
+                "priority" => state.priority = Some(value.parse().map_err(|_| invalid("0-255"))?),
+                _ => {
+                    return Err(ChimeNetError::Config(format!(
+                        "Unknown key '{}' (expected chime, auto, delay or priority)",
+                        key
+                    )))
+                }
+            }
+        }
+
+        state
+            .validate()
+            .map_err(|errors| ChimeNetError::Config(errors.join("; ")))?;
+        Ok(state)
+    }
+
+    // Checks the state is well-formed and does something useful. Returns every
+    // problem found rather than stopping at the first one. `priority` needs no
+    // check here since u8 already limits it to 0-255.
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push("name must not be empty".to_string());
+        }
+
+        if self.auto_response_delay.is_some() && self.auto_response.is_none() {
+            errors.push("auto_response_delay is set but there is no auto_response".to_string());
+        }
+
+        if !self.should_chime && self.auto_response.is_none() && self.conditions.is_empty() {
+            errors
+                .push("state never chimes, never auto-responds and has no conditions".to_string());
+        }
+
+        if let Some(range) = &self.active_hours {
+            errors.extend(
+                range
+                    .validate()
+                    .into_iter()
+                    .map(|e| format!("active_hours: {}", e)),
+            );
+        }
+
+        for (i, condition) in self.conditions.iter().enumerate() {
+            match condition {
+                StateCondition::TimeRange(range) => errors.extend(
+                    range
+                        .validate()
+                        .into_iter()
+                        .map(|e| format!("conditions[{}]: {}", i, e)),
+                ),
+                StateCondition::SystemLoad(threshold)
+                    if !threshold.is_finite() || *threshold < 0.0 =>
+                {
+                    errors.push(format!(
+                        "conditions[{}]: system load threshold must be a non-negative number",
+                        i
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeRange {
     pub start_hour: u8,        // 0-23
@@ -31,6 +309,33 @@ pub struct TimeRange {
     pub days_of_week: Vec<u8>, // 0-6, Sunday = 0
 }
 
+impl TimeRange {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for (field, hour) in [("start_hour", self.start_hour), ("end_hour", self.end_hour)] {
+            if hour > 23 {
+                errors.push(format!("{} must be 0-23, got {}", field, hour));
+            }
+        }
+        for (field, minute) in [
+            ("start_minute", self.start_minute),
+            ("end_minute", self.end_minute),
+        ] {
+            if minute > 59 {
+                errors.push(format!("{} must be 0-59, got {}", field, minute));
+            }
+        }
+        for day in &self.days_of_week {
+            if *day > 6 {
+                errors.push(format!("days_of_week must be 0-6, got {}", day));
+            }
+        }
+
+        errors
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum StateCondition {
     TimeRange(TimeRange),
@@ -41,15 +346,106 @@ pub enum StateCondition {
     Custom(String, String), // key, value pairs for custom conditions
 }
 
+// A value set on a node for its states' conditions to test, see
+// LcgpNode::set_condition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ConditionValue {
+    Bool(bool),
+    Float(f64),
+    Text(String),
+}
+
+impl ConditionValue {
+    // For StateCondition::Custom: the same text, or the same bool or number
+    // however it's written ("1" matches 1.0)
+    pub fn matches(&self, expected: &str) -> bool {
+        match self {
+            ConditionValue::Bool(value) => expected.parse::<bool>() == Ok(*value),
+            ConditionValue::Float(value) => expected.parse::<f64>() == Ok(*value),
+            ConditionValue::Text(value) => value == expected,
+        }
+    }
+}
+
+impl std::str::FromStr for ConditionValue {
+    type Err = std::convert::Infallible;
+
+    // "true"/"false" are bools, anything numeric a float, the rest text
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(value) = s.parse::<bool>() {
+            Ok(ConditionValue::Bool(value))
+        } else if let Ok(value) = s.parse::<f64>() {
+            Ok(ConditionValue::Float(value))
+        } else {
+            Ok(ConditionValue::Text(s.to_string()))
+        }
+    }
+}
+
+impl std::fmt::Display for ConditionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConditionValue::Bool(value) => write!(f, "{}", value),
+            ConditionValue::Float(value) => write!(f, "{}", value),
+            ConditionValue::Text(value) => f.write_str(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomStateInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub has_behavior: bool, // true when a CustomBehavior overrides the config
+}
+
+// What `LcgpNode::import_states` does with a state whose name is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportPolicy {
+    Skip,
+    Overwrite,
+    Rename, // Stored as "{name}_2", "{name}_3", ... whichever is free first
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateImportReport {
+    pub imported: Vec<String>,          // Local names of every state stored
+    pub overwritten: Vec<String>,       // Replaced an existing state
+    pub renamed: Vec<(String, String)>, // (incoming name, local name)
+    pub skipped: Vec<String>,           // Name taken under ImportPolicy::Skip
+    pub invalid: Vec<(String, Vec<String>)>, // Rejected by CustomLcgpState::validate
+    // Imported as config only: behaviors are code and never travel with a state
+    pub without_behavior: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BehaviorResult {
     pub should_chime: bool,
     pub auto_response: Option<ChimeResponse>,
     pub delay_ms: Option<u64>,
     pub next_state: Option<String>, // State to transition to after response
+    #[serde(default)]
+    pub note: Option<String>, // Sent as the response's message, e.g. "in a meeting"
+}
+
+// How a node would react to a ring right now, see LcgpNode::preview_reaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReactionPreview {
+    pub would_chime: bool,
+    pub auto_response: Option<(ChimeResponse, Option<u64>)>, // With its delay in milliseconds
+    pub note: Option<String>,                                // Sent as the auto response's message
+    pub active_state: String, // The mode deciding, e.g. "DoNotDisturb" or "Custom:Meeting"
 }
 
-// Trait for custom behavior implementations
+// What LcgpHandler::handle_incoming_ring made of a ring
+#[derive(Debug, Clone)]
+pub struct RingOutcome {
+    pub should_chime: bool,
+    pub response: Option<ChimeResponseMessage>, // To send now; delayed ones go out later
+}
+
+// Trait for custom behavior implementations. `on_incoming_chime` is also
+// asked for previews, so it must not have side effects beyond logging.
 pub trait CustomBehavior: Send + Sync {
     fn on_incoming_chime(&self, chime: &ChimeMessage, state: &CustomLcgpState) -> BehaviorResult;
     fn on_user_response(&self, response: &ChimeResponse, state: &CustomLcgpState)
@@ -66,6 +462,16 @@ pub struct ModeUpdate {
     pub custom_state: Option<CustomLcgpState>,
 }
 
+// Asks a chime (of any user) to switch mode. Chimes may refuse remote
+// changes, see ChimeInstance::set_allow_remote_mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModeChangeRequest {
+    pub chime_id: String,
+    pub requested_mode: LcgpMode,
+    pub requester_id: String,
+    pub timestamp: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChimeMessage {
     pub timestamp: DateTime<Utc>,
@@ -74,12 +480,89 @@ pub struct ChimeMessage {
     pub chime_id: Option<String>,
     pub notes: Option<Vec<String>>,
     pub chords: Option<Vec<String>>,
+    #[serde(default)]
+    pub request_id: Option<String>, // Of the ring request this came from
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Positive and Negative stay plain strings in JSON ("Positive"); Snooze is
+// {"Snooze":{"minutes":10}}, so older nodes' payloads still deserialize
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChimeResponse {
     Positive,
     Negative,
+    // Not now; the ringer rings again after `minutes`
+    Snooze {
+        #[serde(deserialize_with = "snooze_minutes")]
+        minutes: u32,
+    },
+}
+
+// A zero-minute snooze would have the ringer ring again right away, so it's
+// refused on the wire just like FromStr refuses it
+fn snooze_minutes<'de, D>(deserializer: D) -> std::result::Result<u32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let minutes = u32::deserialize(deserializer)?;
+    if minutes == 0 {
+        return Err(serde::de::Error::custom(
+            "snooze minutes must be at least 1",
+        ));
+    }
+    Ok(minutes)
+}
+
+// For a bare "snooze"
+pub const DEFAULT_SNOOZE_MINUTES: u32 = 10;
+
+impl ChimeResponse {
+    // Parses a response from command words, e.g. ["pos", "abc"] or
+    // ["snooze", "15", "abc"]; returns it with the words after it
+    pub fn from_args<'a>(
+        args: &'a [&'a str],
+    ) -> std::result::Result<(Self, &'a [&'a str]), String> {
+        let (first, rest) = args.split_first().ok_or("Missing response")?;
+        if first.eq_ignore_ascii_case("snooze") {
+            if let Some((minutes, rest)) = rest.split_first() {
+                if minutes.parse::<u32>().is_ok() {
+                    return Ok((format!("snooze:{}", minutes).parse()?, rest));
+                }
+            }
+        }
+        Ok((first.parse()?, rest))
+    }
+}
+
+impl std::str::FromStr for ChimeResponse {
+    type Err = String;
+
+    // "positive", "negative", "snooze" or "snooze:<minutes>" ("snooze <minutes>")
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if let Some(minutes) = s
+            .strip_prefix("snooze")
+            .filter(|rest| rest.is_empty() || rest.starts_with([':', ' ']))
+        {
+            let minutes = match minutes.trim_start_matches(':').trim() {
+                "" => DEFAULT_SNOOZE_MINUTES,
+                minutes => minutes
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|minutes| *minutes > 0)
+                    .ok_or_else(|| format!("Invalid snooze minutes '{}'", minutes))?,
+            };
+            return Ok(ChimeResponse::Snooze { minutes });
+        }
+
+        match s.as_str() {
+            "positive" | "pos" | "yes" | "y" => Ok(ChimeResponse::Positive),
+            "negative" | "neg" | "no" | "n" => Ok(ChimeResponse::Negative),
+            _ => Err(format!(
+                "Unknown response '{}' (expected positive, negative or snooze <minutes>)",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -88,6 +571,95 @@ pub struct ChimeResponseMessage {
     pub response: ChimeResponse,
     pub node_id: String,
     pub original_chime_id: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>, // Optional human-readable explanation
+    #[serde(default)]
+    pub in_reply_to: Option<String>, // request_id of the ring answered
+}
+
+impl ChimeResponseMessage {
+    // Minutes until the responder wants to be rung again, if it snoozed.
+    // `payload` is a response message, e.g. from ChimeNetMqtt::next_response.
+    pub fn snoozed_minutes(payload: &str) -> Option<u32> {
+        match serde_json::from_str::<Self>(payload).ok()?.response {
+            ChimeResponse::Snooze { minutes } => Some(minutes),
+            _ => None,
+        }
+    }
+
+    // The message from the command words after a response, e.g.
+    // ["\"busy", "until", "3\""] is "busy until 3"
+    pub fn message_from_args(args: &[&str]) -> Option<String> {
+        let message = args.join(" ");
+        let message = message.trim();
+        let message = message
+            .strip_prefix('"')
+            .and_then(|m| m.strip_suffix('"'))
+            .unwrap_or(message)
+            .trim();
+        (!message.is_empty()).then(|| message.to_string())
+    }
+}
+
+// An incoming ring still waiting for the user to respond
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingRing {
+    pub chime_id: String,
+    pub from_node: String,
+    pub received_at: DateTime<Utc>,
+    #[serde(default)]
+    pub request_id: Option<String>, // Of the ring, echoed in the response
+}
+
+// Responses a node has given, manual and automatic
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseStats {
+    pub positive: u64,
+    pub negative: u64,
+    #[serde(default)]
+    pub snoozed: u64,
+    pub last_response_at: Option<DateTime<Utc>>,
+}
+
+impl ResponseStats {
+    pub fn record(&mut self, response: &ChimeResponse, at: DateTime<Utc>) {
+        match response {
+            ChimeResponse::Positive => self.positive += 1,
+            ChimeResponse::Negative => self.negative += 1,
+            ChimeResponse::Snooze { .. } => self.snoozed += 1,
+        }
+        self.last_response_at = Some(at);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RingSummary {
+    pub rings_per_hour: f64, // Smoothed, see LcgpNode::ring_rate_per_hour
+    pub last_ring_at: Option<DateTime<Utc>>,
+}
+
+// A chime's whole state in one serializable value, for UIs, HTTP and logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChimeSnapshot {
+    pub info: ChimeInfo,
+    pub user: String,
+    pub node_id: String,
+    pub mode: LcgpMode,
+    pub online: bool, // Transport connected
+    pub following: Option<String>,
+    pub status_message: Option<String>,
+    pub pending_responses: usize,
+    pub recent_rings: RingSummary,
+    pub responses: ResponseStats,
+    pub taken_at: DateTime<Utc>,
+}
+
+// What a soft reset of a chime threw away
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResetSummary {
+    pub pending_rings: usize,
+    pub delayed_responses: usize,
+    pub scheduled_rings: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +672,17 @@ pub struct ChimeInfo {
     pub created_at: DateTime<Utc>,
 }
 
+impl ChimeInfo {
+    // Notes both chimes advertise, in our order and spelling
+    pub fn common_notes(&self, other: &ChimeInfo) -> Vec<String> {
+        notes::common_notes(&self.notes, &other.notes)
+    }
+
+    pub fn common_chords(&self, other: &ChimeInfo) -> Vec<String> {
+        notes::common_chords(&self.chords, &other.chords)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChimeStatus {
     pub chime_id: String,
@@ -107,6 +690,23 @@ pub struct ChimeStatus {
     pub mode: LcgpMode,
     pub last_seen: DateTime<Utc>,
     pub node_id: String,
+    #[serde(default)]
+    pub following: Option<String>, // node_id of the chime whose mode this one mirrors
+    #[serde(default)]
+    pub ring_rate_per_hour: f64, // Smoothed incoming rings, as of this status
+    #[serde(default)]
+    pub status_message: Option<String>, // Free-text presence, e.g. "back at 2pm"
+    #[serde(default)]
+    pub in_reply_to: Option<String>, // request_id of the ChimeStatusRequest this answers
+}
+
+// Asks a chime to publish its status now rather than at its next periodic
+// update. The reply is a non-retained ChimeStatus on the chime's status
+// topic whose in_reply_to is this request_id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChimeStatusRequest {
+    pub request_id: String,
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -138,79 +738,705 @@ pub struct ChimeRingRequest {
     pub notes: Option<Vec<String>>,
     pub chords: Option<Vec<String>>,
     pub duration_ms: Option<u64>,
+    // Per-note lengths in ms, parallel to `notes`. Notes without an entry
+    // fall back to `duration_ms`; extra entries are ignored.
+    #[serde(default)]
+    pub note_durations: Option<Vec<u64>>,
     pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub urgency: Urgency,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>, // None = never expires
+    // Echoed as `in_reply_to` in the target's response, so the ringer can
+    // tell its own answer apart (see ChimeInstance::ring_other_chime_awaiting)
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Emergency,
+}
+
+impl ChimeRingRequest {
+    // Fills notes/chords from the named preset. Explicit notes or chords win,
+    // so the preset is only applied when neither was given.
+    pub fn expand_preset(&mut self) -> Result<()> {
+        let Some(name) = &self.preset else {
+            return Ok(());
+        };
+        if self.notes.is_some() || self.chords.is_some() {
+            return Ok(());
+        }
+
+        let pattern = notes::preset(name).ok_or_else(|| format!("Unknown preset: {}", name))?;
+        self.notes = Some(pattern.notes);
+        self.chords = Some(pattern.chords);
+        Ok(())
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    // Drops the requested notes and chords a chime doesn't advertise, keeping
+    // note_durations lined up with the notes left, and returns what was dropped.
+    // A chime advertising no notes (or chords) is taken to play any.
+    pub fn restrict_to(&mut self, info: &ChimeInfo) -> Vec<String> {
+        let mut rejected = Vec::new();
+
+        if let Some(requested) = self.notes.take_if(|_| !info.notes.is_empty()) {
+            let durations = self.note_durations.take();
+            let mut kept = Vec::new();
+            let mut kept_durations = Vec::new();
+            for (i, note) in requested.into_iter().enumerate() {
+                if notes::common_notes(std::slice::from_ref(&note), &info.notes).is_empty() {
+                    rejected.push(note);
+                    continue;
+                }
+                if let Some(duration) = durations.as_ref().and_then(|d| d.get(i)) {
+                    kept_durations.push(*duration);
+                }
+                kept.push(note);
+            }
+            self.notes = Some(kept);
+            self.note_durations = durations.map(|_| kept_durations);
+        }
+
+        if let Some(requested) = self.chords.take_if(|_| !info.chords.is_empty()) {
+            let (kept, dropped): (Vec<String>, Vec<String>) =
+                requested.into_iter().partition(|chord| {
+                    !notes::common_chords(std::slice::from_ref(chord), &info.chords).is_empty()
+                });
+            self.chords = Some(kept);
+            rejected.extend(dropped);
+        }
+
+        rejected
+    }
+
+    // Whether the request names any notes or chords, rather than leaving the
+    // chime to play its default sound
+    pub fn has_sounds(&self) -> bool {
+        self.notes.as_ref().is_some_and(|notes| !notes.is_empty())
+            || self
+                .chords
+                .as_ref()
+                .is_some_and(|chords| !chords.is_empty())
+    }
+}
+
+// A ring waiting to be sent at a later time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledRing {
+    pub id: String,
+    pub user: UserId,
+    pub chime_id: ChimeId,
+    pub at: DateTime<Utc>,
+    pub notes: Option<Vec<String>>,
+    pub chords: Option<Vec<String>>,
 }
 
 // Topic structure helpers
 pub struct TopicBuilder;
 
 impl TopicBuilder {
+    // Refuses what sanitize_segment would have to rewrite, for callers taking
+    // names from outside (paths, config) that should fail loudly instead
+    pub fn validate_segment(segment: &str) -> Result<()> {
+        if is_valid_topic_segment(segment) {
+            return Ok(());
+        }
+        Err(ChimeNetError::InvalidTopic(format!(
+            "Invalid topic segment {:?}: only letters, digits, '-' and '_' are allowed",
+            segment
+        )))
+    }
+
+    // Last line of defence for ids that skipped validation: every disallowed
+    // character becomes '_', so a segment can never add levels or wildcards
+    pub fn sanitize_segment(segment: &str) -> String {
+        if segment.is_empty() {
+            return "_".to_string();
+        }
+
+        segment
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
     pub fn chime_list(user: &str) -> String {
-        format!("/{}/chime/list", user)
+        format!("/{}/chime/list", Self::sanitize_segment(user))
     }
 
     pub fn chime_notes(user: &str, chime_id: &str) -> String {
-        format!("/{}/chime/{}/notes", user, chime_id)
+        format!(
+            "/{}/chime/{}/notes",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
     }
 
     pub fn chime_chords(user: &str, chime_id: &str) -> String {
-        format!("/{}/chime/{}/chords", user, chime_id)
+        format!(
+            "/{}/chime/{}/chords",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
     }
 
     pub fn chime_status(user: &str, chime_id: &str) -> String {
-        format!("/{}/chime/{}/status", user, chime_id)
+        format!(
+            "/{}/chime/{}/status",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
+    }
+
+    pub fn chime_mode(user: &str, chime_id: &str) -> String {
+        format!(
+            "/{}/chime/{}/mode",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
+    }
+
+    pub fn chime_mode_request(user: &str, chime_id: &str) -> String {
+        format!(
+            "/{}/chime/{}/mode_request",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
+    }
+
+    pub fn chime_status_request(user: &str, chime_id: &str) -> String {
+        format!(
+            "/{}/chime/{}/status_request",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
     }
 
     pub fn chime_ring(user: &str, chime_id: &str) -> String {
-        format!("/{}/chime/{}/ring", user, chime_id)
+        format!(
+            "/{}/chime/{}/ring",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
     }
 
     pub fn chime_response(user: &str, chime_id: &str) -> String {
-        format!("/{}/chime/{}/response", user, chime_id)
+        format!(
+            "/{}/chime/{}/response",
+            Self::sanitize_segment(user),
+            Self::sanitize_segment(chime_id)
+        )
+    }
+
+    // Subscription filters: like the topics above, but a bare "+" segment
+    // stays a single-level wildcard instead of being sanitized to '_'
+    fn filter_segment(segment: &str) -> String {
+        if segment == "+" {
+            return segment.to_string();
+        }
+        Self::sanitize_segment(segment)
+    }
+
+    pub fn chime_list_filter(user: &str) -> String {
+        format!("/{}/chime/list", Self::filter_segment(user))
+    }
+
+    pub fn chime_status_filter(user: &str, chime_id: &str) -> String {
+        format!(
+            "/{}/chime/{}/status",
+            Self::filter_segment(user),
+            Self::filter_segment(chime_id)
+        )
+    }
+
+    pub fn chime_mode_filter(user: &str, chime_id: &str) -> String {
+        format!(
+            "/{}/chime/{}/mode",
+            Self::filter_segment(user),
+            Self::filter_segment(chime_id)
+        )
     }
 
     pub fn ringer_discover(user: &str) -> String {
-        format!("/{}/ringer/discover", user)
+        format!("/{}/ringer/discover", Self::sanitize_segment(user))
+    }
+
+    // Discovery requests from ringers of every user
+    pub fn ringer_discover_all() -> String {
+        "/+/ringer/discover".to_string()
     }
 
     pub fn ringer_available(user: &str) -> String {
-        format!("/{}/ringer/available", user)
+        format!("/{}/ringer/available", Self::sanitize_segment(user))
+    }
+
+    // Extracts the user segment from a topic like "/{user}/chime/..."
+    pub fn user_from_topic(topic: &str) -> Option<&str> {
+        topic.strip_prefix('/')?.split('/').next()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ChimeNetError>;
+
+// Written aside and renamed, so a crash mid-write keeps the last good file
+pub fn write_atomically(
+    path: impl AsRef<std::path::Path>,
+    contents: impl AsRef<[u8]>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// First byte of every MessagePack payload. 0xC1 is unused in MessagePack and
+// never valid in UTF-8, so it can't be mistaken for the start of JSON text.
+pub const MSGPACK_MARKER: u8 = 0xC1;
+
+// Wire encoding of published payloads, chosen per client. Receivers tell the
+// two apart by the marker byte, so mixed networks work.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    MessagePack, // Smaller, for constrained links; marker byte + named-field MessagePack
+}
+
+impl PayloadFormat {
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            PayloadFormat::Json => Ok(serde_json::to_vec(value)?),
+            PayloadFormat::MessagePack => {
+                let mut payload = vec![MSGPACK_MARKER];
+                payload.extend(rmp_serde::to_vec_named(value)?);
+                Ok(payload)
+            }
+        }
+    }
+
+    // Turns any received payload into JSON text, so handlers and the
+    // `serde_json` parsing behind them don't care how it was sent
+    pub fn decode_to_json(payload: &[u8]) -> Result<String> {
+        match payload.split_first() {
+            Some((&MSGPACK_MARKER, packed)) => {
+                let value: serde_json::Value = rmp_serde::from_slice(packed)?;
+                Ok(value.to_string())
+            }
+            _ => Ok(String::from_utf8(payload.to_vec())?),
+        }
+    }
+}
+
+impl std::str::FromStr for PayloadFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(PayloadFormat::Json),
+            "msgpack" | "messagepack" => Ok(PayloadFormat::MessagePack),
+            _ => Err(format!(
+                "Unknown payload format '{}' (expected json or msgpack)",
+                s
+            )),
+        }
     }
 }
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+// The underlying error a ChimeNetError was converted from, kept for
+// `source()`. Shared so errors stay cheap to clone; compared by message.
+#[derive(Clone)]
+pub struct ErrorSource(std::sync::Arc<dyn std::error::Error + Send + Sync>);
+
+impl ErrorSource {
+    pub fn new(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        ErrorSource(std::sync::Arc::new(error))
+    }
+}
+
+impl std::fmt::Debug for ErrorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl std::fmt::Display for ErrorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl PartialEq for ErrorSource {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Eq for ErrorSource {}
+
+// Every failure of the crate, by kind, so callers can match on it (e.g. to
+// pick an exit code or an HTTP status). Errors from other crates are kept as
+// their source; the rest carry a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChimeNetError {
+    Config(String),        // Bad broker URL, settings or local files
+    Connection(String),    // Broker or LAN group unreachable
+    Mqtt(ErrorSource),     // The MQTT client failed an operation
+    Audio(String),         // No usable audio output or unplayable sound
+    Serde(ErrorSource),    // A payload or file that can't be encoded or decoded
+    InvalidTopic(String),  // A user or chime id that can't go into a topic
+    StateNotFound(String), // Name of the missing custom state
+    Timeout(String),       // Nothing arrived in time
+    FollowSelf,            // A chime asked to follow itself
+    NotWatching(String),   // The user discovery was asked to stop watching
+    PayloadTooLarge { size: usize, limit: usize }, // Bytes, over what one message can carry
+    Io(ErrorSource),
+    Other(String),
+}
+
+// The error's old name, from before it was an enum
+#[deprecated(note = "use ChimeNetError")]
+pub type ChimeError = ChimeNetError;
+
+impl std::fmt::Display for ChimeNetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChimeNetError::StateNotFound(name) => write!(f, "Custom state '{}' not found", name),
+            ChimeNetError::FollowSelf => f.write_str("A chime cannot follow itself"),
+            ChimeNetError::NotWatching(user) => write!(f, "Not watching user: {}", user),
+            ChimeNetError::PayloadTooLarge { size, limit } => write!(
+                f,
+                "Message of {} bytes exceeds the {} byte limit",
+                size, limit
+            ),
+            ChimeNetError::Mqtt(source)
+            | ChimeNetError::Serde(source)
+            | ChimeNetError::Io(source) => source.fmt(f),
+            ChimeNetError::Config(message)
+            | ChimeNetError::Connection(message)
+            | ChimeNetError::Audio(message)
+            | ChimeNetError::InvalidTopic(message)
+            | ChimeNetError::Timeout(message)
+            | ChimeNetError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ChimeNetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChimeNetError::Mqtt(source)
+            | ChimeNetError::Serde(source)
+            | ChimeNetError::Io(source) => Some(&*source.0),
+            _ => None,
+        }
+    }
+}
+
+impl ChimeNetError {
+    // None for errors of no particular kind
+    pub fn kind(&self) -> Option<&'static str> {
+        match self {
+            ChimeNetError::Config(_) => Some("config"),
+            ChimeNetError::Connection(_) => Some("connection"),
+            ChimeNetError::Mqtt(_) => Some("mqtt"),
+            ChimeNetError::Audio(_) => Some("audio"),
+            ChimeNetError::Serde(_) => Some("serde"),
+            ChimeNetError::InvalidTopic(_) => Some("invalid_topic"),
+            ChimeNetError::StateNotFound(_) => Some("state_not_found"),
+            ChimeNetError::Timeout(_) => Some("timeout"),
+            ChimeNetError::FollowSelf => Some("follow_self"),
+            ChimeNetError::NotWatching(_) => Some("not_watching"),
+            ChimeNetError::PayloadTooLarge { .. } => Some("payload_too_large"),
+            ChimeNetError::Io(_) => Some("io"),
+            ChimeNetError::Other(_) => None,
+        }
+    }
+
+    // sysexits.h codes, which systemd and most supervisors understand
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ChimeNetError::Config(_) | ChimeNetError::InvalidTopic(_) => 78, // EX_CONFIG
+            ChimeNetError::Connection(_) | ChimeNetError::Mqtt(_) => 69,     // EX_UNAVAILABLE
+            ChimeNetError::Audio(_) | ChimeNetError::Io(_) => 74,            // EX_IOERR
+            ChimeNetError::Serde(_)
+            | ChimeNetError::StateNotFound(_)
+            | ChimeNetError::FollowSelf
+            | ChimeNetError::NotWatching(_)
+            | ChimeNetError::PayloadTooLarge { .. } => 65, // EX_DATAERR
+            ChimeNetError::Timeout(_) => 75,                                 // EX_TEMPFAIL
+            ChimeNetError::Other(_) => 1,
+        }
+    }
+}
+
+impl From<String> for ChimeNetError {
+    fn from(message: String) -> Self {
+        ChimeNetError::Other(message)
+    }
+}
+
+impl From<&str> for ChimeNetError {
+    fn from(message: &str) -> Self {
+        ChimeNetError::Other(message.to_string())
+    }
+}
+
+impl From<paho_mqtt::Error> for ChimeNetError {
+    fn from(e: paho_mqtt::Error) -> Self {
+        ChimeNetError::Mqtt(ErrorSource::new(e))
+    }
+}
+
+impl From<serde_json::Error> for ChimeNetError {
+    fn from(e: serde_json::Error) -> Self {
+        ChimeNetError::Serde(ErrorSource::new(e))
+    }
+}
+
+impl From<rmp_serde::encode::Error> for ChimeNetError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        ChimeNetError::Serde(ErrorSource::new(e))
+    }
+}
+
+impl From<rmp_serde::decode::Error> for ChimeNetError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        ChimeNetError::Serde(ErrorSource::new(e))
+    }
+}
+
+impl From<std::string::FromUtf8Error> for ChimeNetError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        ChimeNetError::Serde(ErrorSource::new(e))
+    }
+}
+
+impl From<std::io::Error> for ChimeNetError {
+    fn from(e: std::io::Error) -> Self {
+        ChimeNetError::Io(ErrorSource::new(e))
+    }
+}
+
+// Printed by the examples as their last line, so scripts and supervisors can
+// tell how a process ended without parsing logs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShutdownSummary {
+    pub program: String,
+    pub status: String, // "ok" or "error"
+    pub kind: Option<String>,
+    pub exit_code: i32,
+    pub error: Option<String>,
+    pub uptime_secs: u64,
+}
+
+impl ShutdownSummary {
+    pub fn new(program: &str, result: &Result<()>, uptime: std::time::Duration) -> Self {
+        let (status, kind, exit_code, error) = match result {
+            Ok(()) => ("ok", None, 0, None),
+            Err(e) => (
+                "error",
+                e.kind().map(str::to_string),
+                e.exit_code(),
+                Some(e.to_string()),
+            ),
+        };
+
+        Self {
+            program: program.to_string(),
+            status: status.to_string(),
+            kind,
+            exit_code,
+            error,
+            uptime_secs: uptime.as_secs(),
+        }
+    }
+
+    // Writes the summary as one JSON line on stderr and returns the exit code
+    pub fn report(&self) -> i32 {
+        match serde_json::to_string(self) {
+            Ok(json) => eprintln!("{}", json),
+            Err(e) => eprintln!("Failed to serialize shutdown summary: {}", e),
+        }
+        self.exit_code
+    }
+}
 
 // Musical note utilities
 pub mod notes {
-    use std::collections::HashMap;
+    const NOTE_NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    // Lowest and highest playable notes, C0 and B8, as semitones above C-1
+    const LOWEST_NOTE: i32 = 12;
+    const HIGHEST_NOTE: i32 = 119;
+    const A4: i32 = 69;
 
+    // Semitones above C-1 (MIDI numbering, A4 = 69) for a note name: a letter,
+    // up to two sharps or flats ("x" is a double sharp too), and an octave
+    fn semitone_number(note: &str) -> Option<i32> {
+        let mut chars = note.trim().chars();
+
+        let base: i32 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+
+        let mut rest = chars.as_str();
+        let mut offset: i32 = 0;
+        if let Some(octave) = rest.strip_prefix('x') {
+            (offset, rest) = (2, octave);
+        } else {
+            for (accidental, step) in [(['#', '♯'], 1), (['b', '♭'], -1)] {
+                while offset.abs() < 2 {
+                    match rest.strip_prefix(accidental) {
+                        Some(octave) => (offset, rest) = (offset + step, octave),
+                        None => break,
+                    }
+                }
+                if offset != 0 {
+                    break;
+                }
+            }
+        }
+
+        let octave: i32 = rest.parse().ok()?;
+        Some((octave + 1) * 12 + base + offset)
+    }
+
+    // Normalizes a note name to its canonical spelling (uppercase letter, sharps
+    // only), so "c4", "Db4" and "C#4" all resolve to "C#4". Flats that cross an
+    // octave boundary are handled too ("Cb4" -> "B3").
+    pub fn normalize_note(note: &str) -> Option<String> {
+        let number = semitone_number(note)?;
+        let name = NOTE_NAMES[number.rem_euclid(12) as usize];
+
+        Some(format!("{}{}", name, number.div_euclid(12) - 1))
+    }
+
+    // Notes in `ours` that `theirs` also has, compared by pitch so "Db4"
+    // matches "C#4". Keeps the order and spelling of `ours`.
+    pub fn common_notes(ours: &[String], theirs: &[String]) -> Vec<String> {
+        let pitch = |note: &String| normalize_note(note).unwrap_or_else(|| note.trim().to_string());
+        let theirs: Vec<String> = theirs.iter().map(pitch).collect();
+
+        ours.iter()
+            .filter(|note| theirs.contains(&pitch(note)))
+            .cloned()
+            .collect()
+    }
+
+    // Chords in `ours` that `theirs` also has. Chord names are matched exactly,
+    // as `chord_notes` looks them up.
+    pub fn common_chords(ours: &[String], theirs: &[String]) -> Vec<String> {
+        ours.iter()
+            .filter(|chord| theirs.iter().any(|theirs| theirs.trim() == chord.trim()))
+            .cloned()
+            .collect()
+    }
+
+    // Requested notes a ring target doesn't advertise, as a warning for the
+    // user. The ring still goes out and the target silently skips them. None
+    // when all are advertised, or when the target advertises nothing at all.
+    pub fn unsupported_notes_warning(
+        requested: &[String],
+        advertised: &[String],
+    ) -> Option<String> {
+        let supported = common_notes(requested, advertised);
+        unsupported_warning("notes", requested, &supported, advertised)
+    }
+
+    // Like `unsupported_notes_warning`, for chords
+    pub fn unsupported_chords_warning(
+        requested: &[String],
+        advertised: &[String],
+    ) -> Option<String> {
+        let supported = common_chords(requested, advertised);
+        unsupported_warning("chords", requested, &supported, advertised)
+    }
+
+    fn unsupported_warning(
+        kind: &str,
+        requested: &[String],
+        supported: &[String],
+        advertised: &[String],
+    ) -> Option<String> {
+        if advertised.is_empty() {
+            return None;
+        }
+
+        let unsupported: Vec<&str> = requested
+            .iter()
+            .filter(|item| !supported.contains(item))
+            .map(String::as_str)
+            .collect();
+        (!unsupported.is_empty()).then(|| {
+            format!(
+                "Target doesn't advertise {} {} (it has: {})",
+                kind,
+                unsupported.join(", "),
+                advertised.join(", ")
+            )
+        })
+    }
+
+    // Equal temperament around A4 = 440 Hz, for C0 through B8
     pub fn frequency_for_note(note: &str) -> Option<f32> {
-        let mut frequencies = HashMap::new();
-
-        // A4 = 440 Hz base
-        frequencies.insert("A4", 440.0);
-        frequencies.insert("A#4", 466.16);
-        frequencies.insert("B4", 493.88);
-        frequencies.insert("C4", 261.63);
-        frequencies.insert("C#4", 277.18);
-        frequencies.insert("D4", 293.66);
-        frequencies.insert("D#4", 311.13);
-        frequencies.insert("E4", 329.63);
-        frequencies.insert("F4", 349.23);
-        frequencies.insert("F#4", 369.99);
-        frequencies.insert("G4", 392.00);
-        frequencies.insert("G#4", 415.30);
-
-        // Add more octaves
-        frequencies.insert("C5", 523.25);
-        frequencies.insert("D5", 587.33);
-        frequencies.insert("E5", 659.25);
-        frequencies.insert("F5", 698.46);
-        frequencies.insert("G5", 783.99);
-        frequencies.insert("A5", 880.00);
-        frequencies.insert("B5", 987.77);
-
-        frequencies.get(note).copied()
+        let number = semitone_number(note)?;
+        if !(LOWEST_NOTE..=HIGHEST_NOTE).contains(&number) {
+            return None;
+        }
+
+        Some(440.0 * 2f32.powf((number - A4) as f32 / 12.0))
+    }
+
+    // A named note/chord pattern that can be rung in place of an explicit list
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Pattern {
+        pub notes: Vec<String>,
+        pub chords: Vec<String>,
+    }
+
+    pub const PRESET_NAMES: [&str; 4] = ["doorbell", "alert", "gentle", "fanfare"];
+
+    pub fn preset(name: &str) -> Option<Pattern> {
+        let (notes, chords): (&[&str], &[&str]) = match name.trim().to_lowercase().as_str() {
+            "doorbell" => (&["E5", "C5"], &[]),
+            "alert" => (&["A5", "A5", "A5"], &[]),
+            "gentle" => (&["C4", "E4", "G4"], &[]),
+            "fanfare" => (&["G4", "C5", "E5", "G5"], &["C"]),
+            _ => return None,
+        };
+
+        Some(Pattern {
+            notes: notes.iter().map(|n| n.to_string()).collect(),
+            chords: chords.iter().map(|c| c.to_string()).collect(),
+        })
     }
 
     pub fn chord_notes(chord: &str) -> Vec<String> {
@@ -221,7 +1447,571 @@ pub mod notes {
             "G" => vec!["G4".to_string(), "B4".to_string(), "D5".to_string()],
             "Dm" => vec!["D4".to_string(), "F4".to_string(), "A4".to_string()],
             "Em" => vec!["E4".to_string(), "G4".to_string(), "B4".to_string()],
+            "D" => vec!["D4".to_string(), "F#4".to_string(), "A4".to_string()],
+            "E" => vec!["E4".to_string(), "G#4".to_string(), "B4".to_string()],
+            "A" => vec!["A4".to_string(), "C#5".to_string(), "E5".to_string()],
+            "Bm" => vec!["B4".to_string(), "D5".to_string(), "F#5".to_string()],
+            "G7" => vec![
+                "G4".to_string(),
+                "B4".to_string(),
+                "D5".to_string(),
+                "F5".to_string(),
+            ],
+            "Cmaj7" => vec![
+                "C4".to_string(),
+                "E4".to_string(),
+                "G4".to_string(),
+                "B4".to_string(),
+            ],
             _ => vec![],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::notes::*;
+    use super::{ChimeId, TopicBuilder, UserId};
+
+    const HOSTILE_IDS: [&str; 7] = ["../../#", "+", "#", "bob/chime", "a b", "", "alice\0"];
+
+    #[test]
+    fn rejects_hostile_ids() {
+        for id in HOSTILE_IDS {
+            assert!(UserId::from(id).validate().is_err(), "{:?}", id);
+            assert!(ChimeId::from(id).validate().is_err(), "{:?}", id);
+        }
+        for id in HOSTILE_IDS {
+            assert!(TopicBuilder::validate_segment(id).is_err(), "{:?}", id);
+        }
+        let error = TopicBuilder::validate_segment("bob/chime").unwrap_err();
+        assert!(error.to_string().contains("\"bob/chime\""), "{}", error);
+        assert!(TopicBuilder::validate_segment("\t").is_err());
+        assert!(TopicBuilder::validate_segment("kitchen_2").is_ok());
+        assert!(UserId::from("alice_work-2").validate().is_ok());
+        assert!(ChimeId::from("6f1c2a9e-1b7d-4c1e-9a43-0d2f8e6b5c10")
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn shutdown_summary_maps_errors_to_exit_codes() {
+        use super::{ChimeNetError, ShutdownSummary};
+        use std::time::Duration;
+
+        let ok = ShutdownSummary::new("test", &Ok(()), Duration::from_secs(3));
+        assert_eq!(
+            (ok.status.as_str(), ok.exit_code, ok.uptime_secs),
+            ("ok", 0, 3)
+        );
+
+        let connection = ShutdownSummary::new(
+            "test",
+            &Err(ChimeNetError::Connection("broker down".to_string())),
+            Duration::ZERO,
+        );
+        assert_eq!(connection.exit_code, 69);
+        assert_eq!(connection.kind.as_deref(), Some("connection"));
+        assert_eq!(connection.error.as_deref(), Some("broker down"));
+
+        let invalid_user = UserId::from("a/b").validate().unwrap_err();
+        assert_eq!(
+            ShutdownSummary::new("test", &Err(invalid_user), Duration::ZERO).exit_code,
+            78
+        );
+
+        let other = ShutdownSummary::new("test", &Err("boom".into()), Duration::ZERO);
+        assert_eq!((other.exit_code, other.kind), (1, None));
+    }
+
+    #[test]
+    fn errors_carry_their_kind() {
+        use super::{ChimeNetError, PayloadFormat};
+        use crate::lcgp::LcgpNode;
+
+        let unreadable = PayloadFormat::decode_to_json(&[0xC1, 0xC1]).unwrap_err();
+        assert_eq!(unreadable.kind(), Some("serde"));
+        let io: ChimeNetError = std::io::Error::other("disk full").into();
+        assert_eq!(
+            (io.kind(), io.to_string().as_str()),
+            (Some("io"), "disk full")
+        );
+        let source = std::error::Error::source(&io).unwrap();
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+        assert_eq!(io.clone(), io);
+        let oversized = ChimeNetError::PayloadTooLarge {
+            size: 70000,
+            limit: 65507,
+        };
+        assert_eq!(
+            (oversized.kind(), oversized.exit_code()),
+            (Some("payload_too_large"), 65)
+        );
+        assert!(std::error::Error::source(&oversized).is_none());
+        assert!(matches!(
+            UserId::from("a/b").validate(),
+            Err(ChimeNetError::InvalidTopic(_))
+        ));
+
+        let missing = LcgpNode::new("node".to_string())
+            .set_custom_mode("Lunch".to_string())
+            .unwrap_err();
+        assert_eq!(missing, ChimeNetError::StateNotFound("Lunch".to_string()));
+        assert_eq!(missing.to_string(), "Custom state 'Lunch' not found");
+
+        let other = ChimeNetError::from("boom");
+        assert_eq!((other.kind(), other.exit_code()), (None, 1));
+    }
+
+    #[test]
+    fn msgpack_payloads_decode_to_equivalent_json() {
+        use super::{ChimeStatus, LcgpMode, PayloadFormat};
+
+        let status = ChimeStatus {
+            chime_id: "abc".to_string(),
+            online: true,
+            mode: LcgpMode::Custom("Focus".to_string()),
+            last_seen: chrono::Utc::now(),
+            node_id: "alice_abc".to_string(),
+            following: None,
+            ring_rate_per_hour: 0.0,
+            status_message: None,
+            in_reply_to: None,
+        };
+
+        let json = PayloadFormat::Json.encode(&status).unwrap();
+        let packed = PayloadFormat::MessagePack.encode(&status).unwrap();
+        assert!(packed.len() < json.len());
+
+        let decoded: ChimeStatus =
+            serde_json::from_str(&PayloadFormat::decode_to_json(&packed).unwrap()).unwrap();
+        assert_eq!(decoded.mode, status.mode);
+        assert_eq!(decoded.last_seen, status.last_seen);
+        assert_eq!(
+            PayloadFormat::decode_to_json(&json).unwrap(),
+            serde_json::to_string(&status).unwrap()
+        );
+        assert!(PayloadFormat::decode_to_json(&[0xC1, 0xC1]).is_err());
+    }
+
+    #[test]
+    fn snooze_responses_parse_and_keep_old_payloads_readable() {
+        use super::ChimeResponse;
+
+        let snooze = |minutes| ChimeResponse::Snooze { minutes };
+        assert_eq!("snooze".parse(), Ok(snooze(super::DEFAULT_SNOOZE_MINUTES)));
+        assert_eq!("Snooze:15".parse(), Ok(snooze(15)));
+        assert_eq!("snooze 5".parse(), Ok(snooze(5)));
+        assert!("snooze 0".parse::<ChimeResponse>().is_err());
+        assert!("snoozy".parse::<ChimeResponse>().is_err());
+
+        let parse = |json: &str| serde_json::from_str::<ChimeResponse>(json).unwrap();
+        assert_eq!(parse(r#""Positive""#), ChimeResponse::Positive);
+        assert_eq!(parse(r#""Negative""#), ChimeResponse::Negative);
+        assert_eq!(
+            serde_json::to_string(&ChimeResponse::Positive).unwrap(),
+            r#""Positive""#
+        );
+        assert_eq!(
+            serde_json::to_string(&snooze(10)).unwrap(),
+            r#"{"Snooze":{"minutes":10}}"#
+        );
+        assert_eq!(parse(r#"{"Snooze":{"minutes":10}}"#), snooze(10));
+        assert!(serde_json::from_str::<ChimeResponse>(r#"{"Snooze":{"minutes":0}}"#).is_err());
+    }
+
+    #[test]
+    fn response_words_split_into_a_response_and_a_message() {
+        use super::{ChimeResponse, ChimeResponseMessage};
+
+        let snooze = |minutes| ChimeResponse::Snooze { minutes };
+        let words = ["\"busy", "until", "3\""];
+        assert_eq!(
+            ChimeResponseMessage::message_from_args(&words).as_deref(),
+            Some("busy until 3")
+        );
+        assert_eq!(
+            ChimeResponseMessage::message_from_args(&["later"]).as_deref(),
+            Some("later")
+        );
+        assert_eq!(ChimeResponseMessage::message_from_args(&["\"\""]), None);
+        assert_eq!(ChimeResponseMessage::message_from_args(&[]), None);
+
+        let (response, rest) = ChimeResponse::from_args(&["snooze", "15", "abc"]).unwrap();
+        assert_eq!((response, rest), (snooze(15), &["abc"][..]));
+        let (response, rest) = ChimeResponse::from_args(&["snooze", "abc"]).unwrap();
+        assert_eq!((response, rest), (snooze(10), &["abc"][..]));
+        let (response, rest) = ChimeResponse::from_args(&["neg"]).unwrap();
+        assert_eq!((response, rest), (ChimeResponse::Negative, &[][..]));
+    }
+
+    #[test]
+    fn custom_state_validation_accepts_useful_states_and_lists_every_problem() {
+        use super::{ChimeResponse, CustomLcgpState, StateCondition, TimeRange};
+
+        let hours = |start_hour, end_minute, days: &[u8]| TimeRange {
+            start_hour,
+            start_minute: 0,
+            end_hour: 17,
+            end_minute,
+            days_of_week: days.to_vec(),
+        };
+        let useful = CustomLcgpState {
+            name: "Office".to_string(),
+            should_chime: false,
+            auto_response: Some(ChimeResponse::Negative),
+            auto_response_delay: Some(2000),
+            description: None,
+            priority: Some(255),
+            active_hours: Some(hours(9, 59, &[1, 2, 3, 4, 5])),
+            conditions: Vec::new(),
+        };
+        assert!(useful.validate().is_ok());
+        let conditions_only = CustomLcgpState {
+            auto_response: None,
+            auto_response_delay: None,
+            conditions: vec![StateCondition::SystemLoad(0.8)],
+            ..useful.clone()
+        };
+        assert!(conditions_only.validate().is_ok());
+
+        let garbage = CustomLcgpState {
+            name: " ".to_string(),
+            auto_response: None,
+            active_hours: Some(hours(25, 60, &[0, 7])),
+            conditions: Vec::new(),
+            ..useful
+        };
+        let errors = garbage.validate().unwrap_err();
+        for expected in [
+            "name must not be empty",
+            "auto_response_delay is set but there is no auto_response",
+            "state never chimes, never auto-responds and has no conditions",
+            "active_hours: start_hour must be 0-23, got 25",
+            "active_hours: end_minute must be 0-59, got 60",
+            "active_hours: days_of_week must be 0-6, got 7",
+        ] {
+            assert!(errors.iter().any(|e| e == expected), "{:?}", errors);
+        }
+        assert_eq!(errors.len(), 6);
+
+        let bad_condition = CustomLcgpState {
+            conditions: vec![
+                StateCondition::SystemLoad(f32::NAN),
+                StateCondition::TimeRange(hours(24, 0, &[])),
+            ],
+            ..conditions_only
+        };
+        assert_eq!(
+            bad_condition.validate().unwrap_err(),
+            vec![
+                "conditions[0]: system load threshold must be a non-negative number".to_string(),
+                "conditions[1]: start_hour must be 0-23, got 24".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_states_build_from_key_value_words() {
+        use super::{ChimeResponse, CustomLcgpState};
+
+        let args = ["chime=false", "auto=neg", "delay=2000", "priority=100"];
+        let state = CustomLcgpState::from_args("Meeting", &args).unwrap();
+        assert_eq!(
+            (
+                state.should_chime,
+                state.auto_response,
+                state.auto_response_delay,
+                state.priority
+            ),
+            (false, Some(ChimeResponse::Negative), Some(2000), Some(100))
+        );
+        assert!(CustomLcgpState::from_args("Lunch", &["chime=yes", "auto=none"]).is_ok());
+        for bad in [
+            &["chime=maybe"][..],
+            &["chime=true", "priority=300"],
+            &["chime=true", "volume=3"],
+            &["chime"],
+            // Validation still applies: a delay needs an auto response
+            &["chime=true", "delay=500"],
+        ] {
+            assert!(
+                matches!(
+                    CustomLcgpState::from_args("Bad", bad),
+                    Err(super::ChimeNetError::Config(_))
+                ),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn condition_values_parse_by_type() {
+        use super::ConditionValue;
+
+        let parse = |s: &str| s.parse::<ConditionValue>().unwrap();
+        assert_eq!(parse("true"), ConditionValue::Bool(true));
+        assert_eq!(parse("0.75"), ConditionValue::Float(0.75));
+        assert_eq!(
+            parse("home office"),
+            ConditionValue::Text("home office".to_string())
+        );
+
+        assert!(parse("2").matches("2.0"));
+        assert!(parse("false").matches("false"));
+        assert!(!parse("false").matches("no"));
+        assert!(!parse("home").matches("Home"));
+    }
+
+    #[test]
+    fn modes_deserialize_from_both_forms() {
+        use super::LcgpMode;
+
+        let parse = |json: &str| serde_json::from_str::<LcgpMode>(json).unwrap();
+        let focus = LcgpMode::Custom("Deep Focus".to_string());
+
+        assert_eq!(parse(r#""Available""#), LcgpMode::Available);
+        assert_eq!(parse(r#""dnd""#), LcgpMode::DoNotDisturb);
+        assert_eq!(parse(r#"{"Custom":"Deep Focus"}"#), focus);
+        assert_eq!(parse(r#""Custom:Deep Focus""#), focus);
+        assert!(serde_json::from_str::<LcgpMode>(r#""Sleeping""#).is_err());
+        assert!(serde_json::from_str::<LcgpMode>(r#""Custom:""#).is_err());
+
+        // Written in the legacy form until every node reads the text one
+        let json = serde_json::to_string(&focus).unwrap();
+        assert_eq!(json, r#"{"Custom":"Deep Focus"}"#);
+        assert_eq!(parse(&json), focus);
+        assert_eq!(focus.to_string().parse::<LcgpMode>().unwrap(), focus);
+    }
+
+    #[test]
+    fn topics_stay_in_their_namespace() {
+        for id in HOSTILE_IDS {
+            for topic in [
+                TopicBuilder::chime_list(id),
+                TopicBuilder::chime_ring("alice", id),
+                TopicBuilder::chime_status(id, "abc"),
+                TopicBuilder::chime_mode("alice", id),
+                TopicBuilder::chime_mode_request(id, id),
+                TopicBuilder::chime_status_request(id, id),
+            ] {
+                assert!(!topic.contains(['+', '#']), "{}", topic);
+                assert!(!topic.contains(".."), "{}", topic);
+                assert_eq!(topic.split('/').count(), topic_levels(&topic), "{}", topic);
+            }
+        }
+        assert_eq!(
+            TopicBuilder::chime_ring("../../#", "x/+"),
+            "/_______/chime/x__/ring"
+        );
+    }
+
+    #[test]
+    fn filters_keep_only_a_bare_wildcard() {
+        assert_eq!(TopicBuilder::chime_list_filter("+"), "/+/chime/list");
+        assert_eq!(
+            TopicBuilder::chime_status_filter("alice", "+"),
+            "/alice/chime/+/status"
+        );
+        assert_eq!(TopicBuilder::chime_mode_filter("+", "+"), "/+/chime/+/mode");
+        for id in HOSTILE_IDS.into_iter().filter(|id| *id != "+") {
+            let topic = TopicBuilder::chime_status_filter(id, id);
+            assert!(!topic.contains(['+', '#']), "{}", topic);
+        }
+    }
+
+    fn topic_levels(topic: &str) -> usize {
+        if topic.ends_with("/list") {
+            4
+        } else {
+            5
+        }
+    }
+
+    #[test]
+    fn normalizes_case() {
+        assert_eq!(normalize_note("c4").as_deref(), Some("C4"));
+        assert_eq!(normalize_note("a#4").as_deref(), Some("A#4"));
+        assert_eq!(frequency_for_note("c4"), frequency_for_note("C4"));
+    }
+
+    #[test]
+    fn resolves_flats_to_sharps() {
+        assert_eq!(normalize_note("Db4").as_deref(), Some("C#4"));
+        assert_eq!(normalize_note("Bb4").as_deref(), Some("A#4"));
+        assert_eq!(normalize_note("Cb5").as_deref(), Some("B4"));
+        assert_eq!(frequency_for_note("Db4"), frequency_for_note("C#4"));
+        assert_eq!(frequency_for_note("Gb4"), frequency_for_note("F#4"));
+    }
+
+    #[test]
+    fn handles_mixed_case_flats() {
+        assert_eq!(normalize_note("db4").as_deref(), Some("C#4"));
+        assert_eq!(normalize_note("eb4").as_deref(), Some("D#4"));
+        assert_eq!(normalize_note(" bb4 ").as_deref(), Some("A#4"));
+        assert!(frequency_for_note("ab4").is_some());
+    }
+
+    #[test]
+    fn finds_common_notes_and_chords() {
+        let strings =
+            |items: &[&str]| -> Vec<String> { items.iter().map(|s| s.to_string()).collect() };
+        let ours = super::ChimeInfo {
+            id: "a".to_string(),
+            name: "Desk".to_string(),
+            description: None,
+            notes: strings(&["C4", "Db4", "E4", "bb4"]),
+            chords: strings(&["C", "Am", "G"]),
+            created_at: chrono::Utc::now(),
+        };
+        let theirs = super::ChimeInfo {
+            notes: strings(&["C#4", "A#4", "C4", "G4"]),
+            chords: strings(&["G", "C", "Dm"]),
+            ..ours.clone()
+        };
+
+        assert_eq!(ours.common_notes(&theirs), strings(&["C4", "Db4", "bb4"]));
+        assert_eq!(ours.common_chords(&theirs), strings(&["C", "G"]));
+        assert!(common_notes(&ours.notes, &[]).is_empty());
+
+        assert_eq!(
+            unsupported_notes_warning(&strings(&["Db4", "D4"]), &theirs.notes).as_deref(),
+            Some("Target doesn't advertise notes D4 (it has: C#4, A#4, C4, G4)")
+        );
+        assert_eq!(
+            unsupported_chords_warning(&strings(&["G"]), &theirs.chords),
+            None
+        );
+        assert_eq!(unsupported_notes_warning(&strings(&["D4"]), &[]), None);
+    }
+
+    #[test]
+    fn rejects_invalid_notes() {
+        assert_eq!(normalize_note(""), None);
+        assert_eq!(normalize_note("H4"), None);
+        assert_eq!(normalize_note("C"), None);
+        assert_eq!(normalize_note("C#x"), None);
+        assert_eq!(normalize_note("C#b4"), None);
+        assert_eq!(normalize_note("C###4"), None);
+        assert_eq!(frequency_for_note("C4.5"), None);
+    }
+
+    #[test]
+    fn computes_frequencies_across_the_range() {
+        let close = |note: &str, expected: f32| {
+            let frequency = frequency_for_note(note).unwrap();
+            assert!(
+                (frequency - expected).abs() < 0.01,
+                "{} = {}",
+                note,
+                frequency
+            );
+        };
+
+        // The octaves the old lookup table covered
+        for (note, expected) in [
+            ("C4", 261.63),
+            ("C#4", 277.18),
+            ("D4", 293.66),
+            ("D#4", 311.13),
+            ("E4", 329.63),
+            ("F4", 349.23),
+            ("F#4", 369.99),
+            ("G4", 392.00),
+            ("G#4", 415.30),
+            ("A4", 440.0),
+            ("A#4", 466.16),
+            ("B4", 493.88),
+            ("C5", 523.25),
+            ("D5", 587.33),
+            ("E5", 659.25),
+            ("F5", 698.46),
+            ("G5", 783.99),
+            ("A5", 880.00),
+            ("B5", 987.77),
+        ] {
+            close(note, expected);
+        }
+        assert_eq!(frequency_for_note("A4"), Some(440.0));
+
+        close("Eb4", 311.13);
+        close("C0", 16.35);
+        close("A2", 110.0);
+        close("B8", 7902.13);
+        close("F##4", 392.00);
+        close("Fx4", 392.00);
+        close("Bbb3", 220.0);
+        assert_eq!(frequency_for_note("Cb0"), None);
+        assert_eq!(frequency_for_note("C9"), None);
+        assert_eq!(frequency_for_note("B#8"), None);
+    }
+
+    #[test]
+    fn ring_expiry_defaults_to_never() {
+        let now = chrono::Utc::now();
+        let mut request: super::ChimeRingRequest = serde_json::from_str(&format!(
+            r#"{{"chime_id":"abc","user":"alice","notes":null,"chords":null,"duration_ms":null,"timestamp":"{}"}}"#,
+            now.to_rfc3339()
+        ))
+        .unwrap();
+        assert_eq!(request.expires_at, None);
+        assert_eq!(request.note_durations, None);
+        assert!(!request.is_expired(now + chrono::Duration::days(365)));
+
+        request.expires_at = Some(now);
+        assert!(!request.is_expired(now - chrono::Duration::seconds(1)));
+        assert!(request.is_expired(now));
+    }
+
+    #[test]
+    fn ring_requests_keep_only_what_the_chime_advertises() {
+        use super::{ChimeInfo, ChimeRingRequest, Urgency};
+
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let info = ChimeInfo {
+            id: "abc".to_string(),
+            name: "Kitchen".to_string(),
+            description: None,
+            notes: strings(&["C4", "E4", "G4"]),
+            chords: strings(&["C", "Am"]),
+            created_at: chrono::Utc::now(),
+        };
+        let mut request = ChimeRingRequest {
+            chime_id: "abc".to_string(),
+            user: "alice".to_string(),
+            notes: Some(strings(&["C4", "Z9", "g4"])),
+            chords: Some(strings(&["Am", "Xmaj13"])),
+            duration_ms: None,
+            note_durations: Some(vec![100, 200, 300]),
+            timestamp: chrono::Utc::now(),
+            preset: None,
+            urgency: Urgency::Normal,
+            expires_at: None,
+            request_id: None,
+        };
+
+        assert!(request.has_sounds());
+        assert_eq!(request.restrict_to(&info), strings(&["Z9", "Xmaj13"]));
+        assert_eq!(request.notes, Some(strings(&["C4", "g4"])));
+        assert_eq!(request.note_durations, Some(vec![100, 300]));
+        assert_eq!(request.chords, Some(strings(&["Am"])));
+        assert!(request.has_sounds());
+
+        request.notes = Some(strings(&["B7"]));
+        request.chords = None;
+        assert_eq!(request.restrict_to(&info), strings(&["B7"]));
+        assert!(!request.has_sounds());
+
+        // A chime advertising nothing takes anything
+        let open = ChimeInfo {
+            notes: vec![],
+            chords: vec![],
+            ..info
+        };
+        request.notes = Some(strings(&["B7"]));
+        request.chords = Some(strings(&["Xmaj13"]));
+        assert!(request.restrict_to(&open).is_empty());
+        assert_eq!(request.notes, Some(strings(&["B7"])));
+    }
+}